@@ -0,0 +1,59 @@
+//! Time `resize_lanczos3` against `image`'s `resize_exact` on synthetic pages
+//! of common scan sizes, and report the largest difference between the two.
+//! Run with `cargo bench --bench resize`.
+
+use image::imageops::FilterType;
+use image::{DynamicImage, RgbImage};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use compress_comics::resize_lanczos3;
+
+/// Runs per measurement; the fastest counts
+const RUNS: usize = 5;
+
+fn main() {
+    // (source width, source height, target height)
+    let cases = [(1988, 3056, 1800), (1988, 3056, 1200), (3975, 3056, 1800), (1200, 1800, 2400)];
+    println!("{:<26} {:>10} {:>10} {:>8} {:>9}", "resize", "image ms", "fast ms", "speedup", "max diff");
+    for (width, height, target_height) in cases {
+        let target_width = (width as u64 * target_height as u64 / height as u64) as u32;
+        let rgb = page(width, height);
+        for (kind, img) in [("rgb", rgb.clone()), ("gray", DynamicImage::ImageLuma8(rgb.to_luma8()))] {
+            let (reference, image_time) = fastest(|| img.resize_exact(target_width, target_height, FilterType::Lanczos3));
+            let (fast, fast_time) = fastest(|| resize_lanczos3(&img, target_width, target_height));
+            let max_diff = reference.as_bytes().iter().zip(fast.as_bytes()).map(|(a, b)| a.abs_diff(*b)).max().unwrap_or(0);
+            println!(
+                "{:<26} {:>10.1} {:>10.1} {:>7.1}x {:>9}",
+                format!("{} {}x{} -> {}", kind, width, height, target_height),
+                image_time.as_secs_f64() * 1000.0,
+                fast_time.as_secs_f64() * 1000.0,
+                image_time.as_secs_f64() / fast_time.as_secs_f64(),
+                max_diff
+            );
+        }
+    }
+}
+
+/// A page with flat areas, gradients and hard-edged line work, like a scan
+fn page(width: u32, height: u32) -> DynamicImage {
+    DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+        if (x / 7 + y / 11) % 23 == 0 || (x * 3 + y) % 97 < 2 {
+            return image::Rgb([20, 20, 20]);
+        }
+        let seed = x.wrapping_mul(2_654_435_761) ^ y.wrapping_mul(40_503);
+        image::Rgb([(x * 255 / width) as u8, (y * 255 / height) as u8, 160 + (seed >> 28) as u8])
+    }))
+}
+
+fn fastest(mut resize: impl FnMut() -> DynamicImage) -> (DynamicImage, Duration) {
+    let mut best = Duration::MAX;
+    let mut result = None;
+    for _ in 0..RUNS {
+        let started = Instant::now();
+        let resized = black_box(resize());
+        best = best.min(started.elapsed());
+        result = Some(resized);
+    }
+    (result.expect("at least one run"), best)
+}
@@ -0,0 +1,65 @@
+//! Example compress_comics plugin: pixels lighter than a threshold on every
+//! colour channel become pure white, so yellowed paper, faint bleed-through
+//! and grain in the gutters cost the encoder nothing. Use it as
+//! `--filters "plugin:path/to/libpaper_white.so=220"`; the argument is the
+//! threshold (default 232).
+
+use std::ffi::{c_char, CStr};
+
+/// Plugin interface version this plugin speaks
+const ABI_VERSION: u32 = 1;
+
+const DEFAULT_THRESHOLD: u8 = 232;
+
+/// The interface version to use: ours, when the host speaks it
+#[no_mangle]
+pub extern "C" fn compress_comics_plugin_abi(host: u32) -> u32 {
+    if host >= ABI_VERSION {
+        ABI_VERSION
+    } else {
+        0
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn compress_comics_plugin_name() -> *const c_char {
+    c"paper-white".as_ptr()
+}
+
+/// Whiten the page in place; 1 when a pixel changed, 0 when none did, -1 for an invalid threshold
+///
+/// # Safety
+///
+/// `pixels` must point to `width × height × channels` bytes and `args` to a
+/// NUL-terminated string (or be null), as the host guarantees
+#[no_mangle]
+pub unsafe extern "C" fn compress_comics_plugin_process(
+    pixels: *mut u8,
+    width: u32,
+    height: u32,
+    channels: u32,
+    args: *const c_char,
+) -> i32 {
+    let args = if args.is_null() { "" } else { CStr::from_ptr(args).to_str().unwrap_or("") };
+    let threshold = match args.trim() {
+        "" => DEFAULT_THRESHOLD,
+        value => match value.parse() {
+            Ok(threshold) => threshold,
+            Err(_) => return -1,
+        },
+    };
+    let channels = channels as usize;
+    // Alpha, the fourth channel, is left alone
+    let colour = channels.min(3);
+    let pixels = std::slice::from_raw_parts_mut(pixels, width as usize * height as usize * channels);
+
+    let mut changed = false;
+    for pixel in pixels.chunks_exact_mut(channels) {
+        let pixel = &mut pixel[..colour];
+        if pixel.iter().all(|&level| level >= threshold) && pixel.iter().any(|&level| level < 255) {
+            pixel.fill(255);
+            changed = true;
+        }
+    }
+    changed as i32
+}
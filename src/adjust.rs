@@ -0,0 +1,109 @@
+//! Tone adjustments applied to each page before it is encoded, for washed-out
+//! or dark scans: `--auto-levels` stretches the levels so the darkest and
+//! lightest 0.5% of pixels clip, then `--brightness`, `--contrast` and
+//! `--gamma` apply in that order, all through one lookup table over every
+//! colour channel, so colours keep their balance. An adjusted page always
+//! replaces the source page, even when larger.
+
+use image::{DynamicImage, GenericImageView, GrayImage};
+
+use crate::filters::with_pixels;
+use crate::Options;
+
+/// Share of pixels clipped at each end when stretching the levels
+pub(crate) const LEVELS_CUTOFF: f64 = 0.005;
+
+/// A set of adjustments, as given by the flags or by a `--filters` step
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Tone {
+    pub brightness: i32,
+    pub contrast: i32,
+    pub gamma: f64,
+    pub auto_levels: bool,
+}
+
+impl Tone {
+    pub(crate) const NEUTRAL: Tone = Tone { brightness: 0, contrast: 0, gamma: 1.0, auto_levels: false };
+
+    pub(crate) fn of(args: &Options) -> Tone {
+        Tone { brightness: args.brightness, contrast: args.contrast, gamma: args.gamma, auto_levels: args.auto_levels }
+    }
+}
+
+/// Whether any adjustment was requested
+pub(crate) fn requested(args: &Options) -> bool {
+    Tone::of(args) != Tone::NEUTRAL
+}
+
+/// The page with the adjustments of `tone`; `None` when there are none
+pub(crate) fn apply(img: &DynamicImage, tone: Tone) -> Option<DynamicImage> {
+    if tone == Tone::NEUTRAL {
+        return None;
+    }
+    let (low, high) = if tone.auto_levels { stretch_range(&histogram(img), LEVELS_CUTOFF) } else { (0.0, 255.0) };
+    let brightness = tone.brightness as f64 / 100.0;
+    let contrast = 1.0 + tone.contrast as f64 / 100.0;
+    let table: Vec<u8> = (0..=255)
+        .map(|value| {
+            let mut level = ((value as f64 - low) / (high - low)).clamp(0.0, 1.0);
+            level = (level + brightness).clamp(0.0, 1.0);
+            level = ((level - 0.5) * contrast + 0.5).clamp(0.0, 1.0);
+            // As in ImageMagick, a gamma above 1 brightens the midtones
+            (level.powf(1.0 / tone.gamma) * 255.0).round() as u8
+        })
+        .collect();
+    Some(map_levels(img, &table))
+}
+
+/// Counts of each level over all colour channels (alpha excluded)
+fn histogram(img: &DynamicImage) -> [u64; 256] {
+    match img {
+        DynamicImage::ImageLuma8(page) => gray_histogram(page),
+        _ if !img.color().has_color() => gray_histogram(&img.to_luma8()),
+        _ => {
+            let mut histogram = [0u64; 256];
+            img.pixels().for_each(|(_, _, pixel)| pixel.0[..3].iter().for_each(|&level| histogram[level as usize] += 1));
+            histogram
+        }
+    }
+}
+
+pub(crate) fn gray_histogram(page: &GrayImage) -> [u64; 256] {
+    let mut histogram = [0u64; 256];
+    page.pixels().for_each(|pixel| histogram[pixel.0[0] as usize] += 1);
+    histogram
+}
+
+/// The levels the darkest and lightest `cutoff` share of `histogram` clip at;
+/// the full range when that leaves nothing to stretch (a blank or single-tone page)
+pub(crate) fn stretch_range(histogram: &[u64; 256], cutoff: f64) -> (f64, f64) {
+    let clipped = (histogram.iter().sum::<u64>() as f64 * cutoff) as u64;
+    let low = percentile(histogram.iter().enumerate(), clipped).unwrap_or(0);
+    let high = percentile(histogram.iter().enumerate().rev(), clipped).unwrap_or(255);
+    if high > low {
+        (low as f64, high as f64)
+    } else {
+        (0.0, 255.0)
+    }
+}
+
+/// The first level, walking `levels` in order, past which more than `cutoff` pixels lie
+fn percentile<'a>(levels: impl Iterator<Item = (usize, &'a u64)>, cutoff: u64) -> Option<usize> {
+    let mut seen = 0;
+    for (level, count) in levels {
+        seen += count;
+        if seen > cutoff {
+            return Some(level);
+        }
+    }
+    None
+}
+
+/// `img` with every colour channel mapped through `table`, keeping alpha
+fn map_levels(img: &DynamicImage, table: &[u8]) -> DynamicImage {
+    with_pixels(img, |pixels, channels, colour| {
+        for pixel in pixels.chunks_exact_mut(channels) {
+            pixel[..colour].iter_mut().for_each(|level| *level = table[*level as usize]);
+        }
+    })
+}
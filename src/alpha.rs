@@ -0,0 +1,59 @@
+//! Transparent pages: PNG pages with see-through areas keep their alpha
+//! channel through grayscale conversion and encoding (WebP, JPEG XL and PNG
+//! all store it) instead of turning black where they were transparent.
+//! `--flatten-alpha COLOR` paints them onto a background instead, for readers
+//! that cannot show transparency.
+
+use image::{DynamicImage, GrayImage, Rgb, RgbImage};
+
+/// Whether `img` has an alpha channel with at least one pixel that is not
+/// fully opaque; an opaque alpha channel is dropped when encoding
+pub(crate) fn is_transparent(img: &DynamicImage) -> bool {
+    match img {
+        _ if !img.color().has_alpha() => false,
+        DynamicImage::ImageLumaA8(page) => page.pixels().any(|pixel| pixel[1] < u8::MAX),
+        DynamicImage::ImageRgba8(page) => page.pixels().any(|pixel| pixel[3] < u8::MAX),
+        _ => img.to_rgba8().pixels().any(|pixel| pixel[3] < u8::MAX),
+    }
+}
+
+/// `img` painted onto `background`: grayscale when both are gray
+pub(crate) fn flatten(img: &DynamicImage, background: [u8; 3]) -> DynamicImage {
+    let rgba = img.to_rgba8();
+    let blend = |value: u8, background: u8, alpha: u8| {
+        ((u32::from(value) * u32::from(alpha) + u32::from(background) * u32::from(u8::MAX - alpha) + 127) / 255) as u8
+    };
+    let flattened = RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let [red, green, blue, alpha] = rgba.get_pixel(x, y).0;
+        Rgb([blend(red, background[0], alpha), blend(green, background[1], alpha), blend(blue, background[2], alpha)])
+    });
+    let gray_background = background[0] == background[1] && background[1] == background[2];
+    if gray_background && !img.color().has_color() {
+        let luma = flattened.pixels().map(|pixel| pixel[0]).collect();
+        return DynamicImage::ImageLuma8(GrayImage::from_raw(flattened.width(), flattened.height(), luma).expect("flattened page size"));
+    }
+    DynamicImage::ImageRgb8(flattened)
+}
+
+/// Parse a background colour: `white`, `black`, `gray`, or hex `#rrggbb` / `#rgb`
+pub(crate) fn parse_color(value: &str) -> Result<[u8; 3], String> {
+    let error = || format!("expected white, black, gray or a hex colour such as #f4ecd8, not '{}'", value);
+    match value.trim().to_ascii_lowercase().as_str() {
+        "white" => Ok([255, 255, 255]),
+        "black" => Ok([0, 0, 0]),
+        "gray" | "grey" => Ok([128, 128, 128]),
+        color => {
+            let hex = color.strip_prefix('#').unwrap_or(color);
+            if !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+                return Err(error());
+            }
+            let channel = |digits: &str| u8::from_str_radix(digits, 16).map_err(|_| error());
+            match hex.len() {
+                6 => Ok([channel(&hex[0..2])?, channel(&hex[2..4])?, channel(&hex[4..6])?]),
+                // #rgb doubles each digit, as in CSS
+                3 => Ok([channel(&hex[0..1])? * 17, channel(&hex[1..2])? * 17, channel(&hex[2..3])? * 17]),
+                _ => Err(error()),
+            }
+        }
+    }
+}
@@ -0,0 +1,164 @@
+//! `bench`: encode a sample of a comic's pages at every combination of
+//! formats, qualities and target heights, and print size, SSIM and encode
+//! time per combination, to pick settings from measurements instead of
+//! guesses. Pages go through the same resize, grayscale and line-art steps as
+//! a real run; SSIM is measured against the page resized to the target height.
+
+use anyhow::{Context, Result};
+use image::DynamicImage;
+use rayon::prelude::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitCode};
+use std::time::{Duration, Instant};
+
+use crate::compare::decode_page;
+use crate::{
+    check_cjxl_available, detect_comic_file, encode_image, metrics, page_target_size, resize_lanczos3, sample_indices,
+    temp, unpack_pages, ImageFormat, Options,
+};
+
+/// The combinations `bench` tries
+pub(crate) struct Matrix {
+    pub formats: Vec<ImageFormat>,
+    pub qualities: Vec<u8>,
+    pub heights: Vec<u32>,
+    /// Pages sampled, evenly spaced through the comic
+    pub pages: usize,
+}
+
+/// One combination, summed over the sampled pages
+struct Row {
+    format: ImageFormat,
+    quality: u8,
+    height: u32,
+    bytes: u64,
+    /// Mean and lowest SSIM; `None` when the pages could not be decoded back
+    ssim: Option<(f64, f64)>,
+    encode_time: Duration,
+}
+
+/// Encode the sampled pages of `path` at every combination of `matrix` and print the table
+pub(crate) fn run(path: &Path, matrix: &Matrix) -> Result<ExitCode> {
+    if matrix.qualities.iter().any(|&quality| !(1..=100).contains(&quality)) {
+        anyhow::bail!("Qualities must be between 1 and 100");
+    }
+    if matrix.heights.contains(&0) {
+        anyhow::bail!("Heights must be greater than 0");
+    }
+    if matrix.formats.contains(&ImageFormat::Jxl) {
+        check_cjxl_available()?;
+    }
+
+    let comic_file = detect_comic_file(path)?;
+    let work_dir = temp::create_dir_in(None)?;
+    let all_pages = unpack_pages(&comic_file, work_dir.path()).with_context(|| format!("Failed to read {}", path.display()))?;
+    let sample: Vec<&PathBuf> = sample_indices(all_pages.len(), matrix.pages).into_iter().map(|i| &all_pages[i]).collect();
+    if sample.is_empty() {
+        anyhow::bail!("{} has no pages", path.display());
+    }
+    let pages = sample
+        .par_iter()
+        .map(|page| decode_page(page))
+        .collect::<Result<Vec<DynamicImage>>>()?;
+    let source_bytes: u64 = sample.iter().filter_map(|page| fs::metadata(page).ok()).map(|m| m.len()).sum();
+    let source_size = fs::metadata(path)?.len();
+    println!(
+        "🧪 {} pages of {} sampled ({:.1} MB in the source)",
+        sample.len(),
+        all_pages.len(),
+        source_bytes as f64 / 1_048_576.0
+    );
+
+    let mut rows = Vec::new();
+    for &height in &matrix.heights {
+        let resized: Vec<DynamicImage> = pages.par_iter().map(|page| resize(page, height)).collect();
+        let references: Vec<image::GrayImage> = resized.iter().map(DynamicImage::to_luma8).collect();
+        for &format in &matrix.formats {
+            for &quality in &matrix.qualities {
+                rows.push(measure(&resized, &references, format, quality, height)?);
+            }
+        }
+    }
+
+    println!();
+    println!("{:<6} {:>7} {:>6} {:>10} {:>8} {:>10} {:>9} {:>9} {:>12}",
+        "Format", "Quality", "Height", "Sample KB", "vs src", "Comic MB", "SSIM", "Min SSIM", "ms per page");
+    for row in &rows {
+        let ratio = row.bytes as f64 / source_bytes.max(1) as f64;
+        let (mean, min) = match row.ssim {
+            Some((mean, min)) => (format!("{:.4}", mean), format!("{:.4}", min)),
+            None => ("–".to_string(), "–".to_string()),
+        };
+        println!("{:<6} {:>7} {:>6} {:>10.0} {:>7.0}% {:>10.1} {:>9} {:>9} {:>12.0}",
+            row.format.extension(),
+            row.quality,
+            row.height,
+            row.bytes as f64 / 1024.0,
+            ratio * 100.0,
+            // Pages kept as they are when re-encoding does not help, as in a real run
+            source_size as f64 * ratio.min(1.0) / 1_048_576.0,
+            mean,
+            min,
+            row.encode_time.as_secs_f64() * 1000.0 / pages.len() as f64);
+    }
+    println!();
+    println!("Comic MB estimates the output from the sample; pages detected as line art are stored losslessly at any quality.");
+    Ok(ExitCode::SUCCESS)
+}
+
+/// A page at `height`, with the default fit and resize policy
+fn resize(page: &DynamicImage, height: u32) -> DynamicImage {
+    let args = Options { target_height: height, ..Options::default() };
+    let (width, new_height) = page_target_size(page.width(), page.height(), &args);
+    if new_height == page.height() {
+        return page.clone();
+    }
+    resize_lanczos3(page, width, new_height)
+}
+
+fn measure(
+    pages: &[DynamicImage],
+    references: &[image::GrayImage],
+    format: ImageFormat,
+    quality: u8,
+    height: u32,
+) -> Result<Row> {
+    let args = Options { format, quality, target_height: height, ..Options::default() };
+    let results = pages
+        .par_iter()
+        .zip(references)
+        .map(|(page, reference)| {
+            let started = Instant::now();
+            let (bytes, extension) = encode_image(page, "", &args)?;
+            let encode_time = started.elapsed();
+            let ssim = decode(&bytes, extension).map(|decoded| metrics::ssim(reference, &decoded.to_luma8()));
+            Ok((bytes.len() as u64, encode_time, ssim))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let ssims: Option<Vec<f64>> = results.iter().map(|(_, _, ssim)| *ssim).collect();
+    Ok(Row {
+        format,
+        quality,
+        height,
+        bytes: results.iter().map(|(bytes, _, _)| bytes).sum(),
+        ssim: ssims.map(|ssims| {
+            let mean = ssims.iter().sum::<f64>() / ssims.len() as f64;
+            (mean, ssims.iter().copied().fold(f64::INFINITY, f64::min))
+        }),
+        encode_time: results.iter().map(|(_, time, _)| *time).sum(),
+    })
+}
+
+/// Decode an encoded page back; JPEG XL needs `djxl`, without which there is no SSIM
+fn decode(bytes: &[u8], extension: &str) -> Option<DynamicImage> {
+    if extension != "jxl" {
+        return image::load_from_memory(bytes).ok();
+    }
+    let work_dir = temp::create_dir_in(None).ok()?;
+    let (input, output) = (work_dir.path().join("page.jxl"), work_dir.path().join("page.png"));
+    fs::write(&input, bytes).ok()?;
+    let status = Command::new("djxl").arg(&input).arg(&output).output().ok()?.status;
+    status.success().then(|| image::open(&output).ok()).flatten()
+}
@@ -0,0 +1,265 @@
+//! Checksums of outputs, to catch bit-rot in a library long after a run:
+//! `--checksums` writes each output's SHA-256 as a `sha256sum`-compatible
+//! `<output>.sha256` sidecar or a line in the `SHA256SUMS` manifest of its
+//! folder, and records it with the source's hash in a JSON checksum database.
+//! `verify-library` re-hashes the outputs. A mismatch in a file whose size and
+//! date are unchanged is corruption; one whose date changed was edited since.
+
+use anyhow::{Context, Result};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+use walkdir::WalkDir;
+
+use crate::cli::EXIT_FILES_FAILED;
+use crate::state::{self, FileFingerprint};
+use crate::ChecksumMode;
+
+/// Manifest written in each output folder with `--checksums manifest`
+pub const MANIFEST_NAME: &str = "SHA256SUMS";
+
+/// Default checksum database, created in each output folder
+pub const DATABASE_NAME: &str = ".compress_comics_checksums.json";
+
+const DATABASE_VERSION: u32 = 1;
+
+/// Serializes updates of manifests and databases shared by worker threads
+static UPDATE: Mutex<()> = Mutex::new(());
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Database {
+    version: u32,
+    /// Absolute output path -> its record
+    files: BTreeMap<String, OutputRecord>,
+}
+
+impl Default for Database {
+    fn default() -> Self {
+        Database { version: DATABASE_VERSION, files: BTreeMap::new() }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct OutputRecord {
+    sha256: String,
+    size: u64,
+    modified: u64,
+    source: SourceRecord,
+    recorded_at: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SourceRecord {
+    path: String,
+    sha256: String,
+}
+
+/// Record the finished `output` of `source`, whose hash was taken before processing
+pub(crate) fn record(
+    output: &Path,
+    source: &Path,
+    source_sha256: String,
+    mode: ChecksumMode,
+    database: Option<&Path>,
+) -> Result<()> {
+    let sha256 = state::sha256_file(output).context("Failed to hash output")?;
+    let fingerprint = state::fingerprint_file(output)?;
+    let name = file_name(output);
+    let folder = output.parent().unwrap_or(Path::new("."));
+
+    let _guard = UPDATE.lock().unwrap_or_else(|e| e.into_inner());
+    match mode {
+        ChecksumMode::Sidecar => {
+            let sidecar = sidecar_path(output);
+            fs::write(&sidecar, format!("{}  {}\n", sha256, name))
+                .with_context(|| format!("Failed to write {}", sidecar.display()))?;
+        }
+        ChecksumMode::Manifest => {
+            let manifest = folder.join(MANIFEST_NAME);
+            let mut lines = if manifest.exists() { read_sums(&manifest)? } else { BTreeMap::new() };
+            lines.insert(name, sha256.clone());
+            let content: String = lines.iter().map(|(name, sha256)| format!("{}  {}\n", sha256, name)).collect();
+            replace(&manifest, &content)?;
+        }
+    }
+
+    let database = database.map(Path::to_path_buf).unwrap_or_else(|| folder.join(DATABASE_NAME));
+    let mut records = load_database(&database)?;
+    records.files.insert(
+        fingerprint.path,
+        OutputRecord {
+            sha256,
+            size: fingerprint.size,
+            modified: fingerprint.modified,
+            source: SourceRecord {
+                path: absolute(source).to_string_lossy().into_owned(),
+                sha256: source_sha256,
+            },
+            recorded_at: std::time::SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+        },
+    );
+    replace(&database, &serde_json::to_string_pretty(&records)?)
+}
+
+/// Paths are compared in absolute form, as the database stores them
+fn absolute(path: &Path) -> PathBuf {
+    std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// `<output>.sha256`
+fn sidecar_path(output: &Path) -> PathBuf {
+    let mut path = output.as_os_str().to_owned();
+    path.push(".sha256");
+    PathBuf::from(path)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+/// Write-then-rename so a crash never leaves a truncated file
+fn replace(path: &Path, content: &str) -> Result<()> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    fs::write(&temp_path, content).with_context(|| format!("Failed to write {}", Path::new(&temp_path).display()))?;
+    fs::rename(&temp_path, path).with_context(|| format!("Failed to update {}", path.display()))
+}
+
+fn load_database(path: &Path) -> Result<Database> {
+    if !path.exists() {
+        return Ok(Database::default());
+    }
+    let content = fs::read_to_string(path).with_context(|| format!("Failed to read checksum database {}", path.display()))?;
+    serde_json::from_str(&content).with_context(|| format!("Failed to parse checksum database {}", path.display()))
+}
+
+/// File name -> hash of a `sha256sum` file (`<hash>  <name>`, or `<hash> *<name>` in binary mode)
+fn read_sums(path: &Path) -> Result<BTreeMap<String, String>> {
+    let content = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let mut sums = BTreeMap::new();
+    for line in content.lines().filter(|line| !line.trim().is_empty()) {
+        let Some((sha256, name)) = line.split_once(' ') else {
+            anyhow::bail!("Malformed line in {}: {}", path.display(), line);
+        };
+        let name = name.strip_prefix([' ', '*']).unwrap_or(name);
+        sums.insert(name.to_string(), sha256.to_ascii_lowercase());
+    }
+    Ok(sums)
+}
+
+/// A file `verify-library` checks, and what it was recorded as
+struct Expected {
+    path: PathBuf,
+    sha256: String,
+    /// Size and date from the database; sidecars and manifests only have the hash
+    fingerprint: Option<(u64, u64)>,
+}
+
+enum Outcome {
+    Ok,
+    Missing,
+    /// Contents changed although size and date did not: bit-rot
+    Corrupted,
+    /// Contents and date changed: edited after it was recorded
+    Changed,
+}
+
+/// `verify-library`: re-hash every output recorded in the databases, sidecars
+/// and manifests at `paths` (files, or folders searched recursively). Exits
+/// with 3 when an output is missing or corrupted; edited outputs only warn
+pub fn verify_library(paths: &[PathBuf]) -> Result<ExitCode> {
+    let mut expected: BTreeMap<PathBuf, Expected> = BTreeMap::new();
+    for path in paths {
+        if !path.exists() {
+            anyhow::bail!("{} does not exist", path.display());
+        }
+        for entry in WalkDir::new(path).into_iter().filter_map(|e| e.ok()).filter(|e| e.file_type().is_file()) {
+            for file in recorded_in(entry.path())? {
+                // A database knows more than a sidecar or manifest for the same file
+                if file.fingerprint.is_some() || !expected.contains_key(&file.path) {
+                    expected.insert(file.path.clone(), file);
+                }
+            }
+        }
+    }
+    if expected.is_empty() {
+        anyhow::bail!("No checksums found; write them with --checksums");
+    }
+
+    let outcomes: Vec<(&Expected, Result<Outcome>)> =
+        expected.values().collect::<Vec<_>>().into_par_iter().map(|file| (file, check(file))).collect();
+
+    let (mut ok, mut changed, mut failed) = (0, 0, 0);
+    for (file, outcome) in outcomes {
+        match outcome {
+            Ok(Outcome::Ok) => ok += 1,
+            Ok(Outcome::Changed) => {
+                changed += 1;
+                println!("⚠️  {}: changed since its checksum was recorded", file.path.display());
+            }
+            Ok(Outcome::Missing) => {
+                failed += 1;
+                println!("❌ {}: missing", file.path.display());
+            }
+            Ok(Outcome::Corrupted) => {
+                failed += 1;
+                println!("❌ {}: corrupted (contents differ, size and date unchanged)", file.path.display());
+            }
+            Err(e) => {
+                failed += 1;
+                println!("❌ {}: {:#}", file.path.display(), e);
+            }
+        }
+    }
+    println!("{} file(s) intact, {} changed, {} missing or corrupted", ok, changed, failed);
+    Ok(if failed > 0 { ExitCode::from(EXIT_FILES_FAILED) } else { ExitCode::SUCCESS })
+}
+
+/// Files recorded in `path`, if it is a checksum database, sidecar or manifest
+fn recorded_in(path: &Path) -> Result<Vec<Expected>> {
+    let name = file_name(path);
+    let folder = path.parent().unwrap_or(Path::new("."));
+    if name == DATABASE_NAME || (path.extension().is_some_and(|ext| ext == "json") && is_database(path)) {
+        let database = load_database(path)?;
+        return Ok(database
+            .files
+            .into_iter()
+            .map(|(output, record)| Expected {
+                path: PathBuf::from(output),
+                sha256: record.sha256,
+                fingerprint: Some((record.size, record.modified)),
+            })
+            .collect());
+    }
+    if name == MANIFEST_NAME || name.ends_with(".sha256") {
+        return Ok(read_sums(path)?
+            .into_iter()
+            .map(|(name, sha256)| Expected { path: absolute(&folder.join(name)), sha256, fingerprint: None })
+            .collect());
+    }
+    Ok(Vec::new())
+}
+
+/// Whether a JSON file given as `--checksum-db` is one (other JSON files, such as reports, are not)
+fn is_database(path: &Path) -> bool {
+    fs::read_to_string(path).is_ok_and(|content| serde_json::from_str::<Database>(&content).is_ok())
+}
+
+fn check(file: &Expected) -> Result<Outcome> {
+    if !file.path.exists() {
+        return Ok(Outcome::Missing);
+    }
+    if state::sha256_file(&file.path)? == file.sha256 {
+        return Ok(Outcome::Ok);
+    }
+    let FileFingerprint { size, modified, .. } = state::fingerprint_file(&file.path)?;
+    Ok(match file.fingerprint {
+        Some(recorded) if recorded != (size, modified) => Outcome::Changed,
+        _ => Outcome::Corrupted,
+    })
+}
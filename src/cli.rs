@@ -0,0 +1,1048 @@
+//! The command-line front end: argument and config handling, file discovery,
+//! progress display and the summary.
+
+use anyhow::{Context, Result};
+use clap::parser::ValueSource;
+use clap::{ArgGroup, ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use crossbeam_channel::unbounded;
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use rayon::prelude::*;
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::num::NonZeroUsize;
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+use crate::config::{self, Config};
+use crate::progress::RunProgress;
+use crate::report::{FileRecord, ReportWriter};
+use crate::history::{self, History, StatsFilter, StatsGroup};
+use crate::{bench, checksums, compare, filters, presets, extract, hooks, inspect, interrupt, library_scan, organize, pipe, precheck, remote, retry, selection, serve, split, temp, throttle, timeout, verify, watch};
+use crate::state::{self, StateFile};
+use crate::{
+    check_ddjvu_available, check_rar_available, compile_globs, detect_comic_file, find_comic_files,
+    find_comic_files_by_glob, find_comic_files_from_list, keeps_pdf, matches_any_glob, output_format_for, provenance, settings_fingerprint, sort_queue,
+    ComicFile, ComicType, FileProgress, GrayscaleMode, ImageFormat, Options, OutputFormat, Pipeline, ProgressMode,
+    Report, ReportFormat,
+};
+
+/// Status output: stdout, or stderr while stdout carries a JSON report
+macro_rules! status {
+    ($to_stderr:expr) => {
+        if $to_stderr { eprintln!() } else { println!() }
+    };
+    ($to_stderr:expr, $($arg:tt)*) => {
+        if $to_stderr { eprintln!($($arg)*) } else { println!($($arg)*) }
+    };
+}
+
+/// Exit code when at least one file failed (fatal errors exit with 1, invalid arguments with 2)
+pub const EXIT_FILES_FAILED: u8 = 3;
+
+/// Exit code when `--fail-fast` or `--max-failures` stopped the run before every file was processed
+pub const EXIT_ABORTED: u8 = 4;
+
+/// Exit code when Ctrl-C stopped the run, as shells report a process killed by SIGINT
+pub const EXIT_INTERRUPTED: u8 = 130;
+
+/// Command line: a subcommand, or the compression options of `compress`
+/// (the default when no subcommand is given)
+#[derive(Parser)]
+#[command(author, version, about = "Compress comic book files (CBR/CBZ/CB7/CBT/PDF/EPUB/DjVu) with parallel processing", long_about = None)]
+#[command(args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    options: Options,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Compress comic files (the default command)
+    Compress(Box<Options>),
+    /// Show the pages (format, dimensions, bit depth, colour, estimated DPI, size), ComicInfo metadata and other files of comic files
+    Inspect {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+
+        /// Print a JSON array with one object per file instead
+        #[arg(long)]
+        json: bool,
+
+        /// Only show how compress_comics made each file (version, settings, source name and SHA-256, time), from the marker in its archive comment or PDF info
+        #[arg(long)]
+        provenance: bool,
+    },
+    /// Check that comic files open and every page decodes; exits with 3 when any does not
+    Verify {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Re-hash outputs recorded by --checksums (checksum databases, .sha256 sidecars and SHA256SUMS manifests in the given files and folders) to find missing or corrupted files; exits with 3 when any is
+    VerifyLibrary {
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// Write the pages of a comic file into a folder
+    Extract {
+        file: PathBuf,
+
+        /// Folder to write the pages to; must not exist or be empty (default: the file name without extension, next to the file)
+        #[arg(long, short = 'o', value_name = "DIR")]
+        output_dir: Option<PathBuf>,
+    },
+    /// Split a comic file into several CBZ files by page ranges, chapter markers in page names or size
+    #[command(group(ArgGroup::new("mode").required(true)))]
+    Split {
+        file: PathBuf,
+
+        /// Comma-separated 1-based page ranges, one part each, e.g. "1-24,25-50,51-"
+        #[arg(long, group = "mode", value_name = "RANGES")]
+        pages: Option<String>,
+
+        /// Start a part at each chapter marker in the page names (c001, ch02, Chapter 3)
+        #[arg(long, group = "mode")]
+        chapters: bool,
+
+        /// Start a part before the pages of the current one exceed this size
+        #[arg(long, group = "mode", value_name = "MB", value_parser = clap::value_parser!(u64).range(1..))]
+        max_size_mb: Option<u64>,
+
+        /// Folder to write the parts to (default: next to the file)
+        #[arg(long, short = 'o', value_name = "DIR")]
+        output_dir: Option<PathBuf>,
+    },
+    /// Write before/after crops at 100% zoom with SSIM and PSNR for sampled pages into an HTML page
+    Compare {
+        original: PathBuf,
+        compressed: PathBuf,
+
+        /// Pages compared, evenly spaced through the comic
+        #[arg(long, default_value_t = 4, value_name = "N")]
+        pages: usize,
+
+        /// Side of the square crop from each page's centre, in pixels of the compressed page
+        #[arg(long, default_value_t = 512, value_name = "PX")]
+        crop: u32,
+
+        /// Folder for index.html and the crops (default: the compressed file name with -compare, next to it)
+        #[arg(long, short = 'o', value_name = "DIR")]
+        output_dir: Option<PathBuf>,
+    },
+    /// Sum up the files recorded with --history-db: sizes, savings, pages and processing time
+    Stats {
+        /// History database (default: history-db from the config files)
+        #[arg(long, value_name = "FILE")]
+        history_db: Option<PathBuf>,
+
+        #[command(flatten)]
+        filter: StatsFilter,
+
+        /// Show totals per month, year, quality or format
+        #[arg(long, value_name = "GROUP")]
+        by: Option<StatsGroup>,
+
+        /// Also list every matching file
+        #[arg(long)]
+        files: bool,
+    },
+    /// Encode a sample of a comic's pages at every combination of formats, qualities and heights, and print size, SSIM and encode time for each
+    Bench {
+        file: PathBuf,
+
+        /// Image formats to try, comma-separated
+        #[arg(long, value_delimiter = ',', default_value = "webp", value_name = "FORMATS")]
+        formats: Vec<ImageFormat>,
+
+        /// Qualities to try, comma-separated
+        #[arg(long, value_delimiter = ',', default_value = "70,80,90", value_name = "QUALITIES")]
+        qualities: Vec<u8>,
+
+        /// Target heights to try, comma-separated (pages are only ever downscaled)
+        #[arg(long, value_delimiter = ',', default_value = "1600,1800,2400", value_name = "HEIGHTS")]
+        heights: Vec<u32>,
+
+        /// Pages sampled, evenly spaced through the comic
+        #[arg(long, default_value_t = 4, value_name = "N")]
+        pages: usize,
+    },
+    /// List the presets for --preset: the built-in ones and those of the config files
+    Presets,
+    /// Run an HTTP API that queues compression jobs (submit a path or upload a file, poll status, fetch the report and output)
+    Serve(Box<ServeArgs>),
+    /// Remove temporary folders left behind by crashed or killed runs (every run also does this when it starts)
+    Clean {
+        /// Also clean this folder, as passed to --temp-dir
+        #[arg(long, value_name = "DIR")]
+        temp_dir: Option<PathBuf>,
+    },
+}
+
+#[derive(clap::Args)]
+struct ServeArgs {
+    /// Address to listen on; the API can read and write any path this user can, so keep it local
+    #[arg(long, default_value = "127.0.0.1:8080", value_name = "ADDR")]
+    listen: String,
+
+    /// Jobs processed at the same time (pages within a job are processed in parallel regardless)
+    #[arg(long, default_value = "1", value_name = "N")]
+    workers: NonZeroUsize,
+
+    /// Directory uploaded files are stored in (default: compress_comics-uploads in the system temp directory)
+    #[arg(long, value_name = "DIR")]
+    upload_dir: Option<PathBuf>,
+
+    #[command(flatten)]
+    options: Options,
+}
+
+/// Run the command-line tool
+pub fn run() -> Result<ExitCode> {
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches)?;
+    match cli.command {
+        Some(Command::Compress(options)) => compress(*options, matches.subcommand_matches("compress").unwrap_or(&matches)),
+        Some(Command::Inspect { files, json, provenance }) => inspect::run(&files, json, provenance),
+        Some(Command::Verify { files }) => verify::run(&files),
+        Some(Command::VerifyLibrary { paths }) => checksums::verify_library(&paths),
+        Some(Command::Extract { file, output_dir }) => extract::run(&file, output_dir.as_deref()),
+        Some(Command::Split { file, pages, chapters, max_size_mb, output_dir }) => {
+            let mode = match (pages, max_size_mb) {
+                (Some(ranges), _) => split::Mode::Ranges(ranges),
+                (_, Some(mb)) => split::Mode::MaxSize(mb * 1_048_576),
+                _ if chapters => split::Mode::Chapters,
+                _ => unreachable!("clap requires a split mode"),
+            };
+            split::run(&file, &mode, output_dir.as_deref())
+        }
+        Some(Command::Compare { original, compressed, pages, crop, output_dir }) => {
+            compare::run(&original, &compressed, pages, crop, output_dir.as_deref())
+        }
+        Some(Command::Bench { file, formats, qualities, heights, pages }) => {
+            bench::run(&file, &bench::Matrix { formats, qualities, heights, pages })
+        }
+        Some(Command::Presets) => presets::list(),
+        Some(Command::Stats { history_db, filter, by, files }) => history::stats(history_db.as_deref(), &filter, by, files),
+        Some(Command::Serve(serve)) => {
+            let serve = *serve;
+            let mut options = serve.options;
+            configure(&mut options, matches.subcommand_matches("serve").unwrap_or(&matches), false)?;
+            let upload_dir = serve.upload_dir.unwrap_or_else(|| std::env::temp_dir().join("compress_comics-uploads"));
+            serve::run(&serve.listen, &upload_dir, serve.workers.get(), Pipeline::new(options)?)
+        }
+        Some(Command::Clean { temp_dir }) => {
+            let swept = temp::sweep(temp_dir.as_deref());
+            match swept.dirs {
+                0 => println!("No leftover temporary folders"),
+                dirs => println!("🧹 Removed {} leftover temporary folder(s), {:.1} MB", dirs, swept.bytes as f64 / 1_048_576.0),
+            }
+            Ok(ExitCode::SUCCESS)
+        }
+        None => compress(cli.options, &matches),
+    }
+}
+
+/// Merge config files into `args` and size the worker thread pool
+fn configure(args: &mut Options, matches: &ArgMatches, to_stderr: bool) -> Result<()> {
+    let config_dir = match (&args.glob_pattern, args.input.as_slice()) {
+        _ if args.watch.is_some() => args.watch.clone().unwrap_or_default(),
+        _ if args.files_from.is_some() => PathBuf::from("."),
+        (None, [input]) if input.is_file() => input.parent().map(Path::to_path_buf).unwrap_or_default(),
+        (None, [input]) => input.clone(),
+        _ => PathBuf::from("."),
+    };
+    let mut configured_presets = Default::default();
+    if !args.no_config {
+        let (config, loaded) = match &args.config {
+            Some(path) => (Config::load(path)?, vec![path.clone()]),
+            None => config::load_layered(&config_dir)?,
+        };
+        apply_config(args, &config, matches)?;
+        configured_presets = config.presets;
+        if args.verbose {
+            for path in &loaded {
+                status!(to_stderr, "⚙️  Loaded config {}", path.display());
+            }
+        }
+    }
+    // Over the config file's plain defaults, under the command line
+    if let Some(name) = args.preset.clone() {
+        let from_cli = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+        presets::apply(args, &name, &configured_presets, from_cli)?;
+    }
+
+    // Before any worker thread starts, so that they all inherit the priority
+    if args.nice {
+        if let Err(e) = throttle::lower_priority() {
+            eprintln!("Warning: {:#}", e);
+        }
+    }
+
+    if let Some(threads) = args.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .context("Failed to configure worker threads")?;
+    }
+    Ok(())
+}
+
+/// The directory an INPUT's outputs mirror under `--output-dir`: a file's
+/// own folder, or the directory itself
+fn mirror_root(input_path: &Path) -> PathBuf {
+    if input_path.is_file() {
+        input_path.parent().map(Path::to_path_buf).unwrap_or_default()
+    } else {
+        input_path.to_path_buf()
+    }
+}
+
+/// Move a file that failed for good into `--quarantine-dir`; a file that
+/// cannot be moved is left where it is
+fn quarantine(path: &Path, quarantine_dir: &Path, input_root: &Path, error: &str) {
+    match retry::quarantine(path, quarantine_dir, input_root, error) {
+        Ok(target) => eprintln!("🚧 Quarantined {} as {}", path.display(), target.display()),
+        Err(e) => eprintln!("Warning: Could not quarantine {}: {:#}", path.display(), e),
+    }
+}
+
+/// Show a file's `outcome`: on its bar, or as a line with its sizes and
+/// savings for `--progress plain`
+fn finish_file(
+    bar: &ProgressBar,
+    mode: ProgressMode,
+    to_stderr: bool,
+    comic_file: &ComicFile,
+    file_stats: Option<&Report>,
+    outcome: String,
+) {
+    if mode != ProgressMode::Plain {
+        bar.finish_with_message(outcome);
+        return;
+    }
+    let name = comic_file.path.file_name().unwrap_or_default().to_string_lossy();
+    match file_stats.filter(|stats| stats.original_size > 0) {
+        Some(stats) => status!(to_stderr, "{}: {} — {:.1} MB → {:.1} MB ({:.1}% saved)",
+            name, outcome,
+            stats.original_size as f64 / 1_048_576.0,
+            stats.compressed_size as f64 / 1_048_576.0,
+            (stats.original_size as f64 - stats.compressed_size as f64) / stats.original_size as f64 * 100.0),
+        None => status!(to_stderr, "{}: {}", name, outcome),
+    }
+}
+
+/// The default run: find comic files and process them in parallel
+fn compress(mut args: Options, matches: &ArgMatches) -> Result<ExitCode> {
+    if args.input.iter().any(|input| input == Path::new("-")) {
+        if args.input.len() > 1 {
+            anyhow::bail!("INPUT \"-\" reads one comic from standard input and cannot be combined with other inputs");
+        }
+        args.input.clear();
+        args.stdin = true;
+    }
+    // A report file is always machine-readable
+    let report_format = match (args.report, &args.report_file) {
+        (ReportFormat::Text, Some(_)) => ReportFormat::Json,
+        (format, _) => format,
+    };
+    // Keep stdout clean for a JSON report or the comic written there
+    let to_stderr = (report_format != ReportFormat::Text && args.report_file.is_none()) || args.stdout;
+
+    configure(&mut args, matches, to_stderr)?;
+    let swept = temp::sweep(args.temp_dir.as_deref());
+    if swept.dirs > 0 {
+        status!(to_stderr, "🧹 Removed {} temporary folder(s) left by earlier runs, {:.1} MB", swept.dirs, swept.bytes as f64 / 1_048_576.0);
+    }
+    let staging = remote::Staging::prepare(&mut args)?;
+    let piping = pipe::Piping::prepare(&mut args)?;
+
+    let pipeline = Pipeline::new(args.clone())?;
+
+    if let Some(dir) = &args.watch {
+        return watch::run(dir, &pipeline.with_input_root(dir), &args);
+    }
+
+    interrupt::install();
+    let input_paths = if args.input.is_empty() { vec![PathBuf::from(".")] } else { args.input.clone() };
+
+    if let Some(input_path) = input_paths.iter().find(|input_path| !input_path.exists()) {
+        anyhow::bail!("Input path does not exist: {}", input_path.display());
+    }
+
+    // Directory that --output-dir mirrors: each INPUT's own when there are several
+    let input_root = match input_paths.as_slice() {
+        _ if args.glob_pattern.is_some() || args.files_from.is_some() => PathBuf::from("."),
+        [input_path] => mirror_root(input_path),
+        _ => PathBuf::from("."),
+    };
+    let pipeline = pipeline.with_input_root(&input_root);
+    let mut file_roots = HashMap::new();
+
+    let report = (report_format != ReportFormat::Text)
+        .then(|| ReportWriter::create(report_format, args.report_file.as_deref()))
+        .transpose()?;
+    // A --dry-run processes nothing worth remembering
+    let history = args
+        .history_db
+        .as_deref()
+        .filter(|_| !args.dry_run)
+        .map(|path| History::open(path, &args))
+        .transpose()?;
+
+    let mut comic_files = if let Some(files) = staging.as_ref().and_then(remote::Staging::comic_files) {
+        files
+    } else if let Some(pattern) = &args.glob_pattern {
+        find_comic_files_by_glob(pattern)?
+    } else if let Some(list) = &args.files_from {
+        find_comic_files_from_list(list, &args)?
+    } else if let [input_path] = input_paths.as_slice() {
+        if input_path.is_file() {
+            vec![detect_comic_file(input_path)?]
+        } else {
+            find_comic_files(input_path, &args)?
+        }
+    } else {
+        // A file reached through several inputs is processed once, under the first
+        let mut files = Vec::new();
+        for input_path in &input_paths {
+            let found = if input_path.is_file() { vec![detect_comic_file(input_path)?] } else { find_comic_files(input_path, &args)? };
+            for file in found {
+                if !file_roots.contains_key(&file.path) {
+                    file_roots.insert(file.path.clone(), mirror_root(input_path));
+                    files.push(file);
+                }
+            }
+        }
+        files
+    };
+    let root_of = |path: &Path| file_roots.get(path).unwrap_or(&input_root).clone();
+
+    if !args.include.is_empty() {
+        let patterns = compile_globs(&args.include)?;
+        comic_files.retain(|file| matches_any_glob(&file.path, &root_of(&file.path), &patterns));
+    }
+    if !args.exclude.is_empty() {
+        let patterns = compile_globs(&args.exclude)?;
+        comic_files.retain(|file| !matches_any_glob(&file.path, &root_of(&file.path), &patterns));
+    }
+
+    if args.min_size.is_some() || args.max_size.is_some() || args.newer_than.is_some() || args.older_than.is_some() {
+        comic_files.retain(|file| selection::matches(&file.path, &args));
+    }
+
+    // Quarantined files wait for a person, also when the folder is inside the library
+    if let Some(quarantined) = args.quarantine_dir.as_deref().and_then(|dir| fs::canonicalize(dir).ok()) {
+        comic_files.retain(|file| !fs::canonicalize(&file.path).is_ok_and(|path| path.starts_with(&quarantined)));
+    }
+
+    if args.skip_processed {
+        let found = comic_files.len();
+        comic_files.retain(|file| {
+            !provenance::is_tool_artifact(&file.path) && provenance::read_marker(&file.path).is_none()
+        });
+        if found > comic_files.len() {
+            status!(to_stderr, "⏩ Skipping {} file(s) already processed by compress_comics", found - comic_files.len());
+        }
+    }
+
+    let settings = settings_fingerprint(&args);
+    let job_state = if (args.resume || args.state_file.is_some()) && !args.dry_run {
+        let state_path = args.state_file.clone().unwrap_or_else(|| input_root.join(state::STATE_FILE_NAME));
+        Some(StateFile::load(&state_path)?)
+    } else {
+        None
+    };
+
+    if let Some(job_state) = &job_state {
+        let found = comic_files.len();
+        comic_files.retain(|file| !job_state.is_completed(&file.path, &settings));
+        if found > comic_files.len() {
+            status!(to_stderr, 
+                "⏩ Resuming from {}: skipping {} already processed file(s)",
+                job_state.path().display(),
+                found - comic_files.len()
+            );
+        }
+        if found > 0 && comic_files.is_empty() {
+            status!(to_stderr, "All {} file(s) were already processed with these settings.", found);
+            return report.map_or(Ok(()), ReportWriter::finish).map(|_| ExitCode::SUCCESS);
+        }
+    }
+
+    if comic_files.is_empty() {
+        if args.glob_pattern.is_some() {
+            // Error message already printed in find_comic_files_by_glob
+        } else if args.files_from.is_some() {
+            status!(to_stderr, "No comic files found in the file list.");
+        } else {
+            status!(to_stderr, "No comic files found in the specified path.");
+        }
+        return report.map_or(Ok(()), ReportWriter::finish).map(|_| ExitCode::SUCCESS);
+    }
+
+    if args.format == ImageFormat::Jxl
+        && comic_files.iter().any(|f| output_format_for(f, &args) == OutputFormat::Pdf && !keeps_pdf(f, &args))
+    {
+        anyhow::bail!("PDF output cannot embed JPEG XL pages; use --format webp or another --output-format");
+    }
+
+    if comic_files.iter().any(|f| output_format_for(f, &args) == OutputFormat::Cbr) {
+        check_rar_available()?;
+    }
+
+    if comic_files.iter().any(|f| matches!(f.file_type, ComicType::Djvu)) {
+        check_ddjvu_available()?;
+    }
+
+    if let Some(order) = args.order {
+        sort_queue(&mut comic_files, order);
+    }
+
+    if args.verbose {
+        status!(to_stderr, "📁 Found files:");
+        for file in &comic_files {
+            status!(to_stderr, "   - {}", file.path.display());
+        }
+        status!(to_stderr);
+    }
+
+    status!(to_stderr, "🚀 Found {} comic file(s) to process", comic_files.len());
+    if args.dry_run {
+        status!(to_stderr, "Dry run: estimating from {} sampled page(s) per file, no output will be written", args.sample_pages);
+    }
+    if args.skip_compression {
+        status!(to_stderr, "Mode: Format conversion (no image compression)");
+    } else {
+        status!(to_stderr, 
+            "Settings: Format={}, Quality={}, Target Height={}px",
+            args.format.extension().to_uppercase(), args.quality, args.target_height
+        );
+        if args.jxl_lossless_jpeg {
+            status!(to_stderr, "JPEG pages: lossless JPEG XL transcoding");
+        }
+        if args.lossless {
+            status!(to_stderr, "Pages: lossless encoding");
+        }
+        if args.format == ImageFormat::Webp && (args.webp_method != 4 || args.webp_sharp_yuv || args.webp_near_lossless.is_some()) {
+            let mut tuning = vec![format!("method {}", args.webp_method)];
+            if args.webp_sharp_yuv {
+                tuning.push("sharp YUV".to_string());
+            }
+            if let Some(level) = args.webp_near_lossless {
+                tuning.push(format!("near-lossless {}", level));
+            }
+            status!(to_stderr, "WebP: {}", tuning.join(", "));
+        }
+        if args.grayscale == GrayscaleMode::Force {
+            status!(to_stderr, "Pages: forced grayscale");
+        }
+    }
+    status!(to_stderr, "-----------------------------------------------------");
+
+    let stats = Arc::new(Mutex::new(HashMap::new()));
+
+    // Damaged sources count as failed files, but do not stop the run like --fail-fast failures
+    let mut damaged = 0;
+    if args.precheck {
+        status!(to_stderr, "🔎 Checking {} source file(s) for damage...", comic_files.len());
+        let damaged_files = precheck::scan(&comic_files);
+        if !damaged_files.is_empty() {
+            let list_dir = args.output_dir.as_deref().unwrap_or(&input_root);
+            let list_path = precheck::write_list(list_dir, &damaged_files)?;
+            status!(to_stderr, "⚠️  {} damaged source file(s) will not be processed; listed in {}", damaged_files.len(), list_path.display());
+            for (path, reason) in damaged_files {
+                let file_stats = failed_report(&path, format!("Damaged source: {}", reason));
+                if let Some(report) = &report {
+                    if let Err(e) = report.record(FileRecord::new(&path, &file_stats, Default::default(), Vec::new())) {
+                        eprintln!("Warning: Failed to write report: {}", e);
+                    }
+                }
+                hooks::after_file(&args, &path, &file_stats);
+                if let Some(dir) = &args.quarantine_dir {
+                    quarantine(&path, dir, &root_of(&path), &format!("Damaged source: {}", reason));
+                }
+                comic_files.retain(|file| file.path != path);
+                stats.lock().unwrap().insert(path, file_stats);
+                damaged += 1;
+            }
+        }
+        status!(to_stderr, "-----------------------------------------------------");
+    }
+
+    let progress_mode = args.progress.unwrap_or_else(|| {
+        if io::stdout().is_terminal() && io::stderr().is_terminal() { ProgressMode::Bar } else { ProgressMode::Plain }
+    });
+    let multi_progress = Arc::new(match progress_mode {
+        ProgressMode::Bar => MultiProgress::new(),
+        ProgressMode::Plain | ProgressMode::None => MultiProgress::with_draw_target(ProgressDrawTarget::hidden()),
+    });
+    let overall_bar = multi_progress.add(ProgressBar::new(0));
+    overall_bar.set_style(
+        ProgressStyle::default_bar()
+            .template("{spinner:.green} {msg} {bytes}/{total_bytes} [{elapsed} < {eta}] [{bar:40.cyan/blue}]")?
+            .progress_chars("█▉▊▋▌▍▎▏ "),
+    );
+    let overall_progress = RunProgress::new(overall_bar, &comic_files);
+
+    let max_failures = if args.fail_fast { Some(1) } else { args.max_failures.map(NonZeroUsize::get) };
+    let failures = AtomicUsize::new(0);
+
+    // Timed files wait on their worker, which must not hold a pool thread the pages need
+    let queued = args.order.is_some() || args.timeout_per_file.is_some() || args.timeout_per_page.is_some();
+    for_each_file(&comic_files, args.file_parallelism, queued, |comic_file| {
+        // Files already running finish; no new ones start once the limit is reached
+        if max_failures.is_some_and(|max| failures.load(Ordering::SeqCst) >= max) || interrupt::requested() {
+            return;
+        }
+        let file_progress = multi_progress.add(ProgressBar::new(100));
+        let style_result = ProgressStyle::default_bar()
+            .template("  {msg} [{elapsed_precise}] [{bar:30.green/yellow}] {percent}%")
+            .unwrap()
+            .progress_chars("█▉▊▋▌▍▎▏ ");
+        file_progress.set_style(style_result);
+        file_progress.set_message(format!(
+            "{}",
+            comic_file.path.file_name().unwrap().to_string_lossy()
+        ));
+
+        let (page_sender, page_events) = unbounded();
+        // Started after the first download, so a remote file counts with its real size
+        let mut share = None;
+        let started = Instant::now();
+        let file_pipeline = file_roots.get(&comic_file.path).map(|root| pipeline.clone().with_input_root(root));
+        let mut source_fingerprint;
+        let mut attempt = 0;
+        let result = loop {
+            // Remote sources are downloaded only now, so only the files in flight take space
+            let fetched = staging.as_ref().map_or(Ok(()), |staging| staging.fetch(comic_file));
+            let share = share.get_or_insert_with(|| overall_progress.start(comic_file));
+
+            // Hash before processing: --in-place and --rename-original move the source
+            source_fingerprint = job_state.as_ref().map(|_| state::fingerprint_source(&comic_file.path));
+
+            // A fresh one each attempt: a timed-out attempt leaves its progress cancelled
+            let mut progress = FileProgress::new(file_progress.clone()).with_share(share.clone());
+            if report.is_some() || history.is_some() {
+                progress = progress.with_events(page_sender.clone());
+            }
+            let result = fetched
+                .and_then(|()| file_pipeline.as_ref().unwrap_or(&pipeline).process(comic_file, &progress))
+                .and_then(|file_stats| match &staging {
+                    Some(staging) => staging.deliver(file_stats),
+                    None => Ok(file_stats),
+                });
+            attempt += 1;
+            match result {
+                // A timed-out attempt's worker may still be running on the same files
+                Err(e) if attempt <= args.retries
+                    && !interrupt::is_interrupted(&e)
+                    && !timeout::is_timed_out(&e)
+                    && !interrupt::requested() =>
+                {
+                    eprintln!("🔁 {}: {:#}; retrying ({} of {})", comic_file.path.display(), e, attempt, args.retries);
+                    // Only the pages of the last attempt are reported
+                    page_events.try_iter().for_each(drop);
+                    thread::sleep(retry::delay(attempt));
+                }
+                result => break result,
+            }
+        };
+        if let Some(staging) = &staging {
+            staging.release(comic_file);
+        }
+        // An interrupted file counts as not processed: nothing is recorded for it
+        if result.as_ref().is_err_and(interrupt::is_interrupted) {
+            finish_file(&file_progress, progress_mode, to_stderr, comic_file, None, "⛔ Interrupted".to_string());
+            return;
+        }
+
+        let file_stats = match result {
+            Ok(file_stats) => {
+                if let (Some(job_state), Some(Ok(source))) = (&job_state, &source_fingerprint) {
+                    if let Err(e) = record_completed(job_state, comic_file, &file_stats, source, &settings, &args) {
+                        eprintln!("Warning: Failed to update state file: {}", e);
+                    }
+                }
+
+                let outcome = if let Some(ref status) = file_stats.status_message {
+                    format!("{} {} ({})",
+                        if file_stats.estimated { "🔍" } else if status.contains("Format") { "⏭️" } else { "✅" },
+                        status, file_stats.page_summary())
+                } else if file_stats.compression_skipped {
+                    format!("⏭️  Skipped - savings below threshold ({})", file_stats.page_summary())
+                } else {
+                    format!("✅ Compressed ({})", file_stats.page_summary())
+                };
+                finish_file(&file_progress, progress_mode, to_stderr, comic_file, Some(&file_stats), outcome);
+                file_stats
+            }
+            Err(e) => {
+                let error_stats = failed_report(&comic_file.path, format!("{:#}", e));
+                failures.fetch_add(1, Ordering::SeqCst);
+                finish_file(&file_progress, progress_mode, to_stderr, comic_file, None, format!("❌ Failed: {}", e));
+                if let Some(dir) = &args.quarantine_dir {
+                    quarantine(&comic_file.path, dir, &root_of(&comic_file.path), &format!("{:#}", e));
+                }
+                error_stats
+            }
+        };
+
+        if report.is_some() || history.is_some() {
+            let record = FileRecord::new(&comic_file.path, &file_stats, started.elapsed(), page_events.try_iter().collect());
+            if let Err(e) = history.as_ref().map_or(Ok(()), |history| history.record(&record)) {
+                eprintln!("Warning: Failed to update history database: {:#}", e);
+            }
+            if let Err(e) = report.as_ref().map_or(Ok(()), |report| report.record(record)) {
+                eprintln!("Warning: Failed to write report: {}", e);
+            }
+        }
+        hooks::after_file(&args, &comic_file.path, &file_stats);
+        stats.lock().unwrap().insert(comic_file.path.clone(), file_stats);
+        if let Some(share) = &share {
+            share.finish();
+        }
+    });
+
+    let not_started = comic_files.len() + damaged - stats.lock().unwrap().len();
+    if not_started > 0 {
+        overall_progress.abandon_with_message("⛔ Stopped early");
+    } else {
+        overall_progress.finish_with_message("🎉 All files processed!");
+    }
+
+    if let Some(staging) = &staging {
+        if let Err(e) = staging.upload_remaining() {
+            eprintln!("Warning: {:#}", e);
+        }
+    }
+
+    if let Some(piping) = &piping {
+        piping.deliver(&stats.lock().unwrap())?;
+    }
+
+    match report {
+        Some(report) => report.finish()?,
+        // The piped comic is the output; its outcome was shown on standard error
+        None if args.stdout => {}
+        None => print_summary(&stats.lock().unwrap()),
+    }
+
+    let changed: BTreeSet<PathBuf> = stats
+        .lock()
+        .unwrap()
+        .iter()
+        .flat_map(|(path, file_stats)| library_scan::changed_folders(path, file_stats))
+        .collect();
+    for scan in library_scan::rescan(&args, &changed) {
+        status!(to_stderr, "📚 {}", scan);
+    }
+
+    let failed = failures.into_inner() + damaged;
+    if interrupt::requested() {
+        eprintln!("⛔ Interrupted; {} file(s) were not processed", not_started);
+        return Ok(ExitCode::from(EXIT_INTERRUPTED));
+    }
+    if not_started > 0 {
+        eprintln!("⛔ Stopped after {} failed file(s); {} file(s) were not processed", failed, not_started);
+        return Ok(ExitCode::from(EXIT_ABORTED));
+    }
+    Ok(if failed > 0 { ExitCode::from(EXIT_FILES_FAILED) } else { ExitCode::SUCCESS })
+}
+
+/// Summary entry of a file that failed with `message`
+pub(crate) fn failed_report(path: &Path, message: String) -> Report {
+    Report {
+        original_size: fs::metadata(path).map(|m| m.len()).unwrap_or(0),
+        compressed_size: 0,
+        images_processed: 0,
+        images_skipped: 0,
+        images_resized_only: 0,
+        page_errors: Vec::new(),
+        removed_pages: Vec::new(),
+        page_text: Vec::new(),
+        compression_skipped: false,
+        estimated: false,
+        output_path: None,
+        error_message: Some(message),
+        status_message: None,
+    }
+}
+
+/// Fill in settings from the config file for every option not given on the command line
+fn apply_config(args: &mut Options, config: &Config, matches: &ArgMatches) -> Result<()> {
+    let from_cli = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+
+    if let (Some(quality), false) = (config.quality, from_cli("quality")) {
+        args.quality = quality;
+    }
+    if let (Some(target_height), false) = (config.target_height, from_cli("target_height")) {
+        args.target_height = target_height;
+    }
+    if let (Some(format), false) = (&config.format, from_cli("format")) {
+        args.format = ImageFormat::from_str(format, true)
+            .map_err(|e| anyhow::anyhow!("Invalid format in config file: {}", e))?;
+    }
+    if let (Some(output_format), false) = (&config.output_format, from_cli("output_format")) {
+        args.output_format = OutputFormat::from_str(output_format, true)
+            .map_err(|e| anyhow::anyhow!("Invalid output-format in config file: {}", e))?;
+    }
+    if let (Some(min_savings), false) = (config.min_savings, from_cli("min_savings")) {
+        args.min_savings = min_savings;
+    }
+    // Output placement defaults would contradict an explicit --in-place
+    if !args.in_place {
+        if args.output_dir.is_none() {
+            args.output_dir = config.output_dir.clone();
+        }
+        if args.name_template.is_none() {
+            args.name_template = config.name_template.clone();
+        }
+    }
+    if args.organize && args.organize_pattern.is_empty() {
+        args.organize_pattern = config
+            .organize_patterns
+            .iter()
+            .map(|pattern| organize::parse_pattern(pattern))
+            .collect::<Result<_, _>>()
+            .map_err(|e| anyhow::anyhow!("Invalid organize-patterns in config file: {}", e))?;
+    }
+    // Exclusions accumulate; command-line inclusions replace the configured ones
+    args.exclude.extend(config.exclude.iter().cloned());
+    if args.include.is_empty() {
+        args.include = config.include.clone();
+    }
+    if let (Some(max_depth), false) = (config.max_depth, from_cli("max_depth")) {
+        args.max_depth = Some(max_depth);
+    }
+    args.follow_symlinks |= config.follow_symlinks.unwrap_or(false);
+    args.one_file_system |= config.one_file_system.unwrap_or(false);
+    args.threads = args.threads.or(config.threads);
+    let processing_flags = ["filters", "denoise", "brightness", "contrast", "gamma", "auto_levels"];
+    if let (Some(filters), false) = (&config.filters, processing_flags.iter().any(|id| from_cli(id))) {
+        args.filters = Some(filters::parse(filters).map_err(|e| anyhow::anyhow!("Invalid filters in config file: {}", e))?);
+    }
+    if args.history_db.is_none() {
+        args.history_db = config.history_db.clone();
+    }
+    if args.preset.is_none() {
+        args.preset = config.preset.clone();
+    }
+    Ok(())
+}
+
+/// Run `f` for every file: on the shared pool, or on `limit` threads of their
+/// own (as many as the pool has when `queued`) that take the files in order,
+/// so that page work still spreads over the whole pool
+fn for_each_file<F: Fn(&ComicFile) + Sync>(files: &[ComicFile], limit: Option<NonZeroUsize>, queued: bool, f: F) {
+    // The pool splits a parallel iterator into chunks, so files only start in
+    // queue order when taken one by one
+    let limit = match limit {
+        Some(limit) => limit,
+        None if queued => NonZeroUsize::new(rayon::current_num_threads()).unwrap_or(NonZeroUsize::MIN),
+        None => {
+            files.par_iter().for_each(&f);
+            return;
+        }
+    };
+    let next = AtomicUsize::new(0);
+    thread::scope(|scope| {
+        for _ in 0..limit.get().min(files.len()) {
+            scope.spawn(|| {
+                while let Some(file) = files.get(next.fetch_add(1, Ordering::SeqCst)) {
+                    f(file);
+                }
+            });
+        }
+    });
+}
+
+pub(crate) fn record_completed(
+    job_state: &StateFile,
+    comic_file: &ComicFile,
+    file_stats: &Report,
+    source: &state::SourceFingerprint,
+    settings: &str,
+    args: &Options,
+) -> Result<()> {
+    let replaced = args.in_place || args.rename_original;
+    let output = file_stats.output_path.as_deref().filter(|_| replaced);
+    job_state.record(&comic_file.path, state::entry_for(source, settings, output)?)?;
+
+    // An in-place result with a new extension is a new path in the library
+    if let Some(output) = output.filter(|output| *output != comic_file.path) {
+        job_state.record(output, state::entry_for(source, settings, Some(output))?)?;
+    }
+    Ok(())
+}
+
+fn print_summary(stats: &HashMap<PathBuf, Report>) {
+    println!("\n📊 Processing Summary:");
+    println!("=====================================================");
+
+    let mut total_original = 0u64;
+    let mut total_compressed = 0u64;
+    let mut total_images = 0;
+    let mut total_skipped = 0;
+    let mut total_failed = 0;
+    let mut files_compressed = 0u32;
+    let mut files_format_converted = 0u32;
+    let mut files_status_skipped = 0u32;
+    let mut files_with_errors = 0u32;
+    let mut files_estimated = 0u32;
+
+    for (path, stat) in stats {
+        if let Some(error_msg) = &stat.error_message {
+            println!("  ❌ {} — {}", path.file_name().unwrap().to_string_lossy(), error_msg);
+            files_with_errors += 1;
+            continue;
+        }
+
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+
+        if stat.estimated {
+            let savings_pct = if stat.original_size > 0 {
+                ((stat.original_size as f64 - stat.compressed_size as f64) / stat.original_size as f64) * 100.0
+            } else { 0.0 };
+            println!("  🔍 {} — ~{:.1}% estimated savings ({:.1} MB → ~{:.1} MB, {})",
+                name, savings_pct,
+                stat.original_size as f64 / 1_048_576.0,
+                stat.compressed_size as f64 / 1_048_576.0,
+                stat.status_message.as_deref().unwrap_or_default().to_lowercase());
+            files_estimated += 1;
+            total_original += stat.original_size;
+            total_compressed += stat.compressed_size;
+            total_images += stat.images_processed;
+        } else if stat.compression_skipped {
+            if stat.images_processed == 0 && stat.images_skipped == 0 && stat.original_size > 0 {
+                println!("  ⏭️  {} — No images found", name);
+            } else if stat.images_processed == 0 && stat.images_skipped > 0 {
+                let compressed_mb = stat.compressed_size as f64 / 1_048_576.0;
+                println!("  ⏭️  {} — {} images kept as originals ({} MB → {:.1} MB)",
+                    name, stat.images_skipped,
+                    stat.original_size as f64 / 1_048_576.0, compressed_mb);
+            } else if let Some(ref status) = stat.status_message {
+                println!("  ⏭️  {} — Skipped, {} ({:.1} MB → {:.1} MB, {}); original kept",
+                    name, status,
+                    stat.original_size as f64 / 1_048_576.0,
+                    stat.compressed_size as f64 / 1_048_576.0,
+                    stat.page_summary());
+            } else {
+                println!("  ⏭️  {} — Skipped, already optimal; original kept", name);
+            }
+            files_status_skipped += 1;
+            // The original stays, so it counts as both the before and after size
+            total_original += stat.original_size;
+            total_compressed += stat.original_size;
+            total_images += stat.images_processed;
+            total_skipped += stat.images_skipped;
+        } else if let Some(ref status) = stat.status_message {
+            let output_name = stat.output_path
+                .as_ref()
+                .map(|p| p.file_name().unwrap().to_string_lossy().to_string())
+                .unwrap_or_else(|| "unknown".to_string());
+            println!("  ⏭️  {} — {} ({:.1} MB → {:.1} MB, {})",
+                name, status,
+                stat.original_size as f64 / 1_048_576.0,
+                stat.compressed_size as f64 / 1_048_576.0,
+                stat.page_summary());
+            println!("     → {}", output_name);
+            files_format_converted += 1;
+            total_original += stat.original_size;
+            total_compressed += stat.compressed_size;
+            total_images += stat.images_processed;
+            total_skipped += stat.images_skipped;
+        } else {
+            let savings_pct = if stat.original_size > stat.compressed_size {
+                ((stat.original_size - stat.compressed_size) as f64 / stat.original_size as f64) * 100.0
+            } else if stat.original_size == stat.compressed_size {
+                0.0
+            } else {
+                -((stat.compressed_size - stat.original_size) as f64 / stat.original_size as f64) * 100.0
+            };
+            let output_name = stat.output_path
+                .as_ref()
+                .map(|p| p.file_name().unwrap().to_string_lossy().to_string())
+                .unwrap_or_else(|| "unknown".to_string());
+            let diff_mb = if stat.original_size >= stat.compressed_size {
+                (stat.original_size - stat.compressed_size) as f64 / 1_048_576.0
+            } else {
+                -((stat.compressed_size - stat.original_size) as f64 / 1_048_576.0)
+            };
+            println!("  ✅ {} — {:.1}% savings ({:.1} MB {}, {})",
+                name, savings_pct, diff_mb.abs(),
+                if diff_mb >= 0.0 { "saved" } else { "overhead" },
+                stat.page_summary());
+            println!("     → {}", output_name);
+            files_compressed += 1;
+            total_original += stat.original_size;
+            total_compressed += stat.compressed_size;
+            total_images += stat.images_processed;
+            total_skipped += stat.images_skipped;
+        }
+
+        for removed in &stat.removed_pages {
+            println!("     🗑️  {}: duplicate of {}, removed", removed.page, removed.duplicate_of);
+        }
+        for error in &stat.page_errors {
+            println!("     ⚠️  {}: {} — {}", error.page, error.kind, error.message);
+        }
+        total_failed += stat.page_errors.len();
+    }
+
+    let overall_savings = if total_original > total_compressed {
+        ((total_original - total_compressed) as f64 / total_original as f64) * 100.0
+    } else {
+        0.0
+    };
+
+    println!("\n  ── Files ──");
+    if files_estimated > 0 {
+        println!("    Estimated (dry run):           {}", files_estimated);
+    }
+    println!("    Successfully compressed:       {}", files_compressed);
+    if files_format_converted > 0 {
+        println!("    Format converted:              {}", files_format_converted);
+    }
+    if files_status_skipped > 0 {
+        println!("    Skipped (already optimal):     {}", files_status_skipped);
+    }
+    if files_with_errors > 0 {
+        println!("    With errors:                   {}", files_with_errors);
+    }
+
+    println!("\n  ── Images ──");
+    println!("    Processed:  {}", total_images);
+    println!("    Skipped:    {}", total_skipped);
+    if total_failed > 0 {
+        println!("    Failed:     {} (of the skipped)", total_failed);
+    }
+
+    println!("\n  ── Size ──");
+    let total_savings_mb = total_original.saturating_sub(total_compressed) as f64 / 1_048_576.0;
+    println!("    Original:    {:.2} MB", total_original as f64 / 1_048_576.0);
+    println!("    Compressed:  {:.2} MB", total_compressed as f64 / 1_048_576.0);
+    if total_original > total_compressed {
+        println!("    Saved:       {:.2} MB ({:.1}% reduction)", total_savings_mb, overall_savings);
+    } else {
+        println!("    No reduction achieved");
+    }
+
+    if files_estimated > 0 {
+        println!("\n  🔍 Dry run — sizes are estimates from sampled pages; no files were written.");
+    }
+
+    if files_status_skipped > 0 {
+        println!("\n  💡 {} file(s) skipped as already optimal — originals left untouched.", files_status_skipped);
+    }
+
+    if files_with_errors > 0 {
+        println!("\n  ⚠️  {} file(s) had errors.", files_with_errors);
+    }
+}
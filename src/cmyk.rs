@@ -0,0 +1,133 @@
+//! CMYK JPEGs, common among the pages of publisher PDFs: `image` turns them
+//! into RGB with a naive formula that assumes Photoshop's inverted values and
+//! drops the press profile they carry, so they come out dull, or negative
+//! when they were not inverted. They are decoded here instead, to the inks
+//! themselves, and converted to sRGB through their embedded CMYK profile
+//! (unless `--assume-srgb`), or with the naive formula when there is none.
+
+use anyhow::{Context, Result};
+use image::metadata::Orientation;
+use image::{DynamicImage, RgbImage};
+use moxcms::{ColorProfile, DataColorSpace, Layout, TransformOptions};
+use zune_core::bytestream::ZCursor;
+use zune_core::colorspace::ColorSpace;
+use zune_core::options::DecoderOptions;
+use zune_jpeg::JpegDecoder;
+
+/// A CMYK or YCCK JPEG converted to sRGB, with its EXIF orientation; `None`
+/// for every other JPEG, which `image` decodes as usual
+pub(crate) fn decode(data: &[u8], to_srgb: bool) -> Result<Option<(DynamicImage, Orientation)>> {
+    let Some(markers) = scan(data) else { return Ok(None) };
+    if markers.components != 4 {
+        return Ok(None);
+    }
+    let mut decoder = JpegDecoder::new(ZCursor::new(data));
+    decoder.decode_headers().context("Failed to read CMYK JPEG headers")?;
+    let colorspace = decoder.input_colorspace().context("CMYK JPEG without a colour space")?;
+    if !matches!(colorspace, ColorSpace::CMYK | ColorSpace::YCCK) {
+        return Ok(None);
+    }
+    let icc = if to_srgb { decoder.icc_profile() } else { None };
+    let orientation = decoder
+        .exif()
+        .and_then(|exif| Orientation::from_exif_chunk(exif))
+        .unwrap_or(Orientation::NoTransforms);
+    let (width, height) = decoder.dimensions().context("CMYK JPEG without dimensions")?;
+
+    // Asking for the input colour space gets the samples as stored
+    let options = DecoderOptions::default().jpeg_set_out_colorspace(colorspace);
+    let mut decoder = JpegDecoder::new_with_options(ZCursor::new(data), options);
+    let mut samples = decoder.decode().context("Failed to decode CMYK JPEG")?;
+    to_inks(&mut samples, colorspace == ColorSpace::YCCK, markers.adobe);
+
+    let rgb = to_rgb(&samples, icc.as_deref());
+    let img = RgbImage::from_raw(width as u32, height as u32, rgb).context("CMYK JPEG size mismatch")?;
+    Ok(Some((DynamicImage::ImageRgb8(img), orientation)))
+}
+
+/// What the markers before the image data tell
+struct Markers {
+    /// Components in the frame: 4 for CMYK and YCCK
+    components: u8,
+    /// Whether there is an Adobe `APP14` segment, whose writers store CMYK inverted
+    adobe: bool,
+}
+
+/// Walk the segments up to the start of the scan
+fn scan(data: &[u8]) -> Option<Markers> {
+    if data.get(0..2)? != [0xff, 0xd8] {
+        return None;
+    }
+    let mut markers = Markers { components: 0, adobe: false };
+    let mut at = 2;
+    loop {
+        if *data.get(at)? != 0xff {
+            return None;
+        }
+        // Any number of fill bytes may precede a marker
+        while *data.get(at + 1)? == 0xff {
+            at += 1;
+        }
+        let marker = data[at + 1];
+        let length = usize::from(u16::from_be_bytes([*data.get(at + 2)?, *data.get(at + 3)?]));
+        let segment = data.get(at + 4..at + 2 + length)?;
+        match marker {
+            0xda => return Some(markers),
+            // Start of frame: precision, height, width, then the component count
+            0xc0..=0xcf if !matches!(marker, 0xc4 | 0xc8 | 0xcc) => markers.components = *segment.get(5)?,
+            0xee if segment.starts_with(b"Adobe") => markers.adobe = true,
+            _ => {}
+        }
+        at += 2 + length;
+    }
+}
+
+/// Turn decoded samples into ink amounts (0 is no ink), as CMYK profiles
+/// expect: Adobe inverts every channel, and YCCK holds the CMY inks as
+/// YCbCr next to an inverted K
+fn to_inks(samples: &mut [u8], ycck: bool, adobe: bool) {
+    for pixel in samples.chunks_exact_mut(4) {
+        if ycck {
+            let [y, cb, cr, k] = [pixel[0], pixel[1], pixel[2], pixel[3]].map(f32::from);
+            let channel = |value: f32| value.round().clamp(0.0, 255.0) as u8;
+            pixel[0] = channel(y + 1.402 * (cr - 128.0));
+            pixel[1] = channel(y - 0.344_136 * (cb - 128.0) - 0.714_136 * (cr - 128.0));
+            pixel[2] = channel(y + 1.772 * (cb - 128.0));
+            pixel[3] = u8::MAX - k as u8;
+        } else if adobe {
+            for value in pixel.iter_mut() {
+                *value = u8::MAX - *value;
+            }
+        }
+    }
+}
+
+/// CMYK inks (0 is no ink, as PDFs store them) as sRGB pixels, through `icc`
+/// when it is a usable CMYK profile
+pub(crate) fn to_rgb(inks: &[u8], icc: Option<&[u8]>) -> Vec<u8> {
+    icc.and_then(|icc| convert(inks, icc))
+        .unwrap_or_else(|| inks.chunks_exact(4).flat_map(naive_rgb).collect())
+}
+
+/// Inks converted through the page's CMYK profile to sRGB; `None` when the
+/// profile is not a CMYK one or cannot be used
+fn convert(inks: &[u8], icc: &[u8]) -> Option<Vec<u8>> {
+    let source = ColorProfile::new_from_slice(icc).ok()?;
+    if source.color_space != DataColorSpace::Cmyk {
+        return None;
+    }
+    // 8-bit CMYK uses the RGBA layout
+    let transform = source
+        .create_transform_8bit(Layout::Rgba, &ColorProfile::new_srgb(), Layout::Rgb, TransformOptions::default())
+        .ok()?;
+    let mut rgb = vec![0; inks.len() / 4 * 3];
+    transform.transform(inks, &mut rgb).ok()?;
+    Some(rgb)
+}
+
+/// The uncalibrated conversion: each colour reduced by its ink and the black
+fn naive_rgb(inks: &[u8]) -> [u8; 3] {
+    let paper = |ink: u8| u32::from(u8::MAX - ink);
+    let channel = |ink: u8| ((paper(ink) * paper(inks[3]) + 127) / 255) as u8;
+    [channel(inks[0]), channel(inks[1]), channel(inks[2])]
+}
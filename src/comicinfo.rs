@@ -0,0 +1,275 @@
+use anyhow::Result;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// File name used by ComicRack-compatible readers for embedded metadata
+pub const COMICINFO_FILE_NAME: &str = "ComicInfo.xml";
+
+const EMPTY_COMICINFO: &str = "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+<ComicInfo xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" xmlns:xsd=\"http://www.w3.org/2001/XMLSchema\">\n\
+</ComicInfo>\n";
+
+/// A ComicInfo.xml document.
+///
+/// The raw XML is kept and edited in place so that fields this tool does not
+/// know about (and reader-specific extensions) survive a round trip.
+#[derive(Debug, Clone)]
+pub struct ComicInfo {
+    xml: String,
+}
+
+/// Page-related facts written into the `<Pages>` block
+#[derive(Debug, Clone)]
+pub struct PageInfo {
+    pub size: u64,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+
+impl ComicInfo {
+    pub fn new() -> Self {
+        ComicInfo {
+            xml: EMPTY_COMICINFO.to_string(),
+        }
+    }
+
+    pub fn parse(xml: &str) -> Result<Self> {
+        let xml = xml.trim_start_matches('\u{feff}');
+        if find_open_tag(xml, "ComicInfo", 0).is_none() || !xml.contains("</ComicInfo>") {
+            anyhow::bail!("Not a ComicInfo document (missing <ComicInfo> root element)");
+        }
+        Ok(ComicInfo { xml: xml.to_string() })
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let bytes = fs::read(path)?;
+        Self::parse(&String::from_utf8_lossy(&bytes))
+    }
+
+    pub fn to_xml(&self) -> &str {
+        &self.xml
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, &self.xml)?;
+        Ok(())
+    }
+
+    /// Replace the content of a simple element, appending it when missing
+    pub fn set_field(&mut self, name: &str, value: &str) {
+        let escaped = escape(value);
+        if let Some((start, content_start, content_end, end)) = find_element(&self.xml, name) {
+            if content_start == end {
+                // Self-closing `<Name />`: expand into a full element
+                self.xml.replace_range(start..end, &format!("<{}>{}</{}>", name, escaped, name));
+            } else {
+                self.xml.replace_range(content_start..content_end, &escaped);
+            }
+        } else {
+            self.insert_before_root_end(&format!("  <{}>{}</{}>\n", name, escaped, name));
+        }
+    }
+
+    /// Rewrite `PageCount` and the `<Pages>` block to describe `pages`.
+    ///
+    /// Attributes of existing `<Page>` entries (Type, DoublePage, Bookmark, ...)
+    /// are carried over by index; size and dimension attributes are refreshed.
+    pub fn set_pages(&mut self, pages: &[PageInfo]) {
+        self.set_field("PageCount", &pages.len().to_string());
+
+        let existing = self.page_attributes();
+        let entries = pages.iter().enumerate().map(|(index, page)| {
+            let mut attributes: Vec<(String, String)> = vec![("Image".to_string(), index.to_string())];
+            if let Some(previous) = existing.get(index) {
+                for (key, value) in previous {
+                    if !matches!(key.as_str(), "Image" | "ImageSize" | "ImageWidth" | "ImageHeight") {
+                        attributes.push((key.clone(), value.clone()));
+                    }
+                }
+            }
+            attributes.push(("ImageSize".to_string(), page.size.to_string()));
+            if let (Some(width), Some(height)) = (page.width, page.height) {
+                attributes.push(("ImageWidth".to_string(), width.to_string()));
+                attributes.push(("ImageHeight".to_string(), height.to_string()));
+            }
+            attributes
+        });
+        self.write_pages(entries);
+    }
+
+    /// Keep only the `<Page>` entries at `indices`, in that order and
+    /// renumbered, e.g. for one part of a split comic
+    pub fn select_pages(&mut self, indices: &[usize]) {
+        let existing = self.page_attributes();
+        if existing.is_empty() {
+            return;
+        }
+        let entries = indices.iter().filter_map(|&index| existing.get(index)).enumerate().map(|(index, previous)| {
+            let mut attributes = vec![("Image".to_string(), index.to_string())];
+            attributes.extend(previous.iter().filter(|(key, _)| key != "Image").cloned());
+            attributes
+        });
+        self.write_pages(entries);
+    }
+
+    /// Replace the `<Pages>` block with one `<Page>` per attribute list
+    fn write_pages(&mut self, entries: impl Iterator<Item = Vec<(String, String)>>) {
+        let mut block = String::from("<Pages>\n");
+        for attributes in entries {
+            block.push_str("    <Page");
+            for (key, value) in attributes {
+                block.push_str(&format!(" {}=\"{}\"", key, escape(&value)));
+            }
+            block.push_str(" />\n");
+        }
+        block.push_str("  </Pages>");
+
+        if let Some((start, _, _, end)) = find_element(&self.xml, "Pages") {
+            self.xml.replace_range(start..end, &block);
+        } else {
+            self.insert_before_root_end(&format!("  {}\n", block));
+        }
+    }
+
+    /// Attributes of each `<Page>` entry, in document order
+    pub fn page_attributes(&self) -> Vec<Vec<(String, String)>> {
+        let mut pages = Vec::new();
+        let Some((_, content_start, content_end, _)) = find_element(&self.xml, "Pages") else {
+            return pages;
+        };
+        let block = &self.xml[content_start..content_end];
+        let mut pos = 0;
+        while let Some(start) = find_open_tag(block, "Page", pos) {
+            let Some(end) = block[start..].find('>').map(|i| start + i) else { break };
+            let inner = block[start + "<Page".len()..end].trim_end_matches('/');
+            pages.push(parse_attributes(inner));
+            pos = end + 1;
+        }
+        pages
+    }
+
+    /// Simple text elements directly below the root (Series, Title, Writer, ...),
+    /// in document order; empty elements and blocks such as `<Pages>` are left out
+    pub fn fields(&self) -> Vec<(String, String)> {
+        let mut fields = Vec::new();
+        let Some(root) = find_open_tag(&self.xml, "ComicInfo", 0) else {
+            return fields;
+        };
+        let Some(mut pos) = self.xml[root..].find('>').map(|i| root + i + 1) else {
+            return fields;
+        };
+        while let Some(start) = self.xml[pos..].find('<').map(|i| pos + i) {
+            let rest = &self.xml[start + 1..];
+            if rest.starts_with('/') {
+                break;
+            }
+            let Some(open_end) = rest.find('>').map(|i| start + 1 + i) else { break };
+            let tag = &self.xml[start + 1..open_end];
+            let name = tag.split(|c: char| c.is_whitespace() || c == '/').next().unwrap_or_default();
+            if tag.starts_with(['!', '?']) || tag.ends_with('/') || name.is_empty() {
+                pos = open_end + 1;
+                continue;
+            }
+            let close = format!("</{}>", name);
+            let Some(content_end) = self.xml[open_end + 1..].find(&close).map(|i| open_end + 1 + i) else { break };
+            let content = self.xml[open_end + 1..content_end].trim();
+            if !content.is_empty() && !content.contains('<') {
+                fields.push((name.to_string(), unescape(content)));
+            }
+            pos = content_end + close.len();
+        }
+        fields
+    }
+
+    fn insert_before_root_end(&mut self, text: &str) {
+        match self.xml.rfind("</ComicInfo>") {
+            Some(end) => {
+                let line_start = self.xml[..end].trim_end_matches([' ', '\t']).len();
+                let needs_newline = !self.xml[..line_start].ends_with('\n');
+                let insert = if needs_newline { format!("\n{}", text) } else { text.to_string() };
+                self.xml.insert_str(line_start, &insert);
+            }
+            None => self.xml.push_str(text),
+        }
+    }
+}
+
+impl Default for ComicInfo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Locate ComicInfo.xml in an extracted archive, matching the name case-insensitively
+pub fn find_in_dir(dir: &Path) -> Option<PathBuf> {
+    fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .find(|path| {
+            path.is_file()
+                && path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().eq_ignore_ascii_case(COMICINFO_FILE_NAME))
+                    .unwrap_or(false)
+        })
+}
+
+/// Position of `<name` followed by whitespace, `>` or `/`, starting at `from`
+fn find_open_tag(xml: &str, name: &str, from: usize) -> Option<usize> {
+    let needle = format!("<{}", name);
+    let mut pos = from;
+    while let Some(offset) = xml.get(pos..)?.find(&needle) {
+        let start = pos + offset;
+        match xml[start + needle.len()..].chars().next() {
+            Some(c) if c.is_whitespace() || c == '>' || c == '/' => return Some(start),
+            _ => pos = start + needle.len(),
+        }
+    }
+    None
+}
+
+/// Returns (element start, content start, content end, element end) for the
+/// first `<name>` element. Self-closing elements have an empty content range.
+fn find_element(xml: &str, name: &str) -> Option<(usize, usize, usize, usize)> {
+    let start = find_open_tag(xml, name, 0)?;
+    let open_end = start + xml[start..].find('>')?;
+    if xml[..open_end].ends_with('/') {
+        return Some((start, open_end + 1, open_end + 1, open_end + 1));
+    }
+    let close = format!("</{}>", name);
+    let content_end = open_end + 1 + xml[open_end + 1..].find(&close)?;
+    Some((start, open_end + 1, content_end, content_end + close.len()))
+}
+
+fn parse_attributes(inner: &str) -> Vec<(String, String)> {
+    let mut attributes = Vec::new();
+    let mut rest = inner.trim();
+    while let Some(eq) = rest.find('=') {
+        let key = rest[..eq].trim().to_string();
+        let after = rest[eq + 1..].trim_start();
+        let Some(quote) = after.chars().next().filter(|c| *c == '"' || *c == '\'') else { break };
+        let Some(end) = after[1..].find(quote) else { break };
+        attributes.push((key, unescape(&after[1..1 + end])));
+        rest = after[end + 2..].trim_start();
+    }
+    attributes
+}
+
+pub fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+pub fn unescape(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
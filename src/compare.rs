@@ -0,0 +1,170 @@
+//! `compare`: before/after crops at 100% zoom for a sample of pages, with
+//! SSIM and PSNR, written as an HTML page to judge a quality setting before a
+//! batch run. Originals are scaled to the compressed page size, so both crops
+//! show the same region as a reader would display it.
+
+use anyhow::{Context, Result};
+use image::{DynamicImage, ImageReader};
+use rayon::prelude::*;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+use std::process::ExitCode;
+
+use crate::comicinfo::escape;
+use crate::{decode_oriented, detect_comic_file, metrics, page_name, resize_lanczos3, sample_indices, temp, unpack_pages};
+
+/// One sampled page pair
+struct PageComparison {
+    number: usize,
+    original: String,
+    compressed: String,
+    ssim: f64,
+    psnr: f64,
+}
+
+/// Compare `pages` evenly spaced page pairs and write `index.html` with the
+/// crops into `output_dir` (default: `<compressed name>-compare` next to it)
+pub(crate) fn run(
+    original: &Path,
+    compressed: &Path,
+    pages: usize,
+    crop: u32,
+    output_dir: Option<&Path>,
+) -> Result<ExitCode> {
+    let output_dir = match output_dir {
+        Some(dir) => dir.to_path_buf(),
+        None => {
+            let stem = compressed.file_stem().unwrap_or_default().to_string_lossy();
+            compressed.with_file_name(format!("{}-compare", stem))
+        }
+    };
+    fs::create_dir_all(&output_dir).with_context(|| format!("Failed to create {}", output_dir.display()))?;
+
+    let original_dir = temp::create_dir_in(None)?;
+    let compressed_dir = temp::create_dir_in(None)?;
+    let original_pages = unpack_pages(&detect_comic_file(original)?, original_dir.path())
+        .with_context(|| format!("Failed to read {}", original.display()))?;
+    let compressed_pages = unpack_pages(&detect_comic_file(compressed)?, compressed_dir.path())
+        .with_context(|| format!("Failed to read {}", compressed.display()))?;
+    if original_pages.len() != compressed_pages.len() {
+        eprintln!(
+            "⚠️  Page counts differ ({} vs {}); pages are paired by position",
+            original_pages.len(),
+            compressed_pages.len()
+        );
+    }
+
+    let count = original_pages.len().min(compressed_pages.len());
+    let results: Vec<Result<PageComparison>> = sample_indices(count, pages)
+        .into_par_iter()
+        .map(|index| {
+            let (original_page, compressed_page) = (&original_pages[index], &compressed_pages[index]);
+            compare_page(original_page, compressed_page, index + 1, crop, &output_dir).map(|(ssim, psnr)| {
+                PageComparison {
+                    number: index + 1,
+                    original: page_name(original_page, original_dir.path()),
+                    compressed: page_name(compressed_page, compressed_dir.path()),
+                    ssim,
+                    psnr,
+                }
+            })
+        })
+        .collect();
+
+    let mut comparisons = Vec::new();
+    for result in results {
+        match result {
+            Ok(comparison) => {
+                println!(
+                    "  Page {}: {}  SSIM {:.4}  PSNR {:.1} dB",
+                    comparison.number, comparison.compressed, comparison.ssim, comparison.psnr
+                );
+                comparisons.push(comparison);
+            }
+            Err(e) => eprintln!("⚠️  {:#}", e),
+        }
+    }
+    if comparisons.is_empty() {
+        anyhow::bail!("No page pairs could be compared");
+    }
+
+    let mean_ssim = comparisons.iter().map(|c| c.ssim).sum::<f64>() / comparisons.len() as f64;
+    let mean_psnr = comparisons.iter().map(|c| c.psnr).sum::<f64>() / comparisons.len() as f64;
+    println!("  Mean: SSIM {:.4}  PSNR {:.1} dB", mean_ssim, mean_psnr);
+
+    let html_path = output_dir.join("index.html");
+    fs::write(&html_path, render_html(original, compressed, &comparisons, mean_ssim, mean_psnr))
+        .with_context(|| format!("Failed to write {}", html_path.display()))?;
+    println!("🖼️  Comparison written to {}", html_path.display());
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Metrics of one page pair at the compressed size; writes the centre crops
+/// of both as `page_NNN_original.png` and `page_NNN_compressed.png`
+fn compare_page(original: &Path, compressed: &Path, number: usize, crop: u32, output_dir: &Path) -> Result<(f64, f64)> {
+    let compressed_img = decode_page(compressed)?;
+    let (width, height) = (compressed_img.width(), compressed_img.height());
+    let mut original_img = decode_page(original)?;
+    if (original_img.width(), original_img.height()) != (width, height) {
+        original_img = resize_lanczos3(&original_img, width, height);
+    }
+
+    let ssim = metrics::ssim(&original_img.to_luma8(), &compressed_img.to_luma8());
+    let psnr = metrics::psnr(&original_img.to_rgb8(), &compressed_img.to_rgb8());
+
+    let (crop_width, crop_height) = (crop.min(width), crop.min(height));
+    let (x, y) = ((width - crop_width) / 2, (height - crop_height) / 2);
+    for (img, label) in [(&original_img, "original"), (&compressed_img, "compressed")] {
+        let path = output_dir.join(crop_file_name(number, label));
+        img.crop_imm(x, y, crop_width, crop_height)
+            .to_rgb8()
+            .save(&path)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+    Ok((ssim, psnr))
+}
+
+pub(crate) fn decode_page(page: &Path) -> Result<DynamicImage> {
+    let extension = page
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_lowercase)
+        .unwrap_or_default();
+    let (img, _) = decode_oriented(ImageReader::open(page)?.with_guessed_format()?, &extension, true)
+        .with_context(|| format!("Failed to decode {}", page.display()))?;
+    Ok(img)
+}
+
+fn crop_file_name(number: usize, label: &str) -> String {
+    format!("page_{:03}_{}.png", number, label)
+}
+
+fn render_html(original: &Path, compressed: &Path, comparisons: &[PageComparison], ssim: f64, psnr: f64) -> String {
+    let mut rows = String::new();
+    for comparison in comparisons {
+        let _ = write!(
+            rows,
+            "<tr><th colspan=\"2\">Page {}: {} → {} · SSIM {:.4} · PSNR {:.1} dB</th></tr>\n\
+             <tr><td><img src=\"{}\"></td><td><img src=\"{}\"></td></tr>\n",
+            comparison.number,
+            escape(&comparison.original),
+            escape(&comparison.compressed),
+            comparison.ssim,
+            comparison.psnr,
+            crop_file_name(comparison.number, "original"),
+            crop_file_name(comparison.number, "compressed"),
+        );
+    }
+    format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>Comparison</title>\n\
+         <style>body{{font-family:sans-serif}}img{{image-rendering:pixelated}}th{{text-align:left;padding-top:1em}}</style>\n\
+         </head><body>\n<h1>{} vs {}</h1>\n<p>Mean SSIM {:.4} · mean PSNR {:.1} dB</p>\n\
+         <table>\n<tr><th>Original</th><th>Compressed</th></tr>\n{}</table>\n</body></html>\n",
+        escape(&original.file_name().unwrap_or_default().to_string_lossy()),
+        escape(&compressed.file_name().unwrap_or_default().to_string_lossy()),
+        ssim,
+        psnr,
+        rows
+    )
+}
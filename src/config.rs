@@ -0,0 +1,105 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::presets::Preset;
+
+/// Config file name looked up in the user config directory and the input directory
+pub const CONFIG_FILE_NAME: &str = "compress_comics.toml";
+
+/// Defaults loaded from `compress_comics.toml`. Every key is optional; command
+/// line flags always take precedence.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    pub quality: Option<u8>,
+    pub target_height: Option<u32>,
+    pub format: Option<String>,
+    pub output_format: Option<String>,
+    pub output_dir: Option<PathBuf>,
+    pub name_template: Option<String>,
+    /// `--organize-pattern` regexes used when none is given
+    pub organize_patterns: Vec<String>,
+    pub min_savings: Option<f64>,
+    pub exclude: Vec<String>,
+    pub include: Vec<String>,
+    pub max_depth: Option<usize>,
+    pub follow_symlinks: Option<bool>,
+    pub one_file_system: Option<bool>,
+    pub threads: Option<usize>,
+    /// `--filters` chain used when none of the processing flags are given
+    pub filters: Option<String>,
+    pub history_db: Option<PathBuf>,
+    /// Preset used when `--preset` is not given
+    pub preset: Option<String>,
+    /// `[presets.NAME]` tables: presets added to (or replacing) the built-in ones
+    pub presets: BTreeMap<String, Preset>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        toml::from_str(&content).with_context(|| format!("Invalid config file {}", path.display()))
+    }
+
+    /// Overlay `other` on top of `self`; values set in `other` win
+    pub fn merge(self, other: Config) -> Config {
+        Config {
+            quality: other.quality.or(self.quality),
+            target_height: other.target_height.or(self.target_height),
+            format: other.format.or(self.format),
+            output_format: other.output_format.or(self.output_format),
+            output_dir: other.output_dir.or(self.output_dir),
+            name_template: other.name_template.or(self.name_template),
+            organize_patterns: if other.organize_patterns.is_empty() { self.organize_patterns } else { other.organize_patterns },
+            min_savings: other.min_savings.or(self.min_savings),
+            exclude: if other.exclude.is_empty() { self.exclude } else { other.exclude },
+            include: if other.include.is_empty() { self.include } else { other.include },
+            max_depth: other.max_depth.or(self.max_depth),
+            follow_symlinks: other.follow_symlinks.or(self.follow_symlinks),
+            one_file_system: other.one_file_system.or(self.one_file_system),
+            threads: other.threads.or(self.threads),
+            filters: other.filters.or(self.filters),
+            history_db: other.history_db.or(self.history_db),
+            preset: other.preset.or(self.preset),
+            presets: self.presets.into_iter().chain(other.presets).collect(),
+        }
+    }
+}
+
+/// Load the user config and then the per-directory config in `input_dir`,
+/// the latter overriding the former. Returns the merged config and the files
+/// that were read.
+pub fn load_layered(input_dir: &Path) -> Result<(Config, Vec<PathBuf>)> {
+    let mut config = Config::default();
+    let mut loaded = Vec::new();
+
+    let candidates = user_config_path().into_iter().chain(std::iter::once(input_dir.join(CONFIG_FILE_NAME)));
+    for path in candidates {
+        if path.is_file() && !loaded.contains(&path) {
+            config = config.merge(Config::load(&path)?);
+            loaded.push(path);
+        }
+    }
+
+    Ok((config, loaded))
+}
+
+/// `$XDG_CONFIG_HOME/compress_comics/compress_comics.toml`, falling back to
+/// `~/.config/...` (or `%APPDATA%\...` on Windows)
+fn user_config_path() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            if cfg!(windows) {
+                std::env::var_os("APPDATA").map(PathBuf::from)
+            } else {
+                std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config"))
+            }
+        })?;
+    Some(base.join("compress_comics").join(CONFIG_FILE_NAME))
+}
@@ -0,0 +1,81 @@
+//! `--contact-sheet`: a grid of small page thumbnails in reading order,
+//! written next to each output to check page order at a glance (e.g. after
+//! spread splitting or PDF extraction). The sheet is rendered from the output
+//! itself, so it shows exactly the pages a reader will see.
+
+use anyhow::{Context, Result};
+use image::imageops::{self, FilterType};
+use image::{ImageReader, Rgb, RgbImage};
+use rayon::prelude::*;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use crate::{decode_oriented, detect_comic_file, memory, temp, unpack_pages, Options};
+
+const CELL_WIDTH: u32 = 150;
+const CELL_HEIGHT: u32 = 225;
+const GAP: u32 = 8;
+const COLUMNS: u32 = 10;
+const BACKGROUND: Rgb<u8> = Rgb([255, 255, 255]);
+/// Fills the cell of a page that could not be decoded, so gaps stay visible
+const PLACEHOLDER: Rgb<u8> = Rgb([200, 200, 200]);
+const JPEG_QUALITY: u8 = 80;
+
+/// Path of the contact sheet for an output: `<name>.contact.jpg`
+pub(crate) fn sheet_path(output: &Path) -> PathBuf {
+    output.with_extension("contact.jpg")
+}
+
+/// Render the pages of `output` into a grid and write it to [`sheet_path`]
+pub(crate) fn write(output: &Path, args: &Options) -> Result<PathBuf> {
+    let dir = temp::create_dir(args)?;
+    let pages = unpack_pages(&detect_comic_file(output)?, dir.path())?;
+    if pages.is_empty() {
+        anyhow::bail!("no pages found in {}", output.display());
+    }
+
+    let thumbnails: Vec<Option<RgbImage>> = pages.par_iter().map(|page| thumbnail(page, args).ok()).collect();
+
+    let count = thumbnails.len() as u32;
+    let columns = count.min(COLUMNS);
+    let rows = count.div_ceil(COLUMNS);
+    let mut sheet = RgbImage::from_pixel(
+        columns * (CELL_WIDTH + GAP) + GAP,
+        rows * (CELL_HEIGHT + GAP) + GAP,
+        BACKGROUND,
+    );
+    for (index, thumbnail) in thumbnails.iter().enumerate() {
+        let (column, row) = (index as u32 % COLUMNS, index as u32 / COLUMNS);
+        let (x, y) = (GAP + column * (CELL_WIDTH + GAP), GAP + row * (CELL_HEIGHT + GAP));
+        match thumbnail {
+            // Centred in its cell
+            Some(thumbnail) => imageops::replace(
+                &mut sheet,
+                thumbnail,
+                (x + (CELL_WIDTH - thumbnail.width()) / 2) as i64,
+                (y + (CELL_HEIGHT - thumbnail.height()) / 2) as i64,
+            ),
+            None => imageops::replace(&mut sheet, &RgbImage::from_pixel(CELL_WIDTH, CELL_HEIGHT, PLACEHOLDER), x as i64, y as i64),
+        }
+    }
+
+    let path = sheet_path(output);
+    let file = File::create(&path).with_context(|| format!("Failed to create {}", path.display()))?;
+    image::codecs::jpeg::JpegEncoder::new_with_quality(BufWriter::new(file), JPEG_QUALITY)
+        .encode_image(&sheet)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(path)
+}
+
+/// Decode a page and shrink it to fit within one cell
+fn thumbnail(page: &Path, args: &Options) -> Result<RgbImage> {
+    let extension = page
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_lowercase)
+        .unwrap_or_default();
+    let _reservation = memory::reserve_page(args.max_memory, || image::image_dimensions(page).ok());
+    let (img, _) = decode_oriented(ImageReader::open(page)?.with_guessed_format()?, &extension, !args.assume_srgb)?;
+    Ok(img.resize(CELL_WIDTH, CELL_HEIGHT, FilterType::Triangle).to_rgb8())
+}
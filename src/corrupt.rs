@@ -0,0 +1,185 @@
+//! `--tolerate-corrupt`: what happens to pages whose image data does not
+//! decode (truncated or damaged files). They are always listed as page
+//! errors; this chooses whether the damaged bytes are kept, replaced by a
+//! "missing page" placeholder under the page's own name, or fail the file.
+//! The placeholder shows that name, drawn in a small built-in bitmap font.
+
+use anyhow::Result;
+use image::{GrayImage, Luma};
+use std::io::Cursor;
+
+use crate::{PageError, PageErrorKind};
+
+const BACKGROUND: Luma<u8> = Luma([230]);
+const MARK: Luma<u8> = Luma([140]);
+const TEXT: Luma<u8> = Luma([40]);
+/// Width over height of a placeholder for a page without readable dimensions
+const DEFAULT_ASPECT: f64 = 2.0 / 3.0;
+
+/// Whether a page failed because its data is damaged, rather than because
+/// of an unsupported feature or an encoder problem
+pub(crate) fn is_corrupt(error: &PageError) -> bool {
+    error.kind == PageErrorKind::Decode
+}
+
+/// Columns and rows of a glyph in the built-in font
+const GLYPH_WIDTH: u32 = 5;
+const GLYPH_HEIGHT: u32 = 7;
+
+/// A grey page with a border, a cross and the damaged page's `name`, as
+/// PNG. It takes the damaged page's `dimensions` when its header was still
+/// readable, otherwise `fallback_height` at a comic page's aspect ratio
+pub(crate) fn placeholder_png(name: &str, dimensions: Option<(u32, u32)>, fallback_height: u32) -> Result<Vec<u8>> {
+    let (width, height) = dimensions.unwrap_or(((fallback_height as f64 * DEFAULT_ASPECT) as u32, fallback_height));
+    let thickness = (width.min(height) / 100).max(1);
+
+    let mut img = GrayImage::from_pixel(width, height, BACKGROUND);
+    for y in 0..height {
+        for x in 0..width {
+            let on_border = x < thickness || y < thickness || x >= width - thickness || y >= height - thickness;
+            // Distance to both diagonals, scaled to pixels along the x axis
+            let diagonal = y as f64 * width as f64 / height as f64;
+            let on_cross = (x as f64 - diagonal).abs() < thickness as f64
+                || (width as f64 - x as f64 - diagonal).abs() < thickness as f64;
+            if on_border || on_cross {
+                img.put_pixel(x, y, MARK);
+            }
+        }
+    }
+
+    draw_label(&mut img, name);
+
+    let mut bytes = Vec::new();
+    img.write_to(&mut Cursor::new(&mut bytes), image::ImageFormat::Png)?;
+    Ok(bytes)
+}
+
+/// Write `name` centred on the page over a background box, at the largest
+/// whole-pixel scale that fits 80% of the width and a tenth of the height.
+/// Names too long even at scale 1 keep their end, where the page number is
+fn draw_label(img: &mut GrayImage, name: &str) {
+    let (width, height) = img.dimensions();
+    // Each glyph is followed by one column of spacing
+    let advance = GLYPH_WIDTH + 1;
+    let max_chars = (width * 4 / 5 / advance) as usize;
+    let chars: Vec<char> = name.chars().collect();
+    let label: Vec<char> = if chars.len() > max_chars {
+        let kept = max_chars.saturating_sub(3);
+        "...".chars().chain(chars[chars.len() - kept..].iter().copied()).take(max_chars).collect()
+    } else {
+        chars
+    };
+    if label.is_empty() {
+        return;
+    }
+
+    let scale = (width * 4 / 5 / (label.len() as u32 * advance)).min(height / 10 / GLYPH_HEIGHT).max(1);
+    let text_width = (label.len() as u32 * advance - 1) * scale;
+    let text_height = GLYPH_HEIGHT * scale;
+    if text_width > width || text_height > height {
+        return;
+    }
+    let left = (width - text_width) / 2;
+    let top = (height - text_height) / 2;
+
+    let padding = 2 * scale;
+    for y in top.saturating_sub(padding)..(top + text_height + padding).min(height) {
+        for x in left.saturating_sub(padding)..(left + text_width + padding).min(width) {
+            img.put_pixel(x, y, BACKGROUND);
+        }
+    }
+    for (index, c) in label.iter().enumerate() {
+        let glyph_left = left + index as u32 * advance * scale;
+        for (row, bits) in glyph(*c).iter().enumerate() {
+            for column in 0..GLYPH_WIDTH {
+                if bits & (1 << (GLYPH_WIDTH - 1 - column)) == 0 {
+                    continue;
+                }
+                for dy in 0..scale {
+                    for dx in 0..scale {
+                        img.put_pixel(glyph_left + column * scale + dx, top + row as u32 * scale + dy, TEXT);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Rows of a 5×7 glyph, most significant of the five bits on the left.
+/// Letters are drawn in upper case; characters without a glyph become `?`
+fn glyph(c: char) -> [u8; 7] {
+    match c.to_ascii_uppercase() {
+        '0' => [0b01110, 0b10001, 0b10011, 0b10101, 0b11001, 0b10001, 0b01110],
+        '1' => [0b00100, 0b01100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110],
+        '2' => [0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b01000, 0b11111],
+        '3' => [0b11111, 0b00010, 0b00100, 0b00010, 0b00001, 0b10001, 0b01110],
+        '4' => [0b00010, 0b00110, 0b01010, 0b10010, 0b11111, 0b00010, 0b00010],
+        '5' => [0b11111, 0b10000, 0b11110, 0b00001, 0b00001, 0b10001, 0b01110],
+        '6' => [0b00110, 0b01000, 0b10000, 0b11110, 0b10001, 0b10001, 0b01110],
+        '7' => [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b01000, 0b01000],
+        '8' => [0b01110, 0b10001, 0b10001, 0b01110, 0b10001, 0b10001, 0b01110],
+        '9' => [0b01110, 0b10001, 0b10001, 0b01111, 0b00001, 0b00010, 0b01100],
+        'A' => [0b01110, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001],
+        'B' => [0b11110, 0b10001, 0b10001, 0b11110, 0b10001, 0b10001, 0b11110],
+        'C' => [0b01110, 0b10001, 0b10000, 0b10000, 0b10000, 0b10001, 0b01110],
+        'D' => [0b11100, 0b10010, 0b10001, 0b10001, 0b10001, 0b10010, 0b11100],
+        'E' => [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b11111],
+        'F' => [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b10000],
+        'G' => [0b01110, 0b10001, 0b10000, 0b10111, 0b10001, 0b10001, 0b01111],
+        'H' => [0b10001, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001],
+        'I' => [0b01110, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110],
+        'J' => [0b00111, 0b00010, 0b00010, 0b00010, 0b00010, 0b10010, 0b01100],
+        'K' => [0b10001, 0b10010, 0b10100, 0b11000, 0b10100, 0b10010, 0b10001],
+        'L' => [0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b11111],
+        'M' => [0b10001, 0b11011, 0b10101, 0b10101, 0b10001, 0b10001, 0b10001],
+        'N' => [0b10001, 0b10001, 0b11001, 0b10101, 0b10011, 0b10001, 0b10001],
+        'O' => [0b01110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110],
+        'P' => [0b11110, 0b10001, 0b10001, 0b11110, 0b10000, 0b10000, 0b10000],
+        'Q' => [0b01110, 0b10001, 0b10001, 0b10001, 0b10101, 0b10010, 0b01101],
+        'R' => [0b11110, 0b10001, 0b10001, 0b11110, 0b10100, 0b10010, 0b10001],
+        'S' => [0b01111, 0b10000, 0b10000, 0b01110, 0b00001, 0b00001, 0b11110],
+        'T' => [0b11111, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100],
+        'U' => [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110],
+        'V' => [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01010, 0b00100],
+        'W' => [0b10001, 0b10001, 0b10001, 0b10101, 0b10101, 0b10101, 0b01010],
+        'X' => [0b10001, 0b10001, 0b01010, 0b00100, 0b01010, 0b10001, 0b10001],
+        'Y' => [0b10001, 0b10001, 0b10001, 0b01010, 0b00100, 0b00100, 0b00100],
+        'Z' => [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b11111],
+        ' ' => [0; 7],
+        '.' => [0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b01100, 0b01100],
+        ',' => [0b00000, 0b00000, 0b00000, 0b00000, 0b01100, 0b00100, 0b01000],
+        '-' => [0b00000, 0b00000, 0b00000, 0b11111, 0b00000, 0b00000, 0b00000],
+        '_' => [0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b11111],
+        '+' => [0b00000, 0b00100, 0b00100, 0b11111, 0b00100, 0b00100, 0b00000],
+        '/' => [0b00001, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b10000],
+        '(' => [0b00010, 0b00100, 0b01000, 0b01000, 0b01000, 0b00100, 0b00010],
+        ')' => [0b01000, 0b00100, 0b00010, 0b00010, 0b00010, 0b00100, 0b01000],
+        '[' => [0b01110, 0b01000, 0b01000, 0b01000, 0b01000, 0b01000, 0b01110],
+        ']' => [0b01110, 0b00010, 0b00010, 0b00010, 0b00010, 0b00010, 0b01110],
+        '#' => [0b01010, 0b01010, 0b11111, 0b01010, 0b11111, 0b01010, 0b01010],
+        '&' => [0b01100, 0b10010, 0b10100, 0b01000, 0b10101, 0b10010, 0b01101],
+        '\'' => [0b01100, 0b00100, 0b01000, 0b00000, 0b00000, 0b00000, 0b00000],
+        '!' => [0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00000, 0b00100],
+        _ => [0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b00000, 0b00100],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text_pixels(name: &str, width: u32, height: u32) -> usize {
+        let png = placeholder_png(name, Some((width, height)), 0).unwrap();
+        let img = image::load_from_memory(&png).unwrap().to_luma8();
+        img.pixels().filter(|pixel| **pixel == TEXT).count()
+    }
+
+    #[test]
+    fn placeholder_shows_the_page_name() {
+        assert!(text_pixels("001.jpg", 400, 600) > 0);
+        // A name too long for the page is shortened rather than dropped
+        assert!(text_pixels(&"long name ".repeat(20), 120, 180) > 0);
+        // Pages narrower than a glyph only get the cross
+        assert_eq!(text_pixels("001.jpg", 4, 6), 0);
+    }
+}
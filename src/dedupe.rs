@@ -0,0 +1,93 @@
+//! `--dedupe-pages`: drop pages that repeat the page before them, as badly
+//! ripped archives often contain the same scan twice. Pages are compared by a
+//! perceptual fingerprint, so a re-saved or slightly re-compressed copy
+//! counts as a duplicate while different pages with a similar layout do not.
+
+use anyhow::Result;
+use image::imageops::FilterType;
+use image::{GrayImage, ImageReader};
+use rayon::prelude::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::{decode_oriented, memory, page_name, Options, RemovedPage};
+
+/// Side of the grayscale thumbnail compared pixel by pixel
+const THUMBNAIL_SIZE: u32 = 32;
+/// Difference hash over a 17x16 grayscale image: one bit per horizontal neighbour pair
+const HASH_WIDTH: u32 = 17;
+const HASH_HEIGHT: u32 = 16;
+/// Most hash bits (of 256) that may differ between duplicates
+const MAX_HASH_DISTANCE: u32 = 8;
+/// Largest mean absolute thumbnail difference (0-255) between duplicates
+const MAX_THUMBNAIL_DIFFERENCE: f64 = 3.0;
+
+/// What two pages are compared by: the hash catches structure, the thumbnail
+/// brightness (a white and a black page have the same, empty, hash)
+struct Fingerprint {
+    hash: [u64; 4],
+    thumbnail: GrayImage,
+}
+
+impl Fingerprint {
+    fn of(page: &Path, args: &Options) -> Result<Self> {
+        let extension = page
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(str::to_lowercase)
+            .unwrap_or_default();
+        let _reservation = memory::reserve_page(args.max_memory, || image::image_dimensions(page).ok());
+        let (img, _) = decode_oriented(ImageReader::open(page)?.with_guessed_format()?, &extension, !args.assume_srgb)?;
+
+        let small = img.resize_exact(HASH_WIDTH, HASH_HEIGHT, FilterType::Triangle).to_luma8();
+        let mut hash = [0u64; 4];
+        for y in 0..HASH_HEIGHT {
+            for x in 0..HASH_WIDTH - 1 {
+                let bit = (y * (HASH_WIDTH - 1) + x) as usize;
+                if small.get_pixel(x, y)[0] > small.get_pixel(x + 1, y)[0] {
+                    hash[bit / 64] |= 1 << (bit % 64);
+                }
+            }
+        }
+        let thumbnail = img.resize_exact(THUMBNAIL_SIZE, THUMBNAIL_SIZE, FilterType::Triangle).to_luma8();
+        Ok(Fingerprint { hash, thumbnail })
+    }
+
+    fn is_duplicate_of(&self, other: &Fingerprint) -> bool {
+        let distance: u32 = self.hash.iter().zip(&other.hash).map(|(a, b)| (a ^ b).count_ones()).sum();
+        if distance > MAX_HASH_DISTANCE {
+            return false;
+        }
+        let difference: u64 = self
+            .thumbnail
+            .as_raw()
+            .iter()
+            .zip(other.thumbnail.as_raw())
+            .map(|(&a, &b)| a.abs_diff(b) as u64)
+            .sum();
+        difference as f64 / self.thumbnail.as_raw().len() as f64 <= MAX_THUMBNAIL_DIFFERENCE
+    }
+}
+
+/// Delete pages of `dir` that duplicate the last kept page before them;
+/// `pages` are in reading order. Pages that do not decode are never removed
+pub(crate) fn remove_duplicates(dir: &Path, pages: &[PathBuf], args: &Options) -> Result<Vec<RemovedPage>> {
+    let fingerprints: Vec<Option<Fingerprint>> = pages.par_iter().map(|page| Fingerprint::of(page, args).ok()).collect();
+
+    let mut removed = Vec::new();
+    let mut last_kept: Option<(&PathBuf, &Fingerprint)> = None;
+    for (page, fingerprint) in pages.iter().zip(&fingerprints) {
+        let Some(fingerprint) = fingerprint else {
+            last_kept = None;
+            continue;
+        };
+        match last_kept {
+            Some((kept, kept_fingerprint)) if fingerprint.is_duplicate_of(kept_fingerprint) => {
+                fs::remove_file(page)?;
+                removed.push(RemovedPage { page: page_name(page, dir), duplicate_of: page_name(kept, dir) });
+            }
+            _ => last_kept = Some((page, fingerprint)),
+        }
+    }
+    Ok(removed)
+}
@@ -0,0 +1,77 @@
+//! `--denoise`: smooth scanner grain before encoding, since grain costs lossy
+//! encoders most of their bytes. A sigma filter averages each pixel with the
+//! neighbours within a few levels of it, so grain is smoothed while ink lines
+//! and edges, which differ by far more, stay sharp (a median or blur would thin
+//! or soften them). Specks with no similar neighbour take the median of their
+//! window instead. Pages are filtered at their target size, after resizing.
+
+use image::DynamicImage;
+use rayon::prelude::*;
+
+use crate::filters::with_pixels;
+use crate::DenoiseLevel;
+
+/// Window radius and the largest difference, in levels, that still counts as grain
+fn strength(level: DenoiseLevel) -> (usize, u8) {
+    match level {
+        DenoiseLevel::Light => (1, 12),
+        DenoiseLevel::Medium => (2, 20),
+        DenoiseLevel::Strong => (2, 32),
+    }
+}
+
+/// The denoised page; `None` without `--denoise`
+pub(crate) fn apply(img: &DynamicImage, level: Option<DenoiseLevel>) -> Option<DynamicImage> {
+    let strength = strength(level?);
+    if img.width() == 0 || img.height() == 0 {
+        return None;
+    }
+    let width = img.width() as usize;
+    Some(with_pixels(img, |pixels, channels, colour| {
+        let source = pixels.to_vec();
+        sigma_filter(&source, pixels, width, channels, colour, strength);
+    }))
+}
+
+/// Filter the first `colour` of every `channels` interleaved values of `pixels`
+/// into `filtered` (alpha is left alone). Neighbours count as similar when no
+/// colour channel differs by more than `threshold`
+fn sigma_filter(
+    pixels: &[u8],
+    filtered: &mut [u8],
+    width: usize,
+    channels: usize,
+    colour: usize,
+    (radius, threshold): (usize, u8),
+) {
+    let height = pixels.len() / (width * channels);
+    filtered.par_chunks_mut(width * channels).enumerate().for_each(|(y, row)| {
+        let rows = y.saturating_sub(radius)..(y + radius + 1).min(height);
+        let mut window = Vec::with_capacity((2 * radius + 1) * (2 * radius + 1));
+        for x in 0..width {
+            let columns = x.saturating_sub(radius)..(x + radius + 1).min(width);
+            let center = &pixels[(y * width + x) * channels..][..colour];
+            let mut sums = [0u32; 3];
+            let mut similar = 0;
+            for neighbour in rows.clone().flat_map(|ny| columns.clone().map(move |nx| (ny * width + nx) * channels)) {
+                let neighbour = &pixels[neighbour..][..colour];
+                if neighbour.iter().zip(center).all(|(&a, &b)| a.abs_diff(b) <= threshold) {
+                    neighbour.iter().zip(&mut sums).for_each(|(&level, sum)| *sum += level as u32);
+                    similar += 1;
+                }
+            }
+            let out = &mut row[x * channels..][..colour];
+            // Only the pixel itself: a speck of dust or noise, not detail
+            if similar == 1 {
+                for (channel, level) in out.iter_mut().enumerate() {
+                    window.clear();
+                    window.extend(rows.clone().flat_map(|ny| columns.clone().map(move |nx| (ny * width + nx) * channels + channel)).map(|i| pixels[i]));
+                    let middle = window.len() / 2;
+                    *level = *window.select_nth_unstable(middle).1;
+                }
+            } else {
+                out.iter_mut().zip(sums).for_each(|(level, sum)| *level = ((sum + similar / 2) / similar) as u8);
+            }
+        }
+    });
+}
@@ -0,0 +1,68 @@
+//! `--eink`: pages rendered for e-ink panels rather than encoded like photos.
+//! Each page is converted to grayscale, its levels are stretched (the darkest
+//! and lightest 0.5% of pixels clip) and its midtones darkened with a gamma of
+//! 1.8, since e-ink shows them washed out. `--dither` then reduces the page to
+//! the 16 gray levels panels can show, with Floyd–Steinberg error diffusion,
+//! instead of letting the panel band smooth gradients. Pages are stored as
+//! single-channel lossless PNG; a lossy encoder would blur the dither pattern.
+
+use anyhow::Result;
+use image::{DynamicImage, GrayImage};
+
+use crate::adjust::{self, LEVELS_CUTOFF};
+use crate::encode_in_format;
+
+/// Midtone darkening; above 1 darkens
+const GAMMA: f64 = 1.8;
+
+/// Gray levels of a 4-bit e-ink panel
+const LEVELS: u32 = 16;
+
+/// The page as a PNG for e-ink
+pub(crate) fn encode(img: &DynamicImage, dither: bool) -> Result<Vec<u8>> {
+    let mut page = img.to_luma8();
+    darken_levels(&mut page);
+    if dither {
+        dither_to_levels(&mut page);
+    }
+    encode_in_format(&DynamicImage::ImageLuma8(page), image::ImageFormat::Png, 0)
+}
+
+/// Stretch the levels and apply the gamma, through one lookup table
+fn darken_levels(page: &mut GrayImage) {
+    let (low, high) = adjust::stretch_range(&adjust::gray_histogram(page), LEVELS_CUTOFF);
+
+    let table: Vec<u8> = (0..=255)
+        .map(|value| {
+            let stretched = ((value as f64 - low) / (high - low)).clamp(0.0, 1.0);
+            (stretched.powf(GAMMA) * 255.0).round() as u8
+        })
+        .collect();
+    for pixel in page.pixels_mut() {
+        pixel.0[0] = table[pixel.0[0] as usize];
+    }
+}
+
+/// Floyd–Steinberg dithering to `LEVELS` evenly spaced gray levels
+fn dither_to_levels(page: &mut GrayImage) {
+    let (width, height) = (page.width() as usize, page.height() as usize);
+    let step = 255.0 / (LEVELS - 1) as f32;
+    // Errors carried to the current and the next row, with a column of padding on each side
+    let mut current = vec![0f32; width + 2];
+    let mut next = vec![0f32; width + 2];
+    for y in 0..height {
+        for x in 0..width {
+            let pixel = page.get_pixel_mut(x as u32, y as u32);
+            let value = (pixel.0[0] as f32 + current[x + 1]).clamp(0.0, 255.0);
+            let quantized = (value / step).round() * step;
+            pixel.0[0] = quantized.round() as u8;
+            let error = value - quantized;
+            current[x + 2] += error * 7.0 / 16.0;
+            next[x] += error * 3.0 / 16.0;
+            next[x + 1] += error * 5.0 / 16.0;
+            next[x + 2] += error / 16.0;
+        }
+        std::mem::swap(&mut current, &mut next);
+        next.iter_mut().for_each(|error| *error = 0.0);
+    }
+}
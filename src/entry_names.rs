@@ -0,0 +1,61 @@
+//! ZIP entry names that are not UTF-8. The format's legacy encoding is
+//! CP437, but archivers store names in the system code page, so Japanese
+//! releases carry Shift-JIS names (Chinese ones GBK or Big5, Korean ones
+//! EUC-KR) that would come out as mojibake, with stray `\` separators from
+//! Shift-JIS trail bytes. The encoding is detected over all of an archive's
+//! non-UTF-8 names at once. Output archives always get UTF-8 names: the `zip`
+//! crate sets the language-encoding flag on every non-ASCII name it writes.
+
+use anyhow::Result;
+use chardetng::{EncodingDetector, Iso2022JpDetection, Utf8Detection};
+use encoding_rs::{Encoding, BIG5, EUC_JP, EUC_KR, GB18030, GBK, SHIFT_JIS};
+use std::io::{Read, Seek};
+use zip::ZipArchive;
+
+/// Legacy encodings worth detecting; single-byte guesses are no more likely
+/// than CP437, which the `zip` crate already decodes
+const MULTI_BYTE_ENCODINGS: &[&Encoding] = &[SHIFT_JIS, EUC_JP, GBK, GB18030, BIG5, EUC_KR];
+
+/// Names of all entries, in index order. Valid UTF-8 is taken as such, also
+/// without the flag (many archivers omit it)
+pub(crate) fn decoded_names<R: Read + Seek>(archive: &mut ZipArchive<R>) -> Result<Vec<String>> {
+    let mut names = Vec::with_capacity(archive.len());
+    let mut legacy = Vec::new();
+    for index in 0..archive.len() {
+        let entry = archive.by_index_raw(index)?;
+        match std::str::from_utf8(entry.name_raw()) {
+            Ok(name) => names.push(name.to_string()),
+            Err(_) => {
+                names.push(entry.name().to_string());
+                legacy.push((index, entry.name_raw().to_vec()));
+            }
+        }
+    }
+
+    if let Some(encoding) = detect(legacy.iter().map(|(_, raw)| raw.as_slice())) {
+        for (index, raw) in legacy {
+            names[index] = encoding.decode_without_bom_handling(&raw).0.into_owned();
+        }
+    }
+    Ok(names)
+}
+
+/// The multi-byte encoding all `raw_names` are written in, if one fits them
+fn detect<'a>(mut raw_names: impl Iterator<Item = &'a [u8]> + Clone) -> Option<&'static Encoding> {
+    let mut detector = EncodingDetector::new(Iso2022JpDetection::Deny);
+    let mut any = false;
+    for raw in raw_names.clone() {
+        detector.feed(raw, false);
+        // Keeps the end of one name and the start of the next from reading as a character
+        detector.feed(b"\n", false);
+        any = true;
+    }
+    if !any {
+        return None;
+    }
+    detector.feed(b"", true);
+    let encoding = detector.guess(None, Utf8Detection::Deny);
+    let fits = MULTI_BYTE_ENCODINGS.contains(&encoding)
+        && raw_names.all(|raw| encoding.decode_without_bom_handling_and_without_replacement(raw).is_some());
+    fits.then_some(encoding)
+}
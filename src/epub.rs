@@ -0,0 +1,181 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+use zip::{write::FileOptions, ZipWriter};
+
+use crate::times::{self, EntryDates};
+use crate::{throttle, zip_entry_options, ZipCompression};
+
+/// Text files inside an EPUB that can reference images by file name
+const TEXT_EXTENSIONS: &[&str] = &["opf", "xhtml", "html", "htm", "ncx", "css", "xml", "svg"];
+
+pub fn media_type_for(extension: &str) -> Option<&'static str> {
+    match extension.to_lowercase().as_str() {
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "png" => Some("image/png"),
+        "gif" => Some("image/gif"),
+        "webp" => Some("image/webp"),
+        "jxl" => Some("image/jxl"),
+        "bmp" => Some("image/bmp"),
+        "tif" | "tiff" => Some("image/tiff"),
+        _ => None,
+    }
+}
+
+/// Update every reference to re-encoded images (which changed extension) in
+/// the package document, content documents and stylesheets of an extracted EPUB.
+/// `renamed` holds (old path, new path) pairs inside `dir`.
+pub fn rewrite_references(dir: &Path, renamed: &[(PathBuf, PathBuf)]) -> Result<()> {
+    if renamed.is_empty() {
+        return Ok(());
+    }
+
+    let names: Vec<(String, String, &'static str)> = renamed
+        .iter()
+        .filter_map(|(old, new)| {
+            let old_name = old.file_name()?.to_string_lossy().into_owned();
+            let new_name = new.file_name()?.to_string_lossy().into_owned();
+            let media_type = media_type_for(new.extension()?.to_str()?)?;
+            Some((old_name, new_name, media_type))
+        })
+        .collect();
+
+    for entry in WalkDir::new(dir).into_iter().filter_map(|e| e.ok()) {
+        let path = entry.path();
+        let is_text = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| TEXT_EXTENSIONS.contains(&e.to_lowercase().as_str()))
+            .unwrap_or(false);
+        if !entry.file_type().is_file() || !is_text {
+            continue;
+        }
+
+        let Ok(original) = fs::read_to_string(path) else { continue };
+        let mut content = original.clone();
+        for (old_name, new_name, _) in &names {
+            content = replace_file_name(&content, old_name, new_name);
+        }
+        if path.extension().map(|e| e.eq_ignore_ascii_case("opf")).unwrap_or(false) {
+            for (_, new_name, media_type) in &names {
+                content = update_manifest_media_type(&content, new_name, media_type);
+            }
+        }
+        if content != original {
+            fs::write(path, content).with_context(|| format!("Failed to update {}", path.display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Replace `old_name` only where it is a whole file name (preceded by a path
+/// separator, quote or bracket), so `1.jpg` does not match inside `11.jpg`
+fn replace_file_name(content: &str, old_name: &str, new_name: &str) -> String {
+    let mut result = String::with_capacity(content.len());
+    let mut rest = content;
+    while let Some(pos) = rest.find(old_name) {
+        let preceding = rest[..pos].chars().last();
+        let boundary = matches!(preceding, None | Some('/' | '"' | '\'' | '(' | '=' | ' '));
+        result.push_str(&rest[..pos]);
+        result.push_str(if boundary { new_name } else { old_name });
+        rest = &rest[pos + old_name.len()..];
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Set the media-type of the manifest `<item>` whose href ends with `file_name`
+fn update_manifest_media_type(opf: &str, file_name: &str, media_type: &str) -> String {
+    let mut result = opf.to_string();
+    let mut search_from = 0;
+    while let Some(offset) = result[search_from..].find("<item") {
+        let start = search_from + offset;
+        let Some(end) = result[start..].find('>').map(|i| start + i) else { break };
+        let tag = &result[start..end];
+        let references_file = tag.contains(&format!("/{}\"", file_name))
+            || tag.contains(&format!("\"{}\"", file_name))
+            || tag.contains(&format!("/{}'", file_name))
+            || tag.contains(&format!("'{}'", file_name));
+        if references_file {
+            if let Some(attr) = tag.find("media-type=") {
+                let value_start = start + attr + "media-type=".len() + 1;
+                let quote = result[value_start - 1..].chars().next().unwrap_or('"');
+                if let Some(len) = result[value_start..].find(quote) {
+                    result.replace_range(value_start..value_start + len, media_type);
+                }
+            }
+        }
+        search_from = start + 1;
+    }
+    result
+}
+
+/// Set `page-progression-direction` on the spine of every package document
+/// (`"rtl"` for manga), replacing any existing value
+pub fn set_page_progression(dir: &Path, direction: &str) -> Result<()> {
+    for entry in WalkDir::new(dir).into_iter().filter_map(|e| e.ok()) {
+        let path = entry.path();
+        let is_opf = path.extension().map(|e| e.eq_ignore_ascii_case("opf")).unwrap_or(false);
+        if !entry.file_type().is_file() || !is_opf {
+            continue;
+        }
+
+        let Ok(mut content) = fs::read_to_string(path) else { continue };
+        let Some(start) = content.find("<spine") else { continue };
+        let Some(end) = content[start..].find('>').map(|i| start + i) else { continue };
+
+        let attribute = "page-progression-direction=";
+        if let Some(attr) = content[start..end].find(attribute) {
+            let value_start = start + attr + attribute.len() + 1;
+            let quote = content[value_start - 1..].chars().next().unwrap_or('"');
+            if let Some(len) = content[value_start..].find(quote) {
+                content.replace_range(value_start..value_start + len, direction);
+            }
+        } else {
+            content.insert_str(start + "<spine".len(), &format!(" page-progression-direction=\"{}\"", direction));
+        }
+        fs::write(path, content).with_context(|| format!("Failed to update {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Package an extracted EPUB directory. The `mimetype` entry must come first
+/// and be stored uncompressed for readers to recognise the file. Entries are
+/// dated by `dates`, from their files when preserved.
+pub(crate) fn write_archive(
+    dir: &Path,
+    output_path: &Path,
+    comment: &str,
+    compression: ZipCompression,
+    dates: EntryDates,
+) -> Result<()> {
+    let file = throttle::create(output_path)?;
+    let mut zip = ZipWriter::new(file);
+    zip.set_comment(comment)?;
+
+    let mut stored = FileOptions::<()>::default().compression_method(zip::CompressionMethod::Stored);
+    if let Some(modified) = dates.date(|| times::entry_time(&dir.join("mimetype"))) {
+        stored = stored.last_modified_time(modified);
+    }
+    zip.start_file("mimetype", stored)?;
+    zip.write_all(b"application/epub+zip")?;
+
+    for entry in WalkDir::new(dir).sort_by_file_name().into_iter().filter_map(|e| e.ok()) {
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let relative_path = entry.path().strip_prefix(dir)?;
+        if relative_path == Path::new("mimetype") {
+            continue;
+        }
+        let name = relative_path.to_string_lossy().replace('\\', "/");
+        let modified = dates.date(|| times::entry_time(entry.path()));
+        zip.start_file(name.as_str(), zip_entry_options(&name, entry.metadata()?.len(), compression, modified))?;
+        std::io::copy(&mut fs::File::open(entry.path())?, &mut zip)?;
+    }
+
+    zip.finish()?;
+    Ok(())
+}
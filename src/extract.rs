@@ -0,0 +1,42 @@
+//! `extract`: dump a comic's pages into a folder, keeping their names and
+//! reading order.
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use crate::{detect_comic_file, move_file, page_name, temp, unpack_pages};
+
+/// Write the pages of a comic below `dir`, which must not exist or be empty.
+/// Returns the written page paths in reading order
+pub fn extract(path: &Path, dir: &Path) -> Result<Vec<PathBuf>> {
+    let comic_file = detect_comic_file(path)?;
+    if fs::read_dir(dir).map(|mut entries| entries.next().is_some()).unwrap_or(false) {
+        anyhow::bail!("{} is not empty", dir.display());
+    }
+    let temp_dir = temp::create_dir_in(None)?;
+    let pages = unpack_pages(&comic_file, temp_dir.path())?;
+
+    let mut written = Vec::with_capacity(pages.len());
+    for page in &pages {
+        let target = dir.join(page_name(page, temp_dir.path()));
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        move_file(page, &target).with_context(|| format!("Failed to write {}", target.display()))?;
+        written.push(target);
+    }
+    Ok(written)
+}
+
+/// Extract `file` into `dir`, by default a folder named after the file next to it
+pub(crate) fn run(file: &Path, dir: Option<&Path>) -> Result<ExitCode> {
+    let dir = match dir {
+        Some(dir) => dir.to_path_buf(),
+        None => file.with_extension(""),
+    };
+    let pages = extract(file, &dir)?;
+    println!("📂 Extracted {} page(s) to {}", pages.len(), dir.display());
+    Ok(ExitCode::SUCCESS)
+}
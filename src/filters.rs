@@ -0,0 +1,446 @@
+//! `--filters`: the page processing steps as a declarative chain, e.g.
+//! `trim,deskew,resize:1800,denoise:light,sharpen:0.5`, run in the given
+//! order before encoding. Without it the chain is built from the one-off
+//! flags: resize to the target size, then `--denoise`, then the tone
+//! adjustments. Each step is a `PageFilter`; new steps only need an
+//! implementation and an entry in `parse_step`. Steps from outside the
+//! crate come in as plugins (see `plugins.rs`).
+
+use anyhow::Result;
+use image::imageops::FilterType;
+use image::{DynamicImage, GrayImage};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::adjust::{self, Tone};
+use crate::plugins::{self, PageProcessor, Processor};
+use crate::{denoise, gpu, page_target_size, resize_lanczos3, Accel, DenoiseLevel, Options};
+
+/// One processing step
+pub(crate) trait PageFilter: Send + Sync {
+    /// The page after this step; `None` when it leaves the page as it is
+    fn apply(&self, img: &DynamicImage, args: &Options) -> Result<Option<DynamicImage>>;
+
+    /// Whether a page this step changed must replace the source page even
+    /// when larger; steps that only save bytes let a smaller source win
+    fn retouches(&self) -> bool {
+        true
+    }
+
+    /// Whether this step resizes; JPEG 2000 pages are never resized
+    fn resizes(&self) -> bool {
+        false
+    }
+}
+
+/// The processing steps of a run, in order
+#[derive(Clone)]
+pub struct FilterChain {
+    spec: String,
+    steps: Vec<Arc<dyn PageFilter>>,
+}
+
+impl fmt::Debug for FilterChain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.spec)
+    }
+}
+
+impl FilterChain {
+    /// The `--filters` chain, or the one the one-off flags describe
+    pub(crate) fn of(args: &Options) -> FilterChain {
+        if let Some(filters) = &args.filters {
+            return filters.clone();
+        }
+        let mut steps: Vec<Arc<dyn PageFilter>> = vec![Arc::new(Resize { height: None })];
+        if let Some(level) = args.denoise {
+            steps.push(Arc::new(Denoise { level }));
+        }
+        if adjust::requested(args) {
+            steps.push(Arc::new(Adjust { tone: Tone::of(args) }));
+        }
+        FilterChain { spec: String::new(), steps }
+    }
+
+    /// The chain with `processor` as its last step
+    pub fn with_processor(mut self, processor: Arc<dyn PageProcessor>) -> FilterChain {
+        self.spec = format!("{}+{}", self.spec, processor.name());
+        self.steps.push(Arc::new(Processor(processor)));
+        self
+    }
+
+    /// The `--filters` value, for the settings fingerprint
+    pub(crate) fn spec(&self) -> &str {
+        &self.spec
+    }
+
+    /// Whether any step retouches pages
+    pub(crate) fn retouches(&self) -> bool {
+        self.steps.iter().any(|step| step.retouches())
+    }
+
+    /// Run the steps over `img`, skipping resizing steps unless `resize`.
+    /// Returns the page, `None` when no step changed it, and whether a
+    /// retouching step did
+    pub(crate) fn apply(&self, img: &DynamicImage, args: &Options, resize: bool) -> Result<(Option<DynamicImage>, bool)> {
+        let mut page: Option<DynamicImage> = None;
+        let mut retouched = false;
+        for step in self.steps.iter().filter(|step| resize || !step.resizes()) {
+            if let Some(changed) = step.apply(page.as_ref().unwrap_or(img), args)? {
+                retouched |= step.retouches();
+                page = Some(changed);
+            }
+        }
+        Ok((page, retouched))
+    }
+}
+
+impl FromStr for FilterChain {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<FilterChain, String> {
+        parse(spec)
+    }
+}
+
+/// Parse a `--filters` value: comma-separated steps, each `NAME` or `NAME:VALUE`
+pub(crate) fn parse(spec: &str) -> Result<FilterChain, String> {
+    let steps = spec
+        .split(',')
+        .map(str::trim)
+        .filter(|step| !step.is_empty())
+        .map(|step| match step.split_once(':') {
+            Some((name, value)) => parse_step(name.trim(), Some(value.trim())),
+            None => parse_step(step, None),
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    if steps.is_empty() {
+        return Err("no filters given".to_string());
+    }
+    Ok(FilterChain { spec: spec.to_string(), steps })
+}
+
+fn parse_step(name: &str, value: Option<&str>) -> Result<Arc<dyn PageFilter>, String> {
+    let invalid = |value: &str| format!("invalid value for {}: {}", name, value);
+    let number = |range: std::ops::RangeInclusive<i32>| -> Result<i32, String> {
+        let value = value.ok_or_else(|| format!("{} needs a value, e.g. {}:10", name, name))?;
+        value.parse().ok().filter(|number| range.contains(number)).ok_or_else(|| invalid(value))
+    };
+    let step: Arc<dyn PageFilter> = match name {
+        "trim" | "deskew" | "auto-levels" if value.is_some() => return Err(format!("{} takes no value", name)),
+        "trim" => Arc::new(Trim),
+        "deskew" => Arc::new(Deskew),
+        "resize" => Arc::new(Resize {
+            height: value.map(|value| value.parse().ok().filter(|&height| height > 0).ok_or_else(|| invalid(value))).transpose()?,
+        }),
+        "denoise" => Arc::new(Denoise {
+            level: match value {
+                Some(value) => clap::ValueEnum::from_str(value, true).map_err(|_| invalid(value))?,
+                None => DenoiseLevel::Light,
+            },
+        }),
+        "sharpen" => Arc::new(Sharpen {
+            amount: match value {
+                Some(value) => value.parse().ok().filter(|&amount: &f32| amount > 0.0 && amount <= 5.0).ok_or_else(|| invalid(value))?,
+                None => 0.5,
+            },
+        }),
+        "brightness" => Arc::new(Adjust { tone: Tone { brightness: number(-100..=100)?, ..Tone::NEUTRAL } }),
+        "contrast" => Arc::new(Adjust { tone: Tone { contrast: number(-100..=100)?, ..Tone::NEUTRAL } }),
+        "gamma" => {
+            let value = value.ok_or("gamma needs a value, e.g. gamma:1.2")?;
+            let gamma = value.parse().ok().filter(|&gamma: &f64| gamma.is_finite() && gamma > 0.0).ok_or_else(|| invalid(value))?;
+            Arc::new(Adjust { tone: Tone { gamma, ..Tone::NEUTRAL } })
+        }
+        "auto-levels" => Arc::new(Adjust { tone: Tone { auto_levels: true, ..Tone::NEUTRAL } }),
+        "plugin" => {
+            let value = value.ok_or("plugin needs the path of the plugin library, e.g. plugin:./libwatermark.so")?;
+            let (path, args) = value.split_once('=').unwrap_or((value, ""));
+            Arc::new(Processor(plugins::load(Path::new(path), args).map_err(|e| format!("{:#}", e))?))
+        }
+        _ => {
+            return Err(format!(
+                "unknown filter {}; available: trim, deskew, resize[:HEIGHT], denoise[:light|medium|strong], sharpen[:AMOUNT], brightness:N, contrast:N, gamma:F, auto-levels, plugin:PATH[=ARGS]",
+                name
+            ))
+        }
+    };
+    Ok(step)
+}
+
+/// `resize[:HEIGHT]`: resize to the target size, with `HEIGHT` as the target height when given
+struct Resize {
+    height: Option<u32>,
+}
+
+impl PageFilter for Resize {
+    fn apply(&self, img: &DynamicImage, args: &Options) -> Result<Option<DynamicImage>> {
+        let (new_width, new_height) = match self.height {
+            Some(target_height) => page_target_size(img.width(), img.height(), &Options { target_height, ..args.clone() }),
+            None => page_target_size(img.width(), img.height(), args),
+        };
+        if new_height == img.height() {
+            return Ok(None);
+        }
+        let on_gpu = (args.accel == Accel::Gpu).then(|| gpu::resize(img, new_width, new_height)).flatten();
+        Ok(Some(on_gpu.unwrap_or_else(|| resize_lanczos3(img, new_width, new_height))))
+    }
+
+    fn retouches(&self) -> bool {
+        false
+    }
+
+    fn resizes(&self) -> bool {
+        true
+    }
+}
+
+/// `denoise[:LEVEL]`, as `--denoise`
+struct Denoise {
+    level: DenoiseLevel,
+}
+
+impl PageFilter for Denoise {
+    fn apply(&self, img: &DynamicImage, _: &Options) -> Result<Option<DynamicImage>> {
+        Ok(denoise::apply(img, Some(self.level)))
+    }
+
+    fn retouches(&self) -> bool {
+        false
+    }
+}
+
+/// `brightness:N`, `contrast:N`, `gamma:F` and `auto-levels`, as the flags
+struct Adjust {
+    tone: Tone,
+}
+
+impl PageFilter for Adjust {
+    fn apply(&self, img: &DynamicImage, _: &Options) -> Result<Option<DynamicImage>> {
+        Ok(adjust::apply(img, self.tone))
+    }
+}
+
+/// `sharpen[:AMOUNT]`: an unsharp mask adding `AMOUNT` times the detail a 1-pixel blur removes
+struct Sharpen {
+    amount: f32,
+}
+
+impl PageFilter for Sharpen {
+    fn apply(&self, img: &DynamicImage, _: &Options) -> Result<Option<DynamicImage>> {
+        let blurred = with_pixels(&img.blur(1.0), |_, _, _| {});
+        Ok(Some(with_pixels(img, |pixels, channels, colour| {
+            for (index, (level, &blurred)) in pixels.iter_mut().zip(blurred.as_bytes()).enumerate() {
+                if index % channels < colour {
+                    let detail = *level as f32 - blurred as f32;
+                    *level = (*level as f32 + self.amount * detail).round().clamp(0.0, 255.0) as u8;
+                }
+            }
+        })))
+    }
+}
+
+/// Least share of a row or column that must differ from the margin colour for it to count as content
+const CONTENT_SHARE: f64 = 0.005;
+
+/// Largest difference, in levels, from the margin colour that still counts as margin
+const MARGIN_TOLERANCE: u8 = 24;
+
+/// `trim`: crop uniform margins, whose colour is taken from the corners
+struct Trim;
+
+impl PageFilter for Trim {
+    fn apply(&self, img: &DynamicImage, _: &Options) -> Result<Option<DynamicImage>> {
+        Ok(trim(img))
+    }
+}
+
+fn trim(img: &DynamicImage) -> Option<DynamicImage> {
+    let luma = img.to_luma8();
+    let margin = margin_level(&luma)?;
+    let (width, height) = luma.dimensions();
+    let is_content = |count: u32, length: u32| count as f64 > length as f64 * CONTENT_SHARE;
+    let differs = |x: u32, y: u32| luma.get_pixel(x, y).0[0].abs_diff(margin) > MARGIN_TOLERANCE;
+    let row = |y: u32| is_content((0..width).filter(|&x| differs(x, y)).count() as u32, width);
+    let column = |x: u32| is_content((0..height).filter(|&y| differs(x, y)).count() as u32, height);
+
+    let top = (0..height).find(|&y| row(y))?;
+    let bottom = (top..height).rev().find(|&y| row(y))? + 1;
+    let left = (0..width).find(|&x| column(x))?;
+    let right = (left..width).rev().find(|&x| column(x))? + 1;
+    // Mostly blank pages (chapter breaks, a lone caption) are left alone
+    if (right - left) * 2 < width || (bottom - top) * 2 < height {
+        return None;
+    }
+    if (left, top, right, bottom) == (0, 0, width, height) {
+        return None;
+    }
+    Some(img.crop_imm(left, top, right - left, bottom - top))
+}
+
+/// The level of the page margins: the corners, when they agree
+fn margin_level(luma: &GrayImage) -> Option<u8> {
+    let (width, height) = luma.dimensions();
+    if width == 0 || height == 0 {
+        return None;
+    }
+    let corners = [(0, 0), (width - 1, 0), (0, height - 1), (width - 1, height - 1)].map(|(x, y)| luma.get_pixel(x, y).0[0]);
+    let (low, high) = (*corners.iter().min()?, *corners.iter().max()?);
+    (high - low <= MARGIN_TOLERANCE).then(|| (corners.iter().map(|&level| level as u32).sum::<u32>() / 4) as u8)
+}
+
+/// Largest skew `deskew` corrects, in degrees
+const MAX_SKEW: f64 = 5.0;
+
+/// Skews smaller than this, in degrees, are left alone
+const MIN_SKEW: f64 = 0.2;
+
+/// `deskew`: rotate scanned pages straight. The skew is the angle at which
+/// the dark pixels line up best in rows (text lines and panel borders),
+/// searched within ±5°
+struct Deskew;
+
+impl PageFilter for Deskew {
+    fn apply(&self, img: &DynamicImage, _: &Options) -> Result<Option<DynamicImage>> {
+        Ok(deskew(img))
+    }
+}
+
+fn deskew(img: &DynamicImage) -> Option<DynamicImage> {
+    let luma = img.to_luma8();
+    let angle = skew_angle(&luma)?;
+    let fill = margin_level(&luma).unwrap_or(255);
+    let (width, height) = (img.width() as usize, img.height() as usize);
+    Some(with_pixels(img, |pixels, channels, colour| {
+        let source = pixels.to_vec();
+        rotate(&source, pixels, (width, height), (channels, colour), angle, fill);
+    }))
+}
+
+/// The skew of the page in degrees, positive when lines descend to the right
+fn skew_angle(luma: &GrayImage) -> Option<f64> {
+    // The search only needs the layout, so large scans are scaled down first
+    let scale = (1000.0 / luma.width().max(luma.height()) as f64).min(1.0);
+    let small = image::imageops::resize(
+        luma,
+        ((luma.width() as f64 * scale) as u32).max(1),
+        ((luma.height() as f64 * scale) as u32).max(1),
+        FilterType::Triangle,
+    );
+    let dark: Vec<(f64, f64)> =
+        small.enumerate_pixels().filter(|(_, _, pixel)| pixel.0[0] < 128).map(|(x, y, _)| (x as f64, y as f64)).collect();
+    if dark.len() < 100 {
+        return None;
+    }
+    let offset = small.width() as f64 * MAX_SKEW.to_radians().tan() + 1.0;
+    let mut rows = vec![0u32; small.height() as usize + 2 * offset as usize + 2];
+    // Dark pixels pile up in few rows when the projection follows the lines
+    let mut score = |degrees: f64| {
+        rows.iter_mut().for_each(|count| *count = 0);
+        let tan = degrees.to_radians().tan();
+        for &(x, y) in &dark {
+            rows[(y - x * tan + offset) as usize] += 1;
+        }
+        rows.iter().map(|&count| (count as f64).powi(2)).sum::<f64>()
+    };
+    let search = |score: &mut dyn FnMut(f64) -> f64, from: f64, to: f64, step: f64| {
+        let steps = ((to - from) / step).round() as i32;
+        (0..=steps).map(|i| from + i as f64 * step).map(|angle| (angle, score(angle))).fold((0.0, f64::MIN), |best, candidate| {
+            if candidate.1 > best.1 {
+                candidate
+            } else {
+                best
+            }
+        })
+    };
+    let (coarse, _) = search(&mut score, -MAX_SKEW, MAX_SKEW, 0.5);
+    // Kept within the range `rows` is sized for
+    let (angle, best) = search(&mut score, (coarse - 0.5).max(-MAX_SKEW), (coarse + 0.5).min(MAX_SKEW), 0.05);
+    // Pages without lines to follow score about the same at every angle
+    (angle.abs() >= MIN_SKEW && best > score(0.0) * 1.05).then_some(angle)
+}
+
+/// Rotate `source` into `rotated` so lines at `degrees` become level, sampling
+/// bilinearly around the centre; corners exposed by the rotation get `fill`
+fn rotate(
+    source: &[u8],
+    rotated: &mut [u8],
+    (width, height): (usize, usize),
+    (channels, colour): (usize, usize),
+    degrees: f64,
+    fill: u8,
+) {
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let (cx, cy) = ((width as f64 - 1.0) / 2.0, (height as f64 - 1.0) / 2.0);
+    for y in 0..height {
+        for x in 0..width {
+            let (dx, dy) = (x as f64 - cx, y as f64 - cy);
+            let (sx, sy) = (cx + dx * cos - dy * sin, cy + dx * sin + dy * cos);
+            let out = &mut rotated[(y * width + x) * channels..][..channels];
+            if sx < 0.0 || sy < 0.0 || sx > (width - 1) as f64 || sy > (height - 1) as f64 {
+                out[..colour].iter_mut().for_each(|level| *level = fill);
+                out[colour..].iter_mut().for_each(|level| *level = 255);
+                continue;
+            }
+            let (x0, y0) = (sx.floor() as usize, sy.floor() as usize);
+            let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+            let (fx, fy) = (sx - x0 as f64, sy - y0 as f64);
+            let at = |x: usize, y: usize, channel: usize| source[(y * width + x) * channels + channel] as f64;
+            for (channel, level) in out.iter_mut().enumerate() {
+                let top = at(x0, y0, channel) * (1.0 - fx) + at(x1, y0, channel) * fx;
+                let bottom = at(x0, y1, channel) * (1.0 - fx) + at(x1, y1, channel) * fx;
+                *level = (top * (1.0 - fy) + bottom * fy).round() as u8;
+            }
+        }
+    }
+}
+
+/// The page as 8-bit luma, RGB or RGBA, whichever keeps its channels, after
+/// `edit` changed its interleaved values; `edit` also gets the channels per
+/// pixel and how many of them are colour (the rest is alpha)
+pub(crate) fn with_pixels(img: &DynamicImage, edit: impl FnOnce(&mut [u8], usize, usize)) -> DynamicImage {
+    match (img.color().has_color(), img.color().has_alpha()) {
+        (false, false) => {
+            let mut page = img.to_luma8();
+            edit(&mut page, 1, 1);
+            DynamicImage::ImageLuma8(page)
+        }
+        (_, true) => {
+            let mut page = img.to_rgba8();
+            edit(&mut page, 4, 3);
+            DynamicImage::ImageRgba8(page)
+        }
+        (true, false) => {
+            let mut page = img.to_rgb8();
+            edit(&mut page, 3, 3);
+            DynamicImage::ImageRgb8(page)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skew_search_stays_within_its_rows() {
+        // Lines rising 6° to the right put the coarse angle at -5°, and a dark
+        // corner pixel is the farthest any angle projects
+        let (width, height) = (700, 1000);
+        let mut page = GrayImage::from_pixel(width, height, image::Luma([255]));
+        let rise = 6f64.to_radians().tan();
+        for start in (100..height).step_by(40) {
+            for x in 0..width {
+                let y = start as f64 - x as f64 * rise;
+                if y >= 0.0 {
+                    page.put_pixel(x, y as u32, image::Luma([0]));
+                }
+            }
+        }
+        page.put_pixel(width - 1, height - 1, image::Luma([0]));
+        let angle = skew_angle(&page).expect("lines to follow");
+        assert!((-MAX_SKEW..=MAX_SKEW).contains(&angle), "{}", angle);
+    }
+}
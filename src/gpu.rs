@@ -0,0 +1,285 @@
+//! `--accel gpu`: resize pages with a Lanczos3 compute shader on the GPU,
+//! through wgpu (Vulkan, Metal, DirectX 12 or OpenGL), instead of on the CPU
+//! workers, which are then free to encode. Only builds with the `gpu` cargo
+//! feature have it. Encoding stays on the CPU, as wgpu exposes no hardware
+//! image encoders. Whatever goes wrong (no GPU, only a software renderer, a
+//! page too big for the device's buffers, a device error) falls back to the
+//! CPU resize, with one warning per run.
+
+use image::DynamicImage;
+use std::sync::Once;
+
+/// The page resized to `width` × `height` on the GPU, or `None` to resize it on the CPU
+#[cfg(not(feature = "gpu"))]
+pub(crate) fn resize(_img: &DynamicImage, _width: u32, _height: u32) -> Option<DynamicImage> {
+    fall_back("this build has no GPU support (build with --features gpu)");
+    None
+}
+
+/// The page resized to `width` × `height` on the GPU, or `None` to resize it on the CPU
+#[cfg(feature = "gpu")]
+pub(crate) fn resize(img: &DynamicImage, width: u32, height: u32) -> Option<DynamicImage> {
+    let gpu = match device::Gpu::shared() {
+        Ok(gpu) => gpu,
+        Err(reason) => {
+            fall_back(reason);
+            return None;
+        }
+    };
+    match gpu.resize(img, width, height) {
+        Ok(resized) => resized,
+        Err(e) => {
+            fall_back(&format!("{:#}", e));
+            None
+        }
+    }
+}
+
+fn fall_back(reason: &str) {
+    static WARNED: Once = Once::new();
+    WARNED.call_once(|| eprintln!("⚠️  --accel gpu: {}; resizing on the CPU", reason));
+}
+
+#[cfg(feature = "gpu")]
+mod device {
+    use anyhow::{Context, Result};
+    use image::{ColorType, DynamicImage, RgbaImage};
+    use std::sync::{Mutex, OnceLock};
+    use wgpu::util::DeviceExt;
+
+    /// Pixels per side of a workgroup, as in the shader
+    const WORKGROUP_SIZE: u32 = 16;
+
+    /// Separable Lanczos3 with the kernel widened when shrinking, like
+    /// `image`'s `FilterType::Lanczos3`: rows first into floats, then columns
+    const SHADER: &str = r#"
+struct Params {
+    src_width: u32,
+    src_height: u32,
+    dst_width: u32,
+    dst_height: u32,
+    ratio_x: f32,
+    ratio_y: f32,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> pixels: array<u32>;
+@group(0) @binding(2) var<storage, read_write> rows: array<vec4<f32>>;
+@group(0) @binding(3) var<storage, read_write> resized: array<u32>;
+
+const PI: f32 = 3.141592653589793;
+
+fn sinc(x: f32) -> f32 {
+    if abs(x) < 1e-6 {
+        return 1.0;
+    }
+    let a = x * PI;
+    return sin(a) / a;
+}
+
+fn lanczos3(x: f32) -> f32 {
+    if abs(x) >= 3.0 {
+        return 0.0;
+    }
+    return sinc(x) * sinc(x / 3.0);
+}
+
+@compute @workgroup_size(16, 16)
+fn horizontal(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= params.dst_width || id.y >= params.src_height {
+        return;
+    }
+    let scale = max(params.ratio_x, 1.0);
+    let center = (f32(id.x) + 0.5) * params.ratio_x;
+    let left = u32(max(floor(center - 3.0 * scale), 0.0));
+    let right = u32(min(ceil(center + 3.0 * scale), f32(params.src_width)));
+    var sum = vec4<f32>(0.0);
+    var total = 0.0;
+    for (var i = left; i < right; i++) {
+        let weight = lanczos3((f32(i) + 0.5 - center) / scale);
+        sum += unpack4x8unorm(pixels[id.y * params.src_width + i]) * weight;
+        total += weight;
+    }
+    rows[id.y * params.dst_width + id.x] = sum / total;
+}
+
+@compute @workgroup_size(16, 16)
+fn vertical(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= params.dst_width || id.y >= params.dst_height {
+        return;
+    }
+    let scale = max(params.ratio_y, 1.0);
+    let center = (f32(id.y) + 0.5) * params.ratio_y;
+    let top = u32(max(floor(center - 3.0 * scale), 0.0));
+    let bottom = u32(min(ceil(center + 3.0 * scale), f32(params.src_height)));
+    var sum = vec4<f32>(0.0);
+    var total = 0.0;
+    for (var i = top; i < bottom; i++) {
+        let weight = lanczos3((f32(i) + 0.5 - center) / scale);
+        sum += rows[i * params.dst_width + id.x] * weight;
+        total += weight;
+    }
+    resized[id.y * params.dst_width + id.x] = pack4x8unorm(clamp(sum / total, vec4<f32>(0.0), vec4<f32>(1.0)));
+}
+"#;
+
+    /// The device of a run, opened on first use
+    pub(super) struct Gpu {
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        horizontal: wgpu::ComputePipeline,
+        vertical: wgpu::ComputePipeline,
+        /// One page at a time, so the pages in flight do not exhaust GPU memory
+        busy: Mutex<()>,
+    }
+
+    impl Gpu {
+        pub(super) fn shared() -> Result<&'static Gpu, &'static str> {
+            static GPU: OnceLock<Result<Gpu, String>> = OnceLock::new();
+            GPU.get_or_init(Gpu::open).as_ref().map_err(String::as_str)
+        }
+
+        fn open() -> Result<Gpu, String> {
+            let instance = wgpu::Instance::default();
+            let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::HighPerformance,
+                ..Default::default()
+            }))
+            .map_err(|e| format!("no GPU found ({})", e))?;
+            let info = adapter.get_info();
+            // A software renderer resizes slower than the CPU workers
+            if info.device_type == wgpu::DeviceType::Cpu {
+                return Err(format!("only the software renderer {} was found", info.name));
+            }
+            let (device, queue) = pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor {
+                label: Some("compress_comics"),
+                required_limits: adapter.limits(),
+                ..Default::default()
+            }))
+            .map_err(|e| format!("could not open {} ({})", info.name, e))?;
+
+            let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("lanczos3"),
+                source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+            });
+            let pipeline = |entry_point| {
+                device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                    label: Some(entry_point),
+                    layout: None,
+                    module: &module,
+                    entry_point: Some(entry_point),
+                    compilation_options: Default::default(),
+                    cache: None,
+                })
+            };
+            let (horizontal, vertical) = (pipeline("horizontal"), pipeline("vertical"));
+            eprintln!("🎮 Resizing pages on {} ({})", info.name, info.backend);
+            Ok(Gpu { device, queue, horizontal, vertical, busy: Mutex::new(()) })
+        }
+
+        /// `img` resized on the GPU; `None` for pages it does not take:
+        /// 16-bit and float pages, and pages too big for its buffers
+        pub(super) fn resize(&self, img: &DynamicImage, width: u32, height: u32) -> Result<Option<DynamicImage>> {
+            let color = img.color();
+            if !matches!(color, ColorType::L8 | ColorType::La8 | ColorType::Rgb8 | ColorType::Rgba8) {
+                return Ok(None);
+            }
+            let (src_width, src_height) = (img.width(), img.height());
+            let limits = self.device.limits();
+            let max_binding = limits.max_storage_buffer_binding_size.min(limits.max_buffer_size);
+            let rows_size = u64::from(width) * u64::from(src_height) * 16;
+            let max_groups = u64::from(limits.max_compute_workgroups_per_dimension) * u64::from(WORKGROUP_SIZE);
+            if rows_size > max_binding
+                || u64::from(src_width) * u64::from(src_height) * 4 > max_binding
+                || u64::from(width.max(src_height).max(height)) > max_groups
+            {
+                return Ok(None);
+            }
+
+            let rgba = img.to_rgba8();
+            let _busy = self.busy.lock().unwrap();
+            let scope = self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+            let memory_scope = self.device.push_error_scope(wgpu::ErrorFilter::OutOfMemory);
+
+            let params: Vec<u8> = [src_width, src_height, width, height]
+                .into_iter()
+                .chain([src_width as f32 / width as f32, src_height as f32 / height as f32].map(f32::to_bits))
+                .chain([0, 0])
+                .flat_map(u32::to_le_bytes)
+                .collect();
+            let params = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("params"),
+                contents: &params,
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+            let pixels = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("pixels"),
+                contents: rgba.as_raw(),
+                usage: wgpu::BufferUsages::STORAGE,
+            });
+            let rows = self.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("rows"),
+                size: rows_size,
+                usage: wgpu::BufferUsages::STORAGE,
+                mapped_at_creation: false,
+            });
+            let resized_size = u64::from(width) * u64::from(height) * 4;
+            let resized = self.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("resized"),
+                size: resized_size,
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            });
+            let readback = self.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("readback"),
+                size: resized_size,
+                usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+
+            let bind_group = |pipeline: &wgpu::ComputePipeline, buffers: [(u32, &wgpu::Buffer); 3]| {
+                let entries = buffers.map(|(binding, buffer)| wgpu::BindGroupEntry { binding, resource: buffer.as_entire_binding() });
+                self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: None,
+                    layout: &pipeline.get_bind_group_layout(0),
+                    entries: &entries,
+                })
+            };
+            let horizontal_group = bind_group(&self.horizontal, [(0, &params), (1, &pixels), (2, &rows)]);
+            let vertical_group = bind_group(&self.vertical, [(0, &params), (2, &rows), (3, &resized)]);
+
+            let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+            {
+                let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor::default());
+                pass.set_pipeline(&self.horizontal);
+                pass.set_bind_group(0, &horizontal_group, &[]);
+                pass.dispatch_workgroups(width.div_ceil(WORKGROUP_SIZE), src_height.div_ceil(WORKGROUP_SIZE), 1);
+                pass.set_pipeline(&self.vertical);
+                pass.set_bind_group(0, &vertical_group, &[]);
+                pass.dispatch_workgroups(width.div_ceil(WORKGROUP_SIZE), height.div_ceil(WORKGROUP_SIZE), 1);
+            }
+            encoder.copy_buffer_to_buffer(&resized, 0, &readback, 0, resized_size);
+            self.queue.submit([encoder.finish()]);
+
+            let (sender, receiver) = crossbeam_channel::bounded(1);
+            readback.slice(..).map_async(wgpu::MapMode::Read, move |mapped| {
+                let _ = sender.send(mapped);
+            });
+            self.device.poll(wgpu::PollType::wait_indefinitely()).context("GPU stopped responding")?;
+            if let Some(error) = pollster::block_on(memory_scope.pop()).or(pollster::block_on(scope.pop())) {
+                anyhow::bail!("GPU error: {}", error);
+            }
+            receiver.recv().context("GPU stopped responding")?.context("Could not read the resized page back")?;
+            let bytes = readback.slice(..).get_mapped_range().context("Could not read the resized page back")?.to_vec();
+            readback.unmap();
+
+            let resized = DynamicImage::ImageRgba8(RgbaImage::from_raw(width, height, bytes).context("Resized page has the wrong size")?);
+            Ok(Some(match color {
+                ColorType::L8 => DynamicImage::ImageLuma8(resized.to_luma8()),
+                ColorType::La8 => DynamicImage::ImageLumaA8(resized.to_luma_alpha8()),
+                ColorType::Rgb8 => DynamicImage::ImageRgb8(resized.to_rgb8()),
+                _ => resized,
+            }))
+        }
+    }
+}
@@ -0,0 +1,363 @@
+//! Job history (`--history-db`): an SQLite database recording every processed
+//! file with the run's settings, sizes, duration and per-page results, across
+//! any number of incremental runs. `stats` sums it up with filters such as
+//! `--since 2026-01-01` or `--max-quality 84`, grouped by month, year,
+//! quality or format.
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+use crate::report::{FileRecord, FileStatus};
+use crate::{settings_fingerprint, Options};
+
+const SCHEMA_VERSION: i64 = 1;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY,
+    started_at INTEGER NOT NULL,
+    version TEXT NOT NULL,
+    settings TEXT NOT NULL,
+    format TEXT NOT NULL,
+    quality INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS files (
+    id INTEGER PRIMARY KEY,
+    run_id INTEGER NOT NULL REFERENCES runs(id),
+    path TEXT NOT NULL,
+    output_path TEXT,
+    status TEXT NOT NULL,
+    original_size INTEGER NOT NULL,
+    output_size INTEGER NOT NULL,
+    pages_processed INTEGER NOT NULL,
+    pages_resized_only INTEGER NOT NULL,
+    pages_skipped INTEGER NOT NULL,
+    pages_failed INTEGER NOT NULL,
+    duration_ms INTEGER NOT NULL,
+    error TEXT,
+    finished_at INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS pages (
+    file_id INTEGER NOT NULL REFERENCES files(id),
+    page TEXT NOT NULL,
+    outcome TEXT NOT NULL,
+    original_size INTEGER NOT NULL,
+    output_size INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS files_finished_at ON files(finished_at);
+CREATE INDEX IF NOT EXISTS pages_file_id ON pages(file_id);
+";
+
+/// The history database of one run, shared by all worker threads
+pub(crate) struct History {
+    connection: Mutex<Connection>,
+    run_id: i64,
+}
+
+impl History {
+    /// Open (or create) the database at `path` and record a run with `args`
+    pub(crate) fn open(path: &Path, args: &Options) -> Result<Self> {
+        let connection = connect(path)?;
+        connection.execute(
+            "INSERT INTO runs (started_at, version, settings, format, quality) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                now(),
+                env!("CARGO_PKG_VERSION"),
+                settings_fingerprint(args),
+                value_name(args.format),
+                args.quality
+            ],
+        )?;
+        let run_id = connection.last_insert_rowid();
+        Ok(History { connection: Mutex::new(connection), run_id })
+    }
+
+    /// Record a finished file and its pages
+    pub(crate) fn record(&self, record: &FileRecord) -> Result<()> {
+        let mut connection = self.connection.lock().unwrap_or_else(|e| e.into_inner());
+        let transaction = connection.transaction()?;
+        transaction.execute(
+            "INSERT INTO files (run_id, path, output_path, status, original_size, output_size, pages_processed,
+                pages_resized_only, pages_skipped, pages_failed, duration_ms, error, finished_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+            params![
+                self.run_id,
+                absolute(&record.path),
+                record.output_path.as_deref().map(absolute),
+                record.status.name(),
+                record.original_size as i64,
+                record.output_size as i64,
+                record.pages_processed as i64,
+                record.pages_resized_only as i64,
+                record.pages_skipped as i64,
+                record.page_errors.len() as i64,
+                record.duration_ms as i64,
+                record.error,
+                now(),
+            ],
+        )?;
+        let file_id = transaction.last_insert_rowid();
+        {
+            let mut insert = transaction.prepare(
+                "INSERT INTO pages (file_id, page, outcome, original_size, output_size) VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?;
+            for page in &record.pages {
+                insert.execute(params![
+                    file_id,
+                    page.page,
+                    page.outcome.name(),
+                    page.original_size as i64,
+                    page.output_size as i64
+                ])?;
+            }
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+}
+
+fn connect(path: &Path) -> Result<Connection> {
+    let connection = Connection::open(path)
+        .with_context(|| format!("Failed to open history database {}", path.display()))?;
+    // Parallel runs (or a watcher next to a batch run) may share the database
+    connection.busy_timeout(std::time::Duration::from_secs(30))?;
+    let version: i64 = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if version > SCHEMA_VERSION {
+        anyhow::bail!("History database {} was written by a newer version of compress_comics", path.display());
+    }
+    connection
+        .execute_batch(SCHEMA)
+        .with_context(|| format!("Failed to set up history database {}", path.display()))?;
+    connection.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+    Ok(connection)
+}
+
+/// Paths are stored absolute: runs start from different directories
+fn absolute(path: &Path) -> String {
+    std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf()).to_string_lossy().into_owned()
+}
+
+fn now() -> i64 {
+    std::time::SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+}
+
+/// The command-line spelling of a value
+fn value_name(value: impl ValueEnum) -> String {
+    value.to_possible_value().map(|value| value.get_name().to_string()).unwrap_or_default()
+}
+
+/// How `stats` groups its totals
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum StatsGroup {
+    Month,
+    Year,
+    Quality,
+    Format,
+}
+
+impl StatsGroup {
+    fn expression(self) -> &'static str {
+        match self {
+            StatsGroup::Month => "strftime('%Y-%m', files.finished_at, 'unixepoch')",
+            StatsGroup::Year => "strftime('%Y', files.finished_at, 'unixepoch')",
+            StatsGroup::Quality => "CAST(runs.quality AS TEXT)",
+            StatsGroup::Format => "runs.format",
+        }
+    }
+}
+
+/// Which recorded files `stats` counts
+#[derive(Debug, Clone, Default, clap::Args)]
+pub struct StatsFilter {
+    /// Files finished on or after this date (YYYY-MM-DD, UTC)
+    #[arg(long, value_name = "DATE")]
+    pub since: Option<String>,
+
+    /// Files finished before this date (YYYY-MM-DD, UTC)
+    #[arg(long, value_name = "DATE")]
+    pub until: Option<String>,
+
+    /// Files processed at this quality or lower
+    #[arg(long, value_name = "N")]
+    pub max_quality: Option<u8>,
+
+    /// Files processed at this quality or higher
+    #[arg(long, value_name = "N")]
+    pub min_quality: Option<u8>,
+
+    /// Files processed to this image format
+    #[arg(long, value_name = "FORMAT")]
+    pub format: Option<String>,
+
+    /// Files that ended with this status (compressed, converted, skipped or failed)
+    #[arg(long, value_name = "STATUS")]
+    pub status: Option<String>,
+}
+
+/// Totals of the files matching a filter
+struct Totals {
+    group: Option<String>,
+    files: i64,
+    failed: i64,
+    original_size: i64,
+    output_size: i64,
+    pages: i64,
+    duration_ms: i64,
+}
+
+/// `stats`: totals of the files recorded in the history database at `path`
+/// (default: `history-db` from the config files), optionally grouped, and with
+/// `list` every matching file
+pub fn stats(path: Option<&Path>, filter: &StatsFilter, group: Option<StatsGroup>, list: bool) -> Result<ExitCode> {
+    let path = match path {
+        Some(path) => path.to_path_buf(),
+        None => configured_path()?,
+    };
+    if !path.is_file() {
+        anyhow::bail!("History database {} does not exist; record one with --history-db", path.display());
+    }
+    let connection = connect(&path)?;
+    let (condition, values) = conditions(&connection, filter)?;
+
+    // Skipped and failed files count at their original size, as in the run summary
+    let group_expression = group.map_or("NULL", StatsGroup::expression);
+    let query = format!(
+        "SELECT {group}, COUNT(*), SUM(files.status = 'failed'), SUM(files.original_size),
+            SUM(CASE WHEN files.status IN ('compressed', 'converted') THEN files.output_size ELSE files.original_size END),
+            SUM(files.pages_processed + files.pages_skipped), SUM(files.duration_ms)
+         FROM files JOIN runs ON runs.id = files.run_id WHERE {condition}
+         GROUP BY 1 ORDER BY 1",
+        group = group_expression,
+        condition = condition
+    );
+    let mut statement = connection.prepare(&query)?;
+    let totals = statement
+        .query_map(rusqlite::params_from_iter(&values), |row| {
+            Ok(Totals {
+                group: row.get(0)?,
+                files: row.get(1)?,
+                failed: row.get(2)?,
+                original_size: row.get(3)?,
+                output_size: row.get(4)?,
+                pages: row.get(5)?,
+                duration_ms: row.get(6)?,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    if list {
+        let mut statement = connection.prepare(&format!(
+            "SELECT strftime('%Y-%m-%d %H:%M', files.finished_at, 'unixepoch'), files.path, files.status, runs.format,
+                runs.quality, files.original_size, files.output_size
+             FROM files JOIN runs ON runs.id = files.run_id WHERE {} ORDER BY files.finished_at",
+            condition
+        ))?;
+        let mut rows = statement.query(rusqlite::params_from_iter(&values))?;
+        while let Some(row) = rows.next()? {
+            let (date, path, status, format, quality): (String, String, String, String, u8) =
+                (row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?);
+            let (original_size, output_size): (i64, i64) = (row.get(5)?, row.get(6)?);
+            println!(
+                "{}  {:<10} {} q{}  {:.1} MB → {:.1} MB  {}",
+                date,
+                status,
+                format,
+                quality,
+                mb(original_size),
+                mb(output_size),
+                path
+            );
+        }
+        println!();
+    }
+
+    if totals.is_empty() {
+        println!("No recorded files match");
+        return Ok(ExitCode::SUCCESS);
+    }
+    for total in &totals {
+        if let Some(group) = &total.group {
+            println!("{}:", group);
+        }
+        let saved = total.original_size - total.output_size;
+        let percent = if total.original_size > 0 { saved as f64 / total.original_size as f64 * 100.0 } else { 0.0 };
+        println!("📊 {} file(s), {} failed, {} page(s)", total.files, total.failed, total.pages);
+        println!(
+            "   {:.1} MB → {:.1} MB, {:.1} MB saved ({:.1}%)",
+            mb(total.original_size),
+            mb(total.output_size),
+            mb(saved),
+            percent
+        );
+        println!(
+            "   {:.1} min processing, {:.1} s per file",
+            total.duration_ms as f64 / 60_000.0,
+            total.duration_ms as f64 / 1000.0 / total.files as f64
+        );
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn mb(bytes: i64) -> f64 {
+    bytes as f64 / 1_048_576.0
+}
+
+/// The `history-db` of the user and current-directory config files
+fn configured_path() -> Result<PathBuf> {
+    let (config, _) = crate::config::load_layered(Path::new("."))?;
+    config
+        .history_db
+        .context("No history database given; pass one or set history-db in the config file")
+}
+
+/// SQL condition and its parameters for `filter`
+fn conditions(connection: &Connection, filter: &StatsFilter) -> Result<(String, Vec<rusqlite::types::Value>)> {
+    use rusqlite::types::Value;
+
+    let mut conditions = vec!["1".to_string()];
+    let mut values = Vec::new();
+    let mut add = |condition: &str, value: Value| {
+        values.push(value);
+        conditions.push(condition.replace('?', &format!("?{}", values.len())));
+    };
+    if let Some(since) = &filter.since {
+        add("files.finished_at >= ?", Value::Integer(timestamp(connection, since)?));
+    }
+    if let Some(until) = &filter.until {
+        add("files.finished_at < ?", Value::Integer(timestamp(connection, until)?));
+    }
+    if let Some(quality) = filter.max_quality {
+        add("runs.quality <= ?", Value::Integer(quality.into()));
+    }
+    if let Some(quality) = filter.min_quality {
+        add("runs.quality >= ?", Value::Integer(quality.into()));
+    }
+    if let Some(format) = &filter.format {
+        add("runs.format = ?", Value::Text(format.to_lowercase()));
+    }
+    if let Some(status) = &filter.status {
+        let status = status.to_lowercase();
+        let known = [FileStatus::Compressed, FileStatus::Converted, FileStatus::Skipped, FileStatus::Failed];
+        if !known.iter().any(|known| known.name() == status) {
+            anyhow::bail!("Unknown status {}; use compressed, converted, skipped or failed", status);
+        }
+        add("files.status = ?", Value::Text(status));
+    }
+    Ok((conditions.join(" AND "), values))
+}
+
+/// Seconds since 1970 at the start of `date` (UTC), parsed by SQLite
+fn timestamp(connection: &Connection, date: &str) -> Result<i64> {
+    let seconds: Option<String> = connection
+        .query_row("SELECT strftime('%s', ?1)", [date], |row| row.get(0))
+        .optional()?
+        .flatten();
+    seconds
+        .and_then(|seconds| seconds.parse().ok())
+        .with_context(|| format!("Invalid date {}; use YYYY-MM-DD", date))
+}
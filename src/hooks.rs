@@ -0,0 +1,63 @@
+//! `--on-success` / `--on-failure`: shell commands run after each file, e.g.
+//! to trigger a library rescan, send a notification or move the output. The
+//! file's outcome is passed in `COMPRESS_COMICS_*` environment variables; a
+//! failing hook only prints a warning.
+
+use std::path::Path;
+use std::process::Command;
+
+use crate::report::FileStatus;
+use crate::{Options, Report};
+
+/// Run the hook matching `report`'s outcome for the file at `input`, if one
+/// is configured. Nothing runs for `--dry-run` estimates
+pub(crate) fn after_file(args: &Options, input: &Path, report: &Report) {
+    let status = FileStatus::of(report);
+    let hook = match status {
+        FileStatus::Failed => &args.on_failure,
+        FileStatus::Estimated => return,
+        _ => &args.on_success,
+    };
+    let Some(command) = hook else {
+        return;
+    };
+
+    let savings = if report.original_size > 0 && report.error_message.is_none() {
+        format!(
+            "{:.1}",
+            (report.original_size as f64 - report.compressed_size as f64) / report.original_size as f64 * 100.0
+        )
+    } else {
+        String::new()
+    };
+    let output = report.output_path.as_deref().map(|path| path.as_os_str()).unwrap_or_default();
+
+    let result = shell(command)
+        .env("COMPRESS_COMICS_INPUT", input)
+        .env("COMPRESS_COMICS_OUTPUT", output)
+        .env("COMPRESS_COMICS_STATUS", status.name())
+        .env("COMPRESS_COMICS_ORIGINAL_SIZE", report.original_size.to_string())
+        .env("COMPRESS_COMICS_OUTPUT_SIZE", report.compressed_size.to_string())
+        .env("COMPRESS_COMICS_SAVINGS", savings)
+        .env("COMPRESS_COMICS_ERROR", report.error_message.as_deref().unwrap_or_default())
+        .status();
+    match result {
+        Ok(exit) if exit.success() => {}
+        Ok(exit) => eprintln!("Warning: Hook for {} exited with {}", input.display(), exit),
+        Err(e) => eprintln!("Warning: Failed to run hook for {}: {}", input.display(), e),
+    }
+}
+
+#[cfg(unix)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(not(unix))]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
@@ -0,0 +1,182 @@
+//! Colour profiles: pages scanned or drawn in Adobe RGB, Display P3 and other
+//! wide-gamut spaces carry an ICC profile that re-encoding would drop, after
+//! which readers show them as sRGB with washed-out or shifted colours. Such
+//! pages are converted to sRGB when they are decoded (`--assume-srgb` skips
+//! this), and `--embed-icc` tags the re-encoded pages with an sRGB profile for
+//! colour-managed viewers. JPEG XL pages always signal sRGB themselves.
+
+use anyhow::{Context, Result};
+use flate2::write::ZlibEncoder;
+use flate2::{Compression, Crc};
+use image::{DynamicImage, RgbImage, RgbaImage};
+use moxcms::{ColorProfile, DataColorSpace, Layout, TransformOptions};
+use std::io::Write;
+use std::sync::OnceLock;
+
+/// How far a profile's colorants may lie from sRGB's and still count as sRGB
+const SRGB_TOLERANCE: f64 = 0.002;
+
+/// `img` converted from the colour space of `icc` to sRGB; unchanged when the
+/// profile is sRGB, not an RGB profile, unreadable, or the page is grayscale
+pub(crate) fn to_srgb(img: DynamicImage, icc: &[u8]) -> DynamicImage {
+    convert(&img, icc).unwrap_or(img)
+}
+
+fn convert(img: &DynamicImage, icc: &[u8]) -> Option<DynamicImage> {
+    let source = ColorProfile::new_from_slice(icc).ok()?;
+    if source.color_space != DataColorSpace::Rgb || !img.color().has_color() || is_srgb(&source) {
+        return None;
+    }
+    let srgb = ColorProfile::new_srgb();
+    let layout = if img.color().has_alpha() { Layout::Rgba } else { Layout::Rgb };
+    let transform = source.create_transform_8bit(layout, &srgb, layout, TransformOptions::default()).ok()?;
+    let (width, height) = (img.width(), img.height());
+    if layout == Layout::Rgba {
+        let pixels = img.to_rgba8();
+        let mut converted = vec![0; pixels.len()];
+        transform.transform(&pixels, &mut converted).ok()?;
+        RgbaImage::from_raw(width, height, converted).map(DynamicImage::ImageRgba8)
+    } else {
+        let pixels = img.to_rgb8();
+        let mut converted = vec![0; pixels.len()];
+        transform.transform(&pixels, &mut converted).ok()?;
+        RgbImage::from_raw(width, height, converted).map(DynamicImage::ImageRgb8)
+    }
+}
+
+/// Whether a profile has the sRGB primaries and white point, as the sRGB
+/// profiles many cameras and editors embed do
+fn is_srgb(profile: &ColorProfile) -> bool {
+    let srgb = ColorProfile::new_srgb();
+    [
+        (profile.red_colorant, srgb.red_colorant),
+        (profile.green_colorant, srgb.green_colorant),
+        (profile.blue_colorant, srgb.blue_colorant),
+        (profile.white_point, srgb.white_point),
+    ]
+    .iter()
+    .all(|(a, b)| (a.x - b.x).abs() < SRGB_TOLERANCE && (a.y - b.y).abs() < SRGB_TOLERANCE && (a.z - b.z).abs() < SRGB_TOLERANCE)
+}
+
+/// The sRGB profile written by `--embed-icc`
+fn srgb_profile() -> Result<&'static [u8]> {
+    static PROFILE: OnceLock<Option<Vec<u8>>> = OnceLock::new();
+    PROFILE
+        .get_or_init(|| ColorProfile::new_srgb().encode().ok())
+        .as_deref()
+        .context("Failed to build the sRGB ICC profile")
+}
+
+/// Tag an encoded page with the sRGB profile; formats that cannot carry one,
+/// or (JPEG XL) signal sRGB already, are returned as they are
+pub(crate) fn embed_srgb(bytes: Vec<u8>, extension: &str) -> Result<Vec<u8>> {
+    let profile = srgb_profile()?;
+    match extension {
+        "webp" => embed_in_webp(bytes, profile),
+        "png" => embed_in_png(bytes, profile),
+        "jpg" | "jpeg" => embed_in_jpeg(bytes, profile),
+        _ => Ok(bytes),
+    }
+}
+
+/// Add an `ICCP` chunk, turning a simple WebP (`VP8 `/`VP8L`) into the
+/// extended format with a `VP8X` header, which the ICC flag lives in
+fn embed_in_webp(bytes: Vec<u8>, profile: &[u8]) -> Result<Vec<u8>> {
+    if bytes.len() < 30 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WEBP" {
+        anyhow::bail!("Not a WebP file");
+    }
+    const ICC_FLAG: u8 = 0x20;
+    const ALPHA_FLAG: u8 = 0x10;
+
+    let mut iccp = Vec::with_capacity(profile.len() + 9);
+    iccp.extend_from_slice(b"ICCP");
+    iccp.extend_from_slice(&(profile.len() as u32).to_le_bytes());
+    iccp.extend_from_slice(profile);
+    if profile.len() % 2 == 1 {
+        iccp.push(0);
+    }
+
+    let mut webp = Vec::with_capacity(bytes.len() + iccp.len() + 18);
+    webp.extend_from_slice(&bytes[..12]);
+    let body = match &bytes[12..16] {
+        b"VP8X" => {
+            let mut header = bytes[12..30].to_vec();
+            header[8] |= ICC_FLAG;
+            webp.extend_from_slice(&header);
+            &bytes[30..]
+        }
+        fourcc => {
+            let data = &bytes[20..];
+            let (width, height, alpha) = match fourcc {
+                // Frame tag, start code, then 14-bit width and height
+                b"VP8 " if data.len() >= 10 => (
+                    u32::from(u16::from_le_bytes([data[6], data[7]]) & 0x3fff),
+                    u32::from(u16::from_le_bytes([data[8], data[9]]) & 0x3fff),
+                    false,
+                ),
+                // Signature, then 14-bit width - 1, 14-bit height - 1 and the alpha bit
+                b"VP8L" if data.len() >= 5 => {
+                    let bits = u32::from_le_bytes([data[1], data[2], data[3], data[4]]);
+                    ((bits & 0x3fff) + 1, ((bits >> 14) & 0x3fff) + 1, bits >> 28 & 1 == 1)
+                }
+                _ => anyhow::bail!("Unexpected WebP chunk layout"),
+            };
+            webp.extend_from_slice(b"VP8X");
+            webp.extend_from_slice(&10u32.to_le_bytes());
+            webp.extend_from_slice(&[ICC_FLAG | if alpha { ALPHA_FLAG } else { 0 }, 0, 0, 0]);
+            webp.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+            webp.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+            &bytes[12..]
+        }
+    };
+    // The ICC profile comes right after the VP8X header
+    webp.extend_from_slice(&iccp);
+    webp.extend_from_slice(body);
+    let riff_size = (webp.len() - 8) as u32;
+    webp[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    Ok(webp)
+}
+
+/// Add a compressed `iCCP` chunk right after `IHDR`
+fn embed_in_png(bytes: Vec<u8>, profile: &[u8]) -> Result<Vec<u8>> {
+    // Signature (8) and IHDR (length, type, 13 bytes, CRC)
+    const IHDR_END: usize = 8 + 8 + 13 + 4;
+    if bytes.len() < IHDR_END || &bytes[12..16] != b"IHDR" {
+        anyhow::bail!("Not a PNG file");
+    }
+    let mut compressed = ZlibEncoder::new(Vec::new(), Compression::best());
+    compressed.write_all(profile)?;
+    let mut chunk = b"iCCPsRGB\0\0".to_vec();
+    chunk.extend_from_slice(&compressed.finish()?);
+
+    let mut crc = Crc::new();
+    crc.update(&chunk);
+    let mut png = Vec::with_capacity(bytes.len() + chunk.len() + 8);
+    png.extend_from_slice(&bytes[..IHDR_END]);
+    png.extend_from_slice(&((chunk.len() - 4) as u32).to_be_bytes());
+    png.extend_from_slice(&chunk);
+    png.extend_from_slice(&crc.sum().to_be_bytes());
+    png.extend_from_slice(&bytes[IHDR_END..]);
+    Ok(png)
+}
+
+/// Add an `APP2` `ICC_PROFILE` segment after the JFIF header (or the start marker)
+fn embed_in_jpeg(bytes: Vec<u8>, profile: &[u8]) -> Result<Vec<u8>> {
+    if bytes.len() < 4 || bytes[0..2] != [0xff, 0xd8] {
+        anyhow::bail!("Not a JPEG file");
+    }
+    let mut at = 2;
+    if bytes[2..4] == [0xff, 0xe0] && bytes.len() >= 6 {
+        at += 2 + usize::from(u16::from_be_bytes([bytes[4], bytes[5]]));
+    }
+    // Marker length, "ICC_PROFILE\0", sequence number and count (one segment)
+    let length = u16::try_from(2 + 12 + 2 + profile.len()).context("ICC profile too large for one JPEG segment")?;
+    let mut jpeg = Vec::with_capacity(bytes.len() + usize::from(length) + 2);
+    jpeg.extend_from_slice(&bytes[..at]);
+    jpeg.extend_from_slice(&[0xff, 0xe2]);
+    jpeg.extend_from_slice(&length.to_be_bytes());
+    jpeg.extend_from_slice(b"ICC_PROFILE\0\x01\x01");
+    jpeg.extend_from_slice(profile);
+    jpeg.extend_from_slice(&bytes[at..]);
+    Ok(jpeg)
+}
@@ -0,0 +1,283 @@
+//! `inspect`: what a comic contains (its pages with format, dimensions, bit
+//! depth and colour, its ComicInfo metadata and any other files) without
+//! processing it, to judge which files are worth recompressing.
+//! `inspect --provenance` only reads the marker compress_comics embeds in
+//! its outputs: how, from what and when each file was made, for auditing a
+//! library without unpacking it.
+
+use anyhow::Result;
+use image::{ImageDecoder, ImageReader};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use walkdir::WalkDir;
+
+use crate::cli::EXIT_FILES_FAILED;
+use crate::comicinfo::{self, ComicInfo};
+use crate::provenance::{self, ProcessingMarker};
+use crate::{detect_comic_file, is_grayscale, page_name, temp, unpack_pages};
+
+/// Print height of a US comic page, used to estimate the scan resolution
+const ASSUMED_PAGE_HEIGHT_INCHES: f64 = 10.25;
+
+/// Contents of one comic file
+#[derive(Debug, Clone, Serialize)]
+pub struct Inspection {
+    pub path: PathBuf,
+    /// Container type, e.g. `CBZ` or `PDF`
+    pub file_type: String,
+    pub size: u64,
+    /// Sum of the page sizes
+    pub pages_size: u64,
+    /// Pages in reading order
+    pub pages: Vec<PageSummary>,
+    /// Fields of ComicInfo.xml (Series, Title, Writer, ...), when present
+    pub comic_info: BTreeMap<String, String>,
+    /// Files that are not pages, e.g. ComicInfo.xml
+    pub other_files: Vec<String>,
+    /// How compress_comics made the file, when it did
+    pub provenance: Option<ProcessingMarker>,
+}
+
+/// The provenance marker of one file, for `inspect --provenance --json`
+#[derive(Serialize)]
+struct ProvenanceRecord<'a> {
+    path: &'a Path,
+    provenance: Option<ProcessingMarker>,
+}
+
+/// One page of an inspected comic
+#[derive(Debug, Clone, Serialize)]
+pub struct PageSummary {
+    /// Page path inside the archive, `/`-separated
+    pub name: String,
+    /// Image format from the page's contents, e.g. `JPEG`; the extension when unrecognised
+    pub format: String,
+    /// Pixel dimensions, when the page header could be read
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Bits per channel
+    pub bit_depth: Option<u16>,
+    /// Colour or visually grayscale (also RGB scans of black-and-white pages)
+    pub color: Option<PageColor>,
+    /// Resolution assuming the page was scanned from a standard US comic page
+    pub estimated_dpi: Option<u32>,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PageColor {
+    Color,
+    Grayscale,
+}
+
+/// List the pages and other files of a comic
+pub fn inspect(path: &Path) -> Result<Inspection> {
+    let comic_file = detect_comic_file(path)?;
+    let size = fs::metadata(path)?.len();
+    let temp_dir = temp::create_dir_in(None)?;
+    let pages = unpack_pages(&comic_file, temp_dir.path())?;
+
+    let other_files = WalkDir::new(temp_dir.path())
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|entry| entry.file_type().is_file() && !pages.iter().any(|page| page == entry.path()))
+        .map(|entry| page_name(entry.path(), temp_dir.path()))
+        .collect();
+
+    let pages: Vec<PageSummary> = pages.par_iter().map(|page| summarize_page(page, temp_dir.path())).collect();
+
+    let comic_info = comicinfo::find_in_dir(temp_dir.path())
+        .and_then(|path| ComicInfo::load(&path).ok())
+        .map(|info| info.fields().into_iter().collect())
+        .unwrap_or_default();
+
+    Ok(Inspection {
+        path: path.to_path_buf(),
+        file_type: format!("{:?}", comic_file.file_type).to_uppercase(),
+        size,
+        pages_size: pages.iter().map(|page| page.size).sum(),
+        pages,
+        comic_info,
+        other_files,
+        provenance: provenance::read_marker(path),
+    })
+}
+
+/// Header facts of a page; the colour check decodes it. Fields that cannot
+/// be read stay empty
+fn summarize_page(page: &Path, dir: &Path) -> PageSummary {
+    let extension = page
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_lowercase)
+        .unwrap_or_default();
+    let mut summary = PageSummary {
+        name: page_name(page, dir),
+        format: extension.to_uppercase(),
+        width: None,
+        height: None,
+        bit_depth: None,
+        color: None,
+        estimated_dpi: None,
+        size: fs::metadata(page).map(|m| m.len()).unwrap_or(0),
+    };
+
+    if extension == "jp2" {
+        summary.format = "JPEG2000".to_string();
+        if let Ok(image) = jpeg2k::Image::from_file(page) {
+            summary.width = Some(image.width());
+            summary.height = Some(image.height());
+        }
+    } else if let Ok(reader) = ImageReader::open(page).and_then(|reader| reader.with_guessed_format()) {
+        if let Some(format) = reader.format() {
+            summary.format = format!("{:?}", format).to_uppercase();
+        }
+        if let Ok(decoder) = reader.into_decoder() {
+            let (width, height) = decoder.dimensions();
+            let color_type = decoder.color_type();
+            summary.width = Some(width);
+            summary.height = Some(height);
+            summary.bit_depth = Some(color_type.bits_per_pixel() / color_type.channel_count() as u16);
+        }
+        let decoded = ImageReader::open(page)
+            .and_then(|reader| reader.with_guessed_format())
+            .ok()
+            .and_then(|reader| reader.decode().ok());
+        summary.color = decoded.map(|img| if is_grayscale(&img) { PageColor::Grayscale } else { PageColor::Color });
+    }
+
+    summary.estimated_dpi = summary.height.map(|height| (height as f64 / ASSUMED_PAGE_HEIGHT_INCHES).round() as u32);
+    summary
+}
+
+/// Print the contents of each file, or with `json` one JSON array of them;
+/// files that cannot be read are reported and make the exit code nonzero
+pub(crate) fn run(files: &[PathBuf], json: bool, provenance: bool) -> Result<ExitCode> {
+    if provenance {
+        return run_provenance(files, json);
+    }
+    let mut failed = 0;
+    let mut inspections = Vec::new();
+    for (index, path) in files.iter().enumerate() {
+        match inspect(path) {
+            Ok(inspection) if json => inspections.push(inspection),
+            Ok(inspection) => {
+                if index > 0 {
+                    println!();
+                }
+                print_inspection(&inspection);
+            }
+            Err(e) => {
+                eprintln!("❌ {} — {:#}", path.display(), e);
+                failed += 1;
+            }
+        }
+    }
+    if json {
+        println!("{}", serde_json::to_string_pretty(&inspections)?);
+    }
+    Ok(if failed > 0 { ExitCode::from(EXIT_FILES_FAILED) } else { ExitCode::SUCCESS })
+}
+
+/// Print the provenance marker of each file, or with `json` one JSON array
+/// of them. Files without one are listed as such; missing files make the exit code nonzero
+fn run_provenance(files: &[PathBuf], json: bool) -> Result<ExitCode> {
+    let mut failed = 0;
+    let mut records = Vec::new();
+    for (index, path) in files.iter().enumerate() {
+        if !path.is_file() {
+            eprintln!("❌ {} — not a file", path.display());
+            failed += 1;
+            continue;
+        }
+        let marker = provenance::read_marker(path);
+        if json {
+            records.push(ProvenanceRecord { path, provenance: marker });
+            continue;
+        }
+        if index > 0 {
+            println!();
+        }
+        println!("🏷️  {}", path.display());
+        match marker {
+            Some(marker) => {
+                println!("  Tool: {} {}", marker.tool, marker.version);
+                println!("  Created: {}", marker.created_at_utc().unwrap_or_else(|| "not recorded (--deterministic)".to_string()));
+                println!("  Source: {}", marker.source_name);
+                if !marker.source_sha256.is_empty() {
+                    println!("  Source SHA-256: {}", marker.source_sha256);
+                }
+                println!("  Settings: {}", marker.settings);
+            }
+            None => println!("  No provenance: not made by compress_comics"),
+        }
+    }
+    if json {
+        println!("{}", serde_json::to_string_pretty(&records)?);
+    }
+    Ok(if failed > 0 { ExitCode::from(EXIT_FILES_FAILED) } else { ExitCode::SUCCESS })
+}
+
+fn print_inspection(inspection: &Inspection) {
+    let mb = |bytes: u64| bytes as f64 / 1_048_576.0;
+    println!(
+        "📖 {} — {}, {:.2} MB, {} page(s) totalling {:.2} MB",
+        inspection.path.display(),
+        inspection.file_type,
+        mb(inspection.size),
+        inspection.pages.len(),
+        mb(inspection.pages_size)
+    );
+    if let Some(marker) = &inspection.provenance {
+        let created = marker.created_at_utc().map(|created| format!(" on {}", created)).unwrap_or_default();
+        println!("  Made by {} {} from {}{}", marker.tool, marker.version, marker.source_name, created);
+    }
+    for (field, value) in &inspection.comic_info {
+        println!("  {}: {}", field, value);
+    }
+
+    let width = inspection.pages.len().to_string().len();
+    for (index, page) in inspection.pages.iter().enumerate() {
+        let dimensions = match (page.width, page.height) {
+            (Some(width), Some(height)) => format!("{}×{}", width, height),
+            _ => "?".to_string(),
+        };
+        let bit_depth = page.bit_depth.map(|bits| format!("{}-bit", bits)).unwrap_or_else(|| "?".to_string());
+        let color = match page.color {
+            Some(PageColor::Color) => "color",
+            Some(PageColor::Grayscale) => "grayscale",
+            None => "?",
+        };
+        let dpi = page.estimated_dpi.map(|dpi| format!("~{} dpi", dpi)).unwrap_or_else(|| "?".to_string());
+        println!(
+            "  {:>width$}  {}  {}  {}  {}  {}  {}  {:.2} MB",
+            index + 1,
+            page.name,
+            dimensions,
+            page.format,
+            bit_depth,
+            color,
+            dpi,
+            mb(page.size),
+            width = width
+        );
+    }
+
+    let mut formats: BTreeMap<&str, usize> = BTreeMap::new();
+    for page in &inspection.pages {
+        *formats.entry(page.format.as_str()).or_default() += 1;
+    }
+    if !formats.is_empty() {
+        let formats: Vec<String> = formats.iter().map(|(format, count)| format!("{} ×{}", format, count)).collect();
+        println!("  Formats: {}", formats.join(", "));
+    }
+    if !inspection.other_files.is_empty() {
+        println!("  Other files: {}", inspection.other_files.join(", "));
+    }
+}
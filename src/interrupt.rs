@@ -0,0 +1,54 @@
+//! Graceful Ctrl-C (and SIGTERM): the first signal stops the run after the
+//! pages in flight. Files that were interrupted write no output, their
+//! temporary directories are removed and their sources are left alone, so
+//! the state file and report stay valid and `--resume` picks up the rest.
+//! A second signal quits immediately.
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::cli::EXIT_INTERRUPTED;
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Error of a file whose processing was stopped by the interrupt
+#[derive(Debug)]
+pub(crate) struct Interrupted;
+
+impl fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Interrupted")
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+/// Handle Ctrl-C for this process. Failing to install the handler (another
+/// one is set, e.g. by an embedding program) keeps the default behaviour
+pub(crate) fn install() {
+    let _ = ctrlc::set_handler(|| {
+        if REQUESTED.swap(true, Ordering::SeqCst) {
+            eprintln!("\n⛔ Quitting now");
+            std::process::exit(EXIT_INTERRUPTED.into());
+        }
+        eprintln!("\n⏸️  Stopping after the pages in progress; press Ctrl-C again to quit now");
+    });
+}
+
+/// Whether the run should stop
+pub(crate) fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Fail with [`Interrupted`] once the run should stop
+pub(crate) fn check() -> anyhow::Result<()> {
+    if requested() {
+        return Err(Interrupted.into());
+    }
+    Ok(())
+}
+
+/// Whether `error` comes from an interrupted file
+pub(crate) fn is_interrupted(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| cause.is::<Interrupted>())
+}
@@ -0,0 +1,4697 @@
+//! Compress comic book files (CBR/CBZ/CB7/CBT/PDF/EPUB/DjVu) by re-encoding
+//! their pages.
+//!
+//! The `compress_comics` binary is a thin wrapper around [`cli::run`]. To embed
+//! the pipeline elsewhere, build [`Options`] (the same settings as the command
+//! line) and process files through a [`Pipeline`]:
+//!
+//! ```no_run
+//! use compress_comics::{Options, Pipeline};
+//!
+//! let options = Options { quality: 80, ..Options::default() };
+//! let pipeline = Pipeline::new(options)?;
+//! let report = pipeline.process_file("Comic.cbz".as_ref())?;
+//! println!("{} -> {} bytes", report.original_size, report.compressed_size);
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use anyhow::{Context, Result};
+use clap::{Parser, ValueEnum};
+use crossbeam_channel::{bounded, Receiver, Sender};
+use glob::glob;
+use image::{GenericImageView, ImageReader};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufReader, Write};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use walkdir::WalkDir;
+use zip::{write::FileOptions, ZipWriter};
+
+mod adjust;
+mod alpha;
+mod bench;
+mod checksums;
+pub mod cli;
+mod cmyk;
+mod compare;
+mod comicinfo;
+mod config;
+mod contact_sheet;
+mod corrupt;
+mod dedupe;
+mod denoise;
+mod eink;
+mod entry_names;
+mod epub;
+mod extract;
+mod filters;
+mod gpu;
+mod history;
+mod hooks;
+mod icc;
+mod inspect;
+mod interrupt;
+mod library_scan;
+mod memory;
+mod metadata;
+mod metrics;
+mod ocr;
+mod organize;
+mod paths;
+mod pdf;
+mod pdf_image;
+mod pdf_pages;
+mod pdf_render;
+mod pipe;
+mod pipeline;
+mod plugins;
+mod presets;
+mod precheck;
+mod progress;
+mod provenance;
+mod recycle;
+mod resize;
+mod remote;
+mod retry;
+mod report;
+mod selection;
+mod serve;
+mod split;
+mod state;
+mod temp;
+mod throttle;
+mod timeout;
+mod times;
+mod verify;
+mod watch;
+
+use comicinfo::{ComicInfo, PageInfo};
+use throttle::Throttled;
+
+use pipeline::FileProgress;
+pub use extract::extract;
+pub use filters::FilterChain;
+pub use inspect::{inspect, Inspection, PageColor, PageSummary};
+pub use pdf_render::PdfRender;
+pub use pipeline::{PageEvent, PageEvents, Pipeline};
+pub use plugins::{PageProcessor, PLUGIN_ABI_VERSION};
+pub use provenance::ProcessingMarker;
+pub use resize::resize_lanczos3;
+pub use verify::{verify, Verification};
+
+/// Processing settings; the binary parses them from the command line
+#[derive(Parser, Clone)]
+pub struct Options {
+    /// Input files or directories to process, all in one run. Directories are searched for comic files
+    #[arg(value_name = "INPUT")]
+    pub input: Vec<PathBuf>,
+
+    /// Settings for a device or purpose: kindle-paperwhite, kobo-clara, kobo-libra, ipad, ipad-pro, phone, archive, or a preset from the config file (list them with the `presets` command). Other flags override it
+    #[arg(long, value_name = "NAME")]
+    pub preset: Option<String>,
+
+    /// Encoding quality (1-100, default: 90)
+    #[arg(short, long, default_value = "90")]
+    pub quality: u8,
+
+    /// Target image encoding (JPEG XL requires the `cjxl` tool on PATH)
+    #[arg(short, long, value_enum, default_value = "webp")]
+    pub format: ImageFormat,
+
+    /// Losslessly transcode JPEG pages to JPEG XL (reversible, no resize)
+    #[arg(long)]
+    pub jxl_lossless_jpeg: bool,
+
+    /// Encode every page losslessly (lossless WebP/JPEG XL or optimized PNG, whichever is smaller).
+    /// Black-and-white line art is detected and encoded losslessly even without this flag
+    #[arg(long)]
+    pub lossless: bool,
+
+    /// WebP compression effort: 0 is fastest, 6 gives the smallest files and is slowest (default: 4, libwebp's default)
+    #[arg(long, default_value = "4", value_name = "0-6", value_parser = clap::value_parser!(u8).range(0..=6))]
+    pub webp_method: u8,
+
+    /// Use libwebp's sharp RGB to YUV conversion for WebP pages: slower, but keeps thin coloured lines and lettering on coloured backgrounds crisp
+    #[arg(long)]
+    pub webp_sharp_yuv: bool,
+
+    /// Let libwebp spread each WebP page over several threads. Speeds up runs with few pages in flight (small --page-parallelism); when every core already encodes a page it only adds overhead
+    #[arg(long)]
+    pub webp_threads: bool,
+
+    /// Store pages that are encoded losslessly as WebP (line art, --lossless) near-losslessly instead: 0 preprocesses most and gives the smallest files, 100 is lossless
+    #[arg(long, value_name = "0-100", value_parser = clap::value_parser!(u8).range(0..=100))]
+    pub webp_near_lossless: Option<u8>,
+
+    /// Paint transparent pages onto this background colour (white, black, gray or hex such as #f4ecd8) for readers that show transparency as black. Transparency is kept by default
+    #[arg(long, value_name = "COLOR", value_parser = alpha::parse_color)]
+    pub flatten_alpha: Option<[u8; 3]>,
+
+    /// Treat every page as sRGB: skip reading embedded ICC profiles, which otherwise convert Adobe RGB, Display P3 and other wide-gamut pages to sRGB before encoding
+    #[arg(long)]
+    pub assume_srgb: bool,
+
+    /// Tag re-encoded WebP, PNG and JPEG pages with an sRGB ICC profile (about 600 bytes each) for colour-managed viewers. JPEG XL pages signal sRGB without it
+    #[arg(long)]
+    pub embed_icc: bool,
+
+    /// Render pages for e-ink readers: grayscale, levels stretched and midtones darkened for the panel, stored as lossless PNG whatever --format. Pages are always replaced, even when the rendering is larger
+    #[arg(long, conflicts_with_all = ["jxl_lossless_jpeg", "skip_compression", "target_ssim", "target_size_mb"])]
+    pub eink: bool,
+
+    /// With --eink, dither pages to the 16 gray levels of e-ink panels (Floyd–Steinberg) instead of letting the panel band gradients
+    #[arg(long, requires = "eink")]
+    pub dither: bool,
+
+    /// Brighten (up to 100) or darken (down to -100) every page before encoding
+    #[arg(long, default_value = "0", allow_negative_numbers = true, value_parser = clap::value_parser!(i32).range(-100..=100))]
+    pub brightness: i32,
+
+    /// Raise (up to 100) or lower (down to -100) the contrast of every page before encoding
+    #[arg(long, default_value = "0", allow_negative_numbers = true, value_parser = clap::value_parser!(i32).range(-100..=100))]
+    pub contrast: i32,
+
+    /// Gamma correction applied to every page before encoding; above 1 brightens the midtones, below 1 darkens them
+    #[arg(long, default_value = "1.0")]
+    pub gamma: f64,
+
+    /// Page processing steps, run in this order before encoding, e.g. "trim,deskew,resize:1800,denoise:light,sharpen:0.5".
+    /// Steps: trim, deskew, resize[:HEIGHT], denoise[:LEVEL], sharpen[:AMOUNT], brightness:N, contrast:N, gamma:F, auto-levels,
+    /// and plugin:PATH[=ARGS] for a plugin library.
+    /// Replaces the resizing, --denoise and tone flags: pages are only resized where the chain says resize
+    #[arg(long, value_name = "STEPS", value_parser = filters::parse,
+        conflicts_with_all = ["denoise", "brightness", "contrast", "gamma", "auto_levels", "jxl_lossless_jpeg", "skip_compression"])]
+    pub filters: Option<FilterChain>,
+
+    /// Smooth scanner grain before encoding (light, medium or strong), keeping ink lines sharp; grain costs lossy encoders most of their bytes
+    #[arg(long, value_enum, conflicts_with_all = ["jxl_lossless_jpeg", "skip_compression"])]
+    pub denoise: Option<DenoiseLevel>,
+
+    /// Stretch the levels of each page so its darkest and lightest 0.5% of pixels become black and white, for washed-out scans.
+    /// Adjusted pages are always replaced, even when larger
+    #[arg(long)]
+    pub auto_levels: bool,
+
+    /// Encode visually grayscale pages as single-channel: detect them (auto), treat every page as grayscale (force), or never (off)
+    #[arg(long, value_enum, default_value = "auto")]
+    pub grayscale: GrayscaleMode,
+
+    /// Output archive format (CBR requires the `rar` tool on PATH; EPUB rebuilds EPUB inputs)
+    #[arg(short = 'o', long, value_enum, default_value = "cbz")]
+    pub output_format: OutputFormat,
+
+    /// With PDF output, recognise the text of each page with the `tesseract` tool and add it as an invisible, searchable text layer
+    #[arg(long)]
+    pub ocr: bool,
+
+    /// Languages --ocr recognises, as tesseract language codes joined by + (e.g. eng+jpn)
+    #[arg(long, value_name = "LANGS", default_value = "eng", requires = "ocr")]
+    pub ocr_lang: String,
+
+    /// Include the text --ocr recognised on each page in the --report output, for indexing
+    #[arg(long, requires = "ocr")]
+    pub ocr_text: bool,
+
+    /// How entries of ZIP-based outputs (CBZ, ZIP, EPUB) are compressed: `auto` stores already-compressed pages and deflates everything else
+    #[arg(long, value_enum, default_value = "auto")]
+    pub zip_compression: ZipCompression,
+
+    /// Keep the input file's extension (and matching archive format) for the output
+    #[arg(short = 'k', long)]
+    pub keep_extension: bool,
+
+    /// Merge pages of nested archives (e.g. one inner CBZ per chapter) into a single page sequence instead of one folder per chapter
+    #[arg(long)]
+    pub flatten_nested: bool,
+
+    /// Drop operating-system junk (Thumbs.db, desktop.ini, .DS_Store, macOS `._*` files and `__MACOSX` folders) from the output. Other non-image members such as ComicInfo.xml, .nfo files or fonts are always carried over unchanged
+    #[arg(long)]
+    pub strip_extras: bool,
+
+    /// Also write `<output>.contact.jpg` next to each output: a grid of small page thumbnails in reading order, for checking page order at a glance
+    #[arg(long)]
+    pub contact_sheet: bool,
+
+    /// Drop pages that are exact or near duplicates of the page before them (compared by a perceptual fingerprint), listing each removed page
+    #[arg(long)]
+    pub dedupe_pages: bool,
+
+    /// Page names in the output: original names, or zero-padded sequential names in reading order
+    #[arg(long, value_enum, default_value = "keep")]
+    pub page_naming: PageNaming,
+
+    /// Right-to-left manga: sets ComicInfo.xml `Manga` to YesAndRightToLeft (adding ComicInfo.xml if missing)
+    /// and marks PDF/EPUB output as read right to left
+    #[arg(long)]
+    pub manga: bool,
+
+    /// Run at low CPU and I/O priority so other services on the machine stay responsive (command line only; Unix)
+    #[arg(long)]
+    pub nice: bool,
+
+    /// Limit reading source archives and writing output archives to this many MB/s in total
+    #[arg(long, value_name = "MB/S", value_parser = parse_io_rate)]
+    pub io_throttle: Option<f64>,
+
+    /// Memory budget in MiB (or with a suffix, e.g. `4G`) for decoded pages across all files, and for page data buffered while streaming CBZ inputs. Pages larger than the budget are processed one at a time (default: no limit on decoding, 256 MiB of buffered data)
+    #[arg(long, value_name = "SIZE", value_parser = memory::parse_memory_size)]
+    pub max_memory: Option<u64>,
+
+    /// Extract pages under this directory instead of the system temporary directory. Files whose extracted pages would not fit in its free space are skipped before extraction starts
+    #[arg(long, value_name = "DIR")]
+    pub temp_dir: Option<PathBuf>,
+
+    /// Recompress PDF inputs inside their original PDF (bookmarks, text layers and page order kept) instead of converting them
+    #[arg(long)]
+    pub keep_pdf: bool,
+
+    /// Render PDF pages that have no image to extract (vector art, or images in a format the extractor cannot decode) at this resolution instead of leaving them out, e.g. `--pdf-render dpi=300`. Needs Poppler's `pdftoppm` or MuPDF's `mutool`
+    #[arg(long, value_name = "dpi=N", value_parser = pdf_render::parse)]
+    pub pdf_render: Option<PdfRender>,
+
+    /// Write outputs into this directory, mirroring the input directory tree
+    #[arg(long, value_name = "DIR")]
+    pub output_dir: Option<PathBuf>,
+
+    /// Read one comic from standard input (also with INPUT "-"); it is spooled into the temporary directory, and without --stdout
+    /// its output goes to --output-dir or the current directory
+    #[arg(long, conflicts_with_all = ["input", "glob_pattern", "watch", "in_place", "rename_original", "trash_original"])]
+    pub stdin: bool,
+
+    /// File name of the comic read from standard input, e.g. "Saga 012.cbz", giving its type, output name and metadata lookup (default: stdin with the extension of its detected type)
+    #[arg(long, value_name = "NAME")]
+    pub stdin_name: Option<String>,
+
+    /// Write the output comic to standard output instead of a file (the source itself when it is kept); progress goes to standard error
+    #[arg(long, conflicts_with_all = ["output_dir", "in_place", "rename_original", "trash_original", "organize", "watch", "glob_pattern", "files_from", "dry_run"])]
+    pub stdout: bool,
+
+    /// Output file name template. Variables: {stem}, {ext}, {format}, {quality}, {date}, {savings},
+    /// and with --organize {series}, {volume}, {chapter}
+    /// (default: "{stem} optimized_{format}_q{quality}", or "{stem}" with --rename-original or --organize)
+    #[arg(long, value_name = "TEMPLATE")]
+    pub name_template: Option<String>,
+
+    /// Write outputs under --output-dir as Series/Volume NN/Series vNN cNNN, reading series, volume and chapter from the file name
+    #[arg(long, conflicts_with_all = ["in_place", "rename_original"])]
+    pub organize: bool,
+
+    /// With --organize, a regex with named groups series and optionally volume and chapter, matched case-insensitively
+    /// against the file name without extension; tried in order before the built-in patterns (repeatable)
+    #[arg(long, value_name = "REGEX", value_parser = organize::parse_pattern, requires = "organize")]
+    pub organize_pattern: Vec<regex::Regex>,
+
+    /// Target height for images (default: 1800)
+    #[arg(short = 'H', long, default_value = "1800")]
+    pub target_height: u32,
+
+    /// When pages are resized to the target height
+    #[arg(long, value_enum, default_value = "downscale-only")]
+    pub resize_policy: ResizePolicy,
+
+    /// Upscale pages shorter than the target height (Lanczos3) instead of keeping their size
+    #[arg(long)]
+    pub upscale: bool,
+
+    /// Largest factor a page is upscaled by with --upscale (default: 2.0)
+    #[arg(long, default_value = "2.0", requires = "upscale")]
+    pub max_upscale_factor: f32,
+
+    /// Target width for pages, used by --fit fit-within and fill
+    #[arg(short = 'W', long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub target_width: Option<u32>,
+
+    /// Cap on the longer side of every page in pixels, e.g. for very tall strips or wide spreads
+    #[arg(short = 'm', long, value_parser = clap::value_parser!(u32).range(1..), visible_alias = "max-dimension")]
+    pub max_long_edge: Option<u32>,
+
+    /// How pages are fitted to the target: exact target height, within target height and width, or covering both
+    #[arg(long, value_enum, default_value = "exact-height")]
+    pub fit: FitMode,
+
+    /// Animated GIF/WebP pages: pass through untouched (keep), flatten to their first frame, or re-encode as animated WebP
+    #[arg(long, value_enum, default_value = "keep")]
+    pub animated: AnimatedMode,
+
+    /// Webtoon/long-strip mode: scale pages to --target-width only (or keep their size), never to a fixed height
+    #[arg(long)]
+    pub webtoon: bool,
+
+    /// With --webtoon, cut strips taller than this many pixels into consecutive pages
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..), requires = "webtoon")]
+    pub slice_height: Option<u32>,
+
+    /// Rename original file to <name>_original.<ext> (numbered when taken) and give compressed file the original name, once the output is verified (implies --verify)
+    #[arg(short, long)]
+    pub rename_original: bool,
+
+    /// Replace the original file with the compressed one when it is smaller (atomic swap)
+    #[arg(long, conflicts_with_all = ["rename_original", "output_dir", "name_template"])]
+    pub in_place: bool,
+
+    /// With --in-place, move originals into this directory (mirroring the input tree) instead of deleting them
+    #[arg(long, value_name = "DIR", requires = "in_place")]
+    pub backup_dir: Option<PathBuf>,
+
+    /// Move the original to the system trash once its output is written and verified (implies --verify)
+    #[arg(long, conflicts_with_all = ["rename_original", "in_place"])]
+    pub trash_original: bool,
+
+    /// With --trash-original, move originals into this folder (mirroring the input tree) instead of the system trash
+    #[arg(long, value_name = "DIR", requires = "trash_original")]
+    pub trash_dir: Option<PathBuf>,
+
+    /// Do not carry the source's modification/access times (creation time on Windows) and its ZIP entry dates over to the output
+    #[arg(long)]
+    pub no_preserve_times: bool,
+
+    /// Make outputs byte-identical across runs of the same input and settings: every ZIP entry is dated 1980-01-01 and the marker comment carries no creation time (CBZ, ZIP, EPUB and PDF output)
+    #[arg(long)]
+    pub deterministic: bool,
+
+    /// Record a SHA-256 of each output for `verify-library`, as a sidecar or in its folder's SHA256SUMS manifest, and in the checksum database together with the source's hash. Both files use the `sha256sum` format
+    #[arg(long, value_name = "KIND")]
+    pub checksums: Option<ChecksumMode>,
+
+    /// Checksum database for --checksums (default: .compress_comics_checksums.json in each output's folder)
+    #[arg(long, value_name = "FILE", requires = "checksums")]
+    pub checksum_db: Option<PathBuf>,
+
+    /// Watch this directory and process comic files dropped into it once they stop changing (runs until stopped)
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["input", "glob_pattern", "dry_run"])]
+    pub watch: Option<PathBuf>,
+
+    /// With --watch, seconds a new file's size and modification time must stay unchanged before it is processed
+    #[arg(long, default_value_t = 10, value_name = "SECS", requires = "watch")]
+    pub settle_secs: u64,
+
+    /// Glob pattern for file selection (e.g., "ABC*.cbr")
+    #[arg(short, long)]
+    pub glob_pattern: Option<String>,
+
+    /// Process the paths listed in this file, one per line ("-" reads the list from standard input), e.g. from find or fd.
+    /// Listed folders are searched like INPUT; relative paths are resolved from the current directory, which --output-dir mirrors
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["input", "glob_pattern", "watch", "stdin"])]
+    pub files_from: Option<PathBuf>,
+
+    /// With --files-from, the paths are separated by NUL bytes instead of newlines (find -print0, fd -0)
+    #[arg(short = '0', long = "null", requires = "files_from")]
+    pub null_delimited: bool,
+
+    /// Search directories at most this many levels deep, like find -maxdepth (1: only the files directly in INPUT)
+    #[arg(long, value_name = "N")]
+    pub max_depth: Option<usize>,
+
+    /// Follow symbolic links to files and folders while searching directories; a file reached through several links is processed once
+    #[arg(long)]
+    pub follow_symlinks: bool,
+
+    /// Do not search folders on other file systems (mount points) below INPUT
+    #[arg(long)]
+    pub one_file_system: bool,
+
+    /// Only process files of at least this size: a number of MB, or with a K, M, G or T suffix, e.g. 300M
+    #[arg(long, value_name = "SIZE", value_parser = selection::parse_size)]
+    pub min_size: Option<u64>,
+
+    /// Only process files of at most this size: a number of MB, or with a K, M, G or T suffix
+    #[arg(long, value_name = "SIZE", value_parser = selection::parse_size)]
+    pub max_size: Option<u64>,
+
+    /// Only process files modified after this time: a UTC date (2026-10-01 or 2026-10-01T18:30), an age (30m, 12h, 7d, 2w),
+    /// or a file whose modification time to compare with
+    #[arg(long, value_name = "WHEN", value_parser = selection::parse_time)]
+    pub newer_than: Option<std::time::SystemTime>,
+
+    /// Only process files modified before this time, given like --newer-than
+    #[arg(long, value_name = "WHEN", value_parser = selection::parse_time)]
+    pub older_than: Option<std::time::SystemTime>,
+
+    /// Skip files matching this glob (repeatable), e.g. "**/To Sort/**"
+    #[arg(long, value_name = "GLOB")]
+    pub exclude: Vec<String>,
+
+    /// Only process files matching this glob (repeatable), e.g. "**/Manga/**"
+    #[arg(long, value_name = "GLOB")]
+    pub include: Vec<String>,
+
+    /// Minimum compression savings required to keep compressed file (default: 5%)
+    #[arg(long, default_value = "5.0")]
+    pub min_savings: f64,
+
+    /// Treat a file as failed (and write no output) when any of its pages cannot be processed
+    #[arg(long)]
+    pub fail_on_skip: bool,
+
+    /// Test every source archive (ZIP/RAR/7z CRCs, TAR header checksums, PDF structure) before processing; damaged files are skipped, reported as failed and listed in `damaged_files.txt` in the output directory
+    #[arg(long)]
+    pub precheck: bool,
+
+    /// Shell command run after each file that completes (also when the original is kept as already optimal). It receives COMPRESS_COMICS_INPUT, _OUTPUT, _STATUS, _ORIGINAL_SIZE, _OUTPUT_SIZE and _SAVINGS in its environment
+    #[arg(long, value_name = "CMD")]
+    pub on_success: Option<String>,
+
+    /// Shell command run after each file that fails, with the same environment as --on-success plus COMPRESS_COMICS_ERROR
+    #[arg(long, value_name = "CMD")]
+    pub on_failure: Option<String>,
+
+    /// Komga server to ask for a rescan of the libraries containing changed files once the run finishes, e.g. http://localhost:25600
+    #[arg(long, value_name = "URL", requires = "komga_token")]
+    pub komga_url: Option<String>,
+
+    /// API key for --komga-url (created under the Komga user's account settings)
+    #[arg(long, value_name = "KEY", requires = "komga_url")]
+    pub komga_token: Option<String>,
+
+    /// Kavita server to ask for a rescan of the folders containing changed files once the run finishes, e.g. http://localhost:5000
+    #[arg(long, value_name = "URL", requires = "kavita_token")]
+    pub kavita_url: Option<String>,
+
+    /// API key for --kavita-url (shown in the Kavita user's settings)
+    #[arg(long, value_name = "KEY", requires = "kavita_url")]
+    pub kavita_token: Option<String>,
+
+    /// Fill in missing ComicInfo.xml fields (writer, publisher, summary, date, ...) from ComicVine or AniList, matching the series and issue in the file name
+    #[arg(long, value_enum, value_name = "SERVICE")]
+    pub fetch_metadata: Option<MetadataSource>,
+
+    /// API key for --fetch-metadata comicvine (default: the COMICVINE_API_KEY environment variable)
+    #[arg(long, value_name = "KEY", requires = "fetch_metadata")]
+    pub api_key: Option<String>,
+
+    /// Folder caching --fetch-metadata responses for 30 days (default: ~/.cache/compress_comics/metadata)
+    #[arg(long, value_name = "DIR", requires = "fetch_metadata")]
+    pub metadata_cache: Option<PathBuf>,
+
+    /// Pages whose data is damaged (e.g. a truncated JPEG): keep the original bytes, substitute a "missing page" placeholder under the page's name, or fail the file. They are listed as page errors either way
+    #[arg(long, value_enum, value_name = "MODE", default_value = "keep")]
+    pub tolerate_corrupt: CorruptPages,
+
+    /// Reopen each output before keeping it: archive CRCs, page count and every page header (`--verify=full` decodes every page). Failing outputs are deleted and the file reported as failed
+    #[arg(long, value_enum, value_name = "MODE", num_args = 0..=1, require_equals = true, default_missing_value = "headers")]
+    pub verify: Option<VerifyMode>,
+
+    /// Stop starting new files after the first failed file (exit code 4)
+    #[arg(long, conflicts_with = "max_failures")]
+    pub fail_fast: bool,
+
+    /// Stop starting new files once this many files have failed (exit code 4)
+    #[arg(long, value_name = "N")]
+    pub max_failures: Option<std::num::NonZeroUsize>,
+
+    /// Try a failed file again up to this many times, after a pause of 5s more each time, to get past transient
+    /// I/O errors such as a NAS that dropped off for a moment
+    #[arg(long, default_value_t = 0, value_name = "N", conflicts_with = "watch")]
+    pub retries: u32,
+
+    /// Move files that still fail after --retries into this folder, mirroring the input tree, and log them with
+    /// their errors in its quarantine.log
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["watch", "dry_run"])]
+    pub quarantine_dir: Option<PathBuf>,
+
+    /// Enable verbose output with detailed warnings
+    #[arg(short, long)]
+    pub verbose: bool,
+
+    /// Progress display: live bars, one line per finished file (plain, for logs and CI), or none. Default: bars when stdout and stderr are terminals, plain otherwise
+    #[arg(long, value_enum, value_name = "MODE")]
+    pub progress: Option<ProgressMode>,
+
+    /// Run report: the text summary, one JSON document at the end, or one JSON line per finished file (ndjson)
+    #[arg(long, value_enum, default_value = "text")]
+    pub report: ReportFormat,
+
+    /// Write the JSON/NDJSON report to this file instead of stdout (JSON unless --report ndjson)
+    #[arg(long, value_name = "FILE")]
+    pub report_file: Option<PathBuf>,
+
+    /// Skip image compression - keep original images, just convert format
+    #[arg(short = 'S', long)]
+    pub skip_compression: bool,
+
+    /// Predict savings from a sample of pages per file without writing any output
+    #[arg(short = 'n', long)]
+    pub dry_run: bool,
+
+    /// Number of pages sampled per file in --dry-run mode and for --target-size-mb
+    #[arg(long, default_value = "5", value_name = "N")]
+    pub sample_pages: usize,
+
+    /// Size budget per output archive in MB: quality is lowered (never raised above --quality) until sampled pages predict a fit
+    #[arg(long, value_name = "MB")]
+    pub target_size_mb: Option<f64>,
+
+    /// Per page, use the lowest quality (up to --quality) whose SSIM against the source reaches this value, e.g. 0.97 (WebP only)
+    #[arg(long, value_name = "SSIM")]
+    pub target_ssim: Option<f64>,
+
+    /// Skip archives already produced by this tool (marker in the archive comment) and its backups
+    #[arg(long)]
+    pub skip_processed: bool,
+
+    /// Record finished files in a state file and skip them when the run is repeated
+    #[arg(long)]
+    pub resume: bool,
+
+    /// State file for --resume (default: .compress_comics_state.json in the input directory)
+    #[arg(long, value_name = "FILE")]
+    pub state_file: Option<PathBuf>,
+
+    /// Record every processed file (settings, sizes, duration and page results) in this SQLite database, for the `stats` subcommand
+    #[arg(long, value_name = "FILE")]
+    pub history_db: Option<PathBuf>,
+
+    /// Read defaults from this config file instead of the user and per-directory compress_comics.toml
+    #[arg(long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Ignore compress_comics.toml config files
+    #[arg(long, conflicts_with = "config")]
+    pub no_config: bool,
+
+    /// Resize pages on the CPU workers or on the GPU (with the `gpu` build feature; falls back to the CPU when no GPU can be used). Encoding always runs on the CPU
+    #[arg(long, value_enum, default_value = "cpu", value_name = "DEVICE")]
+    pub accel: Accel,
+
+    /// Size of the shared worker pool that files and pages run on (default: all cores; config key `threads`)
+    #[arg(short = 'j', long = "jobs", value_name = "N")]
+    pub threads: Option<usize>,
+
+    /// Give up on a file that takes longer than this many seconds: it is reported as failed, its temporary files are removed and the run moves on
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub timeout_per_file: Option<u64>,
+
+    /// Give up on a file when no page (or processing stage) finishes for this many seconds, e.g. while a malformed PDF is being read
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub timeout_per_page: Option<u64>,
+
+    /// Order of the processing queue (default: the order files are found in). size-desc starts the biggest files first, which keeps the workers busy until the end of long runs
+    #[arg(long, value_enum, value_name = "ORDER")]
+    pub order: Option<QueueOrder>,
+
+    /// Files processed at the same time (default: as many as the worker pool runs). Limiting this keeps fewer archives open without idling the page workers
+    #[arg(long, value_name = "N")]
+    pub file_parallelism: Option<NonZeroUsize>,
+
+    /// Page workers per file, on a pool of their own (default: the shared worker pool)
+    #[arg(long, value_name = "N")]
+    pub page_parallelism: Option<NonZeroUsize>,
+}
+
+impl Default for Options {
+    /// The command-line defaults
+    fn default() -> Self {
+        Options::parse_from(["compress_comics"])
+    }
+}
+
+impl Options {
+    /// Reject out-of-range or conflicting settings and check for the external
+    /// tools the output format needs
+    pub fn validate(&self) -> Result<()> {
+        if self.quality < 1 || self.quality > 100 {
+            anyhow::bail!("Quality must be between 1 and 100");
+        }
+
+        if self.target_size_mb.is_some_and(|mb| mb.is_nan() || mb <= 0.0) {
+            anyhow::bail!("--target-size-mb must be greater than 0");
+        }
+
+        if let Some(target) = self.target_ssim {
+            if !(target > 0.0 && target <= 1.0) {
+                anyhow::bail!("--target-ssim must be greater than 0 and at most 1");
+            }
+            if self.format != ImageFormat::Webp {
+                anyhow::bail!("--target-ssim is only supported for WebP output");
+            }
+        }
+
+        // Also set by presets, which clap does not check
+        if self.eink && (self.jxl_lossless_jpeg || self.skip_compression || self.target_ssim.is_some() || self.target_size_mb.is_some()) {
+            anyhow::bail!("--eink renders every page and cannot be combined with --jxl-lossless-jpeg, --skip-compression, --target-ssim or --target-size-mb");
+        }
+        if self.dither && !self.eink {
+            anyhow::bail!("--dither requires --eink");
+        }
+
+        if !(self.gamma.is_finite() && self.gamma > 0.0) {
+            anyhow::bail!("--gamma must be greater than 0");
+        }
+        // The config file can set --filters too
+        if self.filters.is_some() && (self.denoise.is_some() || adjust::requested(self)) {
+            anyhow::bail!("--filters cannot be combined with --denoise, --brightness, --contrast, --gamma or --auto-levels; add them to the chain instead");
+        }
+        if (adjust::requested(self) || self.filters.is_some()) && (self.jxl_lossless_jpeg || self.skip_compression) {
+            anyhow::bail!("--brightness, --contrast, --gamma, --auto-levels and --filters cannot be combined with --jxl-lossless-jpeg or --skip-compression, which keep pages as they are");
+        }
+
+        if self.dedupe_pages && self.keep_pdf {
+            anyhow::bail!("--dedupe-pages cannot be used with --keep-pdf, which keeps the pages of each PDF as they are");
+        }
+
+        if let Some(dir) = &self.temp_dir {
+            if !dir.is_dir() {
+                anyhow::bail!("--temp-dir {} is not an existing directory", dir.display());
+            }
+        }
+
+        if self.max_upscale_factor.is_nan() || self.max_upscale_factor < 1.0 {
+            anyhow::bail!("--max-upscale-factor must be at least 1.0");
+        }
+        if self.upscale && self.resize_policy != ResizePolicy::DownscaleOnly {
+            anyhow::bail!("--upscale only applies to --resize-policy downscale-only");
+        }
+
+        if self.format == ImageFormat::Jxl || self.jxl_lossless_jpeg {
+            check_cjxl_available()?;
+        }
+
+        if self.ocr {
+            if self.output_format != OutputFormat::Pdf || self.keep_pdf {
+                anyhow::bail!("--ocr adds a text layer to PDF output: use --output-format pdf, without --keep-pdf");
+            }
+            ocr::check_tesseract_available()?;
+        }
+
+        if self.fetch_metadata == Some(MetadataSource::Comicvine) && metadata::api_key(self).is_none() {
+            anyhow::bail!("--fetch-metadata comicvine needs --api-key or the COMICVINE_API_KEY environment variable");
+        }
+        if self.stdin && (self.in_place || self.rename_original || self.trash_original) {
+            anyhow::bail!("A comic read from standard input has no original to replace, rename or trash");
+        }
+        if let (Some(min), Some(max)) = (self.min_size, self.max_size) {
+            if min > max {
+                anyhow::bail!("--min-size must not be larger than --max-size");
+            }
+        }
+        if let (Some(newer), Some(older)) = (self.newer_than, self.older_than) {
+            if newer >= older {
+                anyhow::bail!("--newer-than must be before --older-than, or no file can match");
+            }
+        }
+        if self.stdin_name.is_some() && !self.stdin {
+            anyhow::bail!("--stdin-name names the comic read with --stdin (or INPUT \"-\")");
+        }
+        if self.stdout && self.report != ReportFormat::Text && self.report_file.is_none() {
+            anyhow::bail!("--stdout carries the comic; write the --report to a file with --report-file");
+        }
+        if self.organize && self.output_dir.is_none() {
+            anyhow::bail!("--organize writes into a Series/Volume tree and needs --output-dir");
+        }
+        if let Some(template) = &self.name_template {
+            validate_name_template(template)?;
+            if !self.organize && ORGANIZE_TEMPLATE_VARIABLES.iter().any(|name| template.contains(&format!("{{{}}}", name))) {
+                anyhow::bail!("Name template variables {{series}}, {{volume}} and {{chapter}} require --organize");
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ImageFormat {
+    Webp,
+    Jxl,
+}
+
+impl ImageFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Webp => "webp",
+            ImageFormat::Jxl => "jxl",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Cbz,
+    Cbr,
+    Zip,
+    Epub,
+    Pdf,
+}
+
+impl OutputFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Cbz => "cbz",
+            OutputFormat::Cbr => "cbr",
+            OutputFormat::Zip => "zip",
+            OutputFormat::Epub => "epub",
+            OutputFormat::Pdf => "pdf",
+        }
+    }
+}
+
+/// Format of the run report
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ReportFormat {
+    /// Human-readable summary after the run
+    Text,
+    /// One JSON document with every file and run totals
+    Json,
+    /// One JSON line per file, written as files finish
+    Ndjson,
+}
+
+/// Where `--checksums` writes each output's hash, besides the checksum database
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ChecksumMode {
+    /// A `<output>.sha256` file next to each output
+    Sidecar,
+    /// One `SHA256SUMS` file per output folder
+    Manifest,
+}
+
+/// How a run shows its progress
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ProgressMode {
+    /// Live progress bars
+    Bar,
+    /// One line per finished file with its sizes and savings
+    Plain,
+    /// Nothing until the summary
+    None,
+}
+
+/// Where `--accel` resizes pages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Accel {
+    /// On the CPU worker threads
+    Cpu,
+    /// On the GPU, falling back to the CPU (builds with the `gpu` feature)
+    Gpu,
+}
+
+/// Order in which `--order` starts the files of a run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum QueueOrder {
+    /// Biggest files first
+    SizeDesc,
+    /// Smallest files first
+    SizeAsc,
+    /// By path, numbers by value (`Vol 2` before `Vol 10`)
+    Name,
+    /// Least recently modified first
+    Mtime,
+}
+
+/// How thoroughly `--verify` checks each output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum VerifyMode {
+    /// Read every page header
+    Headers,
+    /// Decode every page completely
+    Full,
+}
+
+/// What happens to pages whose image data is damaged
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CorruptPages {
+    /// Copy the damaged bytes into the output unchanged
+    Keep,
+    /// Replace the page with a grey "missing page" image of the same name
+    Placeholder,
+    /// Treat the file as failed and write no output
+    Fail,
+}
+
+/// Compression of entries in ZIP-based outputs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ZipCompression {
+    /// Store JPEG, PNG, WebP and other compressed images; deflate text, XML and uncompressed images
+    Auto,
+    /// Store every entry uncompressed
+    Stored,
+    /// Deflate every entry
+    Deflated,
+}
+
+/// How pages are named in the output archive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum PageNaming {
+    /// Keep original names (only the extension changes when a page is re-encoded)
+    Keep,
+    /// Rename pages to page_0001, page_0002, ... in reading order
+    Sequential,
+}
+
+/// What happens to animated GIF/WebP pages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum AnimatedMode {
+    /// Copy them untouched
+    Keep,
+    /// Encode the first frame as a regular page
+    FirstFrame,
+    /// Resize every frame and re-encode as animated WebP, keeping frame timings
+    Reencode,
+}
+
+/// How the target height and width combine into a page size
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum FitMode {
+    /// Scale to the target height; the width follows the aspect ratio
+    ExactHeight,
+    /// Largest size that fits within the target height and width
+    FitWithin,
+    /// Smallest size that covers the target height and width
+    Fill,
+}
+
+/// Which pages are resized to the target height
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ResizePolicy {
+    /// Shrink taller pages; keep shorter ones as they are (unless --upscale)
+    DownscaleOnly,
+    /// Resize every page to exactly the target height
+    Always,
+    /// Keep every page's dimensions
+    Never,
+}
+
+/// When pages are encoded as single-channel grayscale
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum GrayscaleMode {
+    /// Pages whose sampled pixels carry (almost) no colour
+    Auto,
+    /// Every page
+    Force,
+    /// Never; pages keep their colour channels
+    Off,
+}
+
+/// How strongly `--denoise` smooths scanner grain
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DenoiseLevel {
+    /// Fine grain only, over 3×3 pixels
+    Light,
+    /// Heavier grain, over 5×5 pixels
+    Medium,
+    /// Coarse grain and halftone noise, at some cost to subtle shading
+    Strong,
+}
+
+/// Where `--fetch-metadata` looks comics up
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum MetadataSource {
+    /// comicvine.gamespot.com, for comics and manga volumes; needs an API key
+    Comicvine,
+    /// anilist.co, for manga series
+    Anilist,
+}
+
+#[derive(Debug, Clone)]
+struct ComicFile {
+    path: PathBuf,
+    file_type: ComicType,
+}
+
+#[derive(Debug, Clone)]
+enum ComicType {
+    Cbz,
+    Cbr,
+    Cb7,
+    Cbt,
+    Pdf,
+    Djvu,
+    Epub,
+}
+
+/// Result of processing one comic file
+#[derive(Debug)]
+pub struct Report {
+    pub original_size: u64,
+    pub compressed_size: u64,
+    pub images_processed: usize,
+    pub images_skipped: usize,
+    /// Of the processed pages, those kept in their original format after resizing
+    pub images_resized_only: usize,
+    /// Skipped pages that failed, as opposed to pages kept because nothing was smaller
+    pub page_errors: Vec<PageError>,
+    /// Pages dropped as duplicates of the page before them (`--dedupe-pages`)
+    pub removed_pages: Vec<RemovedPage>,
+    /// Text recognised on each page, with `--ocr-text`
+    pub page_text: Vec<PageText>,
+    pub compression_skipped: bool,
+    /// Sizes are a --dry-run prediction, nothing was written
+    pub estimated: bool,
+    pub output_path: Option<PathBuf>,
+    pub error_message: Option<String>,
+    pub status_message: Option<String>,
+}
+
+impl Report {
+    /// "N processed, M skipped", noting pages that were only resized or failed
+    fn page_summary(&self) -> String {
+        let mut summary = if self.images_resized_only > 0 {
+            format!("{} processed ({} resized only), {} skipped",
+                self.images_processed, self.images_resized_only, self.images_skipped)
+        } else {
+            format!("{} processed, {} skipped", self.images_processed, self.images_skipped)
+        };
+        if !self.page_errors.is_empty() {
+            summary.push_str(&format!(" ({} failed)", self.page_errors.len()));
+        }
+        summary
+    }
+}
+
+/// What happened to a single page
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PageOutcome {
+    /// Re-encoded to the target format
+    Reencoded,
+    /// Resized but kept in its original format, which came out smallest
+    ResizedOnly,
+    /// Original bytes kept (nothing was smaller, or --skip-compression)
+    Kept,
+    /// Could not be decoded or written
+    Failed,
+}
+
+impl PageOutcome {
+    /// The name used in JSON reports
+    pub fn name(self) -> &'static str {
+        match self {
+            PageOutcome::Reencoded => "reencoded",
+            PageOutcome::ResizedOnly => "resized_only",
+            PageOutcome::Kept => "kept",
+            PageOutcome::Failed => "failed",
+        }
+    }
+}
+
+/// A page dropped by `--dedupe-pages`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RemovedPage {
+    /// Page path inside the archive, `/`-separated
+    pub page: String,
+    /// The earlier page it duplicates
+    pub duplicate_of: String,
+}
+
+/// The text `--ocr` recognised on a page (`--ocr-text`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PageText {
+    /// Page path inside the archive, `/`-separated
+    pub page: String,
+    pub text: String,
+}
+
+/// A page that could not be processed and was kept as it was
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PageError {
+    /// Page path inside the archive, `/`-separated
+    pub page: String,
+    pub kind: PageErrorKind,
+    pub message: String,
+}
+
+/// Rough cause of a page error
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PageErrorKind {
+    /// Corrupt or truncated image data
+    Decode,
+    Encode,
+    /// Image format or feature the decoders do not handle
+    Unsupported,
+    /// Dimensions or memory beyond the decoder limits
+    Limits,
+    Io,
+    Other,
+}
+
+impl PageError {
+    fn new(page: &str, error: &anyhow::Error) -> Self {
+        let kind = if let Some(error) = error.downcast_ref::<image::ImageError>() {
+            match error {
+                image::ImageError::Decoding(_) => PageErrorKind::Decode,
+                image::ImageError::Encoding(_) => PageErrorKind::Encode,
+                image::ImageError::Unsupported(_) => PageErrorKind::Unsupported,
+                image::ImageError::Limits(_) => PageErrorKind::Limits,
+                image::ImageError::IoError(_) => PageErrorKind::Io,
+                image::ImageError::Parameter(_) => PageErrorKind::Other,
+            }
+        } else if error.downcast_ref::<std::io::Error>().is_some() {
+            PageErrorKind::Io
+        } else {
+            PageErrorKind::Other
+        };
+        // Some decoder messages span lines; keep the summary to one line per page
+        let message = format!("{:#}", error).lines().map(str::trim).filter(|l| !l.is_empty()).collect::<Vec<_>>().join(" ");
+        PageError { page: page.to_string(), kind, message }
+    }
+}
+
+impl std::fmt::Display for PageErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            PageErrorKind::Decode => "decode error",
+            PageErrorKind::Encode => "encode error",
+            PageErrorKind::Unsupported => "unsupported image",
+            PageErrorKind::Limits => "image too large",
+            PageErrorKind::Io => "I/O error",
+            PageErrorKind::Other => "error",
+        })
+    }
+}
+
+/// Per-file tally of page outcomes, with the errors of failed pages
+#[derive(Debug, Default, Clone)]
+struct PageCounts {
+    reencoded: usize,
+    resized_only: usize,
+    kept: usize,
+    failed: usize,
+    errors: Vec<PageError>,
+    page_text: Vec<PageText>,
+}
+
+impl PageCounts {
+    fn record(&mut self, outcome: PageOutcome) {
+        match outcome {
+            PageOutcome::Reencoded => self.reencoded += 1,
+            PageOutcome::ResizedOnly => self.resized_only += 1,
+            PageOutcome::Kept => self.kept += 1,
+            PageOutcome::Failed => self.failed += 1,
+        }
+    }
+
+    fn processed(&self) -> usize {
+        self.reencoded + self.resized_only
+    }
+
+    fn skipped(&self) -> usize {
+        self.kept + self.failed
+    }
+
+    fn total(&self) -> usize {
+        self.processed() + self.skipped()
+    }
+}
+
+fn parse_io_rate(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(rate) if rate > 0.0 && rate.is_finite() => Ok(rate),
+        _ => Err("expected a positive number of MB/s".to_string()),
+    }
+}
+
+fn compile_globs(patterns: &[String]) -> Result<Vec<glob::Pattern>> {
+    patterns
+        .iter()
+        .map(|p| glob::Pattern::new(p).with_context(|| format!("Invalid glob pattern: {}", p)))
+        .collect()
+}
+
+/// Match a path against glob patterns, both as given and relative to the input root
+fn matches_any_glob(path: &Path, input_root: &Path, patterns: &[glob::Pattern]) -> bool {
+    let relative = path.strip_prefix(input_root).unwrap_or(path);
+    patterns
+        .iter()
+        .any(|pattern| pattern.matches_path(path) || pattern.matches_path(relative))
+}
+
+/// Settings that affect the output; a state entry only counts as done when
+/// the file was processed with the same fingerprint
+fn settings_fingerprint(args: &Options) -> String {
+    format!(
+        "format={};quality={};target_size_mb={:?};target_ssim={:?};height={};width={:?};long_edge={:?};fit={:?};animated={:?};webtoon={};slice={:?};resize={:?};upscale={};output={};keep_ext={};keep_pdf={};pdf_render={:?};page_naming={:?};manga={};jxl_lossless_jpeg={};lossless={};webp={}/{}/{:?};flatten_alpha={:?};icc={};grayscale={:?};skip_compression={};dedupe={};eink={};adjust={};denoise={:?};filters={};ocr={};metadata={:?};strip_extras={};flatten_nested={};zip_compression={:?};tolerate_corrupt={:?};deterministic={}",
+        args.format.extension(),
+        args.quality,
+        args.target_size_mb,
+        args.target_ssim,
+        args.target_height,
+        args.target_width,
+        args.max_long_edge,
+        args.fit,
+        args.animated,
+        args.webtoon,
+        args.slice_height,
+        args.resize_policy,
+        if args.upscale { format!("x{}", args.max_upscale_factor) } else { "off".to_string() },
+        args.output_format.extension(),
+        args.keep_extension,
+        args.keep_pdf,
+        args.pdf_render.map(|render| render.dpi),
+        args.page_naming,
+        args.manga,
+        args.jxl_lossless_jpeg,
+        args.lossless,
+        args.webp_method,
+        args.webp_sharp_yuv,
+        args.webp_near_lossless,
+        args.flatten_alpha,
+        match (args.assume_srgb, args.embed_icc) {
+            (true, false) => "assume-srgb",
+            (true, true) => "assume-srgb/embed",
+            (false, false) => "convert",
+            (false, true) => "convert/embed",
+        },
+        args.grayscale,
+        args.skip_compression,
+        args.dedupe_pages,
+        match (args.eink, args.dither) {
+            (false, _) => "off",
+            (true, false) => "on",
+            (true, true) => "dither",
+        },
+        if adjust::requested(args) {
+            format!("{}/{}/{}/{}", args.brightness, args.contrast, args.gamma, args.auto_levels)
+        } else {
+            "off".to_string()
+        },
+        args.denoise,
+        args.filters.as_ref().map_or("-", FilterChain::spec),
+        if args.ocr { args.ocr_lang.as_str() } else { "off" },
+        args.fetch_metadata,
+        args.strip_extras,
+        args.flatten_nested,
+        args.zip_compression,
+        args.tolerate_corrupt,
+        args.deterministic,
+    )
+}
+
+fn detect_comic_file(path: &Path) -> Result<ComicFile> {
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|s| s.to_lowercase());
+
+    let file_type = match extension.as_deref() {
+        Some("cbz") => ComicType::Cbz,
+        Some("cbr") => ComicType::Cbr,
+        Some("cb7") => ComicType::Cb7,
+        Some("cbt") => ComicType::Cbt,
+        Some("pdf") => ComicType::Pdf,
+        Some("epub") => ComicType::Epub,
+        Some("djvu") | Some("djv") => ComicType::Djvu,
+        _ => anyhow::bail!("Unsupported file type. Only CBR, CBZ, CB7, CBT, PDF, EPUB, and DjVu files are supported."),
+    };
+
+    Ok(ComicFile {
+        path: path.to_path_buf(),
+        file_type,
+    })
+}
+
+/// The comic files below `dir`, within `--max-depth`, following links with
+/// `--follow-symlinks` and staying on one file system with `--one-file-system`
+fn find_comic_files(dir: &Path, args: &Options) -> Result<Vec<ComicFile>> {
+    let mut comic_files = Vec::new();
+    let mut walker = WalkDir::new(dir).follow_links(args.follow_symlinks).same_file_system(args.one_file_system);
+    if let Some(depth) = args.max_depth {
+        walker = walker.max_depth(depth);
+    }
+
+    // Link loops are reported by WalkDir as errors and skipped
+    let root = fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf());
+    let mut seen: HashMap<PathBuf, usize> = HashMap::new();
+    for entry in walker.into_iter().filter_map(|e| e.ok()) {
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let Ok(comic_file) = detect_comic_file(entry.path()) else {
+            continue;
+        };
+        if !args.follow_symlinks {
+            comic_files.push(comic_file);
+            continue;
+        }
+        // A symlinked mirror reaches the same file again under another path;
+        // the path without links wins, whichever is walked first
+        let canonical = fs::canonicalize(entry.path()).unwrap_or_else(|_| entry.path().to_path_buf());
+        let direct = entry.path().strip_prefix(dir).is_ok_and(|relative| root.join(relative) == canonical);
+        match seen.get(&canonical) {
+            Some(&index) if direct => comic_files[index] = comic_file,
+            Some(_) => {}
+            None => {
+                seen.insert(canonical, comic_files.len());
+                comic_files.push(comic_file);
+            }
+        }
+    }
+
+    Ok(comic_files)
+}
+
+/// Sort the queue of a run by `order`; files whose metadata cannot be read
+/// (e.g. remote sources not downloaded yet) sort as empty and oldest
+fn sort_queue(files: &mut [ComicFile], order: QueueOrder) {
+    let metadata = |file: &ComicFile| fs::metadata(&file.path).ok();
+    match order {
+        QueueOrder::SizeDesc => files.sort_by_cached_key(|file| std::cmp::Reverse(metadata(file).map_or(0, |m| m.len()))),
+        QueueOrder::SizeAsc => files.sort_by_cached_key(|file| metadata(file).map_or(0, |m| m.len())),
+        QueueOrder::Name => files.sort_by(|a, b| natural_cmp(&a.path.to_string_lossy(), &b.path.to_string_lossy())),
+        QueueOrder::Mtime => files.sort_by_cached_key(|file| metadata(file).and_then(|m| m.modified().ok())),
+    }
+}
+
+fn find_comic_files_by_glob(pattern: &str) -> Result<Vec<ComicFile>> {
+    let mut comic_files = Vec::new();
+    
+    // Try the pattern as provided first
+    let patterns_to_try = vec![
+        pattern.to_string(),
+        // If pattern doesn't start with / or **, try making it recursive
+        if !pattern.starts_with('/') && !pattern.starts_with("**") {
+            format!("**/{}", pattern)
+        } else {
+            pattern.to_string()
+        }
+    ];
+    
+    for pattern_attempt in patterns_to_try {
+        for entry in glob(&pattern_attempt).context("Failed to read glob pattern")? {
+            match entry {
+                Ok(path) => {
+                    if path.is_file() {
+                        if let Ok(comic_file) = detect_comic_file(&path) {
+                            comic_files.push(comic_file);
+                        }
+                    }
+                }
+                Err(_) => {
+                    // Silently skip glob pattern errors
+                }
+            }
+        }
+        
+        // If we found files with this pattern, don't try others
+        if !comic_files.is_empty() {
+            break;
+        }
+    }
+
+    if comic_files.is_empty() {
+        eprintln!("⚠️  No comic files found matching pattern: '{}'", pattern);
+        eprintln!("💡 Try patterns like:");
+        eprintln!("   - \"**/*Killer*.cbr\" (recursive search)");
+        eprintln!("   - \"/full/path/**/Killer*.cbr\" (absolute path)");
+        eprintln!("   - \"**/De Killer*.cbr\" (your specific case)");
+    }
+
+    Ok(comic_files)
+}
+
+/// The comic files listed in `list` (or on standard input for `-`), one path
+/// per line or NUL-separated; listed folders are searched, and each file is
+/// taken once
+fn find_comic_files_from_list(list: &Path, args: &Options) -> Result<Vec<ComicFile>> {
+    let mut bytes = Vec::new();
+    if list == Path::new("-") {
+        std::io::Read::read_to_end(&mut std::io::stdin(), &mut bytes).context("Failed to read the file list from standard input")?;
+    } else {
+        bytes = fs::read(list).with_context(|| format!("Failed to read file list {}", list.display()))?;
+    }
+    let text = String::from_utf8_lossy(&bytes);
+    let entries = if args.null_delimited {
+        text.split('\0').collect::<Vec<_>>()
+    } else {
+        text.lines().map(|line| line.trim_end_matches('\r')).collect()
+    };
+
+    let mut seen = std::collections::HashSet::new();
+    let mut comic_files = Vec::new();
+    for entry in entries.into_iter().filter(|entry| !entry.is_empty()) {
+        let path = Path::new(entry);
+        let found = if path.is_dir() {
+            find_comic_files(path, args)?
+        } else if path.is_file() {
+            match detect_comic_file(path) {
+                Ok(comic_file) => vec![comic_file],
+                Err(_) => {
+                    eprintln!("Warning: Skipping listed {}: not a comic file", path.display());
+                    continue;
+                }
+            }
+        } else {
+            eprintln!("Warning: Skipping listed {}: no such file", path.display());
+            continue;
+        };
+        comic_files.extend(found.into_iter().filter(|comic_file| seen.insert(comic_file.path.clone())));
+    }
+    Ok(comic_files)
+}
+
+fn process_comic_file(
+    comic_file: &ComicFile,
+    args: &Options,
+    input_root: &Path,
+    progress: &FileProgress,
+) -> Result<Report> {
+    let original_size = fs::metadata(&comic_file.path)?.len();
+    let source_sha256 = if args.dry_run {
+        String::new()
+    } else {
+        state::sha256_file(&comic_file.path).context("Failed to hash source file")?
+    };
+
+    let temp_dir = temp::create_dir(args)?;
+    progress.watch().set_temp_dir(temp_dir.path());
+    progress.set_position(10);
+
+    let output_format = output_format_for(comic_file, args);
+    let rebuild_epub = output_format == OutputFormat::Epub;
+    if rebuild_epub && !matches!(comic_file.file_type, ComicType::Epub) {
+        anyhow::bail!("EPUB output is only supported for EPUB inputs");
+    }
+    if rebuild_epub && args.dedupe_pages {
+        anyhow::bail!("--dedupe-pages cannot be used with EPUB output, whose documents reference each page by name");
+    }
+    if rebuild_epub && args.slice_height.is_some() {
+        anyhow::bail!("--slice-height cannot be used with EPUB output, whose documents reference each page by name");
+    }
+
+    let keep_pdf = keeps_pdf(comic_file, args);
+    let stream_zip = can_stream_zip(comic_file, output_format, args);
+
+    let extracts = !((keep_pdf && !args.dry_run) || stream_zip);
+    if extracts {
+        temp::check_space(temp_dir.path(), comic_file, original_size)?;
+    }
+
+    if !extracts {
+        // Pages are re-encoded straight from the source when the output is written
+    } else if rebuild_epub {
+        // Keep the whole publication; pages are re-encoded where they are
+        extract_zip_archive(&comic_file.path, temp_dir.path()).with_context(|| "extract EPUB failed")?;
+    } else {
+        extract_comic(comic_file, temp_dir.path(), progress, args.pdf_render).with_context(|| "extract_comic failed")?;
+        // Junk goes first, so macOS `._*.cbz` companions are not taken for nested archives
+        let mut stripped = if args.strip_extras { remove_junk_files(temp_dir.path())? } else { 0 };
+        let expanded = expand_nested_archives(temp_dir.path(), args.flatten_nested)
+            .with_context(|| "expand nested archives failed")?;
+        if expanded > 0 && args.verbose {
+            eprintln!("Expanded {} nested archive(s) in {}", expanded, comic_file.path.display());
+        }
+        if expanded > 0 && args.strip_extras {
+            stripped += remove_junk_files(temp_dir.path())?;
+        }
+        if stripped > 0 && args.verbose {
+            eprintln!("Stripped {} junk file(s) from {}", stripped, comic_file.path.display());
+        }
+        let converted = convert_heif_pages(temp_dir.path()).with_context(|| "convert HEIF/AVIF pages failed")?;
+        if converted > 0 && args.verbose {
+            eprintln!("Converted {} HEIC/HEIF/AVIF page(s) in {}", converted, comic_file.path.display());
+        }
+    }
+    progress.check()?;
+    progress.set_position(30);
+
+    let removed_pages = if args.dedupe_pages && extracts {
+        dedupe::remove_duplicates(temp_dir.path(), &find_page_files(temp_dir.path())?, args)?
+    } else {
+        Vec::new()
+    };
+    if !removed_pages.is_empty() && args.verbose {
+        eprintln!("Removed {} duplicate page(s) from {}", removed_pages.len(), comic_file.path.display());
+    }
+
+    let image_files = find_image_files(temp_dir.path())?;
+
+    let tuned_args;
+    let args = match args.target_size_mb {
+        Some(budget_mb) if !image_files.is_empty() && !args.skip_compression => {
+            let budget = (budget_mb * 1_048_576.0) as u64;
+            let quality = tune_quality(temp_dir.path(), &image_files, budget, args);
+            if args.verbose {
+                eprintln!("{}: quality {} for a {} MB budget", comic_file.path.display(), quality, budget_mb);
+            }
+            tuned_args = Options { quality, ..args.clone() };
+            &tuned_args
+        }
+        _ => args,
+    };
+
+    if args.dry_run {
+        let (compressed_size, sampled) = estimate_compressed_size(temp_dir.path(), &image_files, original_size, args);
+        progress.set_position(100);
+
+        return Ok(Report {
+            original_size,
+            compressed_size,
+            images_processed: sampled,
+            images_skipped: 0,
+            images_resized_only: 0,
+            page_errors: Vec::new(),
+            removed_pages,
+            page_text: Vec::new(),
+            compression_skipped: false,
+            estimated: true,
+            output_path: None,
+            error_message: None,
+            status_message: Some(format!("Estimated from {} of {} pages", sampled, image_files.len())),
+        });
+    }
+
+    // Reading order of the original pages, recorded before re-encoding changes their names
+    let page_manifest = find_page_files(temp_dir.path())?;
+
+    let mut stats = process_images(temp_dir.path(), &image_files, args, progress).with_context(|| "process_images failed")?;
+    progress.check()?;
+    progress.set_position(80);
+
+    if args.page_naming == PageNaming::Sequential && !rebuild_epub {
+        apply_sequential_page_names(&page_manifest)?;
+    }
+
+    // EPUBs describe their reading direction in the package document instead
+    if (args.manga || args.fetch_metadata.is_some()) && !rebuild_epub && comicinfo::find_in_dir(temp_dir.path()).is_none() {
+        ComicInfo::new().save(&temp_dir.path().join(comicinfo::COMICINFO_FILE_NAME))?;
+    }
+    update_comicinfo(temp_dir.path(), args.manga, args.verbose)?;
+    // Streamed CBZs are looked up while their ComicInfo.xml is rewritten
+    if extracts && !rebuild_epub {
+        metadata::fill_dir(temp_dir.path(), &comic_file.path, args)?;
+    }
+
+    if rebuild_epub {
+        epub::rewrite_references(temp_dir.path(), &renamed_images(&image_files))?;
+        if args.manga {
+            epub::set_page_progression(temp_dir.path(), "rtl")?;
+        }
+    }
+    let mut stem = comic_file.path.file_stem().unwrap().to_string_lossy().to_string();
+    let organized = args.organize.then(|| organize::parse(&stem, &args.organize_pattern)).flatten();
+    if args.organize && organized.is_none() {
+        eprintln!("⚠️  {}: no series found in the name; written to its mirrored folder", comic_file.path.display());
+    }
+    let output_dir = match (&organized, &args.output_dir) {
+        (Some(organized), Some(root)) => organized.dir(root),
+        _ => output_dir_for(&comic_file.path, args.output_dir.as_deref(), input_root),
+    };
+    fs::create_dir_all(&output_dir)
+        .with_context(|| format!("Failed to create output directory {}", output_dir.display()))?;
+    if let Some(organized) = &organized {
+        stem = organized.name();
+    }
+
+    // Always create compressed file with temporary name first to avoid overwriting original;
+    // the final name may depend on the achieved savings
+    let temp_output_path = output_dir.join(format!("{}_temp_compressed.{}", stem, output_format.extension()));
+
+    let mut marker = ProcessingMarker::new(&settings_fingerprint(args), &comic_file.path, source_sha256);
+    if args.deterministic {
+        marker.created_at = 0;
+    }
+    let written = if keep_pdf {
+        pdf::recompress_images(
+            &comic_file.path,
+            &temp_output_path,
+            &marker.to_comment(),
+            args.quality,
+            (args.resize_policy != ResizePolicy::Never).then_some(args.target_height),
+            args.manga,
+        )
+        .map(|(recompressed, kept)| PageCounts { reencoded: recompressed, kept, ..PageCounts::default() })
+        .with_context(|| "recompress PDF failed")
+    } else if stream_zip {
+        stream_zip_archive(&comic_file.path, &temp_output_path, &marker.to_comment(), args, progress)
+            .with_context(|| "streaming CBZ failed")
+    } else {
+        create_archive(temp_dir.path(), &temp_output_path, output_format, &marker.to_comment(), args)
+            .map(|page_text| PageCounts { page_text, ..stats })
+            .with_context(|| "create_archive failed")
+    };
+    // A half-written archive is never left behind, nor one of a file that timed out meanwhile
+    let written = written.and_then(|stats| progress.watch().check().map(|()| stats));
+    stats = written.inspect_err(|_| {
+        let _ = fs::remove_file(&temp_output_path);
+    })?;
+    progress.set_position(90);
+
+    if args.fail_on_skip && !stats.errors.is_empty() {
+        let _ = fs::remove_file(&temp_output_path);
+        let failures: Vec<String> = stats.errors.iter().map(|e| format!("{}: {}", e.page, e.message)).collect();
+        anyhow::bail!("{} page(s) failed (--fail-on-skip): {}", failures.len(), failures.join("; "));
+    }
+
+    if args.tolerate_corrupt == CorruptPages::Fail {
+        let corrupt: Vec<String> = stats
+            .errors
+            .iter()
+            .filter(|e| corrupt::is_corrupt(e))
+            .map(|e| format!("{}: {}", e.page, e.message))
+            .collect();
+        if !corrupt.is_empty() {
+            let _ = fs::remove_file(&temp_output_path);
+            anyhow::bail!("{} corrupt page(s) (--tolerate-corrupt fail): {}", corrupt.len(), corrupt.join("; "));
+        }
+    }
+
+    // Originals are only renamed or trashed for an output that reads back
+    let implied_verify = (args.rename_original || args.trash_original).then_some(VerifyMode::Headers);
+    if let Some(mode) = args.verify.or(implied_verify) {
+        // EPUBs and recompressed PDFs hold images that are not pages, so their page count is not compared
+        let expected_pages = (!rebuild_epub && !keep_pdf).then(|| stats.total());
+        let checked = verify::check_output(
+            &temp_output_path,
+            output_format,
+            mode,
+            expected_pages,
+            args.slice_height.is_some(),
+            stats.errors.len(),
+        );
+        if let Err(e) = checked {
+            let _ = fs::remove_file(&temp_output_path);
+            return Err(e.context("Output failed verification; the original was kept"));
+        }
+    }
+
+    let compressed_size = fs::metadata(&temp_output_path)?.len();
+
+    // Calculate compression savings
+    let savings_percent = if original_size > 0 {
+        ((original_size as f64 - compressed_size as f64) / original_size as f64) * 100.0
+    } else {
+        0.0
+    };
+
+    // Roll back outputs that do not save at least --min-savings percent, so no
+    // near-duplicate is left next to the original.
+    // With --skip-compression the output is a deliberate format conversion, with --eink
+    // or retouching filters a deliberate change to the pages, and with --ocr it carries
+    // a text layer the original lacks, so it is always kept.
+    let deliberate = args.skip_compression || args.eink || args.ocr || FilterChain::of(args).retouches();
+    let compression_skipped = !deliberate && savings_percent < args.min_savings;
+
+    if compression_skipped {
+        // Remove the compressed file and keep original
+        fs::remove_file(&temp_output_path)
+            .context("Failed to remove temporary compressed file")?;
+
+        progress.set_position(100);
+
+        return Ok(Report {
+            original_size,
+            compressed_size, // Size the rejected output would have had; the original is kept
+            images_processed: stats.processed(),
+            images_skipped: stats.skipped(),
+            images_resized_only: stats.resized_only,
+            page_errors: stats.errors,
+            removed_pages: removed_pages.clone(),
+            page_text: Vec::new(),
+            compression_skipped: true,
+            estimated: false,
+            output_path: None,
+            error_message: None,
+            status_message: Some(format!(
+                "already optimal: {:.1}% savings is below --min-savings {}%",
+                savings_percent, args.min_savings
+            )),
+        });
+    }
+
+    // Past this point the original is replaced, renamed or trashed and the
+    // output written, none of which may happen for a file that timed out
+    if let Err(e) = progress.commit() {
+        let _ = fs::remove_file(&temp_output_path);
+        return Err(e);
+    }
+
+    if args.in_place {
+        let final_output_path = replace_in_place(
+            comic_file,
+            &temp_output_path,
+            output_format,
+            original_size,
+            compressed_size,
+            args.backup_dir.as_deref(),
+            input_root,
+        )?;
+        progress.set_position(100);
+
+        let Some(final_output_path) = final_output_path else {
+            return Ok(Report {
+                original_size,
+                compressed_size: original_size,
+                images_processed: stats.processed(),
+                images_skipped: stats.skipped(),
+                images_resized_only: stats.resized_only,
+                page_errors: stats.errors,
+                removed_pages: removed_pages.clone(),
+                page_text: Vec::new(),
+                compression_skipped: true,
+                estimated: false,
+                output_path: None,
+                error_message: None,
+                status_message: None,
+            });
+        };
+
+        return Ok(Report {
+            original_size,
+            compressed_size,
+            images_processed: stats.processed(),
+            images_skipped: stats.skipped(),
+            images_resized_only: stats.resized_only,
+            page_errors: stats.errors,
+            removed_pages: removed_pages.clone(),
+            page_text: Vec::new(),
+            compression_skipped: false,
+            estimated: false,
+            output_path: Some(final_output_path),
+            error_message: None,
+            status_message: None,
+        });
+    }
+
+    let template_vars = NameTemplateVars {
+        stem: &stem,
+        extension: output_format.extension(),
+        format: args.format.extension(),
+        quality: args.quality,
+        savings_percent,
+        organized: organized.as_ref(),
+    };
+    let final_output_path = output_dir.join(render_name_template(name_template(args), &template_vars));
+    // Differently named inputs can organize to the same name, e.g. Series_v01.cbr and Series v01.cbz;
+    // an output of this same source is simply replaced
+    let same_source = |existing: ProcessingMarker| {
+        existing.source_name == marker.source_name && existing.source_sha256 == marker.source_sha256
+    };
+    if organized.is_some() && final_output_path.exists() && !provenance::read_marker(&final_output_path).is_some_and(same_source) {
+        let _ = fs::remove_file(&temp_output_path);
+        anyhow::bail!("{} already exists; remove it to organize this file there", final_output_path.display());
+    }
+
+    // Handle renaming if requested and compression was beneficial
+    let mut renamed_original = None;
+    if args.rename_original {
+        // Never overwrite another file of the library, e.g. Vol 1.cbz next to Vol 1.cbr
+        if args.output_dir.is_none() && final_output_path != comic_file.path && final_output_path.exists() {
+            let _ = fs::remove_file(&temp_output_path);
+            anyhow::bail!("{} already exists; the original was kept", final_output_path.display());
+        }
+
+        // Rename original file to backup name
+        let backup_path = original_backup_path(&comic_file.path, &stem);
+        fs::rename(&comic_file.path, &backup_path)
+            .context("Failed to rename original file")?;
+        renamed_original = Some(backup_path);
+    } else if final_output_path == comic_file.path {
+        let _ = fs::remove_file(&temp_output_path);
+        anyhow::bail!(
+            "Output name would overwrite the input file; use --rename-original, --output-dir or a different --name-template"
+        );
+    }
+
+    // Move compressed file to its final name
+    if let Err(e) = fs::rename(&temp_output_path, &final_output_path) {
+        if let Some(backup_path) = &renamed_original {
+            let _ = fs::rename(backup_path, &comic_file.path);
+        }
+        let _ = fs::remove_file(&temp_output_path);
+        return Err(e).context("Failed to rename compressed file");
+    }
+
+    if args.trash_original {
+        // The output is already in place, so a failed move does not fail the file
+        if let Err(e) = recycle::trash_original(&comic_file.path, args, input_root) {
+            eprintln!("⚠️  {:#}; the original was kept", e);
+        }
+    }
+
+    progress.set_position(100);
+
+    Ok(Report {
+        original_size,
+        compressed_size,
+        images_processed: stats.processed(),
+        images_skipped: stats.skipped(),
+        images_resized_only: stats.resized_only,
+        compression_skipped: false,
+        estimated: false,
+        output_path: Some(final_output_path),
+        error_message: None,
+        status_message: if stats.processed() > 0 {
+            None
+        } else {
+            Some("Format conversion (no recompression)".to_string())
+        },
+        page_errors: stats.errors,
+        removed_pages,
+        page_text: stats.page_text,
+    })
+}
+
+/// `<stem>_original.<ext>` next to the original for --rename-original, or
+/// `<stem>_original_2.<ext>` and so on when an earlier backup has that name
+fn original_backup_path(original: &Path, stem: &str) -> PathBuf {
+    let parent = original.parent().unwrap_or_else(|| Path::new("."));
+    let extension = original.extension().and_then(|ext| ext.to_str()).unwrap_or("cbr");
+    let mut backup_path = parent.join(format!("{}_original.{}", stem, extension));
+    let mut n = 2;
+    while backup_path.exists() {
+        backup_path = parent.join(format!("{}_original_{}.{}", stem, n, extension));
+        n += 1;
+    }
+    backup_path
+}
+
+/// (old, new) paths of images that were re-encoded under a new extension
+fn renamed_images(image_files: &[PathBuf]) -> Vec<(PathBuf, PathBuf)> {
+    image_files
+        .iter()
+        .filter(|path| !path.exists())
+        .filter_map(|path| processed_page_paths(path).into_iter().next().map(|new_path| (path.clone(), new_path)))
+        .collect()
+}
+
+/// Where a page ended up after `process_images`: unchanged, re-encoded under
+/// a new extension, or cut into slices (`--slice-height`)
+fn processed_page_paths(original: &Path) -> Vec<PathBuf> {
+    if original.exists() {
+        return vec![original.to_path_buf()];
+    }
+    if let Some(path) = find_reencoded(|ext| original.with_extension(ext)) {
+        return vec![path];
+    }
+    (0..)
+        .map_while(|index| find_reencoded(|ext| slice_page_path(original, index, ext)))
+        .collect()
+}
+
+/// The first existing `path(extension)` over the extensions pages are re-encoded to
+fn find_reencoded(path: impl Fn(&str) -> PathBuf) -> Option<PathBuf> {
+    // PNG is the lossless fallback for line art
+    ["webp", "jxl", "png"].iter().map(|ext| path(ext)).find(|candidate| candidate.exists())
+}
+
+/// Path of the `index`-th (0-based) slice of a page cut by `--slice-height`
+fn slice_page_path(original: &Path, index: usize, extension: &str) -> PathBuf {
+    let stem = original.file_stem().unwrap_or_default().to_string_lossy();
+    original.with_file_name(format!("{}_{:03}.{}", stem, index + 1, extension))
+}
+
+/// Zero-padded sequential name for the page at `index` (0-based) out of `total`
+fn sequential_page_name(index: usize, total: usize) -> String {
+    let width = total.to_string().len().max(4);
+    format!("page_{:0width$}", index + 1, width = width)
+}
+
+/// Rename pages (in manifest order) to `page_0001.<ext>`, ... in their folders.
+/// Goes through intermediate names so an existing `page_0002` is never overwritten.
+fn apply_sequential_page_names(manifest: &[PathBuf]) -> Result<()> {
+    let pages: Vec<PathBuf> = manifest.iter().flat_map(|path| processed_page_paths(path)).collect();
+
+    let mut staged = Vec::with_capacity(pages.len());
+    for (index, page) in pages.iter().enumerate() {
+        let extension = page.extension().map(|ext| ext.to_string_lossy().to_lowercase()).unwrap_or_default();
+        let staging = page.with_file_name(format!(".renaming_{}.{}", index, extension));
+        fs::rename(page, &staging)?;
+        staged.push((staging, extension));
+    }
+
+    for (index, (staging, extension)) in staged.into_iter().enumerate() {
+        let name = format!("{}.{}", sequential_page_name(index, pages.len()), extension);
+        fs::rename(&staging, staging.with_file_name(name))?;
+    }
+    Ok(())
+}
+
+/// Compare names the way comic readers order pages: digit runs by numeric
+/// value (`page2` before `page10`), everything else case-insensitively
+fn natural_cmp(a: &str, b: &str) -> std::cmp::Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        match (a.chars().next(), b.chars().next()) {
+            (None, None) => return std::cmp::Ordering::Equal,
+            (None, Some(_)) => return std::cmp::Ordering::Less,
+            (Some(_), None) => return std::cmp::Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let a_len = a.find(|c: char| !c.is_ascii_digit()).unwrap_or(a.len());
+                let b_len = b.find(|c: char| !c.is_ascii_digit()).unwrap_or(b.len());
+                let (a_num, b_num) = (a[..a_len].trim_start_matches('0'), b[..b_len].trim_start_matches('0'));
+                let ordering = a_num.len().cmp(&b_num.len()).then_with(|| a_num.cmp(b_num));
+                if ordering != std::cmp::Ordering::Equal {
+                    return ordering;
+                }
+                a = &a[a_len..];
+                b = &b[b_len..];
+            }
+            (Some(x), Some(y)) => {
+                let ordering = x.to_lowercase().cmp(y.to_lowercase());
+                if ordering != std::cmp::Ordering::Equal {
+                    return ordering;
+                }
+                a = &a[x.len_utf8()..];
+                b = &b[y.len_utf8()..];
+            }
+        }
+    }
+}
+
+/// Swap a finished temporary archive into the original's place.
+///
+/// Returns `None` (and discards the temporary archive) when it is not smaller
+/// than the original. The compressed archive already sits in the original's
+/// directory, so the final rename is atomic; the original is either replaced
+/// directly or moved to `backup_dir` first.
+fn replace_in_place(
+    comic_file: &ComicFile,
+    temp_output_path: &Path,
+    output_format: OutputFormat,
+    original_size: u64,
+    compressed_size: u64,
+    backup_dir: Option<&Path>,
+    input_root: &Path,
+) -> Result<Option<PathBuf>> {
+    if compressed_size >= original_size {
+        fs::remove_file(temp_output_path)
+            .context("Failed to remove temporary compressed file")?;
+        return Ok(None);
+    }
+
+    let original_path = &comic_file.path;
+    let final_path = original_path.with_extension(output_format.extension());
+    let replaces_original = final_path == *original_path;
+
+    // Never overwrite another file of the library, e.g. Vol 1.cbz next to Vol 1.cbr
+    if !replaces_original && final_path.exists() {
+        let _ = fs::remove_file(temp_output_path);
+        anyhow::bail!("{} already exists; the original was kept", final_path.display());
+    }
+
+    let backup_path = match backup_dir {
+        Some(backup_root) => {
+            let backup_dir = output_dir_for(original_path, Some(backup_root), input_root);
+            fs::create_dir_all(&backup_dir)
+                .with_context(|| format!("Failed to create backup directory {}", backup_dir.display()))?;
+            Some(backup_dir.join(original_path.file_name().unwrap()))
+        }
+        None => None,
+    };
+
+    // The rename below replaces an original of the same name, so its backup
+    // is taken first: a hard link where possible, a copy otherwise
+    if let (true, Some(backup_path)) = (replaces_original, &backup_path) {
+        fs::hard_link(original_path, backup_path)
+            .or_else(|_| fs::copy(original_path, backup_path).map(|_| ()))
+            .with_context(|| format!("Failed to back up original to {}", backup_path.display()))?;
+    }
+
+    if let Err(e) = fs::rename(temp_output_path, &final_path) {
+        if let (true, Some(backup_path)) = (replaces_original, &backup_path) {
+            let _ = fs::remove_file(backup_path);
+        }
+        let _ = fs::remove_file(temp_output_path);
+        return Err(e).context("Failed to move compressed file into place");
+    }
+
+    // A different output extension leaves the original under its old name
+    if !replaces_original && original_path.exists() {
+        match &backup_path {
+            // The output is already in place, so a failed move does not fail the file
+            Some(backup_path) => {
+                if let Err(e) = move_file(original_path, backup_path) {
+                    eprintln!("⚠️  Failed to move original to {}: {:#}; the original was kept", backup_path.display(), e);
+                }
+            }
+            None => fs::remove_file(original_path).context("Failed to remove original file")?,
+        }
+    }
+
+    Ok(Some(final_path))
+}
+
+/// Rename a file, falling back to copy + delete across filesystems
+fn move_file(from: &Path, to: &Path) -> Result<()> {
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    fs::copy(from, to)?;
+    fs::remove_file(from)?;
+    Ok(())
+}
+
+/// Archive format for a file's output, honouring --keep-extension where the
+/// input extension names an archive format we can write
+fn output_format_for(comic_file: &ComicFile, args: &Options) -> OutputFormat {
+    if keeps_pdf(comic_file, args) {
+        return OutputFormat::Pdf;
+    }
+    if !args.keep_extension {
+        return args.output_format;
+    }
+    let extension = comic_file.path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|s| s.to_lowercase());
+    match extension.as_deref() {
+        Some("cbz") => OutputFormat::Cbz,
+        Some("cbr") => OutputFormat::Cbr,
+        Some("zip") => OutputFormat::Zip,
+        Some("epub") => OutputFormat::Epub,
+        Some("pdf") => OutputFormat::Pdf,
+        _ => args.output_format,
+    }
+}
+
+/// Whether a PDF input is recompressed within its own structure (--keep-pdf)
+fn keeps_pdf(comic_file: &ComicFile, args: &Options) -> bool {
+    args.keep_pdf && matches!(comic_file.file_type, ComicType::Pdf)
+}
+
+/// Fail on a file whose output needs an unsupported combination or a missing external tool
+fn check_supported(comic_file: &ComicFile, args: &Options) -> Result<()> {
+    let output_format = output_format_for(comic_file, args);
+    if args.format == ImageFormat::Jxl && output_format == OutputFormat::Pdf && !keeps_pdf(comic_file, args) {
+        anyhow::bail!("PDF output cannot embed JPEG XL pages; use --format webp or another --output-format");
+    }
+    if output_format == OutputFormat::Cbr {
+        check_rar_available()?;
+    }
+    if matches!(comic_file.file_type, ComicType::Djvu) {
+        check_ddjvu_available()?;
+    }
+    if matches!(comic_file.file_type, ComicType::Pdf) && !keeps_pdf(comic_file, args) && args.pdf_render.is_some() {
+        pdf_render::check_available()?;
+    }
+    Ok(())
+}
+
+fn extract_comic(comic_file: &ComicFile, temp_dir: &Path, _progress: &FileProgress, pdf_render: Option<PdfRender>) -> Result<()> {
+    match comic_file.file_type {
+        ComicType::Cbz => {
+            extract_zip_archive(&comic_file.path, temp_dir)?;
+        }
+        ComicType::Cbr => {
+            // Try RAR first, fallback to ZIP if it fails (some CBR files are actually ZIP)
+            if extract_rar_archive(&comic_file.path, temp_dir).is_err() {
+                extract_zip_archive(&comic_file.path, temp_dir)
+                    .context("Failed to extract CBR file as both RAR and ZIP")?;
+            }
+        }
+        ComicType::Cb7 => {
+            extract_7z_archive(&comic_file.path, temp_dir)?;
+        }
+        ComicType::Cbt => {
+            extract_tar_archive(&comic_file.path, temp_dir)?;
+        }
+        ComicType::Pdf => {
+            extract_pdf_archive(&comic_file.path, temp_dir, pdf_render)?;
+        }
+        ComicType::Epub => {
+            extract_epub_archive(&comic_file.path, temp_dir)?;
+        }
+        ComicType::Djvu => {
+            extract_djvu_pages(&comic_file.path, temp_dir)?;
+        }
+    }
+    Ok(())
+}
+
+/// Unpack a comic's pages into `dir` the way processing does (nested archives
+/// expanded, HEIF pages converted) and return them in reading order
+fn unpack_pages(comic_file: &ComicFile, dir: &Path) -> Result<Vec<PathBuf>> {
+    extract_comic(comic_file, dir, &FileProgress::new(indicatif::ProgressBar::hidden()), None)?;
+    expand_nested_archives(dir, false)?;
+    convert_heif_pages(dir)?;
+    find_page_files(dir)
+}
+
+/// A page's path inside an unpacked comic, `/`-separated as in the archive
+fn page_name(page: &Path, dir: &Path) -> String {
+    page.strip_prefix(dir).unwrap_or(page).to_string_lossy().replace('\\', "/")
+}
+
+/// File names operating systems leave behind in folders, dropped by `--strip-extras`
+const JUNK_FILE_NAMES: &[&str] = &["thumbs.db", "ehthumbs.db", "desktop.ini", ".ds_store"];
+
+/// Folders that only hold operating-system metadata, dropped by `--strip-extras`
+const JUNK_DIR_NAMES: &[&str] = &["__macosx", ".appledouble"];
+
+/// Whether an archive entry is operating-system junk: a known junk file, a
+/// macOS `._*` resource fork, or anything inside a metadata folder
+fn is_junk_entry(name: &str) -> bool {
+    let mut components = name.split(['/', '\\']).filter(|c| !c.is_empty()).peekable();
+    while let Some(component) = components.next() {
+        let lower = component.to_lowercase();
+        if components.peek().is_none() {
+            return JUNK_FILE_NAMES.contains(&lower.as_str()) || lower.starts_with("._");
+        }
+        if JUNK_DIR_NAMES.contains(&lower.as_str()) {
+            return true;
+        }
+    }
+    false
+}
+
+/// Delete junk files and metadata folders from an extracted comic; returns
+/// the number of files removed
+fn remove_junk_files(dir: &Path) -> Result<usize> {
+    let junk: Vec<PathBuf> = WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file() && is_junk_entry(&page_name(e.path(), dir)))
+        .map(|e| e.into_path())
+        .collect();
+    for path in &junk {
+        fs::remove_file(path)?;
+    }
+    for entry in fs::read_dir(dir)?.filter_map(|e| e.ok()) {
+        let name = entry.file_name().to_string_lossy().to_lowercase();
+        if entry.path().is_dir() && JUNK_DIR_NAMES.contains(&name.as_str()) {
+            fs::remove_dir_all(entry.path())?;
+        }
+    }
+    Ok(junk.len())
+}
+
+/// Extensions of archives found inside an extracted comic that are unpacked in turn
+const NESTED_ARCHIVE_EXTENSIONS: &[&str] = &["zip", "cbz", "rar", "cbr", "7z", "cb7", "tar", "cbt"];
+
+/// Guards against archive bombs that nest archives indefinitely
+const MAX_NESTING_DEPTH: usize = 4;
+
+/// Unpack archives nested inside an extracted comic, each into a folder named
+/// after it so chapter order follows the archive names. With `flatten`, all
+/// pages are then moved to the top level with an index prefix that keeps that
+/// order. Returns the number of archives expanded.
+fn expand_nested_archives(dir: &Path, flatten: bool) -> Result<usize> {
+    let mut expanded = 0;
+
+    for _ in 0..MAX_NESTING_DEPTH {
+        let mut nested: Vec<PathBuf> = WalkDir::new(dir)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+            .map(|e| e.into_path())
+            .filter(|path| {
+                path.extension()
+                    .and_then(|ext| ext.to_str())
+                    .map(|ext| NESTED_ARCHIVE_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+                    .unwrap_or(false)
+            })
+            .collect();
+        if nested.is_empty() {
+            break;
+        }
+        nested.sort();
+
+        for archive_path in nested {
+            let mut target = archive_path.with_extension("");
+            while target.exists() {
+                target = PathBuf::from(format!("{}_", target.display()));
+            }
+            fs::create_dir_all(&target)?;
+            extract_nested_archive(&archive_path, &target)
+                .with_context(|| format!("Failed to extract nested archive {}", archive_path.display()))?;
+            fs::remove_file(&archive_path)?;
+            expanded += 1;
+        }
+    }
+
+    if flatten && expanded > 0 {
+        flatten_pages(dir)?;
+    }
+    Ok(expanded)
+}
+
+fn extract_nested_archive(archive_path: &Path, target: &Path) -> Result<()> {
+    let extension = archive_path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "rar" | "cbr" => {
+            if extract_rar_archive(archive_path, target).is_err() {
+                extract_zip_archive(archive_path, target)?;
+            }
+            Ok(())
+        }
+        "7z" | "cb7" => extract_7z_archive(archive_path, target),
+        "tar" | "cbt" => extract_tar_archive(archive_path, target),
+        _ => extract_zip_archive(archive_path, target),
+    }
+}
+
+/// Move every page to the top of `dir` in reading order and drop the per-chapter
+/// folders (including chapter-level ComicInfo.xml files)
+fn flatten_pages(dir: &Path) -> Result<()> {
+    let pages = find_page_files(dir)?;
+    let width = pages.len().to_string().len().max(3);
+
+    for (index, page) in pages.iter().enumerate() {
+        let file_name = page.file_name().unwrap_or_default().to_string_lossy();
+        let flattened = dir.join(format!("{:0width$}_{}", index + 1, file_name, width = width));
+        fs::rename(page, &flattened)?;
+    }
+
+    for entry in fs::read_dir(dir)?.filter_map(|e| e.ok()) {
+        if entry.file_type()?.is_dir() {
+            fs::remove_dir_all(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+fn extract_epub_archive(epub_path: &Path, temp_dir: &Path) -> Result<()> {
+    let mime_to_ext = |mime: &str| -> &str {
+        match mime {
+            "image/jpeg" => "jpg",
+            "image/png" => "png",
+            "image/gif" => "gif",
+            "image/bmp" => "bmp",
+            "image/webp" => "webp",
+            "image/tiff" | "image/tif" => "tiff",
+            _ => "",
+        }
+    };
+
+    // Extract src attributes from XHTML/HTML content.
+    fn extract_src_attrs(content: &str) -> Vec<String> {
+        let mut results = Vec::new();
+        let lower = content.to_lowercase();
+        let mut search_start = 0;
+        while let Some(pos) = lower[search_start..].find("src=") {
+            let pos = search_start + pos;
+            let rest = &content[pos + 4..];
+            let Some(quote) = rest.chars().next() else { break };
+            let quote_end = if quote == '"' { '"' } else { '\'' };
+            if let Some(end) = rest[1..].find(quote_end) {
+                results.push(rest[1..][..end].to_string());
+                search_start = pos + 4 + 1 + end;
+            } else {
+                break;
+            }
+        }
+        results
+    }
+
+    let mut doc = ::epub::doc::EpubDoc::new(epub_path)
+        .map_err(|e| anyhow::anyhow!("Failed to parse EPUB file: {:?}. Ensure it's a valid EPUB.", e))?;
+
+    #[derive(Clone)]
+    struct ImageRef {
+        path: std::path::PathBuf,
+        ext: String,
+    }
+
+    #[derive(Clone)]
+    struct SpineEntry {
+        idref: String,
+        is_image: bool,
+        image_path: Option<std::path::PathBuf>,
+        image_ext: Option<String>,
+        xhtml_content: Option<String>,
+    }
+
+    /// Try to find a resource by resolving a src reference against the spine
+    /// item's resource path.
+    fn find_resource<'a>(
+        src: &str,
+        spine_resource_path: &std::path::Path,
+        resources: &'a std::collections::HashMap<String, ::epub::doc::ResourceItem>,
+    ) -> Option<&'a ::epub::doc::ResourceItem> {
+        // Try exact path match first
+        if let Some(r) = resources.get(src) {
+            return Some(r);
+        }
+        // Try resolving relative to spine resource directory
+        let base_dir = spine_resource_path.parent().map(|p| p.to_string_lossy().to_string());
+        if let Some(dir) = base_dir {
+            let resolved = if let Some(stripped) = src.strip_prefix('/') {
+                stripped.to_string()
+            } else {
+                format!("{}/{}", dir, src)
+            };
+            if let Some(r) = resources.get(&resolved) {
+                return Some(r);
+            }
+        }
+        // Fallback: match by basename only
+        if let Some(basename) = std::path::Path::new(src).file_name() {
+            for r in resources.values() {
+                if r.path.file_name().map(|n| n == basename).unwrap_or(false) {
+                    return Some(r);
+                }
+            }
+        }
+        None
+    }
+
+    // Phase 1a: Collect spine IDs and image info (immutable phase)
+    let mut spine_image_info = Vec::new();
+    for item in &doc.spine {
+        let idref = &item.idref;
+        let is_image;
+        let (image_path, image_ext) = if let Some(resource) = doc.resources.get(idref) {
+            let ext = mime_to_ext(&resource.mime);
+            if !ext.is_empty() {
+                is_image = true;
+                (Some(resource.path.clone()), Some(ext.to_string()))
+            } else {
+                is_image = false;
+                (None, None)
+            }
+        } else {
+            is_image = false;
+            (None, None)
+        };
+        spine_image_info.push((idref.clone(), is_image, image_path, image_ext));
+    }
+    // End immutable phase — `doc.spine` and `doc.resources` borrows released
+
+    // Phase 1b: Fetch XHTML content for non-image spine items
+    let entries: Vec<SpineEntry> = spine_image_info.into_iter().map(|(idref, is_image, image_path, image_ext)| {
+        let xhtml_content = if is_image {
+            None
+        } else {
+            doc.get_resource_str(&idref)
+                .filter(|(_, mime)| mime.contains("html") || mime.contains("xml"))
+                .map(|(content, _)| content)
+        };
+        SpineEntry {
+            idref,
+            is_image,
+            image_path,
+            image_ext,
+            xhtml_content,
+        }
+    }).collect();
+
+    // Phase 2: Collect images in reading order (doc is no longer borrowed immutably)
+    let mut seen = std::collections::HashSet::new();
+    let mut images: Vec<ImageRef> = Vec::new();
+
+    for entry in &entries {
+        if entry.is_image {
+            if let (Some(path), Some(ext)) = (&entry.image_path, &entry.image_ext) {
+                if seen.insert(path.clone()) {
+                    images.push(ImageRef {
+                        path: path.clone(),
+                        ext: ext.clone(),
+                    });
+                }
+            }
+            continue;
+        }
+
+        // Parse XHTML content for <img src="..."> refs
+        if let Some(ref content) = entry.xhtml_content {
+            let srcs = extract_src_attrs(content);
+            let spine_res_path = doc.resources
+                .get(&entry.idref)
+                .map(|r| r.path.clone());
+            for src in srcs {
+                let resolved_resource = spine_res_path
+                    .as_ref()
+                    .and_then(|p| find_resource(&src, p, &doc.resources))
+                    .or_else(|| find_resource(&src, &std::path::PathBuf::new(), &doc.resources));
+                if let Some(r) = resolved_resource {
+                    let ext = mime_to_ext(&r.mime);
+                    if !ext.is_empty() && seen.insert(r.path.clone()) {
+                        images.push(ImageRef {
+                            path: r.path.clone(),
+                            ext: ext.to_string(),
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    // Fallback: no spine images — use all image resources from the manifest
+    if images.is_empty() {
+        let mut all: Vec<ImageRef> = doc.resources.values()
+            .filter_map(|resource| {
+                let ext = mime_to_ext(&resource.mime);
+                if !ext.is_empty() {
+                    Some(ImageRef {
+                        path: resource.path.clone(),
+                        ext: ext.to_string(),
+                    })
+                } else {
+                    None
+                }
+            })
+            .collect();
+        // Sort by path for deterministic ordering
+        all.sort_by(|a, b| a.path.cmp(&b.path));
+        for img in all {
+            if seen.insert(img.path.clone()) {
+                images.push(img);
+            }
+        }
+    }
+
+    // Now extract images in order
+    let mut image_count = 0u32;
+    for img in &images {
+        image_count += 1;
+        let out_name = format!("page_{:04}.{}", image_count, img.ext);
+        let out_path = temp_dir.join(&out_name);
+
+        if let Some(parent) = img.path.parent() {
+            let dir_path = temp_dir.join(parent);
+            fs::create_dir_all(&dir_path)?;
+        }
+
+        if let Some(data) = doc.get_resource_by_path(&img.path) {
+            fs::write(&out_path, data)
+                .map_err(|e| anyhow::anyhow!("Failed to write EPUB image {}: {:?}", out_name, e))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn extract_zip_archive(archive_path: &Path, temp_dir: &Path) -> Result<()> {
+    let file = throttle::open(archive_path)?;
+    let reader = BufReader::new(file);
+    let mut archive = zip::ZipArchive::new(reader)?;
+    let names = entry_names::decoded_names(&mut archive)?;
+
+    for (i, name) in names.iter().enumerate() {
+        let mut file = archive.by_index(i)?;
+        let file_path = sanitized_entry_path(temp_dir, name)?;
+
+        if let Some(parent) = file_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // Skip directories - they are created by create_dir_all above
+        if name.ends_with('/') || name.ends_with('\\') {
+            continue;
+        }
+
+        let mut output_file = File::create(&file_path)?;
+        std::io::copy(&mut file, &mut output_file)?;
+        // Kept so the entry date can be carried over into the output
+        if let Some(modified) = file.last_modified().and_then(times::system_time) {
+            let _ = output_file.set_modified(modified);
+        }
+    }
+
+    Ok(())
+}
+
+fn extract_rar_archive(archive_path: &Path, temp_dir: &Path) -> Result<()> {
+    let archive = unrar::Archive::new(&paths::long_path(archive_path))
+        .open_for_processing()
+        .map_err(|e| anyhow::anyhow!("Failed to open RAR archive: {:?}", e))?;
+
+    let mut current_archive = archive;
+
+    loop {
+        match current_archive.read_header() {
+            Ok(Some(archive_with_header)) => {
+                let header = archive_with_header.entry();
+                let file_path = sanitized_entry_path(temp_dir, &header.filename.to_string_lossy())?;
+
+                if header.is_directory() {
+                    fs::create_dir_all(&file_path)?;
+                    current_archive = archive_with_header
+                        .skip()
+                        .map_err(|e| anyhow::anyhow!("Failed to read RAR entry: {:?}", e))?;
+                    continue;
+                }
+                if let Some(parent) = file_path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+
+                // Extract the current file to its sanitized path in the temp directory
+                let archive_after_extract = archive_with_header
+                    .extract_to(paths::long_path(&file_path))
+                    .map_err(|e| anyhow::anyhow!("Failed to extract RAR entry: {:?}", e))?;
+
+                current_archive = archive_after_extract;
+            }
+            Ok(None) => {
+                // No more files in the archive
+                break;
+            }
+            Err(e) => {
+                return Err(anyhow::anyhow!("Failed to read RAR header: {:?}", e));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn extract_7z_archive(archive_path: &Path, temp_dir: &Path) -> Result<()> {
+    sevenz_rust::decompress_with_extract_fn(throttle::open(archive_path)?, temp_dir, |entry, reader, _| {
+        let file_path = sanitized_entry_path(temp_dir, entry.name())
+            .map_err(|e| sevenz_rust::Error::other(e.to_string()))?;
+        sevenz_rust::default_entry_extract_fn(entry, reader, &file_path)
+    })
+    .map_err(|e| anyhow::anyhow!("Failed to extract 7z archive: {:?}", e))
+}
+
+fn extract_tar_archive(archive_path: &Path, temp_dir: &Path) -> Result<()> {
+    let file = throttle::open(archive_path)?;
+    let mut archive = tar::Archive::new(BufReader::new(file));
+
+    for entry in archive.entries().context("Failed to read TAR archive")? {
+        let mut entry = entry.context("Failed to read TAR entry")?;
+        let entry_type = entry.header().entry_type();
+        // Links could point outside the temp directory; comics only need files and folders
+        if !entry_type.is_file() && !entry_type.is_dir() {
+            continue;
+        }
+
+        let name = entry.path()?.to_string_lossy().into_owned();
+        let file_path = sanitized_entry_path(temp_dir, &name)?;
+        if entry_type.is_dir() {
+            fs::create_dir_all(&file_path)?;
+            continue;
+        }
+        if let Some(parent) = file_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        entry
+            .unpack(&file_path)
+            .map_err(|e| anyhow::anyhow!("Failed to extract TAR entry {}: {:?}", name, e))?;
+    }
+    Ok(())
+}
+
+/// Resolve an archive entry name inside `base`, rejecting names that could
+/// escape it (zip-slip): absolute paths, drive prefixes and `..` components.
+/// Backslashes are treated as separators so Windows-made archives behave the same.
+/// On Windows, components are made legal on NTFS (`paths::ntfs_component`).
+pub(crate) fn sanitized_entry_path(base: &Path, entry_name: &str) -> Result<PathBuf> {
+    let normalized = entry_name.replace('\\', "/");
+    let unsafe_name = || anyhow::anyhow!("Refusing to extract unsafe archive entry path: {}", entry_name);
+
+    let bytes = normalized.as_bytes();
+    let has_drive_prefix = bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':';
+    if normalized.starts_with('/') || has_drive_prefix {
+        return Err(unsafe_name());
+    }
+
+    let mut path = base.to_path_buf();
+    for component in normalized.split('/') {
+        match component {
+            "" | "." => continue,
+            ".." => return Err(unsafe_name()),
+            _ if cfg!(windows) => path.push(&*paths::ntfs_component(component)),
+            _ => path.push(component),
+        }
+    }
+    Ok(path)
+}
+
+fn check_ddjvu_available() -> Result<()> {
+    Command::new("ddjvu")
+        .arg("--help")
+        .output()
+        .map(|_| ())
+        .map_err(|_| anyhow::anyhow!("DjVu input requires the `ddjvu` tool (DjVuLibre) to be installed and on PATH"))
+}
+
+/// Render every DjVu page with `ddjvu` and store it as a lossless PNG, so the
+/// pages enter the normal image pipeline
+fn extract_djvu_pages(djvu_path: &Path, temp_dir: &Path) -> Result<()> {
+    // Rendered next to the extracted pages, so `--temp-dir` covers both
+    let render_dir = tempfile::tempdir_in(temp_dir).context("Failed to create DjVu render directory")?;
+
+    let result = Command::new("ddjvu")
+        .args(["-format=ppm", "-eachpage", "-quality=100"])
+        .arg(paths::long_path(djvu_path).as_ref())
+        .arg(paths::long_path(&render_dir.path().join("page_%04d.ppm")).as_ref())
+        .output()
+        .context("Failed to run ddjvu")?;
+
+    if !result.status.success() {
+        anyhow::bail!("ddjvu failed: {}", String::from_utf8_lossy(&result.stderr).trim());
+    }
+
+    let mut rendered: Vec<PathBuf> = fs::read_dir(render_dir.path())?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().map(|ext| ext == "ppm").unwrap_or(false))
+        .collect();
+    rendered.sort();
+
+    if rendered.is_empty() {
+        anyhow::bail!("ddjvu produced no pages");
+    }
+
+    for page in rendered {
+        let img = image::open(&page)
+            .map_err(|e| anyhow::anyhow!("Failed to read rendered DjVu page {}: {:?}", page.display(), e))?;
+        let output = temp_dir.join(page.with_extension("png").file_name().unwrap_or_default());
+        img.save(&output)
+            .map_err(|e| anyhow::anyhow!("Failed to save rendered DjVu page: {:?}", e))?;
+    }
+
+    Ok(())
+}
+
+/// Page formats the image crate cannot decode; converted with libheif's CLI
+const HEIF_EXTENSIONS: &[&str] = &["heic", "heif", "avif"];
+
+/// libheif's decoder, `heif-dec` (called `heif-convert` before libheif 1.17)
+fn heif_decoder() -> Result<&'static str> {
+    ["heif-dec", "heif-convert"]
+        .into_iter()
+        .find(|tool| Command::new(tool).arg("--version").output().is_ok())
+        .ok_or_else(|| anyhow::anyhow!("HEIC/HEIF/AVIF pages require libheif's `heif-dec` (or `heif-convert`) tool to be installed and on PATH"))
+}
+
+/// Decode HEIC/HEIF/AVIF pages to lossless PNGs next to them, so they enter
+/// the normal image pipeline. Returns the number of pages converted.
+fn convert_heif_pages(dir: &Path) -> Result<usize> {
+    let pages: Vec<PathBuf> = WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.into_path())
+        .filter(|path| HEIF_EXTENSIONS.contains(&entry_extension(&path.to_string_lossy()).unwrap_or_default().as_str()))
+        .collect();
+    if pages.is_empty() {
+        return Ok(0);
+    }
+
+    let decoder = heif_decoder()?;
+    pages.par_iter().try_for_each(|page| -> Result<()> {
+        // Keep both pages when `001.heic` sits next to `001.png`
+        let mut output = page.with_extension("png");
+        if output.exists() {
+            let stem = page.file_stem().unwrap_or_default().to_string_lossy();
+            let extension = page.extension().unwrap_or_default().to_string_lossy();
+            output = page.with_file_name(format!("{}_{}.png", stem, extension));
+        }
+
+        let result = Command::new(decoder)
+            .arg(paths::long_path(page).as_ref())
+            .arg(paths::long_path(&output).as_ref())
+            .output()
+            .with_context(|| format!("Failed to run {}", decoder))?;
+        if !result.status.success() || !output.exists() {
+            anyhow::bail!("{} failed on {}: {}", decoder, page.display(), String::from_utf8_lossy(&result.stderr).trim());
+        }
+        fs::remove_file(page)?;
+        Ok(())
+    })?;
+    Ok(pages.len())
+}
+
+/// Extract the images of every PDF page into `page_NNNN.png`; with
+/// `pdf_render`, pages without an image to extract are rendered instead
+fn extract_pdf_archive(pdf_path: &Path, temp_dir: &Path, pdf_render: Option<PdfRender>) -> Result<()> {
+    use lopdf::{Document, Object};
+
+    let doc = Document::load_from(BufReader::new(throttle::open(pdf_path)?))
+        .map_err(|e| anyhow::anyhow!("Failed to load PDF: {:?}", e))?;
+
+    let pages = doc.get_pages();
+
+    // Decode a JP2 or standard image file into an RgbImage
+    fn decode_to_rgb(path: &Path) -> Result<image::RgbImage> {
+        if path.extension().and_then(|e| e.to_str()).map(|e| e.eq_ignore_ascii_case("jp2")).unwrap_or(false) {
+            let jp2 = jpeg2k::Image::from_file(path)
+                .map_err(|e| anyhow::anyhow!("JP2 decode failed {}: {:?}", path.display(), e))?;
+            let px = jp2.get_pixels(None)
+                .map_err(|e| anyhow::anyhow!("JP2 get_pixels failed {}: {:?}", path.display(), e))?;
+            let (w, h) = (px.width, px.height);
+            let rgb = match px.data {
+                jpeg2k::ImagePixelData::Rgb8(d) => d,
+                jpeg2k::ImagePixelData::Rgba8(d) => d.chunks(4).flat_map(|c| [c[0], c[1], c[2]]).collect(),
+                jpeg2k::ImagePixelData::L8(d) => d.iter().flat_map(|&v| [v, v, v]).collect(),
+                _ => return Err(anyhow::anyhow!("Unsupported JP2 pixel format in {}", path.display())),
+            };
+            image::RgbImage::from_raw(w, h, rgb)
+                .ok_or_else(|| anyhow::anyhow!("Failed to build RgbImage from {}", path.display()))
+        } else {
+            Ok(image::ImageReader::open(path)?.decode()?.into_rgb8())
+        }
+    }
+
+    // Decode a JP2 or standard image as grayscale (used for SMask alpha)
+    fn decode_to_luma(path: &Path) -> Result<image::GrayImage> {
+        if path.extension().and_then(|e| e.to_str()).map(|e| e.eq_ignore_ascii_case("jp2")).unwrap_or(false) {
+            let jp2 = jpeg2k::Image::from_file(path)
+                .map_err(|e| anyhow::anyhow!("JP2 decode failed {}: {:?}", path.display(), e))?;
+            let px = jp2.get_pixels(None)
+                .map_err(|e| anyhow::anyhow!("JP2 get_pixels failed {}: {:?}", path.display(), e))?;
+            let (w, h) = (px.width, px.height);
+            let gray = match px.data {
+                jpeg2k::ImagePixelData::L8(d) => d,
+                jpeg2k::ImagePixelData::Rgb8(d) => d.chunks(3)
+                    .map(|c| (0.299 * c[0] as f32 + 0.587 * c[1] as f32 + 0.114 * c[2] as f32) as u8)
+                    .collect(),
+                jpeg2k::ImagePixelData::Rgba8(d) => d.chunks(4)
+                    .map(|c| (0.299 * c[0] as f32 + 0.587 * c[1] as f32 + 0.114 * c[2] as f32) as u8)
+                    .collect(),
+                _ => return Err(anyhow::anyhow!("Unsupported JP2 pixel format in {}", path.display())),
+            };
+            image::GrayImage::from_raw(w, h, gray)
+                .ok_or_else(|| anyhow::anyhow!("Failed to build GrayImage from {}", path.display()))
+        } else {
+            Ok(image::ImageReader::open(path)?.decode()?.into_luma8())
+        }
+    }
+
+    // Porter-Duff OVER: result = overlay * a + base * (1 - a).
+    // PDF SMask semantics: alpha=1 → overlay opaque (replaces base),
+    // alpha=0 → overlay transparent (base shows). For IA's MRC pages, the
+    // JBIG2 mask is binary: 255 where the foreground layer (ink/text)
+    // should show, 0 where the background (photo) should show.
+    fn composite_over(base: &mut image::RgbImage, overlay: &image::RgbImage, alpha: &image::GrayImage) {
+        let (w, h) = (base.width(), base.height());
+        for y in 0..h {
+            for x in 0..w {
+                let a = alpha.get_pixel(x, y).0[0];
+                if a == 0 { continue; }
+                if a == 255 {
+                    base.put_pixel(x, y, *overlay.get_pixel(x, y));
+                    continue;
+                }
+                let af = a as f32 / 255.0;
+                let [br, bg, bb] = base.get_pixel(x, y).0;
+                let [or, og, ob] = overlay.get_pixel(x, y).0;
+                let nr = (or as f32 * af + br as f32 * (1.0 - af)).round() as u8;
+                let ng = (og as f32 * af + bg as f32 * (1.0 - af)).round() as u8;
+                let nb = (ob as f32 * af + bb as f32 * (1.0 - af)).round() as u8;
+                base.put_pixel(x, y, image::Rgb([nr, ng, nb]));
+            }
+        }
+    }
+
+    // Extract a stream to a file; returns empty PathBuf for unsupported filters.
+    fn extract_stream(stream: &lopdf::Stream, doc: &Document, temp_dir: &Path, base: &str) -> Result<PathBuf> {
+        let (path, _) = extract_image_from_stream_to(stream, doc, temp_dir, &(0, 0), base)?;
+        Ok(path)
+    }
+
+    // Decode a JBIG2-encoded mask stream into a binary GrayImage of the layer's size.
+    // Returns None if decoding fails (caller will skip compositing and use base alone).
+    fn decode_jbig2_mask(stream: &lopdf::Stream, doc: &Document, width: u32, height: u32) -> Option<image::GrayImage> {
+        let gray = pdf_image::decode_jbig2(stream, doc).ok()?;
+        let (pw, ph) = gray.dimensions();
+        if pw != width || ph != height {
+            Some(image::imageops::resize(&gray, width, height, image::imageops::FilterType::Nearest))
+        } else {
+            Some(gray)
+        }
+    }
+
+    // The images of every page in paint order, read up front so that images
+    // shared between pages are decoded once and kept until their last page
+    let drawn: Vec<Vec<pdf_pages::PageImage>> = pages.values().map(|&id| pdf_pages::page_images(&doc, id)).collect();
+    let mut uses: HashMap<(u32, u16), usize> = HashMap::new();
+    for image in drawn.iter().flatten() {
+        if let pdf_pages::PageImage::Object(id) = image {
+            *uses.entry(*id).or_default() += 1;
+        }
+    }
+    let mut shared: HashMap<(u32, u16), image::RgbImage> = HashMap::new();
+
+    // Pages left without (all of) their images, for `pdf_render`
+    let mut unextracted: Vec<usize> = Vec::new();
+
+    for (page_num, images) in drawn.iter().enumerate() {
+        let output_num = page_num + 1;
+        if images.is_empty() {
+            unextracted.push(output_num);
+            continue;
+        }
+
+        // Masked images are layered onto the picture below them (as in the
+        // MRC pages of scans); any other image is a picture of its own
+        let mut pictures: Vec<image::RgbImage> = Vec::new();
+        let mut skipped_layer = false;
+
+        for (index, image) in images.iter().enumerate() {
+            let (stream, ref_id) = match image {
+                pdf_pages::PageImage::Object(id) => match doc.get_object(*id) {
+                    Ok(Object::Stream(stream)) => (stream, Some(*id)),
+                    _ => continue,
+                },
+                pdf_pages::PageImage::Inline(stream) => (stream, None),
+            };
+
+            let cached = ref_id.and_then(|id| shared.get(&id).cloned());
+            let layer_rgb = match cached {
+                Some(rgb) => rgb,
+                None => {
+                    let base = match ref_id {
+                        Some(id) => format!("img_{:04}_{:04}", id.0, id.1),
+                        None => format!("inline_{:04}_{:02}", output_num, index),
+                    };
+                    let path = extract_stream(stream, &doc, temp_dir, &base)?;
+                    if path == PathBuf::new() {
+                        skipped_layer = true;
+                        continue;
+                    }
+                    let rgb = decode_to_rgb(&path)?;
+                    let _ = fs::remove_file(&path);
+                    if let Some(id) = ref_id.filter(|id| uses[id] > 1) {
+                        shared.insert(id, rgb.clone());
+                    }
+                    rgb
+                }
+            };
+            if let Some(id) = ref_id {
+                let remaining = uses.get_mut(&id).expect("counted above");
+                *remaining -= 1;
+                if *remaining == 0 {
+                    shared.remove(&id);
+                }
+            }
+
+            let (w, h) = (layer_rgb.width(), layer_rgb.height());
+
+            // Get alpha mask for this layer (if it has an SMask)
+            let smask_ref = stream.dict.get(b"SMask").and_then(Object::as_reference).ok();
+            let alpha: Option<image::GrayImage> = if let Some(smask_id) = smask_ref {
+                if let Ok(Object::Stream(smask_stream)) = doc.get_object(smask_id) {
+                    match pdf_image::single_filter(smask_stream) {
+                        Some(b"JBIG2Decode") => {
+                            decode_jbig2_mask(smask_stream, &doc, w, h)
+                        }
+                        _ => {
+                            // Try extracting via the normal path (JPXDecode etc.)
+                            let base = format!("img_{:04}_{:04}", smask_id.0, smask_id.1);
+                            let path = extract_stream(smask_stream, &doc, temp_dir, &base)?;
+                            if path == PathBuf::new() { None } else {
+                                let gray = decode_to_luma(&path).ok();
+                                let _ = fs::remove_file(&path);
+                                gray
+                            }
+                        }
+                    }
+                } else { None }
+            } else { None };
+
+            match (pictures.last_mut(), alpha) {
+                (_, None) => {
+                    // Opaque image — a picture by itself
+                    pictures.push(layer_rgb);
+                }
+                (None, Some(alpha)) => {
+                    // Base layer with mask: composite over white. Soft masks
+                    // may have their own resolution
+                    let alpha = if alpha.width() != w || alpha.height() != h {
+                        image::imageops::resize(&alpha, w, h, image::imageops::FilterType::Triangle)
+                    } else { alpha };
+                    let mut base = image::RgbImage::from_pixel(w, h, image::Rgb([255u8, 255, 255]));
+                    composite_over(&mut base, &layer_rgb, &alpha);
+                    pictures.push(base);
+                }
+                (Some(base), Some(alpha)) => {
+                    // Overlay with mask: composite over existing base
+                    let (bw, bh) = (base.width(), base.height());
+                    let layer_rgb = if layer_rgb.width() != bw || layer_rgb.height() != bh {
+                        image::imageops::resize(&layer_rgb, bw, bh, image::imageops::FilterType::Lanczos3)
+                    } else { layer_rgb };
+                    let alpha = if alpha.width() != bw || alpha.height() != bh {
+                        image::imageops::resize(&alpha, bw, bh, image::imageops::FilterType::Nearest)
+                    } else { alpha };
+                    composite_over(base, &layer_rgb, &alpha);
+                }
+            }
+        }
+
+        // A rendered page beats one missing a layer
+        if pictures.is_empty() || (skipped_layer && pdf_render.is_some()) {
+            unextracted.push(output_num);
+            continue;
+        }
+        // Several pictures on a page are numbered after the page
+        let numbered = pictures.len() > 1;
+        for (index, img) in pictures.into_iter().enumerate() {
+            let name = if numbered {
+                format!("page_{:04}_{:02}.png", output_num, index + 1)
+            } else {
+                format!("page_{:04}.png", output_num)
+            };
+            img.save(temp_dir.join(&name)).map_err(|e| anyhow::anyhow!("save page {} failed: {:?}", output_num, e))?;
+        }
+    }
+
+    if let Some(settings) = pdf_render {
+        pdf_render::render_pages(pdf_path, &unextracted, settings, temp_dir)?;
+    }
+    Ok(())
+}
+
+fn extract_image_from_stream_to(
+    stream: &lopdf::Stream,
+    _doc: &lopdf::Document,
+    temp_dir: &Path,
+    _ref_id: &(u32, u16),
+    base_name: &str,
+) -> Result<(PathBuf, usize)> {
+    // Get image properties
+    let width = stream.dict.get(b"Width")
+        .ok()
+        .and_then(|obj| obj.as_i64().ok())
+        .unwrap_or(0);
+
+    let height = stream.dict.get(b"Height")
+        .ok()
+        .and_then(|obj| obj.as_i64().ok())
+        .unwrap_or(0);
+
+    // Check the filter to determine image format
+    if stream.dict.has(b"Filter") {
+        match pdf_image::single_filter(stream).unwrap_or_default() {
+            b"DCTDecode" => {
+                let output_path = temp_dir.join(format!("{}.jpg", base_name));
+                fs::write(&output_path, &stream.content)
+                    .map_err(|e| anyhow::anyhow!("Failed to save JPEG image: {:?}", e))?;
+                Ok((output_path, 0))
+            }
+            b"FlateDecode" => {
+                use flate2::read::ZlibDecoder;
+                use std::io::Read;
+
+                let mut decoder = ZlibDecoder::new(stream.content.as_slice());
+                let mut decompressed_data = Vec::new();
+                decoder.read_to_end(&mut decompressed_data)
+                    .map_err(|e| anyhow::anyhow!("Failed to decompress image data: {:?}", e))?;
+                let output_path = extract_decoded_samples(stream, _doc, &decompressed_data, temp_dir, base_name)?;
+                Ok((output_path, 0))
+            }
+            filter @ (b"CCITTFaxDecode" | b"JBIG2Decode") => {
+                let img = if filter == b"JBIG2Decode" {
+                    pdf_image::decode_jbig2(stream, _doc)?
+                } else {
+                    pdf_image::decode_ccitt(stream, _doc, width as u32, height as u32)?
+                };
+                let output_path = temp_dir.join(format!("{}.png", base_name));
+                img.save(&output_path)
+                    .map_err(|e| anyhow::anyhow!("Failed to save PNG image: {:?}", e))?;
+                Ok((output_path, 0))
+            }
+            b"JPXDecode" => {
+                let output_path = temp_dir.join(format!("{}.jp2", base_name));
+                fs::write(&output_path, &stream.content)
+                    .map_err(|e| anyhow::anyhow!("Failed to save JPEG 2000 image: {:?}", e))?;
+                // Extract ICC profile if present
+                extract_icc_profile_to(stream, _doc, temp_dir, base_name)?;
+                Ok((output_path, 0))
+            }
+            _ => {
+                Ok((PathBuf::new(), 0))
+            }
+        }
+    } else {
+        // No filter - raw image data
+        let output_path = extract_decoded_samples(stream, _doc, &stream.content, temp_dir, base_name)?;
+        Ok((output_path, 0))
+    }
+}
+
+
+/// Save the samples of a Flate-compressed (`data` decompressed) or
+/// uncompressed image as a PNG; an empty path for colour spaces it cannot show
+fn extract_decoded_samples(
+    stream: &lopdf::Stream,
+    doc: &lopdf::Document,
+    data: &[u8],
+    temp_dir: &Path,
+    base_name: &str,
+) -> Result<PathBuf> {
+    let Some(img) = pdf_image::decode_samples(stream, doc, data)? else {
+        return Ok(PathBuf::new());
+    };
+    let output_path = temp_dir.join(format!("{}.png", base_name));
+    img.save(&output_path)
+        .map_err(|e| anyhow::anyhow!("Failed to save PNG image: {:?}", e))?;
+    Ok(output_path)
+}
+
+fn extract_icc_profile_to(
+    stream: &lopdf::Stream,
+    doc: &lopdf::Document,
+    temp_dir: &Path,
+    base_name: &str,
+) -> Result<()> {
+    use lopdf::Object;
+
+    if let Ok(Object::Array(arr)) = stream.dict.get(b"ColorSpace") {
+        for i in 0..arr.len() {
+            let is_iccbased = match &arr[i] {
+                Object::Name(name) => name.as_slice() == b"ICCBased",
+                Object::Reference(ref_id) => {
+                    if let Ok(Object::Name(name)) = doc.get_object(*ref_id) {
+                        name.as_slice() == b"ICCBased"
+                    } else {
+                        false
+                    }
+                }
+                _ => false,
+            };
+
+            if is_iccbased && i + 1 < arr.len() {
+                let profile_ref = match &arr[i + 1] {
+                    Object::Reference(ref_id) => *ref_id,
+                    _ => continue,
+                };
+
+                if let Ok(Object::Stream(profile_stream)) = doc.get_object(profile_ref) {
+                    let icc_path = temp_dir.join(format!("{}.icc", base_name));
+                    let _ = fs::write(&icc_path, &profile_stream.content);
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    // Also check for simple reference
+    if let Ok(Object::Reference(colorspace_ref)) = stream.dict.get(b"ColorSpace") {
+        if let Ok(Object::Name(name)) = doc.get_object(*colorspace_ref) {
+            if name.as_slice() == b"ICCBased" {
+                if let Ok(Object::Stream(profile_stream)) = doc.get_object(*colorspace_ref) {
+                    let icc_path = temp_dir.join(format!("{}.icc", base_name));
+                    let _ = fs::write(&icc_path, &profile_stream.content);
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+
+fn find_image_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut image_files = Vec::new();
+
+    for entry in WalkDir::new(dir).into_iter().filter_map(|e| e.ok()) {
+        if entry.file_type().is_file() {
+            let path = entry.path();
+            if let Some(extension) = path.extension().and_then(|ext| ext.to_str()) {
+                match extension.to_lowercase().as_str() {
+                    "jpg" | "jpeg" | "png" | "bmp" | "tiff" | "tif" | "jp2" | "gif" | "webp" => {
+                        image_files.push(path.to_path_buf());
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    image_files.sort();
+    Ok(image_files)
+}
+
+/// Extensions of page images as they appear in the output archive
+const PAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "bmp", "tiff", "tif", "jp2", "webp", "jxl", "gif"];
+
+fn find_page_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut page_files = Vec::new();
+
+    for entry in WalkDir::new(dir).into_iter().filter_map(|e| e.ok()) {
+        if entry.file_type().is_file() {
+            let path = entry.path();
+            if let Some(extension) = path.extension().and_then(|ext| ext.to_str()) {
+                if PAGE_EXTENSIONS.contains(&extension.to_lowercase().as_str()) {
+                    page_files.push(path.to_path_buf());
+                }
+            }
+        }
+    }
+
+    page_files.sort_by(|a, b| natural_cmp(&a.to_string_lossy(), &b.to_string_lossy()));
+    Ok(page_files)
+}
+
+/// Refresh page-related fields of an existing ComicInfo.xml to match the re-encoded pages
+fn update_comicinfo(temp_dir: &Path, manga: bool, verbose: bool) -> Result<()> {
+    let Some(comicinfo_path) = comicinfo::find_in_dir(temp_dir) else {
+        return Ok(());
+    };
+
+    let mut info = match ComicInfo::load(&comicinfo_path) {
+        Ok(info) => info,
+        Err(e) => {
+            if verbose {
+                eprintln!("Warning: Keeping unparseable {} as-is: {}", comicinfo_path.display(), e);
+            }
+            return Ok(());
+        }
+    };
+
+    let pages: Vec<PageInfo> = find_page_files(temp_dir)?
+        .iter()
+        .map(|path| {
+            let dimensions = ImageReader::open(path)
+                .and_then(|reader| reader.with_guessed_format())
+                .ok()
+                .and_then(|reader| reader.into_dimensions().ok());
+            PageInfo {
+                size: fs::metadata(path).map(|m| m.len()).unwrap_or(0),
+                width: dimensions.map(|(w, _)| w),
+                height: dimensions.map(|(_, h)| h),
+            }
+        })
+        .collect();
+
+    info.set_pages(&pages);
+    if manga {
+        info.set_field("Manga", "YesAndRightToLeft");
+    }
+    info.save(&comicinfo_path)
+        .with_context(|| format!("Failed to write {}", comicinfo_path.display()))
+}
+
+/// Re-encode extracted pages in parallel; page events name pages relative to `temp_dir`
+fn process_images(
+    temp_dir: &Path,
+    image_files: &[PathBuf],
+    args: &Options,
+    progress: &FileProgress,
+) -> Result<PageCounts> {
+    let (sender, receiver): (Sender<(PageEvent, Option<PageError>)>, Receiver<_>) = bounded(100);
+    let counts = Arc::new(Mutex::new(PageCounts::default()));
+    let total_images = image_files.len();
+
+    let progress_clone = progress.clone();
+    let counts_clone = Arc::clone(&counts);
+
+    let counter = thread::spawn(move || {
+        for (event, error) in receiver {
+            let current = {
+                let mut counts = counts_clone.lock().unwrap();
+                counts.record(event.outcome);
+                counts.errors.extend(error);
+                counts.total()
+            };
+            progress_clone.page(event);
+
+            let progress_percent = 30 + ((current * 50) / total_images);
+            // Only update progress every 10% to reduce output noise, plus important milestones
+            if progress_percent.is_multiple_of(10) || current == total_images || progress_percent >= 80 {
+                progress_clone.set_position(progress_percent as u64);
+            }
+        }
+    });
+
+    image_files.par_iter().for_each(|image_path| {
+        // Pages not started yet are left; the file is abandoned once the running ones finish
+        if progress.stopped() {
+            return;
+        }
+        let page = page_name(image_path, temp_dir);
+        let original_size = fs::metadata(image_path).map(|m| m.len()).unwrap_or(0);
+        let mut placeholder = None;
+        let (outcome, error) = match process_single_image(image_path, args) {
+            Ok(outcome) => (outcome, None),
+            Err(e) => {
+                if args.verbose {
+                    eprintln!("Warning: Failed to process image {}: {}. Skipping...",
+                              image_path.display(), e);
+                }
+                let error = PageError::new(&page, &e);
+                if args.tolerate_corrupt == CorruptPages::Placeholder && corrupt::is_corrupt(&error) {
+                    match replace_with_placeholder(image_path, args) {
+                        Ok(path) => placeholder = Some(path),
+                        Err(e) => eprintln!("Warning: Failed to write placeholder for {}: {}", image_path.display(), e),
+                    }
+                }
+                (PageOutcome::Failed, Some(error))
+            }
+        };
+        let output_paths = match placeholder {
+            Some(path) => vec![path],
+            None => processed_page_paths(image_path),
+        };
+        let output_size = output_paths
+            .iter()
+            .filter_map(|path| fs::metadata(path).ok())
+            .map(|m| m.len())
+            .sum();
+        sender.send((PageEvent { page, outcome, original_size, output_size }, error)).unwrap();
+    });
+
+    drop(sender);
+    let _ = counter.join();
+
+    let counts = std::mem::take(&mut *counts.lock().unwrap());
+    Ok(counts)
+}
+
+/// Swap a damaged page on disk for a placeholder PNG of the same name, or
+/// `<stem> (2).png` and so on when another page already has that name.
+/// Returns the placeholder's path
+fn replace_with_placeholder(image_path: &Path, args: &Options) -> Result<PathBuf> {
+    let name = image_path.file_name().unwrap_or_default().to_string_lossy();
+    let placeholder = corrupt::placeholder_png(&name, image::image_dimensions(image_path).ok(), args.target_height)?;
+    if entry_extension(&name).as_deref() == Some("png") {
+        fs::write(image_path, placeholder)?;
+        return Ok(image_path.to_path_buf());
+    }
+    let dir = image_path.parent().unwrap_or(Path::new(""));
+    let placeholder_path = recycle::free_path(dir, Path::new(name.as_ref()).with_extension("png").as_os_str());
+    fs::write(&placeholder_path, placeholder)?;
+    fs::remove_file(image_path)?;
+    Ok(placeholder_path)
+}
+
+/// Extensions the streaming path re-encodes; other entries are copied verbatim
+const STREAMABLE_IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "bmp", "tiff", "tif"];
+
+/// Whether a CBZ can be re-encoded entry by entry without extracting it.
+/// Features that need pages on disk (JPEG 2000 colour management, lossless
+/// JPEG XL transcoding) fall back to the temp-dir pipeline.
+fn can_stream_zip(comic_file: &ComicFile, output_format: OutputFormat, args: &Options) -> bool {
+    if !matches!(comic_file.file_type, ComicType::Cbz)
+        || !matches!(output_format, OutputFormat::Cbz | OutputFormat::Zip)
+        || args.dry_run
+        || args.jxl_lossless_jpeg
+        || args.slice_height.is_some()
+        || args.target_size_mb.is_some()
+        || args.dedupe_pages
+    {
+        return false;
+    }
+    let Ok(file) = File::open(&comic_file.path) else {
+        return false;
+    };
+    let Ok(archive) = zip::ZipArchive::new(BufReader::new(file)) else {
+        return false;
+    };
+    let needs_extraction = archive.file_names().any(|name| {
+        let extension = entry_extension(name).unwrap_or_default();
+        extension == "jp2"
+            || NESTED_ARCHIVE_EXTENSIONS.contains(&extension.as_str())
+            // GIFs may be animated, which the in-memory encoder cannot tell apart
+            || extension == "gif"
+            || HEIF_EXTENSIONS.contains(&extension.as_str())
+            // Static WebP pages are copied when streaming, but --eink and retouching filters render every page,
+            // and --flatten-alpha renders transparent ones
+            || (extension == "webp"
+                && (args.animated != AnimatedMode::Keep
+                    || args.eink
+                    || args.flatten_alpha.is_some()
+                    || FilterChain::of(args).retouches()))
+    });
+    !needs_extraction
+}
+
+fn entry_extension(name: &str) -> Option<String> {
+    Path::new(name).extension().and_then(|ext| ext.to_str()).map(|ext| ext.to_lowercase())
+}
+
+/// Page data buffered while streaming a CBZ when --max-memory is not given
+const DEFAULT_STREAM_BUFFER_MIB: u64 = 256;
+
+/// Re-encode a CBZ straight into the output archive: entries are read, decoded,
+/// re-encoded and written in order, holding roughly `--max-memory` MiB of
+/// source page data at a time. Returns the page outcomes like `process_images`.
+fn stream_zip_archive(
+    input_path: &Path,
+    output_path: &Path,
+    comment: &str,
+    args: &Options,
+    progress: &FileProgress,
+) -> Result<PageCounts> {
+    let mut archive = zip::ZipArchive::new(BufReader::new(throttle::open(input_path)?))?;
+    // Entries are re-emitted in reading order, which is also the sequential naming order
+    let mut order: Vec<(usize, String)> = entry_names::decoded_names(&mut archive)?
+        .into_iter()
+        // Named like the extracting path names them
+        .map(|name| if cfg!(windows) { paths::ntfs_entry_name(&name) } else { name })
+        .enumerate()
+        .collect();
+    order.sort_by(|a, b| natural_cmp(&a.1, &b.1));
+
+    let mut sequential_names = HashMap::new();
+    if args.page_naming == PageNaming::Sequential {
+        let pages: Vec<&String> = order
+            .iter()
+            .map(|(_, name)| name)
+            .filter(|name| PAGE_EXTENSIONS.contains(&entry_extension(name).unwrap_or_default().as_str()))
+            .filter(|name| !(args.strip_extras && is_junk_entry(name)))
+            .collect();
+        for (index, name) in pages.iter().enumerate() {
+            let renamed = Path::new(name.as_str()).with_file_name(sequential_page_name(index, pages.len()));
+            sequential_names.insert(name.to_string(), renamed.to_string_lossy().replace('\\', "/"));
+        }
+    }
+
+    let mut writer = StreamingZipWriter {
+        zip: ZipWriter::new(throttle::create(output_path)?),
+        pages: Vec::new(),
+        sequential_names,
+        compression: args.zip_compression,
+        dates: times::EntryDates::of(args),
+        entry_times: HashMap::new(),
+        counts: PageCounts::default(),
+    };
+    writer.zip.set_comment(comment)?;
+
+    let budget = args.max_memory.unwrap_or(DEFAULT_STREAM_BUFFER_MIB).saturating_mul(1024 * 1024);
+    let total_entries = archive.len().max(1);
+    let mut comicinfo_xml: Option<(String, Vec<u8>)> = None;
+    let mut batch: Vec<(String, Vec<u8>)> = Vec::new();
+    let mut batch_bytes = 0;
+
+    for (position, (index, name)) in order.iter().enumerate() {
+        progress.check()?;
+        let mut entry = archive.by_index(*index)?;
+        if entry.is_dir() {
+            continue;
+        }
+        let name = name.clone();
+        // Unsafe names would otherwise be carried over into the output archive
+        sanitized_entry_path(Path::new(""), &name)?;
+        if args.strip_extras && is_junk_entry(&name) {
+            continue;
+        }
+        let extension = entry_extension(&name).unwrap_or_default();
+        let is_comicinfo = name.eq_ignore_ascii_case(comicinfo::COMICINFO_FILE_NAME);
+        let modified = writer.dates.date(|| entry.last_modified());
+        if !is_comicinfo && !PAGE_EXTENSIONS.contains(&extension.as_str()) {
+            // Other members are copied through without buffering, however large
+            writer.copy_entry(&name, entry.size(), modified, &mut entry)?;
+            continue;
+        }
+        let mut data = Vec::with_capacity(entry.size() as usize);
+        std::io::Read::read_to_end(&mut entry, &mut data)?;
+        drop(entry);
+
+        if let Some(modified) = modified {
+            writer.entry_times.insert(name.clone(), modified);
+        }
+        // ComicInfo.xml is rewritten once the final page list is known
+        if is_comicinfo {
+            comicinfo_xml = Some((name, data));
+            continue;
+        }
+
+        if !STREAMABLE_IMAGE_EXTENSIONS.contains(&extension.as_str()) {
+            let output_name = writer.page_name(&name, None);
+            writer.write_entry(&output_name, &data, modified, true)?;
+            continue;
+        }
+
+        batch_bytes += data.len() as u64;
+        batch.push((name, data));
+        if batch_bytes >= budget {
+            writer.flush_batch(std::mem::take(&mut batch), args, progress)?;
+            batch_bytes = 0;
+        }
+        progress.set_position(30 + ((position * 50) / total_entries) as u64);
+    }
+    writer.flush_batch(batch, args, progress)?;
+
+    if (args.manga || args.fetch_metadata.is_some()) && comicinfo_xml.is_none() {
+        let info = ComicInfo::new();
+        comicinfo_xml = Some((comicinfo::COMICINFO_FILE_NAME.to_string(), info.to_xml().as_bytes().to_vec()));
+    }
+    if let Some((name, data)) = comicinfo_xml {
+        let data = match ComicInfo::parse(&String::from_utf8_lossy(&data)) {
+            Ok(mut info) => {
+                writer.pages.sort_by(|a, b| natural_cmp(&a.0, &b.0));
+                let pages: Vec<PageInfo> = writer.pages.iter().map(|(_, page)| page.clone()).collect();
+                info.set_pages(&pages);
+                if args.manga {
+                    info.set_field("Manga", "YesAndRightToLeft");
+                }
+                metadata::fill(&mut info, input_path, args);
+                info.to_xml().as_bytes().to_vec()
+            }
+            Err(e) => {
+                if args.verbose {
+                    eprintln!("Warning: Keeping unparseable {} as-is: {}", name, e);
+                }
+                data
+            }
+        };
+        // A ComicInfo.xml added for --manga or --fetch-metadata has no source entry
+        let modified = writer.entry_times.get(&name).copied().or_else(|| writer.dates.date(|| None));
+        writer.write_entry(&name, &data, modified, false)?;
+    }
+
+    writer.zip.finish()?;
+    Ok(writer.counts)
+}
+
+/// Output side of `stream_zip_archive`, tracking page facts for ComicInfo.xml
+struct StreamingZipWriter {
+    zip: ZipWriter<Throttled<File>>,
+    pages: Vec<(String, PageInfo)>,
+    /// Original entry name -> sequential name without extension (--page-naming sequential)
+    sequential_names: HashMap<String, String>,
+    compression: ZipCompression,
+    dates: times::EntryDates,
+    /// Original entry name -> its date, carried over to the entries written from it
+    entry_times: HashMap<String, zip::DateTime>,
+    counts: PageCounts,
+}
+
+impl StreamingZipWriter {
+    /// Output name of a page, with `extension` replacing the original one when re-encoded
+    fn page_name(&self, name: &str, extension: Option<&str>) -> String {
+        let original_extension = entry_extension(name).unwrap_or_default();
+        let extension = extension.unwrap_or(&original_extension);
+        match self.sequential_names.get(name) {
+            Some(base) => format!("{}.{}", base, extension),
+            None => Path::new(name).with_extension(extension).to_string_lossy().replace('\\', "/"),
+        }
+    }
+
+    fn write_entry(&mut self, name: &str, data: &[u8], modified: Option<zip::DateTime>, is_page: bool) -> Result<()> {
+        self.zip.start_file(name, zip_entry_options(name, data.len() as u64, self.compression, modified))?;
+        self.zip.write_all(data)?;
+
+        if is_page {
+            let dimensions = ImageReader::new(std::io::Cursor::new(data))
+                .with_guessed_format()
+                .ok()
+                .and_then(|reader| reader.into_dimensions().ok());
+            self.pages.push((
+                name.to_string(),
+                PageInfo {
+                    size: data.len() as u64,
+                    width: dimensions.map(|(w, _)| w),
+                    height: dimensions.map(|(_, h)| h),
+                },
+            ));
+        }
+        Ok(())
+    }
+
+    /// Copy a non-page member of `size` bytes into the output unchanged
+    fn copy_entry(
+        &mut self,
+        name: &str,
+        size: u64,
+        modified: Option<zip::DateTime>,
+        reader: &mut impl std::io::Read,
+    ) -> Result<()> {
+        self.zip.start_file(name, zip_entry_options(name, size, self.compression, modified))?;
+        std::io::copy(reader, &mut self.zip)?;
+        Ok(())
+    }
+
+    /// Encode a batch of pages in parallel and write them in archive order
+    fn flush_batch(&mut self, batch: Vec<(String, Vec<u8>)>, args: &Options, progress: &FileProgress) -> Result<()> {
+        let results: Vec<Result<PageEncoding>> = batch
+            .par_iter()
+            .map(|(name, data)| {
+                if args.skip_compression {
+                    return Ok(PageEncoding::Keep);
+                }
+                let reader = ImageReader::new(std::io::Cursor::new(data)).with_guessed_format()?;
+                let _reservation = memory::reserve_page(args.max_memory, || {
+                    ImageReader::new(std::io::Cursor::new(data)).with_guessed_format().ok()?.into_dimensions().ok()
+                });
+                let (img, reoriented) = decode_oriented(reader, &entry_extension(name).unwrap_or_default(), !args.assume_srgb)?;
+                let source_size = if reoriented { u64::MAX } else { data.len() as u64 };
+                encode_decoded_page(&img, name, source_size, resizable_source_format(name), args)
+            })
+            .collect();
+
+        for ((name, data), result) in batch.into_iter().zip(results) {
+            let modified = self.entry_times.get(&name).copied();
+            let (outcome, output_size) = match result {
+                Ok(PageEncoding::Replace { bytes, extension }) => {
+                    let new_name = self.page_name(&name, Some(extension));
+                    self.write_entry(&new_name, &bytes, modified, true)?;
+                    (PageOutcome::Reencoded, bytes.len())
+                }
+                Ok(PageEncoding::Resized { bytes }) => {
+                    let output_name = self.page_name(&name, None);
+                    self.write_entry(&output_name, &bytes, modified, true)?;
+                    (PageOutcome::ResizedOnly, bytes.len())
+                }
+                Ok(PageEncoding::Sliced { parts }) => {
+                    for (index, (bytes, extension)) in parts.iter().enumerate() {
+                        let slice_name = slice_page_path(Path::new(&name), index, extension);
+                        self.write_entry(&slice_name.to_string_lossy().replace('\\', "/"), bytes, modified, true)?;
+                    }
+                    (PageOutcome::Reencoded, parts.iter().map(|(bytes, _)| bytes.len()).sum())
+                }
+                Ok(PageEncoding::Keep) => {
+                    let output_name = self.page_name(&name, None);
+                    self.write_entry(&output_name, &data, modified, true)?;
+                    (PageOutcome::Kept, data.len())
+                }
+                Err(e) => {
+                    if args.verbose {
+                        eprintln!("Warning: Failed to process image {}: {}. Skipping...", name, e);
+                    }
+                    let error = PageError::new(&name, &e);
+                    let placeholder = (args.tolerate_corrupt == CorruptPages::Placeholder && corrupt::is_corrupt(&error))
+                        .then(|| {
+                            let dimensions = ImageReader::new(std::io::Cursor::new(&data))
+                                .with_guessed_format()
+                                .ok()
+                                .and_then(|reader| reader.into_dimensions().ok());
+                            let page = name.rsplit('/').next().unwrap_or(&name);
+                            corrupt::placeholder_png(page, dimensions, args.target_height)
+                        })
+                        .transpose()?;
+                    self.counts.errors.push(error);
+                    let output_size = match placeholder {
+                        Some(bytes) => {
+                            self.write_entry(&self.page_name(&name, Some("png")), &bytes, modified, true)?;
+                            bytes.len()
+                        }
+                        None => {
+                            self.write_entry(&self.page_name(&name, None), &data, modified, true)?;
+                            data.len()
+                        }
+                    };
+                    (PageOutcome::Failed, output_size)
+                }
+            };
+            self.counts.record(outcome);
+            progress.page(PageEvent {
+                original_size: data.len() as u64,
+                output_size: output_size as u64,
+                page: name,
+                outcome,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Frames of an animated GIF or WebP; `None` for a single-frame image
+fn decode_animation(data: &[u8], extension: &str) -> Result<Option<Vec<image::Frame>>> {
+    use image::AnimationDecoder;
+
+    let cursor = std::io::Cursor::new(data);
+    let frames = if extension == "gif" {
+        image::codecs::gif::GifDecoder::new(cursor)?.into_frames().collect_frames()?
+    } else {
+        let decoder = image::codecs::webp::WebPDecoder::new(cursor)?;
+        if !decoder.has_animation() {
+            return Ok(None);
+        }
+        decoder.into_frames().collect_frames()?
+    };
+    Ok((frames.len() > 1).then_some(frames))
+}
+
+/// Resize every frame like a page and encode them as an animated WebP at
+/// `--quality`, keeping each frame's display time
+fn encode_animated_webp(frames: Vec<image::Frame>, args: &Options) -> Result<Vec<u8>> {
+    let (width, height) = frames[0].buffer().dimensions();
+    let (new_width, new_height) = page_target_size(width, height, args);
+
+    let mut timestamp = 0;
+    let mut canvases = Vec::with_capacity(frames.len());
+    for frame in frames {
+        let (numerator, denominator) = frame.delay().numer_denom_ms();
+        let buffer = frame.into_buffer();
+        let buffer = if (new_width, new_height) == (width, height) {
+            buffer
+        } else {
+            resize_lanczos3(&image::DynamicImage::ImageRgba8(buffer), new_width, new_height).into_rgba8()
+        };
+        canvases.push((buffer, timestamp));
+        timestamp += (numerator / denominator.max(1)) as i32;
+    }
+
+    let config = webp_config(args, Some(args.quality))?;
+    let mut encoder = webp::AnimEncoder::new(new_width, new_height, &config);
+    for (buffer, timestamp) in &canvases {
+        encoder.add_frame(webp::AnimFrame::from_rgba(buffer.as_raw(), new_width, new_height, *timestamp));
+    }
+    let encoded = encoder
+        .try_encode()
+        .map_err(|e| anyhow::anyhow!("Failed to encode animated WebP: {:?}", e))?;
+    Ok(encoded.to_vec())
+}
+
+/// Highest quality, up to `--quality`, at which the sampled pages predict an
+/// archive of at most `budget` bytes (binary search; falls back to quality 1).
+/// Re-encoded pages are stored as-is, so the prediction is the scaled page
+/// bytes plus the other extracted files.
+fn tune_quality(temp_dir: &Path, image_files: &[PathBuf], budget: u64, args: &Options) -> u8 {
+    let (image_bytes, extracted_bytes) = extracted_sizes(temp_dir, image_files);
+    let other_bytes = extracted_bytes.saturating_sub(image_bytes) as f64;
+    let fits = |quality: u8| {
+        let trial = Options { quality, ..args.clone() };
+        let ratio = sampled_encoding_ratio(image_files, &trial).unwrap_or(1.0);
+        image_bytes as f64 * ratio + other_bytes <= budget as f64
+    };
+    if fits(args.quality) {
+        return args.quality;
+    }
+
+    // Invariant: `high` does not fit; `low` fits or is the lowest quality
+    let (mut low, mut high) = (1, args.quality);
+    while high - low > 1 {
+        let middle = low + (high - low) / 2;
+        if fits(middle) {
+            low = middle;
+        } else {
+            high = middle;
+        }
+    }
+    low
+}
+
+/// Predict the output archive size by re-encoding an evenly spaced sample of
+/// pages in memory. Returns (estimated size, pages sampled).
+fn estimate_compressed_size(temp_dir: &Path, image_files: &[PathBuf], original_size: u64, args: &Options) -> (u64, usize) {
+    if image_files.is_empty() || args.skip_compression {
+        return (original_size, 0);
+    }
+    let sample_count = args.sample_pages.clamp(1, image_files.len());
+    let Some(ratio) = sampled_encoding_ratio(image_files, args) else {
+        return (original_size, sample_count);
+    };
+
+    // Scale the sampled ratio over all image bytes; everything else is carried
+    // over. The saved share of the extracted content is applied to the archive
+    // size, as the source archive may itself be compressed.
+    let (image_bytes, extracted_bytes) = extracted_sizes(temp_dir, image_files);
+    if extracted_bytes == 0 {
+        return (original_size, sample_count);
+    }
+    let saved_fraction = (image_bytes as f64 * (1.0 - ratio) / extracted_bytes as f64).clamp(0.0, 1.0);
+    ((original_size as f64 * (1.0 - saved_fraction)) as u64, sample_count)
+}
+
+/// Indices of `count` evenly spaced items out of `len`, each centred in its stretch
+fn sample_indices(len: usize, count: usize) -> Vec<usize> {
+    if len == 0 {
+        return Vec::new();
+    }
+    let count = count.clamp(1, len);
+    let step = len as f64 / count as f64;
+    (0..count).map(|i| ((i as f64 + 0.5) * step) as usize).collect()
+}
+
+/// Encoded size over source size for an evenly spaced sample of
+/// `--sample-pages` pages, re-encoded in memory; `None` when the sample is empty
+fn sampled_encoding_ratio(image_files: &[PathBuf], args: &Options) -> Option<f64> {
+    let sample: Vec<&PathBuf> = sample_indices(image_files.len(), args.sample_pages)
+        .into_iter()
+        .map(|i| &image_files[i])
+        .collect();
+
+    let (sampled_original, sampled_encoded) = sample
+        .par_iter()
+        .map(|path| {
+            let original = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+            let encoded = match encode_page(path, args) {
+                Ok(PageEncoding::Replace { bytes, .. } | PageEncoding::Resized { bytes }) => bytes.len() as u64,
+                Ok(PageEncoding::Sliced { parts }) => parts.iter().map(|(bytes, _)| bytes.len() as u64).sum(),
+                _ => original,
+            };
+            (original, encoded)
+        })
+        .reduce(|| (0, 0), |a, b| (a.0 + b.0, a.1 + b.1));
+
+    (sampled_original > 0).then(|| sampled_encoded as f64 / sampled_original as f64)
+}
+
+/// (bytes of `image_files`, bytes of everything extracted into `temp_dir`)
+fn extracted_sizes(temp_dir: &Path, image_files: &[PathBuf]) -> (u64, u64) {
+    let image_bytes = image_files
+        .iter()
+        .map(|path| fs::metadata(path).map(|m| m.len()).unwrap_or(0))
+        .sum();
+    let extracted_bytes = WalkDir::new(temp_dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.metadata().map(|m| m.len()).unwrap_or(0))
+        .sum();
+    (image_bytes, extracted_bytes)
+}
+
+/// Outcome of re-encoding one page in memory
+enum PageEncoding {
+    /// Replace the source file with these bytes under a new extension
+    Replace { bytes: Vec<u8>, extension: &'static str },
+    /// Resized in the page's original format; overwrite the source file
+    Resized { bytes: Vec<u8> },
+    /// Cut into consecutive slices (`--slice-height`) that replace the source file
+    Sliced { parts: Vec<(Vec<u8>, &'static str)> },
+    /// Keep the source file as-is
+    Keep,
+}
+
+fn process_single_image(image_path: &Path, args: &Options) -> Result<PageOutcome> {
+    // Skip compression: keep image as-is
+    if args.skip_compression {
+        return Ok(PageOutcome::Kept);
+    }
+
+    let modified = fs::metadata(image_path).and_then(|metadata| metadata.modified()).ok();
+    let encoding = encode_page(image_path, args);
+    // The ICC profile extracted next to a JPEG 2000 page has been applied by now; it is
+    // only removed here because sampling (--dry-run, --target-size-mb) encodes pages too
+    if entry_extension(&image_path.to_string_lossy()).as_deref() == Some("jp2") {
+        let _ = fs::remove_file(image_path.with_extension("icc"));
+    }
+
+    match encoding? {
+        PageEncoding::Replace { bytes, extension } => {
+            let new_path = image_path.with_extension(extension);
+            fs::write(&new_path, bytes)?;
+            times::set_modified(&new_path, modified);
+            if new_path != image_path {
+                fs::remove_file(image_path)?;
+            }
+            Ok(PageOutcome::Reencoded)
+        }
+        PageEncoding::Resized { bytes } => {
+            fs::write(image_path, bytes)?;
+            times::set_modified(image_path, modified);
+            Ok(PageOutcome::ResizedOnly)
+        }
+        PageEncoding::Sliced { parts } => {
+            for (index, (bytes, extension)) in parts.iter().enumerate() {
+                let slice_path = slice_page_path(image_path, index, extension);
+                fs::write(&slice_path, bytes)?;
+                times::set_modified(&slice_path, modified);
+            }
+            fs::remove_file(image_path)?;
+            Ok(PageOutcome::Reencoded)
+        }
+        PageEncoding::Keep => Ok(PageOutcome::Kept),
+    }
+}
+
+/// Re-encode a page without touching the source file. Errors when the page
+/// cannot be decoded.
+fn encode_page(image_path: &Path, args: &Options) -> Result<PageEncoding> {
+    let _reservation = memory::reserve_page(args.max_memory, || {
+        ImageReader::open(image_path).ok()?.with_guessed_format().ok()?.into_dimensions().ok()
+    });
+
+    // Handle JPEG 2000 files with ICC profile color management
+    if image_path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase() == "jp2")
+        .unwrap_or(false)
+    {
+        let jp2_img = jpeg2k::Image::from_file(image_path)
+            .map_err(|e| anyhow::anyhow!("Failed to open JPEG 2000 image: {:?}", e))?;
+        let pixels = jp2_img.get_pixels(None)
+            .map_err(|e| anyhow::anyhow!("Failed to get JPEG 2000 pixels: {:?}", e))?;
+
+        let (rgb_data, width, height) = match pixels.data {
+            jpeg2k::ImagePixelData::Rgb8(data) => (data, pixels.width, pixels.height),
+            jpeg2k::ImagePixelData::Rgba8(data) => {
+                // Convert RGBA to RGB (strip alpha)
+                let mut rgb = Vec::with_capacity(pixels.width as usize * pixels.height as usize * 3);
+                for i in (0..data.len()).step_by(4) {
+                    if i + 2 < data.len() {
+                        rgb.push(data[i]);
+                        rgb.push(data[i + 1]);
+                        rgb.push(data[i + 2]);
+                    }
+                }
+                (rgb, pixels.width, pixels.height)
+            }
+            jpeg2k::ImagePixelData::L8(data) => {
+                // Grayscale: keep as-is (no color profile needed)
+                let img = image::DynamicImage::ImageLuma8(
+                    image::GrayImage::from_raw(pixels.width, pixels.height, data.clone())
+                        .ok_or_else(|| anyhow::anyhow!("Failed to create grayscale image from JPEG 2000 data"))?
+                );
+                let (filtered, retouched) = FilterChain::of(args).apply(&img, args, false)?;
+                let (encoded_bytes, extension) =
+                    encode_image(filtered.as_ref().unwrap_or(&img), &image_path.to_string_lossy(), args)?;
+                if args.eink || retouched || encoded_bytes.len() < fs::metadata(image_path)?.len() as usize {
+                    return Ok(PageEncoding::Replace { bytes: encoded_bytes, extension });
+                }
+                return Ok(PageEncoding::Keep);
+            }
+            _ => {
+                return Ok(PageEncoding::Keep); // Unsupported format, keep as-is
+            }
+        };
+
+        // Try to apply ICC profile for color management
+        let icc_path = image_path.with_extension("icc");
+        let rgb_data = if icc_path.exists() {
+            let icc_data = fs::read(&icc_path)
+                .map_err(|e| anyhow::anyhow!("Failed to read ICC profile: {:?}", e))?;
+            let source_profile = moxcms::ColorProfile::new_from_slice(&icc_data)
+                .map_err(|e| anyhow::anyhow!("Failed to load ICC profile: {:?}", e))?;
+            let dest_profile = moxcms::ColorProfile::new_srgb();
+            let transform = source_profile
+                .create_transform_8bit(
+                    moxcms::Layout::Rgb,
+                    &dest_profile,
+                    moxcms::Layout::Rgb,
+                    moxcms::TransformOptions::default(),
+                )
+                .map_err(|e| anyhow::anyhow!("Failed to create color transform: {:?}", e))?;
+
+            let mut transformed = vec![0u8; rgb_data.len()];
+            let img_width = width as usize;
+            for chunk in rgb_data
+                .chunks_exact(img_width * 3)
+                .zip(transformed.chunks_exact_mut(img_width * 3))
+            {
+                transform
+                    .transform(chunk.0, chunk.1)
+                    .map_err(|e| anyhow::anyhow!("Color transform failed: {:?}", e))?;
+            }
+            transformed
+        } else {
+            // No ICC profile: assume sRGB (standard assumption for WebP)
+            rgb_data
+        };
+
+        let img = image::DynamicImage::ImageRgb8(
+            image::RgbImage::from_raw(width, height, rgb_data)
+                .ok_or_else(|| anyhow::anyhow!("Failed to create RGB image from transformed JPEG 2000 data"))?,
+        );
+
+        let (filtered, _) = FilterChain::of(args).apply(&img, args, false)?;
+        let (encoded_bytes, extension) =
+            encode_image(filtered.as_ref().unwrap_or(&img), &image_path.to_string_lossy(), args)?;
+
+        // Always re-encode JP2 files (ICC color management takes priority over size)
+        return Ok(PageEncoding::Replace { bytes: encoded_bytes, extension });
+    }
+
+    let extension = entry_extension(&image_path.to_string_lossy()).unwrap_or_default();
+    if extension == "gif" || extension == "webp" {
+        let data = fs::read(image_path)?;
+        match (decode_animation(&data, &extension)?, args.animated) {
+            // Static WebP pages are already in a modern format and are left alone,
+            // unless they only display upright through their EXIF orientation (or --eink, retouching filters
+            // or --flatten-alpha on a transparent page render them)
+            (None, _) if extension == "webp" => {
+                let reader = ImageReader::with_format(std::io::Cursor::new(&data), image::ImageFormat::WebP);
+                return match decode_oriented(reader, &extension, !args.assume_srgb)? {
+                    (img, reoriented) if reoriented
+                        || args.eink
+                        || FilterChain::of(args).retouches()
+                        || (args.flatten_alpha.is_some() && alpha::is_transparent(&img)) =>
+                    {
+                        encode_decoded_page(&img, &image_path.to_string_lossy(), u64::MAX, None, args)
+                    }
+                    (_, _) => Ok(PageEncoding::Keep),
+                };
+            }
+            (None, _) => {}
+            (Some(_), AnimatedMode::Keep) => return Ok(PageEncoding::Keep),
+            (Some(frames), AnimatedMode::FirstFrame) => {
+                let first = image::DynamicImage::ImageRgba8(frames.into_iter().next().unwrap().into_buffer());
+                return encode_decoded_page(&first, &image_path.to_string_lossy(), data.len() as u64, None, args);
+            }
+            (Some(frames), AnimatedMode::Reencode) => {
+                let bytes = encode_animated_webp(frames, args)?;
+                if bytes.len() < data.len() {
+                    return Ok(PageEncoding::Replace { bytes, extension: "webp" });
+                }
+                return Ok(PageEncoding::Keep);
+            }
+        }
+    }
+
+    if args.jxl_lossless_jpeg && is_jpeg_path(image_path) {
+        let jxl_bytes = transcode_jpeg_to_jxl(image_path)?;
+        if jxl_bytes.len() < fs::metadata(image_path)?.len() as usize {
+            return Ok(PageEncoding::Replace { bytes: jxl_bytes, extension: "jxl" });
+        }
+        return Ok(PageEncoding::Keep);
+    }
+
+    let (img, reoriented) = decode_oriented(ImageReader::open(image_path)?.with_guessed_format()?, &extension, !args.assume_srgb)?;
+    let source_format = resizable_source_format(&image_path.to_string_lossy());
+    let source_size = if reoriented { u64::MAX } else { fs::metadata(image_path)?.len() };
+    encode_decoded_page(&img, &image_path.to_string_lossy(), source_size, source_format, args)
+}
+
+/// Decode a page, rotating JPEG and WebP pages upright per their EXIF
+/// orientation and, with `to_srgb`, converting pages with an embedded ICC
+/// profile to sRGB. CMYK JPEGs come out as sRGB, and 16-bit pages (PNG, TIFF)
+/// as 8-bit. Returns whether the source must not be kept: its pixels were
+/// rotated, and it still carries the tag while re-encoded output has no EXIF,
+/// or it is a CMYK JPEG, which many readers cannot show (a source with an RGB
+/// profile shows correctly and may be kept)
+fn decode_oriented<R: std::io::BufRead + std::io::Seek>(
+    reader: ImageReader<R>,
+    extension: &str,
+    to_srgb: bool,
+) -> Result<(image::DynamicImage, bool)> {
+    use image::ImageDecoder;
+
+    let reader = if reader.format() == Some(image::ImageFormat::Jpeg) {
+        let mut inner = reader.into_inner();
+        let start = inner.stream_position()?;
+        let mut data = Vec::new();
+        inner.read_to_end(&mut data)?;
+        if let Some((mut img, orientation)) = cmyk::decode(&data, to_srgb)? {
+            img.apply_orientation(orientation);
+            return Ok((img, true));
+        }
+        inner.seek(std::io::SeekFrom::Start(start))?;
+        ImageReader::with_format(inner, image::ImageFormat::Jpeg)
+    } else {
+        reader
+    };
+    let mut decoder = reader.into_decoder()?;
+    let icc = if to_srgb { decoder.icc_profile().ok().flatten() } else { None };
+    let orientation = if matches!(extension, "jpg" | "jpeg" | "webp") {
+        decoder.orientation().unwrap_or(image::metadata::Orientation::NoTransforms)
+    } else {
+        image::metadata::Orientation::NoTransforms
+    };
+    let mut img = image::DynamicImage::from_decoder(decoder)?;
+    img.apply_orientation(orientation);
+    // Every encoder and the fast resize work on 8 bits, as readers show them
+    img = match img {
+        image::DynamicImage::ImageLuma16(_) => image::DynamicImage::ImageLuma8(img.to_luma8()),
+        image::DynamicImage::ImageLumaA16(_) => image::DynamicImage::ImageLumaA8(img.to_luma_alpha8()),
+        image::DynamicImage::ImageRgb16(_) | image::DynamicImage::ImageRgb32F(_) => image::DynamicImage::ImageRgb8(img.to_rgb8()),
+        image::DynamicImage::ImageRgba16(_) | image::DynamicImage::ImageRgba32F(_) => image::DynamicImage::ImageRgba8(img.to_rgba8()),
+        img => img,
+    };
+    if let Some(icc) = icc {
+        img = icc::to_srgb(img, &icc);
+    }
+    Ok((img, orientation != image::metadata::Orientation::NoTransforms))
+}
+
+/// Formats a page can be resized in without switching format
+fn resizable_source_format(name: &str) -> Option<image::ImageFormat> {
+    match entry_extension(name).as_deref() {
+        Some("jpg" | "jpeg") => Some(image::ImageFormat::Jpeg),
+        Some("png") => Some(image::ImageFormat::Png),
+        _ => None,
+    }
+}
+
+/// Pick the smallest of: resize and re-encode to the target format, resize
+/// only (keeping `source_format`), or the untouched source of `source_size` bytes
+fn encode_decoded_page(
+    img: &image::DynamicImage,
+    page: &str,
+    source_size: u64,
+    source_format: Option<image::ImageFormat>,
+    args: &Options,
+) -> Result<PageEncoding> {
+    // Painted before anything else, so no candidate keeps the transparency
+    let flattened = args.flatten_alpha.filter(|_| alpha::is_transparent(img)).map(|background| alpha::flatten(img, background));
+    let source_size = if flattened.is_some() { u64::MAX } else { source_size };
+    let img = flattened.as_ref().unwrap_or(img);
+
+    let grayscale = to_grayscale(img, args.grayscale);
+    let img = grayscale.as_ref().unwrap_or(img);
+    let height = img.height();
+
+    let (filtered, retouched) = FilterChain::of(args).apply(img, args, true)?;
+    let resized = filtered.as_ref().unwrap_or(img);
+    // The source lacks the retouching, so it is never kept
+    let source_size = if retouched { u64::MAX } else { source_size };
+
+    // Slices are for readers that cannot cope with very tall images, so they win regardless of size
+    if let Some(slice_height) = args.slice_height.filter(|&h| resized.height() > h) {
+        let parts = (0..resized.height())
+            .step_by(slice_height as usize)
+            .map(|y| {
+                let slice = resized.crop_imm(0, y, resized.width(), slice_height.min(resized.height() - y));
+                encode_image(&slice, page, args)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        return Ok(PageEncoding::Sliced { parts });
+    }
+
+    // As with slices, the rendering is the point of --eink
+    if args.eink {
+        let (bytes, extension) = encode_image(resized, page, args)?;
+        return Ok(PageEncoding::Replace { bytes, extension });
+    }
+
+    let mut best = PageEncoding::Keep;
+    let mut best_size = source_size;
+
+    let (encoded_bytes, extension) = encode_image(resized, page, args)?;
+    if (encoded_bytes.len() as u64) < best_size {
+        best_size = encoded_bytes.len() as u64;
+        best = PageEncoding::Replace { bytes: encoded_bytes, extension };
+    }
+
+    // Resizing alone only helps when the page actually got smaller
+    if let Some(format) = source_format.filter(|_| resized.height() < height) {
+        let mut resized_bytes = encode_in_format(resized, format, args.quality)?;
+        if args.embed_icc {
+            resized_bytes = icc::embed_srgb(resized_bytes, format.extensions_str()[0])?;
+        }
+        if (resized_bytes.len() as u64) < best_size {
+            best = PageEncoding::Resized { bytes: resized_bytes };
+        }
+    }
+
+    Ok(best)
+}
+
+/// Size a page is resized to. `--fit` combines the target height, optional
+/// `--target-width` and `--max-long-edge` into one scale factor (`--webtoon`
+/// uses the target width alone), which
+/// `--resize-policy` then limits: with downscale-only, pages are not enlarged
+/// unless `--upscale` is given, and then by at most `--max-upscale-factor`
+fn page_target_size(width: u32, height: u32, args: &Options) -> (u32, u32) {
+    let height_scale = args.target_height as f64 / height as f64;
+    let width_scale = args.target_width.map(|target| target as f64 / width as f64);
+    let mut scale = match args.fit {
+        // Long strips are only ever constrained by their width
+        _ if args.webtoon => width_scale.unwrap_or(1.0),
+        FitMode::ExactHeight => height_scale,
+        FitMode::FitWithin => width_scale.map_or(height_scale, |w| w.min(height_scale)),
+        FitMode::Fill => width_scale.map_or(height_scale, |w| w.max(height_scale)),
+    };
+    if let Some(long_edge) = args.max_long_edge.filter(|_| !args.webtoon) {
+        scale = scale.min(long_edge as f64 / width.max(height) as f64);
+    }
+
+    let scale = match args.resize_policy {
+        ResizePolicy::Never => 1.0,
+        ResizePolicy::Always => scale,
+        ResizePolicy::DownscaleOnly if scale <= 1.0 => scale,
+        ResizePolicy::DownscaleOnly if args.upscale => scale.min(args.max_upscale_factor as f64),
+        ResizePolicy::DownscaleOnly => 1.0,
+    };
+    (
+        ((width as f64 * scale).round() as u32).max(1),
+        ((height as f64 * scale).round() as u32).max(1),
+    )
+}
+
+fn encode_in_format(img: &image::DynamicImage, format: image::ImageFormat, quality: u8) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    match format {
+        image::ImageFormat::Jpeg => {
+            let encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut bytes, quality);
+            if img.color().has_color() {
+                img.to_rgb8().write_with_encoder(encoder)?;
+            } else {
+                img.to_luma8().write_with_encoder(encoder)?;
+            }
+        }
+        _ => {
+            let encoder = image::codecs::png::PngEncoder::new_with_quality(
+                &mut bytes,
+                image::codecs::png::CompressionType::Best,
+                image::codecs::png::FilterType::Adaptive,
+            );
+            img.write_with_encoder(encoder)?;
+        }
+    }
+    Ok(bytes)
+}
+
+fn is_jpeg_path(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.eq_ignore_ascii_case("jpg") || e.eq_ignore_ascii_case("jpeg"))
+        .unwrap_or(false)
+}
+
+/// Encode a page in the target format, returning the bytes and their
+/// extension; tagged with the sRGB profile with `--embed-icc`
+fn encode_image(img: &image::DynamicImage, page: &str, args: &Options) -> Result<(Vec<u8>, &'static str)> {
+    let (bytes, extension) = encode_pixels(img, page, args)?;
+    if args.embed_icc {
+        return Ok((icc::embed_srgb(bytes, extension)?, extension));
+    }
+    Ok((bytes, extension))
+}
+
+/// Line art (and every page with --lossless) is stored losslessly, since lossy
+/// encoding leaves ringing artifacts around ink lines. `--eink` pages are
+/// always a grayscale PNG rendered for the panel.
+fn encode_pixels(img: &image::DynamicImage, page: &str, args: &Options) -> Result<(Vec<u8>, &'static str)> {
+    if args.eink {
+        return Ok((eink::encode(img, args.dither)?, "png"));
+    }
+    let grayscale = to_grayscale(img, args.grayscale);
+    let img = grayscale.as_ref().unwrap_or(img);
+    let line_art = is_line_art(img);
+    if args.lossless || line_art {
+        return encode_lossless(img, line_art && args.grayscale != GrayscaleMode::Off, args);
+    }
+    let bytes = match (args.format, args.target_ssim) {
+        (ImageFormat::Webp, Some(target)) => {
+            let (bytes, quality, ssim) = encode_webp_for_ssim(img, target, args)?;
+            if args.verbose {
+                eprintln!("{}: quality {} (SSIM {:.4})", page, quality, ssim);
+            }
+            bytes
+        }
+        (ImageFormat::Webp, None) => encode_webp(img, args.quality, args)?,
+        (ImageFormat::Jxl, _) => encode_jxl(img, args.quality)?,
+    };
+    Ok((bytes, args.format.extension()))
+}
+
+/// Lowest WebP quality up to `--quality` whose decoded result reaches
+/// `target` SSIM against `img` (binary search); `--quality` when none does.
+/// Returns (bytes, quality, SSIM).
+fn encode_webp_for_ssim(img: &image::DynamicImage, target: f64, args: &Options) -> Result<(Vec<u8>, u8, f64)> {
+    let max_quality = args.quality;
+    let reference = img.to_luma8();
+    let attempt = |quality: u8| -> Result<(Vec<u8>, f64)> {
+        let bytes = encode_webp(img, quality, args)?;
+        let decoded = image::load_from_memory_with_format(&bytes, image::ImageFormat::WebP)?;
+        let ssim = metrics::ssim(&reference, &decoded.to_luma8());
+        Ok((bytes, ssim))
+    };
+
+    let (mut best_bytes, mut best_ssim) = attempt(max_quality)?;
+    let mut best_quality = max_quality;
+    if best_ssim < target {
+        return Ok((best_bytes, best_quality, best_ssim));
+    }
+
+    // Invariant: `high` meets the target and the lowest such quality lies in `low..=high`
+    let (mut low, mut high) = (1, max_quality);
+    while low < high {
+        let middle = low + (high - low) / 2;
+        let (bytes, ssim) = attempt(middle)?;
+        if ssim >= target {
+            (best_bytes, best_ssim, best_quality) = (bytes, ssim, middle);
+            high = middle;
+        } else {
+            low = middle + 1;
+        }
+    }
+    Ok((best_bytes, best_quality, best_ssim))
+}
+
+/// Smaller of the target format's lossless mode (near-lossless WebP with
+/// `--webp-near-lossless`) and an optimized PNG; `grayscale` pages are stored
+/// single-channel in the PNG
+fn encode_lossless(img: &image::DynamicImage, grayscale: bool, args: &Options) -> Result<(Vec<u8>, &'static str)> {
+    let format = args.format;
+    let lossless = match format {
+        ImageFormat::Webp => {
+            let config = webp_config(args, None)?;
+            encode_webp_with(img, &config)?
+        }
+        ImageFormat::Jxl => encode_jxl_lossless(img)?,
+    };
+
+    let png_source = match (grayscale || !img.color().has_color(), alpha::is_transparent(img)) {
+        (true, false) => image::DynamicImage::ImageLuma8(img.to_luma8()),
+        (true, true) => image::DynamicImage::ImageLumaA8(img.to_luma_alpha8()),
+        (false, false) => image::DynamicImage::ImageRgb8(img.to_rgb8()),
+        (false, true) => image::DynamicImage::ImageRgba8(img.to_rgba8()),
+    };
+    let png = encode_in_format(&png_source, image::ImageFormat::Png, 0)?;
+
+    if png.len() < lossless.len() {
+        Ok((png, "png"))
+    } else {
+        Ok((lossless, format.extension()))
+    }
+}
+
+/// Black-and-white line art: nearly colourless, with most pixels close to
+/// pure black or white
+fn is_line_art(img: &image::DynamicImage) -> bool {
+    let tone = sample_tone(img);
+    tone.total > 0 && tone.coloured * 100 <= tone.total && tone.extreme * 100 >= tone.total * 90
+}
+
+/// Visually grayscale: at most 1% of sampled pixels carry noticeable chroma,
+/// which tolerates the colour noise of RGB scans of black-and-white pages
+fn is_grayscale(img: &image::DynamicImage) -> bool {
+    if !img.color().has_color() {
+        return true;
+    }
+    let tone = sample_tone(img);
+    tone.total > 0 && tone.coloured * 100 <= tone.total
+}
+
+/// Single-channel copy of a colour page that should be encoded as grayscale,
+/// keeping the alpha channel of a transparent page
+fn to_grayscale(img: &image::DynamicImage, mode: GrayscaleMode) -> Option<image::DynamicImage> {
+    let convert = match mode {
+        GrayscaleMode::Off => false,
+        GrayscaleMode::Force => true,
+        GrayscaleMode::Auto => is_grayscale(img),
+    };
+    (convert && img.color().has_color()).then(|| {
+        if alpha::is_transparent(img) {
+            image::DynamicImage::ImageLumaA8(img.to_luma_alpha8())
+        } else {
+            image::DynamicImage::ImageLuma8(img.to_luma8())
+        }
+    })
+}
+
+/// Counts over an evenly spaced sample of pixels
+struct ToneSample {
+    total: usize,
+    /// Pixels whose channels differ noticeably
+    coloured: usize,
+    /// Pixels close to pure black or white
+    extreme: usize,
+}
+
+fn sample_tone(img: &image::DynamicImage) -> ToneSample {
+    const SAMPLES_PER_AXIS: u32 = 100;
+    let (width, height) = (img.width(), img.height());
+
+    let (mut total, mut coloured, mut extreme) = (0usize, 0usize, 0usize);
+    for sy in 0..SAMPLES_PER_AXIS.min(height) {
+        for sx in 0..SAMPLES_PER_AXIS.min(width) {
+            let x = sx * width / SAMPLES_PER_AXIS.min(width);
+            let y = sy * height / SAMPLES_PER_AXIS.min(height);
+            let [r, g, b, _] = img.get_pixel(x, y).0;
+            let (max, min) = (r.max(g).max(b), r.min(g).min(b));
+            if max - min > 24 {
+                coloured += 1;
+            }
+            let luma = (r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000;
+            if !(64..=192).contains(&luma) {
+                extreme += 1;
+            }
+            total += 1;
+        }
+    }
+
+    ToneSample { total, coloured, extreme }
+}
+
+fn encode_webp(img: &image::DynamicImage, quality: u8, args: &Options) -> Result<Vec<u8>> {
+    let config = webp_config(args, Some(quality))?;
+    encode_webp_with(img, &config)
+}
+
+/// Encode a page as WebP with `config`, keeping the alpha channel of a
+/// transparent page
+fn encode_webp_with(img: &image::DynamicImage, config: &webp::WebPConfig) -> Result<Vec<u8>> {
+    let encoded = if alpha::is_transparent(img) {
+        let rgba_img = img.to_rgba8();
+        webp::Encoder::from_rgba(&rgba_img, rgba_img.width(), rgba_img.height()).encode_advanced(config)
+    } else {
+        let rgb_img = img.to_rgb8();
+        webp::Encoder::from_rgb(&rgb_img, rgb_img.width(), rgb_img.height()).encode_advanced(config)
+    };
+    let encoded = encoded.map_err(|e| anyhow::anyhow!("Failed to encode WebP: {:?}", e))?;
+
+    Ok(encoded.to_vec())
+}
+
+/// libwebp settings with the `--webp-*` tuning: lossy at `quality`, or
+/// lossless (near-lossless with `--webp-near-lossless`) for `None`
+fn webp_config(args: &Options, quality: Option<u8>) -> Result<webp::WebPConfig> {
+    let mut config = webp::WebPConfig::new().map_err(|_| anyhow::anyhow!("Failed to create WebP configuration"))?;
+    config.method = args.webp_method.into();
+    config.use_sharp_yuv = args.webp_sharp_yuv.into();
+    config.thread_level = args.webp_threads.into();
+    match quality {
+        Some(quality) => config.quality = quality as f32,
+        None => {
+            // As `webp::Encoder::encode_lossless`
+            config.lossless = 1;
+            config.quality = 75.0;
+            config.alpha_compression = 0;
+            config.near_lossless = args.webp_near_lossless.map_or(100, i32::from);
+        }
+    }
+    Ok(config)
+}
+
+fn check_cjxl_available() -> Result<()> {
+    Command::new("cjxl")
+        .arg("--version")
+        .output()
+        .map(|_| ())
+        .map_err(|_| anyhow::anyhow!("JPEG XL output requires the `cjxl` tool (libjxl) to be installed and on PATH"))
+}
+
+fn run_cjxl(input: &Path, output: &Path, extra_args: &[String]) -> Result<Vec<u8>> {
+    let result = Command::new("cjxl")
+        .arg(paths::long_path(input).as_ref())
+        .arg(paths::long_path(output).as_ref())
+        .args(extra_args)
+        .arg("--quiet")
+        .output()
+        .context("Failed to run cjxl")?;
+
+    if !result.status.success() {
+        anyhow::bail!("cjxl failed: {}", String::from_utf8_lossy(&result.stderr).trim());
+    }
+
+    fs::read(output).context("Failed to read cjxl output")
+}
+
+fn encode_jxl(img: &image::DynamicImage, quality: u8) -> Result<Vec<u8>> {
+    // cjxl only reads from files, so stage a lossless PNG next to the output
+    let work_dir = temp::create_dir_in(None).context("Failed to create JPEG XL work directory")?;
+    let input = work_dir.path().join("page.png");
+    let output = work_dir.path().join("page.jxl");
+
+    stage_png(img, &input)?;
+
+    run_cjxl(&input, &output, &["-q".to_string(), quality.to_string()])
+}
+
+fn encode_jxl_lossless(img: &image::DynamicImage) -> Result<Vec<u8>> {
+    let work_dir = temp::create_dir_in(None).context("Failed to create JPEG XL work directory")?;
+    let input = work_dir.path().join("page.png");
+    let output = work_dir.path().join("page.jxl");
+
+    stage_png(img, &input)?;
+
+    run_cjxl(&input, &output, &["-d".to_string(), "0".to_string()])
+}
+
+/// Write a page as PNG for cjxl, single-channel when the page is grayscale
+/// and with its alpha channel when it is transparent
+fn stage_png(img: &image::DynamicImage, path: &Path) -> Result<()> {
+    let staged = match (img.color().has_color(), alpha::is_transparent(img)) {
+        (true, false) => image::DynamicImage::ImageRgb8(img.to_rgb8()),
+        (true, true) => image::DynamicImage::ImageRgba8(img.to_rgba8()),
+        (false, false) => image::DynamicImage::ImageLuma8(img.to_luma8()),
+        (false, true) => image::DynamicImage::ImageLumaA8(img.to_luma_alpha8()),
+    };
+    staged
+        .save(path)
+        .map_err(|e| anyhow::anyhow!("Failed to stage image for cjxl: {:?}", e))
+}
+
+fn transcode_jpeg_to_jxl(jpeg_path: &Path) -> Result<Vec<u8>> {
+    let work_dir = temp::create_dir_in(None).context("Failed to create JPEG XL work directory")?;
+    let output = work_dir.path().join("page.jxl");
+
+    // Bit-exact JPEG reconstruction data is kept, so `djxl` can restore the original file
+    run_cjxl(jpeg_path, &output, &["--lossless_jpeg=1".to_string()])
+}
+
+fn check_rar_available() -> Result<()> {
+    Command::new("rar")
+        .output()
+        .map(|_| ())
+        .map_err(|_| anyhow::anyhow!("CBR output requires the `rar` tool (WinRAR/RAR for Unix) to be installed and on PATH. Use --output-format cbz for ZIP-based output."))
+}
+
+fn create_archive(
+    temp_dir: &Path,
+    output_path: &Path,
+    format: OutputFormat,
+    comment: &str,
+    args: &Options,
+) -> Result<Vec<PageText>> {
+    match format {
+        OutputFormat::Cbz | OutputFormat::Zip => {
+            create_zip_archive(temp_dir, output_path, comment, args.zip_compression, times::EntryDates::of(args))?
+        }
+        OutputFormat::Cbr => create_rar_archive(temp_dir, output_path, comment)?,
+        OutputFormat::Epub => {
+            epub::write_archive(temp_dir, output_path, comment, args.zip_compression, times::EntryDates::of(args))?
+        }
+        OutputFormat::Pdf => {
+            let pages = find_page_files(temp_dir)?;
+            let ocr_languages = args.ocr.then_some(args.ocr_lang.as_str());
+            let texts = pdf::write_pdf(&pages, output_path, comment, args.quality, args.manga, ocr_languages)?;
+            if args.ocr_text {
+                return Ok(pages
+                    .iter()
+                    .zip(texts)
+                    .map(|(page, text)| PageText { page: page_name(page, temp_dir), text })
+                    .collect());
+            }
+        }
+    }
+    Ok(Vec::new())
+}
+
+fn create_rar_archive(temp_dir: &Path, output_path: &Path, comment: &str) -> Result<()> {
+    // rar runs inside the extraction directory, so it needs an absolute output path
+    let output_path = std::path::absolute(output_path)?;
+    if output_path.exists() {
+        fs::remove_file(&output_path)?;
+    }
+
+    // rar reads the archive comment from a file, which must live outside the archived directory
+    let mut comment_file = tempfile::NamedTempFile::new().context("Failed to create RAR comment file")?;
+    comment_file.write_all(comment.as_bytes())?;
+
+    let result = Command::new("rar")
+        .current_dir(temp_dir)
+        .args(["a", "-r", "-m5", "-idq", "-ep1"])
+        .arg(format!("-z{}", comment_file.path().display()))
+        .arg(paths::long_path(&output_path).as_ref())
+        .arg("*")
+        .output()
+        .context("Failed to run rar")?;
+
+    if !result.status.success() {
+        anyhow::bail!("rar failed: {}", String::from_utf8_lossy(&result.stderr).trim());
+    }
+    Ok(())
+}
+
+/// Entry formats whose data is already compressed, so Deflate only costs time
+const PRECOMPRESSED_EXTENSIONS: &[&str] = &[
+    "jpg", "jpeg", "png", "webp", "gif", "jxl", "jp2", "avif", "heic", "heif", "zip", "cbz", "rar", "cbr", "7z", "cb7", "gz",
+];
+
+/// Entries from this size on get ZIP64 headers: 4 GiB less a margin, as
+/// Deflate can grow incompressible data slightly past the original size
+const ZIP64_ENTRY_SIZE: u64 = u32::MAX as u64 - (64 << 20);
+
+/// Options for writing a `size`-byte entry called `name`. Entries near 4 GiB
+/// or larger are written with ZIP64 headers; the archive itself switches to
+/// ZIP64 when it is finished if it exceeds 4 GiB or 65535 entries. Without a
+/// `modified` date the entry is dated now
+pub(crate) fn zip_entry_options(
+    name: &str,
+    size: u64,
+    compression: ZipCompression,
+    modified: Option<zip::DateTime>,
+) -> FileOptions<'static, ()> {
+    let stored = match compression {
+        ZipCompression::Auto => PRECOMPRESSED_EXTENSIONS.contains(&entry_extension(name).unwrap_or_default().as_str()),
+        ZipCompression::Stored => true,
+        ZipCompression::Deflated => false,
+    };
+    let method = if stored { zip::CompressionMethod::Stored } else { zip::CompressionMethod::Deflated };
+    let options = FileOptions::default()
+        .compression_method(method)
+        .large_file(size >= ZIP64_ENTRY_SIZE);
+    match modified {
+        Some(modified) => options.last_modified_time(modified),
+        None => options,
+    }
+}
+
+/// Pack `temp_dir`; entries are dated by `dates`, from their files when preserved
+fn create_zip_archive(
+    temp_dir: &Path,
+    output_path: &Path,
+    comment: &str,
+    compression: ZipCompression,
+    dates: times::EntryDates,
+) -> Result<()> {
+    let file = throttle::create(output_path)?;
+    let mut zip = ZipWriter::new(file);
+    zip.set_comment(comment)?;
+
+    // Sorted so readers that follow archive order see chapters and pages in sequence
+    let walker = WalkDir::new(temp_dir)
+        .sort_by(|a, b| natural_cmp(&a.file_name().to_string_lossy(), &b.file_name().to_string_lossy()));
+    for entry in walker.into_iter().filter_map(|e| e.ok()) {
+        if entry.file_type().is_file() {
+            let path = entry.path();
+            let name = path.strip_prefix(temp_dir)?.to_string_lossy().replace('\\', "/");
+
+            let modified = dates.date(|| times::entry_time(path));
+            zip.start_file(name.as_str(), zip_entry_options(&name, entry.metadata()?.len(), compression, modified))?;
+            std::io::copy(&mut File::open(path)?, &mut zip)?;
+        }
+    }
+
+    zip.finish()?;
+    Ok(())
+}
+
+/// Values available to --name-template
+struct NameTemplateVars<'a> {
+    stem: &'a str,
+    extension: &'a str,
+    format: &'a str,
+    quality: u8,
+    savings_percent: f64,
+    organized: Option<&'a organize::Organized>,
+}
+
+const NAME_TEMPLATE_VARIABLES: &[&str] = &["stem", "ext", "format", "quality", "date", "savings", "series", "volume", "chapter"];
+
+/// Template variables filled in only with --organize
+const ORGANIZE_TEMPLATE_VARIABLES: &[&str] = &["series", "volume", "chapter"];
+
+fn name_template(args: &Options) -> &str {
+    match &args.name_template {
+        Some(template) => template,
+        None if args.rename_original || args.organize => "{stem}",
+        None => "{stem} optimized_{format}_q{quality}",
+    }
+}
+
+fn validate_name_template(template: &str) -> Result<()> {
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        let Some(close) = rest[open..].find('}') else {
+            anyhow::bail!("Unclosed '{{' in name template: {}", template);
+        };
+        let name = &rest[open + 1..open + close];
+        if !NAME_TEMPLATE_VARIABLES.contains(&name) {
+            anyhow::bail!(
+                "Unknown name template variable {{{}}}. Available: {}",
+                name,
+                NAME_TEMPLATE_VARIABLES.iter().map(|v| format!("{{{}}}", v)).collect::<Vec<_>>().join(", ")
+            );
+        }
+        rest = &rest[open + close + 1..];
+    }
+    if template.contains('/') || template.contains('\\') {
+        anyhow::bail!("Name template must not contain path separators; use --output-dir instead");
+    }
+    Ok(())
+}
+
+/// Render an output file name; the archive extension is appended unless the
+/// template already ends with it. Volume and chapter are empty when unknown
+fn render_name_template(template: &str, vars: &NameTemplateVars) -> String {
+    let organized = |part: fn(&organize::Organized) -> Option<&str>| vars.organized.and_then(part).unwrap_or_default();
+    let name = template
+        .replace("{stem}", vars.stem)
+        .replace("{series}", organized(|organized| Some(&organized.series)))
+        .replace("{volume}", organized(|organized| organized.volume.as_deref()))
+        .replace("{chapter}", organized(|organized| organized.chapter.as_deref()))
+        .replace("{ext}", vars.extension)
+        .replace("{format}", vars.format)
+        .replace("{quality}", &vars.quality.to_string())
+        .replace("{date}", &today_iso_date())
+        .replace("{savings}", &format!("{:.0}", vars.savings_percent));
+
+    let suffix = format!(".{}", vars.extension);
+    if name.to_lowercase().ends_with(&suffix) {
+        name
+    } else {
+        format!("{}{}", name, suffix)
+    }
+}
+
+/// Current UTC date as YYYY-MM-DD
+fn today_iso_date() -> String {
+    let seconds = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (year, month, day) = civil_from_days((seconds / 86_400) as i64);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Convert days since 1970-01-01 to a (year, month, day) civil date
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Directory an input's output goes to: next to the input, or its mirrored
+/// location under --output-dir
+fn output_dir_for(input_path: &Path, output_root: Option<&Path>, input_root: &Path) -> PathBuf {
+    let parent = input_path.parent().unwrap_or_else(|| Path::new("."));
+    match output_root {
+        Some(root) => {
+            let relative = parent.strip_prefix(input_root).unwrap_or(if parent.is_relative() {
+                parent
+            } else {
+                Path::new("")
+            });
+            root.join(relative)
+        }
+        None => parent.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UNSAFE_NAMES: &[&str] = &[
+        "../evil.jpg",
+        "c/../../evil.jpg",
+        "/evil.jpg",
+        "C:/evil.jpg",
+        "C:evil.jpg",
+        "..\\evil.jpg",
+        "\\evil.jpg",
+        "c\\..\\..\\evil.jpg",
+    ];
+
+    #[test]
+    fn sanitized_entry_path_rejects_escaping_names() {
+        let base = Path::new("/tmp/extract");
+        for name in UNSAFE_NAMES {
+            assert!(sanitized_entry_path(base, name).is_err(), "{} was accepted", name);
+        }
+    }
+
+    #[test]
+    fn sanitized_entry_path_keeps_names_inside_the_base() {
+        let base = Path::new("/tmp/extract");
+        assert_eq!(sanitized_entry_path(base, "c/001.jpg").unwrap(), base.join("c").join("001.jpg"));
+        assert_eq!(sanitized_entry_path(base, "./c//001.jpg").unwrap(), base.join("c").join("001.jpg"));
+        assert_eq!(sanitized_entry_path(base, "c\\001.jpg").unwrap(), base.join("c").join("001.jpg"));
+        // Dots inside a name are not a parent reference
+        assert_eq!(sanitized_entry_path(base, "c/..001.jpg").unwrap(), base.join("c").join("..001.jpg"));
+    }
+
+    fn write_zip(path: &Path, entries: &[(&str, &[u8])]) {
+        let mut zip = zip::ZipWriter::new(File::create(path).unwrap());
+        for (name, data) in entries {
+            zip.start_file(*name, zip::write::SimpleFileOptions::default()).unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    fn write_7z(path: &Path, entries: &[(&str, &[u8])]) {
+        let mut archive = sevenz_rust::SevenZWriter::create(path).unwrap();
+        for (name, data) in entries {
+            let mut entry = sevenz_rust::SevenZArchiveEntry::new();
+            entry.name = name.to_string();
+            archive.push_archive_entry(entry, Some(*data)).unwrap();
+        }
+        archive.finish().unwrap();
+    }
+
+    /// Header names are written raw, as `tar::Builder` refuses `..` itself
+    fn write_tar(path: &Path, entries: &[(&str, &[u8])]) {
+        let mut tar = tar::Builder::new(File::create(path).unwrap());
+        for (name, data) in entries {
+            let mut header = tar::Header::new_old();
+            header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_cksum();
+            tar.append(&header, *data).unwrap();
+        }
+        tar.finish().unwrap();
+    }
+
+    type Writer = fn(&Path, &[(&str, &[u8])]);
+    type Extractor = fn(&Path, &Path) -> Result<()>;
+    const FORMATS: &[(&str, Writer, Extractor)] = &[
+        ("cbz", write_zip, extract_zip_archive),
+        ("cb7", write_7z, extract_7z_archive),
+        ("cbt", write_tar, extract_tar_archive),
+    ];
+
+    #[test]
+    fn extraction_refuses_entries_escaping_the_target() {
+        for (extension, write, extract) in FORMATS {
+            for name in UNSAFE_NAMES {
+                let dir = tempfile::tempdir().unwrap();
+                let archive = dir.path().join(format!("comic.{}", extension));
+                write(&archive, &[(name, b"evil")]);
+                let target = dir.path().join("a").join("b");
+                fs::create_dir_all(&target).unwrap();
+
+                assert!(extract(&archive, &target).is_err(), "{} accepted {}", extension, name);
+                let written: Vec<_> = WalkDir::new(dir.path())
+                    .into_iter()
+                    .filter_map(|entry| entry.ok())
+                    .filter(|entry| entry.file_name().to_string_lossy().contains("evil"))
+                    .collect();
+                assert!(written.is_empty(), "{} wrote {:?} for {}", extension, written, name);
+            }
+        }
+    }
+
+    #[test]
+    fn extraction_writes_safe_entries_below_the_target() {
+        for (extension, write, extract) in FORMATS {
+            let dir = tempfile::tempdir().unwrap();
+            let archive = dir.path().join(format!("comic.{}", extension));
+            write(&archive, &[("c/001.jpg", b"one"), ("./c/002.jpg", b"two")]);
+            let target = dir.path().join("out");
+            fs::create_dir_all(&target).unwrap();
+
+            extract(&archive, &target).unwrap();
+            assert_eq!(fs::read(target.join("c/001.jpg")).unwrap(), b"one", "{}", extension);
+            assert_eq!(fs::read(target.join("c/002.jpg")).unwrap(), b"two", "{}", extension);
+        }
+    }
+
+    #[test]
+    fn pdf_soft_masks_composite_over_white() {
+        // A red image whose half-resolution soft mask is opaque over its top half
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/smask.pdf");
+        let dir = tempfile::tempdir().unwrap();
+        extract_pdf_archive(&fixture, dir.path(), None).unwrap();
+
+        let page = image::open(dir.path().join("page_0001.png")).unwrap().into_rgb8();
+        assert_eq!(page.dimensions(), (8, 8));
+        assert_eq!(page.get_pixel(4, 0).0, [255, 0, 0]);
+        assert_eq!(page.get_pixel(4, 7).0, [255, 255, 255]);
+    }
+
+    #[test]
+    fn archives_over_65535_entries_round_trip_through_zip64() {
+        const ENTRIES: usize = 65_600;
+        let dir = tempfile::tempdir().unwrap();
+        let pages = dir.path().join("pages");
+        for chapter in 0..ENTRIES / 1000 + 1 {
+            fs::create_dir_all(pages.join(format!("{:03}", chapter))).unwrap();
+        }
+        for index in 0..ENTRIES {
+            fs::write(pages.join(format!("{:03}/{:06}.jpg", index / 1000, index)), index.to_le_bytes()).unwrap();
+        }
+
+        let archive = dir.path().join("large.cbz");
+        create_zip_archive(&pages, &archive, "", ZipCompression::Stored, times::EntryDates::Fixed).unwrap();
+        // The ZIP64 end of central directory record
+        let bytes = fs::read(&archive).unwrap();
+        assert!(bytes.windows(4).any(|window| window == b"PK\x06\x06"));
+        assert_eq!(zip::ZipArchive::new(File::open(&archive).unwrap()).unwrap().len(), ENTRIES);
+
+        let extracted = dir.path().join("extracted");
+        fs::create_dir_all(&extracted).unwrap();
+        extract_zip_archive(&archive, &extracted).unwrap();
+        let files = WalkDir::new(&extracted).into_iter().filter_map(|e| e.ok()).filter(|e| e.file_type().is_file()).count();
+        assert_eq!(files, ENTRIES);
+        let last = ENTRIES - 1;
+        let data = fs::read(extracted.join(format!("{:03}/{:06}.jpg", last / 1000, last))).unwrap();
+        assert_eq!(data, last.to_le_bytes());
+    }
+
+    #[test]
+    fn cb7_and_cbt_fixtures_extract_their_pages_and_metadata() {
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        let formats: [(&str, Extractor); 2] = [("comic.cb7", extract_7z_archive), ("comic.cbt", extract_tar_archive)];
+        for (fixture, extract) in formats {
+            let dir = tempfile::tempdir().unwrap();
+            extract(&fixtures.join(fixture), dir.path()).unwrap();
+
+            let page = |name: &str| image::open(dir.path().join("Chapter 1").join(name)).unwrap().into_rgb8();
+            assert_eq!(page("001.png").get_pixel(1, 1).0, [255, 0, 0], "{}", fixture);
+            assert_eq!(page("002.png").get_pixel(1, 1).0, [0, 0, 255], "{}", fixture);
+            let info = fs::read_to_string(dir.path().join("ComicInfo.xml")).unwrap();
+            assert!(info.contains("<Title>Fixture</Title>"), "{}", fixture);
+        }
+    }
+}
@@ -0,0 +1,105 @@
+//! `--komga-url` / `--kavita-url`: ask a Komga or Kavita server to rescan
+//! what a run changed, so re-compressed files are picked up without a manual
+//! scan. Komga scans whole libraries, Kavita single folders. The scan is
+//! requested once per run (per batch of settled files in `--watch` mode);
+//! an unreachable server only prints a warning.
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use ureq::Agent;
+
+use crate::report::FileStatus;
+use crate::{Options, Report};
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Deserialize)]
+struct KomgaLibrary {
+    id: String,
+    name: String,
+    root: String,
+}
+
+/// Folders changed by a finished file: the output's and the source's. Files
+/// kept as they were change nothing
+pub(crate) fn changed_folders(input: &Path, report: &Report) -> Vec<PathBuf> {
+    if !matches!(FileStatus::of(report), FileStatus::Compressed | FileStatus::Converted) {
+        return Vec::new();
+    }
+    [Some(input), report.output_path.as_deref()]
+        .into_iter()
+        .flatten()
+        .filter_map(|path| std::path::absolute(path).ok()?.parent().map(Path::to_path_buf))
+        .collect()
+}
+
+/// Request the scans for `folders` from every configured server; returns a
+/// line per started scan
+pub(crate) fn rescan(args: &Options, folders: &BTreeSet<PathBuf>) -> Vec<String> {
+    let mut started = Vec::new();
+    if folders.is_empty() {
+        return started;
+    }
+    let agent: Agent = Agent::config_builder().timeout_global(Some(TIMEOUT)).build().into();
+
+    if let (Some(url), Some(token)) = (&args.komga_url, &args.komga_token) {
+        match scan_komga(&agent, url.trim_end_matches('/'), token, folders) {
+            Ok(libraries) => started.extend(libraries.iter().map(|name| format!("Komga: scanning library {}", name))),
+            Err(e) => eprintln!("Warning: Komga library scan failed: {:#}", e),
+        }
+    }
+    if let (Some(url), Some(token)) = (&args.kavita_url, &args.kavita_token) {
+        for folder in folders {
+            match scan_kavita(&agent, url.trim_end_matches('/'), token, folder) {
+                Ok(()) => started.push(format!("Kavita: scanning {}", folder.display())),
+                Err(e) => eprintln!("Warning: Kavita scan of {} failed: {:#}", folder.display(), e),
+            }
+        }
+    }
+    started
+}
+
+/// Scan the libraries whose root contains a changed folder; returns their names
+fn scan_komga(agent: &Agent, base: &str, token: &str, folders: &BTreeSet<PathBuf>) -> Result<Vec<String>> {
+    let url = format!("{}/api/v1/libraries", base);
+    let body = agent
+        .get(&url)
+        .header("X-API-Key", token)
+        .call()
+        .and_then(|mut response| response.body_mut().read_to_string())
+        .with_context(|| format!("GET {}", url))?;
+    let libraries: Vec<KomgaLibrary> = serde_json::from_str(&body).context("Unexpected library list")?;
+
+    let mut affected: Vec<&KomgaLibrary> = libraries
+        .iter()
+        .filter(|library| folders.iter().any(|folder| folder.starts_with(&library.root)))
+        .collect();
+    // The server may see the files under other paths (e.g. mounted in a container)
+    if affected.is_empty() {
+        affected = libraries.iter().collect();
+    }
+    for library in &affected {
+        let url = format!("{}/api/v1/libraries/{}/scan", base, library.id);
+        agent
+            .post(&url)
+            .header("X-API-Key", token)
+            .send_empty()
+            .with_context(|| format!("POST {}", url))?;
+    }
+    Ok(affected.iter().map(|library| library.name.clone()).collect())
+}
+
+/// Scan the series in `folder`; Kavita finds the library itself
+fn scan_kavita(agent: &Agent, base: &str, token: &str, folder: &Path) -> Result<()> {
+    let url = format!("{}/api/Library/scan-folder", base);
+    let body = serde_json::json!({ "apiKey": token, "folderPath": folder });
+    agent
+        .post(&url)
+        .header("Content-Type", "application/json")
+        .send(body.to_string())
+        .with_context(|| format!("POST {}", url))?;
+    Ok(())
+}
@@ -0,0 +1,88 @@
+//! `--max-memory`: a process-wide budget for decoded pages. Each page reserves
+//! an estimate of its working set before it is decoded and waits while the
+//! budget is used up; a page larger than the whole budget waits until nothing
+//! else is decoding and then runs alone.
+
+use anyhow::Result;
+use std::sync::{Condvar, Mutex, OnceLock};
+
+/// Decoded RGBA page plus resized copy and encoder buffers, relative to the
+/// decoded page alone
+const WORKING_SET_FACTOR: u64 = 3;
+
+pub(crate) struct MemoryBudget {
+    limit: u64,
+    in_use: Mutex<u64>,
+    released: Condvar,
+}
+
+/// Bytes held until dropped
+pub(crate) struct Reservation<'a> {
+    budget: &'a MemoryBudget,
+    bytes: u64,
+}
+
+static PAGE_MEMORY: OnceLock<MemoryBudget> = OnceLock::new();
+
+/// The budget shared by every file in the process; the first configured
+/// `--max-memory` sets its size
+fn page_memory(limit_mib: u64) -> &'static MemoryBudget {
+    PAGE_MEMORY.get_or_init(|| MemoryBudget::new(limit_mib.saturating_mul(1024 * 1024)))
+}
+
+/// With `--max-memory` set, reserve the estimated working set of a page of
+/// `dimensions` (read only then); pages without readable dimensions reserve nothing
+pub(crate) fn reserve_page(max_memory: Option<u64>, dimensions: impl FnOnce() -> Option<(u32, u32)>) -> Option<Reservation<'static>> {
+    let limit = max_memory?;
+    let working_set = dimensions().map_or(0, |(width, height)| width as u64 * height as u64 * 4 * WORKING_SET_FACTOR);
+    Some(page_memory(limit).reserve(working_set))
+}
+
+impl MemoryBudget {
+    fn new(limit: u64) -> Self {
+        MemoryBudget { limit, in_use: Mutex::new(0), released: Condvar::new() }
+    }
+
+    /// Reserve `bytes`, waiting until they fit. Requests above the limit are
+    /// capped to it, so they run once everything else has finished
+    pub(crate) fn reserve(&self, bytes: u64) -> Reservation<'_> {
+        let bytes = bytes.min(self.limit);
+        let mut in_use = self.in_use.lock().unwrap();
+        while *in_use > 0 && *in_use + bytes > self.limit {
+            in_use = self.released.wait(in_use).unwrap();
+        }
+        *in_use += bytes;
+        Reservation { budget: self, bytes }
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        *self.budget.in_use.lock().unwrap() -= self.bytes;
+        self.budget.released.notify_all();
+    }
+}
+
+/// Parse a memory size: a number of MiB, or with a `K`, `M`, `G` or `T`
+/// suffix (binary units, optional `B`/`iB`). Returns MiB
+pub(crate) fn parse_memory_size(value: &str) -> Result<u64> {
+    let value = value.trim();
+    let upper = value.to_ascii_uppercase();
+    let number = upper.trim_end_matches("IB").trim_end_matches('B');
+    let (digits, unit_kib) = match number.chars().last() {
+        Some('K') => (&number[..number.len() - 1], 1.0),
+        Some('M') => (&number[..number.len() - 1], 1024.0),
+        Some('G') => (&number[..number.len() - 1], 1024.0 * 1024.0),
+        Some('T') => (&number[..number.len() - 1], 1024.0 * 1024.0 * 1024.0),
+        _ => (number, 1024.0),
+    };
+    let amount: f64 = digits
+        .trim()
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Invalid memory size '{}': use e.g. 512M or 4G", value))?;
+    let mib = (amount * unit_kib / 1024.0).round();
+    if !mib.is_finite() || mib < 1.0 {
+        anyhow::bail!("Memory size '{}' must be at least 1M", value);
+    }
+    Ok(mib as u64)
+}
@@ -0,0 +1,402 @@
+//! `--fetch-metadata`: fill in missing ComicInfo.xml fields from ComicVine or
+//! AniList. The series and issue are read from the file name the way
+//! `--organize` reads them. On ComicVine, where manga volumes are issues, the
+//! volume number is the issue when there is one, else the chapter. AniList
+//! describes whole series, so it gives their summary, staff and genres but no
+//! date or publisher. Fields the comic already has are kept.
+//!
+//! Responses are cached for `CACHE_DAYS` and requests are spaced a second
+//! apart, within both services' rate limits. A failed lookup only warns.
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use ureq::Agent;
+
+use crate::comicinfo::{self, ComicInfo};
+use crate::{organize, MetadataSource, Options};
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Time between requests, across all files being processed
+const REQUEST_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Cached responses older than this are fetched again
+const CACHE_DAYS: u64 = 30;
+
+const COMICVINE_API: &str = "https://comicvine.gamespot.com/api";
+const ANILIST_API: &str = "https://graphql.anilist.co";
+
+/// ComicVine turns away requests without a descriptive user agent
+const USER_AGENT: &str = concat!("compress_comics/", env!("CARGO_PKG_VERSION"));
+
+/// ComicVine credit roles and the ComicInfo fields they go to
+const CREDIT_FIELDS: &[(&str, &str)] = &[
+    ("writer", "Writer"),
+    ("penciler", "Penciller"),
+    ("penciller", "Penciller"),
+    ("artist", "Penciller"),
+    ("inker", "Inker"),
+    ("colorist", "Colorist"),
+    ("letterer", "Letterer"),
+    ("cover", "CoverArtist"),
+    ("editor", "Editor"),
+];
+
+const ANILIST_QUERY: &str = "query ($search: String) { Media(search: $search, type: MANGA) { siteUrl title { romaji english } \
+    description(asHtml: false) genres staff(perPage: 25) { edges { role node { name { full } } } } } }";
+
+static LAST_REQUEST: Mutex<Option<Instant>> = Mutex::new(None);
+
+#[derive(Deserialize)]
+struct Volume {
+    id: u64,
+    name: String,
+    start_year: Option<String>,
+    publisher: Option<Named>,
+}
+
+#[derive(Deserialize)]
+struct Named {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct IssueRef {
+    id: u64,
+}
+
+#[derive(Deserialize)]
+struct Issue {
+    name: Option<String>,
+    issue_number: Option<String>,
+    cover_date: Option<String>,
+    description: Option<String>,
+    site_detail_url: Option<String>,
+    #[serde(default)]
+    person_credits: Vec<Credit>,
+}
+
+#[derive(Deserialize)]
+struct Credit {
+    name: String,
+    /// Comma-separated, e.g. "artist, cover"
+    role: String,
+}
+
+/// The ComicVine API key: `--api-key`, else `COMICVINE_API_KEY`
+pub(crate) fn api_key(args: &Options) -> Option<String> {
+    args.api_key.clone().or_else(|| std::env::var("COMICVINE_API_KEY").ok().filter(|key| !key.is_empty()))
+}
+
+/// Fill the ComicInfo.xml in `dir` from the service, for the comic at `source`
+pub(crate) fn fill_dir(dir: &Path, source: &Path, args: &Options) -> Result<()> {
+    let Some(path) = comicinfo::find_in_dir(dir) else {
+        return Ok(());
+    };
+    // Unparseable files are kept as they are, like update_comicinfo() keeps them
+    let Ok(mut info) = ComicInfo::load(&path) else {
+        return Ok(());
+    };
+    fill(&mut info, source, args);
+    info.save(&path).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Set the fields `info` lacks from the service, for the comic at `source`
+pub(crate) fn fill(info: &mut ComicInfo, source: &Path, args: &Options) {
+    let Some(service) = args.fetch_metadata else {
+        return;
+    };
+    let stem = source.file_stem().unwrap_or_default().to_string_lossy();
+    match lookup(service, &stem, args) {
+        Ok(Some(fields)) => {
+            let present = info.fields();
+            for (name, value) in fields {
+                let missing = !present.iter().any(|(field, existing)| field == name && !existing.trim().is_empty());
+                if missing && !value.trim().is_empty() {
+                    info.set_field(name, value.trim());
+                }
+            }
+        }
+        Ok(None) => {
+            if args.verbose {
+                eprintln!("{}: no metadata match for the file name", source.display());
+            }
+        }
+        Err(e) => eprintln!("⚠️  {}: metadata lookup failed: {:#}", source.display(), e),
+    }
+}
+
+fn lookup(service: MetadataSource, stem: &str, args: &Options) -> Result<Option<Vec<(&'static str, String)>>> {
+    let client = Client {
+        agent: Agent::config_builder().timeout_global(Some(TIMEOUT)).http_status_as_error(false).build().into(),
+        cache: args.metadata_cache.clone().or_else(default_cache_dir),
+    };
+    let organized = organize::parse(stem, &args.organize_pattern);
+    match service {
+        MetadataSource::Comicvine => {
+            let Some(organized) = organized else {
+                return Ok(None);
+            };
+            let Some(issue) = organized.volume.as_deref().or(organized.chapter.as_deref()) else {
+                return Ok(None);
+            };
+            let api_key = api_key(args).context("--fetch-metadata comicvine needs --api-key or COMICVINE_API_KEY")?;
+            // Issue numbers are stored unpadded: "12", "12.5"
+            let (whole, fraction) = issue.split_once('.').unwrap_or((issue, ""));
+            let whole = whole.parse::<u32>().map_or(whole.to_string(), |whole| whole.to_string());
+            let issue = if fraction.is_empty() { whole } else { format!("{}.{}", whole, fraction) };
+            comicvine(&client, &api_key, &organized.series, &issue, year_in(stem))
+        }
+        MetadataSource::Anilist => {
+            let series = organized.map_or(stem.to_string(), |organized| organized.series);
+            anilist(&client, series.trim())
+        }
+    }
+}
+
+/// Look up the volume by name, the issue in it, and its details
+fn comicvine(client: &Client, api_key: &str, series: &str, issue: &str, year: Option<u32>) -> Result<Option<Vec<(&'static str, String)>>> {
+    let get = |resource: &str, query: &[(&str, &str)]| -> Result<Value> {
+        let url = format!("{}/{}/", COMICVINE_API, resource);
+        let key = format!("{}?{}", url, query.iter().map(|(name, value)| format!("{}={}", name, value)).collect::<Vec<_>>().join("&"));
+        let body = client.cached(&key, |agent| {
+            let mut response = agent
+                .get(&url)
+                .header("User-Agent", USER_AGENT)
+                .query("api_key", api_key)
+                .query("format", "json")
+                .query_pairs(query.iter().copied())
+                .call()?;
+            let body = response.body_mut().read_to_string()?;
+            let parsed: Value = serde_json::from_str(&body).with_context(|| format!("HTTP {}", response.status()))?;
+            if parsed["status_code"].as_u64() != Some(1) {
+                anyhow::bail!("{}", parsed["error"].as_str().unwrap_or("unexpected response"));
+            }
+            Ok(body)
+        });
+        let mut response: Value = serde_json::from_str(&body.with_context(|| format!("ComicVine {}", resource))?)?;
+        Ok(response["results"].take())
+    };
+
+    let volumes: Vec<Volume> = serde_json::from_value(get(
+        "search",
+        &[("resources", "volume"), ("query", series), ("field_list", "id,name,start_year,publisher"), ("limit", "10")],
+    )?)
+    .context("Unexpected ComicVine search results")?;
+    // Of the runs with this name, the latest one started by the year in the file name
+    let named: Vec<&Volume> = volumes.iter().filter(|volume| volume.name.eq_ignore_ascii_case(series)).collect();
+    let started = |volume: &Volume| volume.start_year.as_deref().and_then(|start| start.parse::<u32>().ok());
+    let Some(volume) = year
+        .and_then(|year| {
+            named.iter().copied().filter(|volume| started(volume).is_some_and(|start| start <= year)).max_by_key(|volume| started(volume))
+        })
+        .or(named.first().copied())
+        .or(volumes.first())
+    else {
+        return Ok(None);
+    };
+
+    let filter = format!("volume:{},issue_number:{}", volume.id, issue);
+    let issues: Vec<IssueRef> = serde_json::from_value(get("issues", &[("filter", &filter), ("field_list", "id")])?)
+        .context("Unexpected ComicVine issue list")?;
+    let Some(found) = issues.first() else {
+        return Ok(None);
+    };
+    let issue: Issue = serde_json::from_value(get(
+        &format!("issue/4000-{}", found.id),
+        &[("field_list", "name,issue_number,cover_date,description,site_detail_url,person_credits")],
+    )?)
+    .context("Unexpected ComicVine issue")?;
+
+    let mut fields = vec![("Series", volume.name.clone())];
+    fields.extend(issue.name.map(|name| ("Title", name)));
+    fields.extend(issue.issue_number.map(|number| ("Number", number)));
+    fields.extend(volume.publisher.as_ref().map(|publisher| ("Publisher", publisher.name.clone())));
+    fields.extend(issue.description.map(|description| ("Summary", plain_text(&description))));
+    // Cover dates name a month; their day is nominal
+    if let Some(date) = issue.cover_date {
+        let mut parts = date.split('-');
+        fields.extend(parts.next().map(|year| ("Year", year.to_string())));
+        fields.extend(parts.next().and_then(|month| month.parse::<u32>().ok()).map(|month| ("Month", month.to_string())));
+    }
+    fields.extend(issue.site_detail_url.map(|url| ("Web", url)));
+
+    let mut credits: BTreeMap<&'static str, Vec<String>> = BTreeMap::new();
+    for credit in &issue.person_credits {
+        for role in credit.role.split(',').map(str::trim) {
+            if let Some((_, field)) = CREDIT_FIELDS.iter().find(|(name, _)| role.eq_ignore_ascii_case(name)) {
+                let names = credits.entry(field).or_default();
+                if !names.contains(&credit.name) {
+                    names.push(credit.name.clone());
+                }
+            }
+        }
+    }
+    fields.extend(credits.into_iter().map(|(field, names)| (field, names.join(", "))));
+    Ok(Some(fields))
+}
+
+/// Look up the manga series `series`
+fn anilist(client: &Client, series: &str) -> Result<Option<Vec<(&'static str, String)>>> {
+    let request = json!({ "query": ANILIST_QUERY, "variables": { "search": series } }).to_string();
+    let body = client
+        .cached(&format!("{} {}", ANILIST_API, request), |agent| {
+            let mut response = agent
+                .post(ANILIST_API)
+                .header("User-Agent", USER_AGENT)
+                .header("Content-Type", "application/json")
+                .header("Accept", "application/json")
+                .send(request.as_str())?;
+            // AniList answers "Not Found" with 404, which is a valid answer
+            if !matches!(response.status().as_u16(), 200 | 404) {
+                anyhow::bail!("HTTP {}", response.status());
+            }
+            Ok(response.body_mut().read_to_string()?)
+        })
+        .context("AniList")?;
+    let response: Value = serde_json::from_str(&body).context("Unexpected AniList response")?;
+    let media = &response["data"]["Media"];
+    if media.is_null() {
+        return Ok(None);
+    }
+
+    let text = |value: &Value| value.as_str().map(str::to_string);
+    let mut fields = Vec::new();
+    fields.extend(text(&media["title"]["english"]).or_else(|| text(&media["title"]["romaji"])).map(|title| ("Series", title)));
+    fields.extend(text(&media["description"]).map(|description| ("Summary", plain_text(&description))));
+    if let Some(genres) = media["genres"].as_array().filter(|genres| !genres.is_empty()) {
+        fields.push(("Genre", genres.iter().filter_map(Value::as_str).collect::<Vec<_>>().join(", ")));
+    }
+    fields.extend(text(&media["siteUrl"]).map(|url| ("Web", url)));
+
+    let mut writers = Vec::new();
+    let mut artists = Vec::new();
+    for edge in media["staff"]["edges"].as_array().into_iter().flatten() {
+        let (Some(role), Some(name)) = (edge["role"].as_str(), text(&edge["node"]["name"]["full"])) else {
+            continue;
+        };
+        if role.contains("Assistant") {
+            continue;
+        }
+        if role.contains("Story") && !writers.contains(&name) {
+            writers.push(name.clone());
+        }
+        if role.contains("Art") && !artists.contains(&name) {
+            artists.push(name);
+        }
+    }
+    if !writers.is_empty() {
+        fields.push(("Writer", writers.join(", ")));
+    }
+    if !artists.is_empty() {
+        fields.push(("Penciller", artists.join(", ")));
+    }
+    Ok(Some(fields))
+}
+
+/// HTTP access with the response cache and request spacing
+struct Client {
+    agent: Agent,
+    cache: Option<PathBuf>,
+}
+
+impl Client {
+    /// The response body for the request `key` (without credentials): cached,
+    /// or fetched by `fetch`, which fails for answers not worth caching
+    fn cached(&self, key: &str, fetch: impl FnOnce(&Agent) -> Result<String>) -> Result<String> {
+        let hash: String = Sha256::digest(key.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect();
+        let path = self.cache.as_ref().map(|dir| dir.join(format!("{}.json", hash)));
+        let fresh = |path: &PathBuf| {
+            fs::metadata(path)
+                .and_then(|metadata| metadata.modified())
+                .is_ok_and(|modified| modified.elapsed().is_ok_and(|age| age < Duration::from_secs(CACHE_DAYS * 86_400)))
+        };
+        if let Some(body) = path.as_ref().filter(|path| fresh(path)).and_then(|path| fs::read_to_string(path).ok()) {
+            return Ok(body);
+        }
+
+        {
+            let mut last = LAST_REQUEST.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            if let Some(wait) = last.and_then(|last| REQUEST_INTERVAL.checked_sub(last.elapsed())) {
+                std::thread::sleep(wait);
+            }
+            *last = Some(Instant::now());
+        }
+        let body = fetch(&self.agent)?;
+
+        // The cache only saves requests; failing to write it does not fail the lookup
+        if let Some(path) = path {
+            let _ = path.parent().map(fs::create_dir_all);
+            let _ = fs::write(&path, &body);
+        }
+        Ok(body)
+    }
+}
+
+/// `$XDG_CACHE_HOME/compress_comics/metadata`, falling back to `~/.cache/...`
+/// (or `%LOCALAPPDATA%\...` on Windows)
+fn default_cache_dir() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CACHE_HOME")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            if cfg!(windows) {
+                std::env::var_os("LOCALAPPDATA").map(PathBuf::from)
+            } else {
+                std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache"))
+            }
+        })?;
+    Some(base.join("compress_comics").join("metadata"))
+}
+
+/// A year in parentheses in the file name, e.g. "Saga 012 (2013)"
+fn year_in(stem: &str) -> Option<u32> {
+    stem.match_indices('(').find_map(|(open, _)| {
+        let year = stem.get(open + 1..open + 5)?;
+        (year.bytes().all(|b| b.is_ascii_digit()) && stem[open + 5..].starts_with(')')).then(|| year.parse().ok())?
+    })
+}
+
+/// Text of an HTML description: tags dropped, line and paragraph breaks kept, common entities decoded
+fn plain_text(html: &str) -> String {
+    let mut text = String::new();
+    let mut rest = html;
+    while let Some(open) = rest.find('<') {
+        text.push_str(&rest[..open]);
+        let Some(close) = rest[open..].find('>') else {
+            rest = &rest[open..];
+            break;
+        };
+        let tag = rest[open + 1..open + close].trim().to_lowercase();
+        if tag.starts_with("br") || tag == "/p" || tag.starts_with("/h") || tag == "/li" {
+            text.push('\n');
+        }
+        rest = &rest[open + close + 1..];
+    }
+    text.push_str(rest);
+    let text = text
+        .replace("&nbsp;", " ")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&");
+    // At most one blank line between paragraphs
+    let lines: Vec<&str> = text.lines().map(str::trim_end).collect();
+    let mut plain = String::new();
+    for (index, line) in lines.iter().enumerate() {
+        if line.is_empty() && (index == 0 || lines[index - 1].is_empty()) {
+            continue;
+        }
+        plain.push_str(line);
+        plain.push('\n');
+    }
+    plain.trim().to_string()
+}
@@ -0,0 +1,78 @@
+use image::{GrayImage, RgbImage};
+
+/// Side of the square windows SSIM is computed over
+const WINDOW: u32 = 8;
+
+/// Stabilising constants for 8-bit samples, from the SSIM paper
+const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+/// Mean structural similarity of two equally sized grayscale images, averaged
+/// over non-overlapping 8x8 windows (1.0 means identical). Edge pixels that do
+/// not fill a whole window are ignored; images smaller than one window are
+/// compared as a single window.
+pub fn ssim(reference: &GrayImage, candidate: &GrayImage) -> f64 {
+    assert_eq!(reference.dimensions(), candidate.dimensions(), "SSIM needs equally sized images");
+    let (width, height) = reference.dimensions();
+    if width < WINDOW || height < WINDOW {
+        return window_ssim(reference, candidate, 0, 0, width, height);
+    }
+
+    let (mut total, mut windows) = (0.0, 0usize);
+    for y in (0..=height - WINDOW).step_by(WINDOW as usize) {
+        for x in (0..=width - WINDOW).step_by(WINDOW as usize) {
+            total += window_ssim(reference, candidate, x, y, WINDOW, WINDOW);
+            windows += 1;
+        }
+    }
+    total / windows as f64
+}
+
+fn window_ssim(a: &GrayImage, b: &GrayImage, x0: u32, y0: u32, width: u32, height: u32) -> f64 {
+    let count = (width * height) as f64;
+    if count == 0.0 {
+        return 1.0;
+    }
+
+    let (mut sum_a, mut sum_b, mut sum_aa, mut sum_bb, mut sum_ab) = (0.0, 0.0, 0.0, 0.0, 0.0);
+    for y in y0..y0 + height {
+        for x in x0..x0 + width {
+            let pa = a.get_pixel(x, y).0[0] as f64;
+            let pb = b.get_pixel(x, y).0[0] as f64;
+            sum_a += pa;
+            sum_b += pb;
+            sum_aa += pa * pa;
+            sum_bb += pb * pb;
+            sum_ab += pa * pb;
+        }
+    }
+
+    let (mean_a, mean_b) = (sum_a / count, sum_b / count);
+    let variance_a = sum_aa / count - mean_a * mean_a;
+    let variance_b = sum_bb / count - mean_b * mean_b;
+    let covariance = sum_ab / count - mean_a * mean_b;
+
+    ((2.0 * mean_a * mean_b + C1) * (2.0 * covariance + C2))
+        / ((mean_a * mean_a + mean_b * mean_b + C1) * (variance_a + variance_b + C2))
+}
+
+/// Peak signal-to-noise ratio in dB of two equally sized RGB images over all
+/// channels; identical images give infinity
+pub fn psnr(reference: &RgbImage, candidate: &RgbImage) -> f64 {
+    assert_eq!(reference.dimensions(), candidate.dimensions(), "PSNR needs equally sized images");
+    let samples = reference.as_raw().len();
+    if samples == 0 {
+        return f64::INFINITY;
+    }
+    let squared_error: f64 = reference
+        .as_raw()
+        .iter()
+        .zip(candidate.as_raw())
+        .map(|(&a, &b)| (a as f64 - b as f64).powi(2))
+        .sum();
+    let mse = squared_error / samples as f64;
+    if mse == 0.0 {
+        return f64::INFINITY;
+    }
+    10.0 * (255.0 * 255.0 / mse).log10()
+}
@@ -0,0 +1,139 @@
+//! `--ocr`: recognise the text of each page with the `tesseract` tool and lay
+//! it over the page image of PDF output as invisible text, so readers can
+//! search and select it. `--ocr-text` also puts each page's text in the
+//! report, for indexing. Words are placed at the boxes tesseract found and
+//! stretched to their width; characters outside Latin-1 are written as `?`
+//! because the layer uses a standard PDF font.
+
+use anyhow::{Context, Result};
+use std::path::Path;
+use std::process::Command;
+
+use crate::temp;
+
+/// Words tesseract is less sure of (0-100) are left out, mostly artwork read as letters
+const MIN_CONFIDENCE: f64 = 40.0;
+
+/// Resource name of the text layer font
+pub(crate) const FONT: &str = "Ocr";
+
+/// A recognised word and its box, in image pixels from the top left
+pub(crate) struct Word {
+    text: String,
+    left: f64,
+    top: f64,
+    width: f64,
+    height: f64,
+    /// Block, paragraph and line numbers, to rebuild the lines of plain text
+    line: (u32, u32, u32),
+}
+
+pub(crate) fn check_tesseract_available() -> Result<()> {
+    Command::new("tesseract")
+        .arg("--version")
+        .output()
+        .map(|_| ())
+        .map_err(|_| anyhow::anyhow!("--ocr requires the `tesseract` tool to be installed and on PATH"))
+}
+
+/// The words of the page image at `page`, in reading order, recognised in `languages` (e.g. "eng+jpn")
+pub(crate) fn recognize(page: &Path, languages: &str) -> Result<Vec<Word>> {
+    let extension = page.extension().and_then(|ext| ext.to_str()).map(str::to_lowercase).unwrap_or_default();
+    // Tesseract reads these itself; other pages are handed over as PNG
+    let work_dir;
+    let input = if matches!(extension.as_str(), "jpg" | "jpeg" | "png" | "tif" | "tiff" | "bmp") {
+        page.to_path_buf()
+    } else {
+        work_dir = temp::create_dir_in(None)?;
+        let png = work_dir.path().join("page.png");
+        image::open(page)
+            .and_then(|img| img.save(&png))
+            .with_context(|| format!("Failed to convert {} for OCR", page.display()))?;
+        png
+    };
+
+    let result = Command::new("tesseract")
+        .arg(&input)
+        .arg("stdout")
+        .args(["-l", languages, "tsv"])
+        .output()
+        .context("Failed to run tesseract")?;
+    if !result.status.success() {
+        anyhow::bail!("tesseract failed on {}: {}", page.display(), String::from_utf8_lossy(&result.stderr).trim());
+    }
+    Ok(parse_tsv(&String::from_utf8_lossy(&result.stdout)))
+}
+
+/// Words from tesseract's TSV output: level, page, block, paragraph, line and
+/// word numbers, left, top, width, height, confidence and text
+fn parse_tsv(tsv: &str) -> Vec<Word> {
+    tsv.lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.splitn(12, '\t').collect();
+            let [level, _, block, paragraph, line, _, left, top, width, height, confidence, text] = fields[..] else {
+                return None;
+            };
+            let text = text.trim();
+            if level != "5" || text.is_empty() || confidence.parse::<f64>().ok()? < MIN_CONFIDENCE {
+                return None;
+            }
+            Some(Word {
+                text: text.to_string(),
+                left: left.parse().ok()?,
+                top: top.parse().ok()?,
+                width: width.parse().ok()?,
+                height: height.parse().ok()?,
+                line: (block.parse().ok()?, paragraph.parse().ok()?, line.parse().ok()?),
+            })
+        })
+        .collect()
+}
+
+/// The words as plain text, one line per recognised line and a blank line between blocks
+pub(crate) fn plain_text(words: &[Word]) -> String {
+    let mut text = String::new();
+    let mut previous: Option<(u32, u32, u32)> = None;
+    for word in words {
+        match previous {
+            Some(line) if line == word.line => text.push(' '),
+            Some((block, _, _)) if block != word.line.0 => text.push_str("\n\n"),
+            Some(_) => text.push('\n'),
+            None => {}
+        }
+        text.push_str(&word.text);
+        previous = Some(word.line);
+    }
+    text
+}
+
+/// Content stream operators drawing `words` as invisible text on a page
+/// `page_height` points high, where one image pixel is one point
+pub(crate) fn text_layer(words: &[Word], page_height: f64) -> Vec<u8> {
+    let mut content = b"BT 3 Tr\n".to_vec();
+    for word in words.iter().filter(|word| word.height > 0.0 && word.width > 0.0) {
+        let size = word.height;
+        let text = latin1(&word.text);
+        // Helvetica glyphs average about half the font size wide; Tz stretches the word to its box
+        let scale = 100.0 * word.width / (0.5 * size * text.len() as f64);
+        let baseline = page_height - word.top - word.height * 0.8;
+        content.extend(
+            format!("/{} {:.1} Tf {:.1} Tz 1 0 0 1 {:.1} {:.1} Tm (", FONT, size, scale, word.left, baseline).into_bytes(),
+        );
+        for byte in text {
+            match byte {
+                b'(' | b')' | b'\\' => content.extend([b'\\', byte]),
+                0x20..=0x7e => content.push(byte),
+                _ => content.extend(format!("\\{:03o}", byte).into_bytes()),
+            }
+        }
+        content.extend(b") Tj\n");
+    }
+    content.extend(b"ET\n");
+    content
+}
+
+/// `text` in the font's WinAnsi encoding, which matches Latin-1 for letters
+fn latin1(text: &str) -> Vec<u8> {
+    text.chars().map(|c| if (c as u32) < 256 { c as u8 } else { b'?' }).collect()
+}
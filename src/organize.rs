@@ -0,0 +1,104 @@
+//! `--organize`: file outputs under `--output-dir` as `Series/Volume NN/`,
+//! named after the series, volume and chapter read from the input's file
+//! name (`Series v01 c003`). `--organize-pattern` regexes are tried before the
+//! built-in ones, which read names such as `Series v01 c003`, `Series Vol. 2`,
+//! `Series - Chapter 12.5` and `Series 012`. Inputs no pattern matches keep
+//! their name and mirrored folder.
+
+use regex::{Regex, RegexBuilder};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::paths;
+
+/// Tried in order after the user's patterns; a bare number counts as a chapter (issue)
+const DEFAULT_PATTERNS: &[&str] = &[
+    r"^(?P<series>.+?)[\s._-]+(?:v|vol\.?|volume)[\s._]*(?P<volume>\d+)(?:[\s._-]+(?:c|ch\.?|chapter)[\s._]*(?P<chapter>\d+(?:\.\d+)?))?",
+    r"^(?P<series>.+?)[\s._-]+(?:c|ch\.?|chapter)[\s._]*(?P<chapter>\d+(?:\.\d+)?)",
+    r"^(?P<series>.+?)[\s._-]+#?(?P<chapter>\d+(?:\.\d+)?)(?:\s*\(.*\))?$",
+];
+
+/// Where an input belongs in the organized tree
+pub(crate) struct Organized {
+    pub series: String,
+    /// Zero-padded to two digits
+    pub volume: Option<String>,
+    /// Zero-padded to three digits, with any fraction (`012.5`)
+    pub chapter: Option<String>,
+}
+
+/// Compile an `--organize-pattern`: case-insensitive, with at least a `series` group
+pub(crate) fn parse_pattern(pattern: &str) -> Result<Regex, String> {
+    let regex = RegexBuilder::new(pattern).case_insensitive(true).build().map_err(|e| e.to_string())?;
+    if !regex.capture_names().any(|name| name == Some("series")) {
+        return Err("the pattern needs a (?P<series>...) group; (?P<volume>...) and (?P<chapter>...) are optional".to_string());
+    }
+    Ok(regex)
+}
+
+/// Series, volume and chapter of the file name `stem`, by the first of
+/// `patterns` and then the built-in ones that matches
+pub(crate) fn parse(stem: &str, patterns: &[Regex]) -> Option<Organized> {
+    static DEFAULTS: OnceLock<Vec<Regex>> = OnceLock::new();
+    let defaults = DEFAULTS.get_or_init(|| DEFAULT_PATTERNS.iter().map(|pattern| parse_pattern(pattern).unwrap()).collect());
+
+    patterns.iter().chain(defaults).find_map(|regex| {
+        let captures = regex.captures(stem)?;
+        let series = normalize_series(captures.name("series")?.as_str());
+        if series.is_empty() {
+            return None;
+        }
+        Some(Organized {
+            series,
+            volume: captures.name("volume").and_then(|volume| padded(volume.as_str(), 2)),
+            chapter: captures.name("chapter").and_then(|chapter| padded(chapter.as_str(), 3)),
+        })
+    })
+}
+
+impl Organized {
+    /// `root/Series/Volume NN`, or `root/Series` without a volume
+    pub(crate) fn dir(&self, root: &Path) -> PathBuf {
+        let dir = root.join(&self.series);
+        match &self.volume {
+            Some(volume) => dir.join(format!("Volume {}", volume)),
+            None => dir,
+        }
+    }
+
+    /// `Series v01 c003`, leaving out what is unknown
+    pub(crate) fn name(&self) -> String {
+        let mut name = self.series.clone();
+        if let Some(volume) = &self.volume {
+            name.push_str(&format!(" v{}", volume));
+        }
+        if let Some(chapter) = &self.chapter {
+            name.push_str(&format!(" c{}", chapter));
+        }
+        name
+    }
+}
+
+/// The series without leading `[Group]` tags, with `_` (and `.` in names
+/// without spaces) as spaces and no trailing separators, legal as a folder name
+fn normalize_series(raw: &str) -> String {
+    let mut name = raw.trim();
+    while let Some(rest) = name.strip_prefix('[').and_then(|rest| rest.split_once(']')).map(|(_, rest)| rest.trim_start()) {
+        name = rest;
+    }
+    let spaced = if name.contains(' ') { name.replace('_', " ") } else { name.replace(['_', '.'], " ") };
+    let name = spaced.split_whitespace().collect::<Vec<_>>().join(" ");
+    paths::ntfs_component(name.trim_end_matches([' ', '-', '.', ','])).into_owned()
+}
+
+/// `number` without leading zeros, padded to `width` digits; a fraction is kept
+fn padded(number: &str, width: usize) -> Option<String> {
+    let (whole, fraction) = number.split_once('.').unwrap_or((number, ""));
+    let whole: u32 = whole.parse().ok()?;
+    let fraction = fraction.trim_end_matches('0');
+    Some(if fraction.is_empty() {
+        format!("{:0width$}", whole, width = width)
+    } else {
+        format!("{:0width$}.{}", whole, fraction, width = width)
+    })
+}
@@ -0,0 +1,120 @@
+//! Windows path limits. `std::fs` already switches paths past `MAX_PATH`
+//! (260 characters) to the `\\?\` form, but the unrar library and the
+//! external tools (`rar`, `ddjvu`, `cjxl`, ...) get paths as they are, so deep
+//! library trees fail only there; `long_path()` gives them the verbatim form.
+//! Entry names are made legal on NTFS while extracting (and streaming), so
+//! their pages end up in the repacked archive under the replacement names.
+
+use std::borrow::Cow;
+use std::path::Path;
+
+/// Characters NTFS does not allow in names, with the full-width look-alikes
+/// that replace them
+const RESERVED_CHARACTERS: &[(char, char)] = &[
+    ('<', '＜'),
+    ('>', '＞'),
+    (':', '：'),
+    ('"', '＂'),
+    ('|', '｜'),
+    ('?', '？'),
+    ('*', '＊'),
+];
+
+/// Device names Windows resolves in every folder, with or without an extension
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9", "LPT1", "LPT2",
+    "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// `path` for APIs outside `std::fs`: on Windows absolute and in the `\\?\`
+/// (or `\\?\UNC\`) form, which lifts the `MAX_PATH` limit. Other platforms
+/// have no such limit
+#[cfg(windows)]
+pub(crate) fn long_path(path: &Path) -> Cow<'_, Path> {
+    let Ok(absolute) = std::path::absolute(path) else {
+        return Cow::Borrowed(path);
+    };
+    let Some(text) = absolute.to_str() else {
+        return Cow::Owned(absolute);
+    };
+    if text.starts_with(r"\\?\") || text.starts_with(r"\\.\") {
+        return Cow::Owned(absolute);
+    }
+    let verbatim = match text.strip_prefix(r"\\") {
+        Some(share) => format!(r"\\?\UNC\{}", share),
+        None => format!(r"\\?\{}", text),
+    };
+    Cow::Owned(verbatim.into())
+}
+
+#[cfg(not(windows))]
+pub(crate) fn long_path(path: &Path) -> Cow<'_, Path> {
+    Cow::Borrowed(path)
+}
+
+/// A path component of an archive entry, made legal on NTFS: reserved
+/// characters become full-width look-alikes, control characters `_`,
+/// trailing dots and spaces (which Windows strips) `．` and `_`, and device
+/// names such as `CON` get a `_` appended. `.` and `..` are left for the
+/// zip-slip check to handle
+pub(crate) fn ntfs_component(component: &str) -> Cow<'_, str> {
+    if component == "." || component == ".." {
+        return Cow::Borrowed(component);
+    }
+    let kept = component.trim_end_matches(['.', ' ']);
+    let stem = kept.split('.').next().unwrap_or_default();
+    let reserved_name = RESERVED_NAMES.iter().any(|name| name.eq_ignore_ascii_case(stem.trim_end()));
+    let legal = |c: char| !c.is_control() && !RESERVED_CHARACTERS.iter().any(|(reserved, _)| *reserved == c);
+    if kept.len() == component.len() && !reserved_name && component.chars().all(legal) {
+        return Cow::Borrowed(component);
+    }
+
+    let mut name: String = kept
+        .chars()
+        .map(|c| match RESERVED_CHARACTERS.iter().find(|(reserved, _)| *reserved == c) {
+            Some((_, replacement)) => *replacement,
+            None if c.is_control() => '_',
+            None => c,
+        })
+        .collect();
+    if reserved_name {
+        name.insert(stem.len(), '_');
+    }
+    name.extend(component[kept.len()..].chars().map(|c| if c == '.' { '．' } else { '_' }));
+    Cow::Owned(name)
+}
+
+/// An archive entry name with every component made legal on NTFS
+pub(crate) fn ntfs_entry_name(name: &str) -> String {
+    name.split('/').map(ntfs_component).collect::<Vec<_>>().join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn components_are_made_legal_on_ntfs() {
+        assert_eq!(ntfs_component("a:b"), "a：b");
+        assert_eq!(ntfs_component("x."), "x．");
+        assert_eq!(ntfs_component("x. "), "x．_");
+        assert_eq!(ntfs_component("CON.jpg"), "CON_.jpg");
+        assert_eq!(ntfs_component("lpt1"), "lpt1_");
+        assert_eq!(ntfs_component("page\t1.jpg"), "page_1.jpg");
+    }
+
+    #[test]
+    fn legal_components_are_borrowed() {
+        for component in ["001.jpg", "Chapter 1", "CONTENTS.jpg", ".", ".."] {
+            assert!(matches!(ntfs_component(component), Cow::Borrowed(name) if name == component), "{}", component);
+        }
+    }
+
+    #[test]
+    fn entry_names_are_made_legal_per_component() {
+        assert_eq!(ntfs_entry_name("Vol. 1: Start/CON.jpg"), "Vol. 1： Start/CON_.jpg");
+        assert_eq!(ntfs_entry_name("c./x."), "c．/x．");
+        // Left for the zip-slip check
+        assert_eq!(ntfs_entry_name("../a:b"), "../a：b");
+    }
+}
@@ -0,0 +1,395 @@
+use anyhow::{Context, Result};
+use image::GenericImageView;
+use lopdf::{dictionary, Document, Object, ObjectId, Stream};
+use rayon::prelude::*;
+use std::fs;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use crate::{interrupt, ocr, throttle};
+
+/// Info dictionary key holding the processing marker (PDF has no archive comment)
+pub const MARKER_KEY: &str = "CompressComicsMarker";
+
+/// Page image ready to embed: encoded stream data plus its image dictionary entries
+struct EmbeddedImage {
+    width: u32,
+    height: u32,
+    color_space: &'static str,
+    filter: Option<&'static str>,
+    data: Vec<u8>,
+}
+
+/// Build a PDF with one page per image, each page sized to its image (1 px = 1 pt).
+///
+/// JPEG pages are embedded as-is (DCTDecode). Lossless pages (PNG, BMP, TIFF)
+/// are stored losslessly with FlateDecode; anything else, such as WebP, is not
+/// supported by PDF and is transcoded to JPEG at `quality`. `right_to_left`
+/// sets the viewer's reading direction for manga. With `ocr_languages`, each
+/// page gets an invisible text layer; returns the text of each page then.
+pub fn write_pdf(
+    pages: &[PathBuf],
+    output_path: &Path,
+    comment: &str,
+    quality: u8,
+    right_to_left: bool,
+    ocr_languages: Option<&str>,
+) -> Result<Vec<String>> {
+    if pages.is_empty() {
+        anyhow::bail!("No pages to write to PDF");
+    }
+
+    // Recognition is the slow part, so pages are read in parallel up front
+    let words = match ocr_languages {
+        Some(languages) => pages
+            .par_iter()
+            .map(|page| ocr::recognize(page, languages).with_context(|| format!("OCR failed on {}", page.display())))
+            .collect::<Result<Vec<_>>>()?,
+        None => Vec::new(),
+    };
+
+    let mut doc = Document::with_version("1.7");
+    let pages_id = doc.new_object_id();
+    let mut kids = Vec::with_capacity(pages.len());
+    let font_id = ocr_languages.map(|_| {
+        doc.add_object(dictionary! {
+            "Type" => "Font",
+            "Subtype" => "Type1",
+            "BaseFont" => "Helvetica",
+            "Encoding" => "WinAnsiEncoding",
+        })
+    });
+
+    for (index, page) in pages.iter().enumerate() {
+        let image = embed_image(page, quality)
+            .with_context(|| format!("Failed to embed {} in PDF", page.display()))?;
+        let (width, height) = (image.width, image.height);
+
+        let mut image_dict = dictionary! {
+            "Type" => "XObject",
+            "Subtype" => "Image",
+            "Width" => width as i64,
+            "Height" => height as i64,
+            "ColorSpace" => image.color_space,
+            "BitsPerComponent" => 8,
+        };
+        if let Some(filter) = image.filter {
+            image_dict.set("Filter", filter);
+        }
+        let mut image_stream = Stream::new(image_dict, image.data);
+        if image.filter.is_none() {
+            image_stream.compress()?;
+        }
+        let image_id = doc.add_object(image_stream);
+
+        let mut content = format!("q {} 0 0 {} 0 0 cm /Im0 Do Q\n", width, height).into_bytes();
+        let mut resources = dictionary! {
+            "XObject" => dictionary! { "Im0" => image_id },
+        };
+        if let (Some(words), Some(font_id)) = (words.get(index), font_id) {
+            content.extend(ocr::text_layer(words, height as f64));
+            resources.set("Font", dictionary! { ocr::FONT => font_id });
+        }
+        let content_id = doc.add_object(Stream::new(dictionary! {}, content));
+
+        let page_id = doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "MediaBox" => vec![0.into(), 0.into(), (width as i64).into(), (height as i64).into()],
+            "Resources" => resources,
+            "Contents" => content_id,
+        });
+        kids.push(Object::Reference(page_id));
+    }
+
+    let count = kids.len() as i64;
+    doc.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => kids,
+            "Count" => count,
+        }),
+    );
+
+    let catalog_id = doc.add_object(dictionary! {
+        "Type" => "Catalog",
+        "Pages" => pages_id,
+    });
+    let info_id = doc.add_object(dictionary! {
+        "Producer" => Object::string_literal(format!("compress_comics {}", env!("CARGO_PKG_VERSION"))),
+        MARKER_KEY => Object::string_literal(comment),
+    });
+    doc.trailer.set("Root", catalog_id);
+    doc.trailer.set("Info", info_id);
+    if right_to_left {
+        set_right_to_left(&mut doc);
+    }
+
+    doc.save(output_path)
+        .with_context(|| format!("Failed to write PDF {}", output_path.display()))?;
+    Ok(words.iter().map(|words| ocr::plain_text(words)).collect())
+}
+
+/// Recompress the images of an existing PDF in place, keeping its structure
+/// (page tree, outlines, text layers, annotations) untouched.
+///
+/// Only 8-bit DeviceRGB/DeviceGray images stored as JPEG, Flate or raw data are
+/// touched; masks, images with soft masks and exotic colour spaces are left
+/// alone. Each image is shrunk to `target_height` (never upscaled; `None`
+/// keeps image sizes) and stored as JPEG at `quality` when that is smaller.
+/// Returns (recompressed, kept).
+pub fn recompress_images(
+    pdf_path: &Path,
+    output_path: &Path,
+    comment: &str,
+    quality: u8,
+    target_height: Option<u32>,
+    right_to_left: bool,
+) -> Result<(usize, usize)> {
+    let mut doc = Document::load_from(BufReader::new(throttle::open(pdf_path)?))
+        .map_err(|e| anyhow::anyhow!("Failed to load PDF: {:?}", e))?;
+
+    let mut image_ids: Vec<ObjectId> = doc
+        .objects
+        .iter()
+        .filter(|(_, object)| {
+            matches!(object, Object::Stream(stream) if stream.dict.get(b"Subtype").and_then(|o| o.as_name()).ok() == Some(b"Image"))
+        })
+        .map(|(id, _)| *id)
+        .collect();
+    image_ids.sort();
+
+    // Soft masks are images too, but must keep their exact size and encoding
+    let smask_ids: Vec<ObjectId> = image_ids
+        .iter()
+        .filter_map(|id| doc.get_object(*id).ok()?.as_stream().ok()?.dict.get(b"SMask").ok()?.as_reference().ok())
+        .collect();
+
+    let (mut recompressed, mut kept) = (0, 0);
+    for id in image_ids {
+        if smask_ids.contains(&id) {
+            continue;
+        }
+        interrupt::check()?;
+        let Ok(Object::Stream(stream)) = doc.get_object_mut(id) else { continue };
+        match recompress_stream(stream, quality, target_height) {
+            Ok(true) => recompressed += 1,
+            _ => kept += 1,
+        }
+    }
+
+    set_info_entry(&mut doc, MARKER_KEY, comment);
+    if right_to_left {
+        set_right_to_left(&mut doc);
+    }
+    doc.save(output_path)
+        .with_context(|| format!("Failed to write PDF {}", output_path.display()))?;
+    Ok((recompressed, kept))
+}
+
+/// Replace an image stream's content with a smaller JPEG; `Ok(false)` when the
+/// image is unsupported or would not shrink
+fn recompress_stream(stream: &mut Stream, quality: u8, target_height: Option<u32>) -> Result<bool> {
+    let dict = &stream.dict;
+    let is_mask = dict.get(b"ImageMask").and_then(|o| o.as_bool()).unwrap_or(false);
+    let bits = dict.get(b"BitsPerComponent").and_then(|o| o.as_i64()).unwrap_or(8);
+    if is_mask || dict.has(b"SMask") || dict.has(b"Mask") || dict.has(b"Decode") || bits != 8 {
+        return Ok(false);
+    }
+    let grayscale = match dict.get(b"ColorSpace").and_then(|o| o.as_name()) {
+        Ok(b"DeviceRGB") => false,
+        Ok(b"DeviceGray") => true,
+        _ => return Ok(false),
+    };
+    let width = dict.get(b"Width").and_then(|o| o.as_i64())? as u32;
+    let height = dict.get(b"Height").and_then(|o| o.as_i64())? as u32;
+
+    let filters = stream.filters().unwrap_or_default();
+    let img = match filters.as_slice() {
+        [b"DCTDecode"] => image::load_from_memory_with_format(&stream.content, image::ImageFormat::Jpeg)?,
+        [] | [b"FlateDecode"] => {
+            if dict.has(b"DecodeParms") {
+                return Ok(false);
+            }
+            let raw = stream.decompressed_content().unwrap_or_else(|_| stream.content.clone());
+            let image = if grayscale {
+                image::GrayImage::from_raw(width, height, raw).map(image::DynamicImage::ImageLuma8)
+            } else {
+                image::RgbImage::from_raw(width, height, raw).map(image::DynamicImage::ImageRgb8)
+            };
+            let Some(image) = image else { return Ok(false) };
+            image
+        }
+        _ => return Ok(false),
+    };
+
+    // The page's transformation matrix places the image, so its pixel size can change freely
+    let img = if let Some(target_height) = target_height.filter(|&h| img.height() > h) {
+        let new_width = (target_height as f32 * img.width() as f32 / img.height() as f32) as u32;
+        crate::resize_lanczos3(&img, new_width.max(1), target_height)
+    } else {
+        img
+    };
+
+    let mut data = Vec::new();
+    let mut encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut data, quality);
+    if grayscale {
+        encoder.encode_image(&img.to_luma8())?;
+    } else {
+        encoder.encode_image(&img.to_rgb8())?;
+    }
+    if data.len() >= stream.content.len() {
+        return Ok(false);
+    }
+
+    stream.dict.set("Width", img.width() as i64);
+    stream.dict.set("Height", img.height() as i64);
+    stream.dict.set("Filter", "DCTDecode");
+    stream.dict.remove(b"DecodeParms");
+    stream.set_content(data);
+    Ok(true)
+}
+
+/// Set a string entry in the document's Info dictionary, creating it if needed
+fn set_info_entry(doc: &mut Document, key: &str, value: &str) {
+    let info_id = match doc.trailer.get(b"Info").and_then(|o| o.as_reference()) {
+        Ok(id) if doc.get_dictionary(id).is_ok() => id,
+        _ => {
+            let id = doc.add_object(dictionary! {});
+            doc.trailer.set("Info", id);
+            id
+        }
+    };
+    if let Ok(info) = doc.get_dictionary_mut(info_id) {
+        info.set(key, Object::string_literal(value));
+    }
+}
+
+/// Mark the document as read right to left (`/ViewerPreferences /Direction /R2L`),
+/// keeping any other viewer preferences
+fn set_right_to_left(doc: &mut Document) {
+    let Ok(catalog_id) = doc.trailer.get(b"Root").and_then(|o| o.as_reference()) else { return };
+    let preferences = doc
+        .get_dictionary(catalog_id)
+        .ok()
+        .and_then(|catalog| catalog.get(b"ViewerPreferences").ok())
+        .cloned();
+
+    match preferences {
+        Some(Object::Reference(id)) => {
+            if let Ok(preferences) = doc.get_dictionary_mut(id) {
+                preferences.set("Direction", "R2L");
+            }
+        }
+        other => {
+            let mut preferences = match other {
+                Some(Object::Dictionary(dict)) => dict,
+                _ => dictionary! {},
+            };
+            preferences.set("Direction", "R2L");
+            if let Ok(catalog) = doc.get_dictionary_mut(catalog_id) {
+                catalog.set("ViewerPreferences", preferences);
+            }
+        }
+    }
+}
+
+/// The processing marker stored in a PDF's Info dictionary, if any
+pub fn read_comment(path: &Path) -> Option<String> {
+    let doc = Document::load(path).ok()?;
+    let info = match doc.trailer.get(b"Info").ok()? {
+        Object::Reference(id) => doc.get_dictionary(*id).ok()?,
+        Object::Dictionary(dict) => dict,
+        _ => return None,
+    };
+    match info.get(MARKER_KEY.as_bytes()).ok()? {
+        Object::String(bytes, _) => Some(String::from_utf8_lossy(bytes).into_owned()),
+        _ => None,
+    }
+}
+
+fn embed_image(path: &Path, quality: u8) -> Result<EmbeddedImage> {
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_lowercase())
+        .unwrap_or_default();
+
+    if matches!(extension.as_str(), "jpg" | "jpeg") {
+        let data = fs::read(path)?;
+        // CMYK JPEGs are transcoded; their inverted-Adobe encoding is unreliable across readers
+        if let Some((width, height, color_space)) = jpeg_info(&data) {
+            return Ok(EmbeddedImage {
+                width,
+                height,
+                color_space,
+                filter: Some("DCTDecode"),
+                data,
+            });
+        }
+    }
+
+    let img = image::open(path).map_err(|e| anyhow::anyhow!("Failed to decode image: {:?}", e))?;
+    let (width, height) = img.dimensions();
+    let grayscale = !img.color().has_color();
+
+    if matches!(extension.as_str(), "png" | "bmp" | "tif" | "tiff") {
+        let (color_space, data) = if grayscale {
+            ("DeviceGray", img.to_luma8().into_raw())
+        } else {
+            ("DeviceRGB", img.to_rgb8().into_raw())
+        };
+        return Ok(EmbeddedImage {
+            width,
+            height,
+            color_space,
+            filter: None,
+            data,
+        });
+    }
+
+    let mut data = Vec::new();
+    let mut encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut data, quality);
+    let color_space = if grayscale {
+        encoder.encode_image(&img.to_luma8())?;
+        "DeviceGray"
+    } else {
+        encoder.encode_image(&img.to_rgb8())?;
+        "DeviceRGB"
+    };
+    Ok(EmbeddedImage {
+        width,
+        height,
+        color_space,
+        filter: Some("DCTDecode"),
+        data,
+    })
+}
+
+/// Dimensions and colour space from a JPEG's SOF header; `None` for CMYK or
+/// unparseable files
+fn jpeg_info(data: &[u8]) -> Option<(u32, u32, &'static str)> {
+    let mut pos = 2;
+    while pos + 4 <= data.len() {
+        if data[pos] != 0xFF {
+            return None;
+        }
+        let marker = data[pos + 1];
+        let length = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        let is_sof = (0xC0..=0xCF).contains(&marker) && !matches!(marker, 0xC4 | 0xC8 | 0xCC);
+        if is_sof {
+            let header = data.get(pos + 4..pos + 10)?;
+            let height = u16::from_be_bytes([header[1], header[2]]) as u32;
+            let width = u16::from_be_bytes([header[3], header[4]]) as u32;
+            let color_space = match header[5] {
+                1 => "DeviceGray",
+                3 => "DeviceRGB",
+                _ => return None,
+            };
+            return Some((width, height, color_space));
+        }
+        pos += 2 + length;
+    }
+    None
+}
@@ -0,0 +1,438 @@
+//! Image streams of PDFs that need a decoder of their own before they can be
+//! saved as pages: scanned black-and-white comics are mostly stored as CCITT
+//! fax (Group 3 or 4) or JBIG2 bitmaps, which the extractor skipped, leaving
+//! such PDFs without pages. Flate-compressed and uncompressed images are
+//! plain samples, rebuilt here in any of the colour spaces comics use: device
+//! gray, RGB and CMYK, `ICCBased` (converted through its profile) and
+//! `Indexed` palettes over those, at 1 to 16 bits per component, with PNG or
+//! TIFF predictors undone and `Decode` arrays (often inverting) applied.
+//! JPEG and JPEG 2000 streams are saved as they are.
+
+use crate::{cmyk, icc};
+use anyhow::{Context, Result};
+use image::{DynamicImage, GrayImage, RgbImage};
+use lopdf::{Dictionary, Document, Object, Stream};
+use std::borrow::Cow;
+
+/// The stream's only filter, given as a name or a one-element array;
+/// `None` without a filter or with a chain of several
+pub(crate) fn single_filter(stream: &Stream) -> Option<&[u8]> {
+    match stream.dict.get(b"Filter").ok()? {
+        Object::Name(name) => Some(name),
+        Object::Array(filters) if filters.len() == 1 => filters[0].as_name().ok(),
+        _ => None,
+    }
+}
+
+/// The stream's `DecodeParms` for its only filter, following references
+fn decode_parms<'a>(stream: &'a Stream, doc: &'a Document) -> Option<&'a Dictionary> {
+    let parms = match stream.dict.get(b"DecodeParms").ok()? {
+        Object::Array(parms) if parms.len() == 1 => &parms[0],
+        parms => parms,
+    };
+    match parms {
+        Object::Dictionary(parms) => Some(parms),
+        Object::Reference(id) => doc.get_dictionary(*id).ok(),
+        _ => None,
+    }
+}
+
+/// A `CCITTFaxDecode` stream as a black-and-white image of the stream's
+/// `width` × `height`, rows the data ends before left white
+pub(crate) fn decode_ccitt(stream: &Stream, doc: &Document, width: u32, height: u32) -> Result<GrayImage> {
+    let parms = decode_parms(stream, doc);
+    let int = |key: &[u8], default: i64| parms.and_then(|parms| parms.get(key).ok()?.as_i64().ok()).unwrap_or(default);
+    let flag = |key: &[u8], default: bool| parms.and_then(|parms| parms.get(key).ok()?.as_bool().ok()).unwrap_or(default);
+
+    let columns = u32::try_from(int(b"Columns", i64::from(width))).context("Invalid CCITT column count")?;
+    let rows = match int(b"Rows", 0) {
+        rows if rows > 0 => rows as u32,
+        _ => height,
+    };
+    let k = int(b"K", 0);
+    let settings = hayro_ccitt::DecodeSettings {
+        columns,
+        rows,
+        end_of_block: flag(b"EndOfBlock", true),
+        end_of_line: flag(b"EndOfLine", false),
+        rows_are_byte_aligned: flag(b"EncodedByteAlign", false),
+        encoding: match k {
+            k if k < 0 => hayro_ccitt::EncodingMode::Group4,
+            0 => hayro_ccitt::EncodingMode::Group3_1D,
+            k => hayro_ccitt::EncodingMode::Group3_2D { k: k as u32 },
+        },
+        invert_black: flag(b"BlackIs1", false),
+    };
+
+    struct Pixels(Vec<u8>);
+    impl hayro_ccitt::Decoder for Pixels {
+        fn push_pixel(&mut self, white: bool) {
+            self.0.push(if white { 255 } else { 0 });
+        }
+        fn push_pixel_chunk(&mut self, white: bool, chunk_count: u32) {
+            let luma = if white { 255 } else { 0 };
+            self.0.extend(std::iter::repeat_n(luma, chunk_count as usize * 8));
+        }
+        fn next_line(&mut self) {}
+    }
+
+    let size = columns as usize * rows as usize;
+    let mut pixels = Pixels(Vec::with_capacity(size));
+    let mut context = hayro_ccitt::DecoderContext::new(settings);
+    // Scanners often write a few bad codes at the end; keep the rows before them
+    if let Err(e) = hayro_ccitt::decode(&stream.content, &mut pixels, &mut context) {
+        if pixels.0.is_empty() {
+            anyhow::bail!("CCITT decode failed: {}", e);
+        }
+    }
+    pixels.0.resize(size, 255);
+    GrayImage::from_raw(columns, rows, pixels.0).context("CCITT image size mismatch")
+}
+
+/// A `JBIG2Decode` stream (the embedded organisation of Annex D.3, with the
+/// symbols shared between pages in its `JBIG2Globals` stream) as a
+/// black-and-white image
+pub(crate) fn decode_jbig2(stream: &Stream, doc: &Document) -> Result<GrayImage> {
+    let globals = decode_parms(stream, doc)
+        .and_then(|parms| parms.get(b"JBIG2Globals").ok())
+        .and_then(|globals| match globals {
+            Object::Reference(id) => doc.get_object(*id).ok()?.as_stream().ok(),
+            Object::Stream(globals) => Some(globals),
+            _ => None,
+        })
+        .map(|globals| globals.decompressed_content().unwrap_or_else(|_| globals.content.clone()));
+
+    struct Pixels(Vec<u8>);
+    impl hayro_jbig2::Decoder for Pixels {
+        fn push_pixel(&mut self, black: bool) {
+            self.0.push(if black { 0 } else { 255 });
+        }
+        fn push_pixel_chunk(&mut self, black: bool, chunk_count: u32) {
+            let luma = if black { 0 } else { 255 };
+            self.0.extend(std::iter::repeat_n(luma, chunk_count as usize * 8));
+        }
+        fn next_line(&mut self) {}
+    }
+
+    let img = hayro_jbig2::Image::new_embedded(&stream.content, globals.as_deref())
+        .map_err(|e| anyhow::anyhow!("JBIG2 parse failed: {:?}", e))?;
+    let (width, height) = (img.width(), img.height());
+    let mut pixels = Pixels(Vec::with_capacity(width as usize * height as usize));
+    img.decode(&mut pixels).map_err(|e| anyhow::anyhow!("JBIG2 decode failed: {:?}", e))?;
+    pixels.0.truncate(width as usize * height as usize);
+    GrayImage::from_raw(width, height, pixels.0).context("JBIG2 image size mismatch")
+}
+
+/// A resolved image colour space
+enum ColorSpace {
+    Gray,
+    /// With the profile of an `ICCBased` space
+    Rgb(Option<Vec<u8>>),
+    Cmyk(Option<Vec<u8>>),
+    /// Palette entries of `base` colours, one byte per component
+    Indexed { base: Box<ColorSpace>, palette: Vec<u8> },
+}
+
+impl ColorSpace {
+    /// Resolve a `ColorSpace` entry; `None` for spaces comics do not use
+    /// (Lab, Separation, DeviceN, patterns)
+    fn from_object(object: &Object, doc: &Document) -> Option<ColorSpace> {
+        match object {
+            Object::Reference(id) => ColorSpace::from_object(doc.get_object(*id).ok()?, doc),
+            Object::Name(name) => match name.as_slice() {
+                b"DeviceGray" | b"G" | b"CalGray" => Some(ColorSpace::Gray),
+                b"DeviceRGB" | b"RGB" | b"CalRGB" => Some(ColorSpace::Rgb(None)),
+                b"DeviceCMYK" | b"CMYK" => Some(ColorSpace::Cmyk(None)),
+                _ => None,
+            },
+            Object::Array(array) => {
+                let family = resolve(array.first()?, doc).as_name().ok()?;
+                match family {
+                    b"CalGray" => Some(ColorSpace::Gray),
+                    b"CalRGB" => Some(ColorSpace::Rgb(None)),
+                    b"ICCBased" => {
+                        let profile = resolve(array.get(1)?, doc).as_stream().ok()?;
+                        let data = profile.decompressed_content().unwrap_or_else(|_| profile.content.clone());
+                        match profile.dict.get(b"N").ok()?.as_i64().ok()? {
+                            1 => Some(ColorSpace::Gray),
+                            3 => Some(ColorSpace::Rgb(Some(data))),
+                            4 => Some(ColorSpace::Cmyk(Some(data))),
+                            _ => None,
+                        }
+                    }
+                    b"Indexed" | b"I" => {
+                        let base = ColorSpace::from_object(array.get(1)?, doc)?;
+                        let entries = usize::try_from(resolve(array.get(2)?, doc).as_i64().ok()?).ok()?.min(255) + 1;
+                        let mut palette = match resolve(array.get(3)?, doc) {
+                            Object::String(bytes, _) => bytes.clone(),
+                            Object::Stream(lookup) => lookup.decompressed_content().unwrap_or_else(|_| lookup.content.clone()),
+                            _ => return None,
+                        };
+                        // Short tables (seen in the wild) read as black
+                        palette.resize(entries * base.components(), 0);
+                        Some(ColorSpace::Indexed { base: Box::new(base), palette })
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn components(&self) -> usize {
+        match self {
+            ColorSpace::Gray | ColorSpace::Indexed { .. } => 1,
+            ColorSpace::Rgb(_) => 3,
+            ColorSpace::Cmyk(_) => 4,
+        }
+    }
+
+    /// 8-bit samples of this space as an image, converted to sRGB
+    fn to_image(&self, samples: Vec<u8>, width: u32, height: u32) -> Option<DynamicImage> {
+        match self {
+            ColorSpace::Gray => GrayImage::from_raw(width, height, samples).map(DynamicImage::ImageLuma8),
+            ColorSpace::Rgb(profile) => {
+                let img = DynamicImage::ImageRgb8(RgbImage::from_raw(width, height, samples)?);
+                Some(match profile {
+                    Some(profile) => icc::to_srgb(img, profile),
+                    None => img,
+                })
+            }
+            ColorSpace::Cmyk(profile) => {
+                RgbImage::from_raw(width, height, cmyk::to_rgb(&samples, profile.as_deref())).map(DynamicImage::ImageRgb8)
+            }
+            ColorSpace::Indexed { base, palette } => {
+                let components = base.components();
+                let entries = palette.len() / components;
+                let colors = samples
+                    .iter()
+                    .flat_map(|&index| {
+                        let entry = usize::from(index).min(entries - 1) * components;
+                        &palette[entry..entry + components]
+                    })
+                    .copied()
+                    .collect();
+                base.to_image(colors, width, height)
+            }
+        }
+    }
+}
+
+fn resolve<'a>(object: &'a Object, doc: &'a Document) -> &'a Object {
+    match object {
+        Object::Reference(id) => doc.get_object(*id).unwrap_or(object),
+        _ => object,
+    }
+}
+
+/// The decoded `data` of a Flate-compressed or uncompressed image stream as
+/// an sRGB (or gray) image; `None` for colour spaces it cannot show
+pub(crate) fn decode_samples(stream: &Stream, doc: &Document, data: &[u8]) -> Result<Option<DynamicImage>> {
+    let number = |key: &[u8], default: i64| u32::try_from(stream.dict.get(key).and_then(Object::as_i64).unwrap_or(default)).unwrap_or(0);
+    let (width, height, bits) = (number(b"Width", 0), number(b"Height", 0), number(b"BitsPerComponent", 8));
+    let image_mask = stream.dict.get(b"ImageMask").and_then(Object::as_bool).unwrap_or(false);
+    // A stencil mask paints (black, on a white page) where its bit is 0
+    let space = if image_mask {
+        ColorSpace::Gray
+    } else {
+        match stream.dict.get(b"ColorSpace").ok().and_then(|space| ColorSpace::from_object(space, doc)) {
+            Some(space) => space,
+            None => return Ok(None),
+        }
+    };
+    let bits = if image_mask { 1 } else { bits };
+    if !matches!(bits, 1 | 2 | 4 | 8 | 16) {
+        anyhow::bail!("Unsupported {} bits per component", bits);
+    }
+
+    let data = unpredict(stream, doc, data)?;
+    let values_per_row = width as usize * space.components();
+    let row_len = (values_per_row * bits as usize).div_ceil(8);
+    if width == 0 || height == 0 || data.len() < row_len * height as usize {
+        anyhow::bail!("Image data size mismatch");
+    }
+    let max = (1u32 << bits) - 1;
+    let indexed = matches!(space, ColorSpace::Indexed { .. });
+    let mut samples = Vec::with_capacity(values_per_row * height as usize);
+    for row in data.chunks_exact(row_len).take(height as usize) {
+        samples.extend((0..values_per_row).map(|i| {
+            let value = match bits {
+                16 => u32::from(row[i * 2]),
+                8 => u32::from(row[i]),
+                _ => {
+                    let bit = i * bits as usize;
+                    u32::from(row[bit / 8] >> (8 - bits as usize - bit % 8)) & max
+                }
+            };
+            // Palette indices stay as they are; levels are stretched to 8 bits
+            match bits {
+                16 | 8 => value as u8,
+                _ if indexed => value as u8,
+                _ => (value * 255 / max) as u8,
+            }
+        }));
+    }
+    if let Some(tables) = decode_tables(stream, doc, &space, bits) {
+        for (i, sample) in samples.iter_mut().enumerate() {
+            *sample = tables[i % tables.len()][usize::from(*sample)];
+        }
+    }
+    Ok(space.to_image(samples, width, height))
+}
+
+/// The data with the predictor its `DecodeParms` name undone: PNG
+/// predictors (10 to 15) start every row with the filter it was stored
+/// with, the TIFF predictor (2) stores each sample as the difference to the
+/// one to its left
+fn unpredict<'a>(stream: &Stream, doc: &Document, data: &'a [u8]) -> Result<Cow<'a, [u8]>> {
+    let Some(parms) = decode_parms(stream, doc) else { return Ok(Cow::Borrowed(data)) };
+    let number = |key: &[u8], default: i64| {
+        let value = parms.get(key).map(|value| resolve(value, doc)).and_then(Object::as_i64).unwrap_or(default);
+        usize::try_from(value).unwrap_or(0)
+    };
+    let (colors, bits, columns) = (number(b"Colors", 1), number(b"BitsPerComponent", 8), number(b"Columns", 1));
+    let pixel_len = (colors * bits).div_ceil(8).max(1);
+    let row_len = (colors * bits * columns).div_ceil(8);
+    match number(b"Predictor", 1) {
+        1 => Ok(Cow::Borrowed(data)),
+        2 => {
+            let mut samples = data.to_vec();
+            for row in samples.chunks_mut(row_len.max(1)) {
+                match bits {
+                    8 => {
+                        for i in colors..row.len() {
+                            row[i] = row[i].wrapping_add(row[i - colors]);
+                        }
+                    }
+                    16 => {
+                        for i in colors..row.len() / 2 {
+                            let left = u16::from_be_bytes([row[(i - colors) * 2], row[(i - colors) * 2 + 1]]);
+                            let value = u16::from_be_bytes([row[i * 2], row[i * 2 + 1]]).wrapping_add(left);
+                            row[i * 2..i * 2 + 2].copy_from_slice(&value.to_be_bytes());
+                        }
+                    }
+                    _ => anyhow::bail!("Unsupported TIFF predictor at {} bits per component", bits),
+                }
+            }
+            Ok(Cow::Owned(samples))
+        }
+        10..=15 => {
+            let mut samples = Vec::with_capacity(data.len() / (row_len + 1) * row_len);
+            let mut previous = vec![0u8; row_len];
+            for stored in data.chunks(row_len + 1) {
+                let (&filter, stored) = stored.split_first().context("Empty predicted row")?;
+                // A short last row is padded with zeros
+                let mut row = stored.to_vec();
+                row.resize(row_len, 0);
+                for i in 0..row_len {
+                    let left = if i >= pixel_len { row[i - pixel_len] } else { 0 };
+                    let up = previous[i];
+                    let upper_left = if i >= pixel_len { previous[i - pixel_len] } else { 0 };
+                    let prediction = match filter {
+                        0 => 0,
+                        1 => left,
+                        2 => up,
+                        3 => ((u16::from(left) + u16::from(up)) / 2) as u8,
+                        4 => paeth(left, up, upper_left),
+                        _ => anyhow::bail!("Unknown PNG predictor row filter {}", filter),
+                    };
+                    row[i] = row[i].wrapping_add(prediction);
+                }
+                samples.extend_from_slice(&row);
+                previous = row;
+            }
+            Ok(Cow::Owned(samples))
+        }
+        predictor => anyhow::bail!("Unsupported predictor {}", predictor),
+    }
+}
+
+/// The neighbour closest to `left + up - upper_left`, as PNG's Paeth filter picks
+fn paeth(left: u8, up: u8, upper_left: u8) -> u8 {
+    let (a, b, c) = (i16::from(left), i16::from(up), i16::from(upper_left));
+    let estimate = a + b - c;
+    let (to_left, to_up, to_upper_left) = ((estimate - a).abs(), (estimate - b).abs(), (estimate - c).abs());
+    if to_left <= to_up && to_left <= to_upper_left {
+        left
+    } else if to_up <= to_upper_left {
+        up
+    } else {
+        upper_left
+    }
+}
+
+/// Per-component tables taking the 8-bit samples (palette indices for
+/// `Indexed`) through the image's `Decode` array, which maps the lowest and
+/// highest stored value to its pairs of bounds; `None` without one, or with
+/// the default
+fn decode_tables(stream: &Stream, doc: &Document, space: &ColorSpace, bits: u32) -> Option<Vec<[u8; 256]>> {
+    let decode = resolve(stream.dict.get(b"Decode").ok()?, doc).as_array().ok()?;
+    let bounds: Vec<f32> = decode.iter().map(|bound| resolve(bound, doc).as_float().ok()).collect::<Option<_>>()?;
+    let components = space.components();
+    if bounds.len() < components * 2 {
+        return None;
+    }
+    let indexed = matches!(space, ColorSpace::Indexed { .. });
+    // Indices run up to the highest stored value, levels were stretched to 255
+    let max = if indexed { ((1u32 << bits.min(8)) - 1) as f32 } else { 255.0 };
+    let scale = if indexed { 1.0 } else { 255.0 };
+    if bounds.chunks_exact(2).take(components).all(|pair| pair[0] == 0.0 && pair[1] * scale == max) {
+        return None;
+    }
+    let tables = bounds
+        .chunks_exact(2)
+        .take(components)
+        .map(|pair| {
+            std::array::from_fn(|value| {
+                let decoded = pair[0] + value as f32 * (pair[1] - pair[0]) / max;
+                (decoded * scale).round().clamp(0.0, 255.0) as u8
+            })
+        })
+        .collect();
+    Some(tables)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    /// The decoded pixels of the only image in `tests/fixtures/<name>`
+    fn fixture_image(name: &str) -> RgbImage {
+        let doc = Document::load(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)).unwrap();
+        let stream = doc
+            .objects
+            .values()
+            .filter_map(|object| object.as_stream().ok())
+            .find(|stream| stream.dict.get(b"Subtype").and_then(Object::as_name).ok() == Some(b"Image"))
+            .unwrap();
+        decode_samples(stream, &doc, &stream.decompressed_content().unwrap()).unwrap().unwrap().into_rgb8()
+    }
+
+    /// The colour of each quarter of the image, from top to bottom
+    fn quarters(img: &RgbImage) -> Vec<[u8; 3]> {
+        (0..4).map(|quarter| img.get_pixel(0, quarter * img.height() / 4).0).collect()
+    }
+
+    fn assert_close(actual: [u8; 3], expected: [u8; 3]) {
+        let close = actual.iter().zip(expected).all(|(&a, e)| a.abs_diff(e) <= 2);
+        assert!(close, "{:?} is not close to {:?}", actual, expected);
+    }
+
+    #[test]
+    fn indexed_images_use_their_palette() {
+        // Entries 1, 5, 10 and 15 of a palette of [16 i, 255 - 16 i, 37 i mod 256]
+        let img = fixture_image("indexed.pdf");
+        assert_eq!(quarters(&img), [[16, 239, 37], [80, 175, 185], [160, 95, 114], [240, 15, 43]]);
+    }
+
+    #[test]
+    fn icc_based_images_are_converted_to_srgb() {
+        // Adobe RGB primaries clip to the sRGB ones; the mid blue moves
+        let img = fixture_image("iccbased.pdf");
+        let expected = [[255, 0, 0], [0, 255, 0], [0, 0, 255], [98, 151, 203]];
+        for (actual, expected) in quarters(&img).into_iter().zip(expected) {
+            assert_close(actual, expected);
+        }
+    }
+}
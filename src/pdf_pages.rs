@@ -0,0 +1,278 @@
+//! The images a PDF page draws, in the order it draws them. The extractor
+//! took the image XObjects listed in a page's own resources, sorted by name,
+//! so it missed images placed through Form XObjects, inline images and
+//! resources inherited from the page tree, and layered them in name order
+//! rather than paint order. Here the page's content stream is followed
+//! instead: every `Do` of an image, also inside forms, and every inline
+//! image (`BI`…`EI`), each image once per page.
+
+use lopdf::content::Content;
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream};
+
+/// Forms nested deeper than this are not followed (and cycles end here)
+const MAX_FORM_DEPTH: usize = 8;
+
+/// Page tree levels searched for inherited resources
+const MAX_TREE_DEPTH: usize = 32;
+
+/// An image drawn on a page
+pub(crate) enum PageImage {
+    /// An image XObject, possibly shared with other pages
+    Object(ObjectId),
+    /// An inline image, with its abbreviated keys and names spelled out
+    Inline(Stream),
+}
+
+/// The images drawn on the page, bottom first; when the content stream
+/// cannot be parsed, the image XObjects of its resources by name
+pub(crate) fn page_images(doc: &Document, page_id: ObjectId) -> Vec<PageImage> {
+    let resources = page_resources(doc, page_id);
+    let mut images = Vec::new();
+    match doc.get_page_content(page_id).ok().and_then(|data| parse(doc, &data, &resources)) {
+        Some((content, inline)) => walk(doc, &content, &inline, &resources, 0, &mut images),
+        None => images = listed_images(doc, &resources),
+    }
+    images
+}
+
+/// The page's resource dictionaries, its own first, then those inherited
+/// from the page tree
+fn page_resources(doc: &Document, page_id: ObjectId) -> Vec<&Dictionary> {
+    let mut resources = Vec::new();
+    let mut node = doc.get_dictionary(page_id).ok();
+    for _ in 0..MAX_TREE_DEPTH {
+        let Some(dict) = node else { break };
+        resources.extend(dictionary(doc, dict.get(b"Resources").ok()));
+        node = dict.get(b"Parent").and_then(Object::as_reference).and_then(|id| doc.get_dictionary(id)).ok();
+    }
+    resources
+}
+
+/// A dictionary given directly or by reference
+fn dictionary<'a>(doc: &'a Document, object: Option<&'a Object>) -> Option<&'a Dictionary> {
+    match object? {
+        Object::Dictionary(dict) => Some(dict),
+        Object::Reference(id) => doc.get_dictionary(*id).ok(),
+        _ => None,
+    }
+}
+
+/// The `XObject` dictionaries of `resources`, following references
+fn xobjects<'a>(doc: &'a Document, resources: &[&'a Dictionary]) -> Vec<&'a Dictionary> {
+    resources
+        .iter()
+        .filter_map(|resources| dictionary(doc, resources.get(b"XObject").ok()))
+        .collect()
+}
+
+/// Collect the images the content draws, entering forms
+fn walk(doc: &Document, content: &Content, inline: &[Stream], resources: &[&Dictionary], depth: usize, images: &mut Vec<PageImage>) {
+    for operation in &content.operations {
+        let (Some(Object::Name(name)), "Do") = (operation.operands.first(), operation.operator.as_str()) else { continue };
+        let Some(id) = xobjects(doc, resources)
+            .into_iter()
+            .find_map(|xobjects| xobjects.get(name).and_then(Object::as_reference).ok())
+        else {
+            let index = name.strip_prefix(INLINE_NAME).and_then(|index| std::str::from_utf8(index).ok()?.parse::<usize>().ok());
+            if let Some(stream) = index.and_then(|index| inline.get(index)) {
+                images.push(PageImage::Inline(stream.clone()));
+            }
+            continue;
+        };
+        let Ok(stream) = doc.get_object(id).and_then(Object::as_stream) else { continue };
+        match stream.dict.get(b"Subtype").and_then(Object::as_name) {
+            // Painted once more on the same page: still one image
+            Ok(b"Image") if !images.iter().any(|image| matches!(image, PageImage::Object(seen) if *seen == id)) => {
+                images.push(PageImage::Object(id));
+            }
+            Ok(b"Form") if depth < MAX_FORM_DEPTH => {
+                // A form without resources of its own uses the page's
+                let own = dictionary(doc, stream.dict.get(b"Resources").ok());
+                let form_resources: Vec<&Dictionary> = own.into_iter().chain(resources.iter().copied()).collect();
+                let data = stream.decompressed_content().unwrap_or_else(|_| stream.content.clone());
+                if let Some((form, form_inline)) = parse(doc, &data, &form_resources) {
+                    walk(doc, &form, &form_inline, &form_resources, depth + 1, images);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Content with its inline images taken out, each replaced by a `Do` of
+/// `INLINE_NAME` and its index: lopdf's parser keeps only unfiltered inline
+/// images in a few colour spaces and drops the rest
+fn parse(doc: &Document, data: &[u8], resources: &[&Dictionary]) -> Option<(Content, Vec<Stream>)> {
+    let mut content = Vec::with_capacity(data.len());
+    let mut inline = Vec::new();
+    let mut at = 0;
+    while let Some(begin) = find_operator(data, at, b"BI") {
+        let Some(id) = find_operator(data, begin + 2, b"ID") else { break };
+        let dict = match Content::decode(&[b"<<", &data[begin + 2..id], b">> x"].concat()).ok()?.operations.first()?.operands.first()? {
+            Object::Dictionary(dict) => expand_inline(doc, dict, resources),
+            _ => return None,
+        };
+        // One white-space character separates `ID` from the data
+        let start = (id + 3).min(data.len());
+        let (end, resume) = inline_end(data, start, &dict)?;
+        content.extend_from_slice(&data[at..begin]);
+        content.extend_from_slice(format!(" /{}{} Do ", String::from_utf8_lossy(INLINE_NAME), inline.len()).as_bytes());
+        inline.push(Stream::new(dict, data[start..end].to_vec()));
+        at = resume;
+    }
+    content.extend_from_slice(&data[at..]);
+    Some((Content::decode(&content).ok()?, inline))
+}
+
+/// Names standing for the inline images taken out of a content stream
+const INLINE_NAME: &[u8] = b"compress_comics.inline.";
+
+/// The next `operator` from `from` on that stands as a token of its own
+fn find_operator(data: &[u8], from: usize, operator: &[u8]) -> Option<usize> {
+    let is_delimiter = |byte: u8| byte.is_ascii_whitespace() || b"[]<>(){}/%".contains(&byte);
+    (from..data.len().saturating_sub(operator.len() - 1)).find(|&at| {
+        data[at..].starts_with(operator)
+            && (at == 0 || (is_delimiter(data[at - 1]) && data[at - 1] != b'/'))
+            && data.get(at + operator.len()).is_none_or(|&next| is_delimiter(next))
+    })
+}
+
+/// Where the inline image data from `start` ends, and where the content
+/// resumes after its `EI`: unfiltered data has a known length, otherwise
+/// the data runs up to the first `EI` between white space
+fn inline_end(data: &[u8], start: usize, dict: &Dictionary) -> Option<(usize, usize)> {
+    let ei_at = |at: usize| {
+        let after = at + data[at..].iter().take_while(|byte| byte.is_ascii_whitespace()).count();
+        (data[after..].starts_with(b"EI") && data.get(after + 2).is_none_or(u8::is_ascii_whitespace)).then_some(after + 2)
+    };
+    if let Some(end) = unfiltered_length(dict).map(|length| start + length).filter(|&end| end <= data.len()) {
+        if let Some(resume) = ei_at(end) {
+            return Some((end, resume));
+        }
+    }
+    let at = (start..data.len().saturating_sub(2)).find(|&at| {
+        data[at].is_ascii_whitespace() && data[at + 1..].starts_with(b"EI") && data.get(at + 3).is_none_or(u8::is_ascii_whitespace)
+    })?;
+    Some((at, at + 3))
+}
+
+/// The byte length of an unfiltered inline image, when its colour space
+/// tells the number of components
+fn unfiltered_length(dict: &Dictionary) -> Option<usize> {
+    if dict.has(b"Filter") {
+        return None;
+    }
+    let number = |key: &[u8]| dict.get(key).and_then(Object::as_i64).ok().and_then(|value| usize::try_from(value).ok());
+    // Stencil masks are one bit deep
+    let (components, bits) = if dict.get(b"ImageMask").and_then(Object::as_bool).unwrap_or(false) {
+        (1, 1)
+    } else {
+        let components = match dict.get(b"ColorSpace").ok()? {
+            Object::Name(name) if name == b"DeviceGray" => 1,
+            Object::Name(name) if name == b"DeviceRGB" => 3,
+            Object::Name(name) if name == b"DeviceCMYK" => 4,
+            Object::Array(array) if array.first().and_then(|name| name.as_name().ok()) == Some(b"Indexed") => 1,
+            _ => return None,
+        };
+        (components, number(b"BitsPerComponent")?)
+    };
+    Some(number(b"Height")? * (number(b"Width")? * components * bits).div_ceil(8))
+}
+
+/// The image XObjects of `resources` in name order, leaving out those that
+/// are the soft masks of others
+fn listed_images(doc: &Document, resources: &[&Dictionary]) -> Vec<PageImage> {
+    let mut named: Vec<(&[u8], ObjectId)> = Vec::new();
+    for xobjects in xobjects(doc, resources) {
+        for (name, object) in xobjects.iter() {
+            let Ok(id) = object.as_reference() else { continue };
+            let is_image = doc
+                .get_object(id)
+                .and_then(Object::as_stream)
+                .is_ok_and(|stream| stream.dict.get(b"Subtype").and_then(Object::as_name).is_ok_and(|subtype| subtype == b"Image"));
+            if is_image && !named.iter().any(|(_, seen)| *seen == id) {
+                named.push((name, id));
+            }
+        }
+    }
+    let masks: Vec<ObjectId> = named
+        .iter()
+        .filter_map(|(_, id)| doc.get_object(*id).and_then(Object::as_stream).ok())
+        .filter_map(|stream| stream.dict.get(b"SMask").and_then(Object::as_reference).ok())
+        .collect();
+    named.sort_by(|a, b| a.0.cmp(b.0));
+    named
+        .into_iter()
+        .filter(|(_, id)| !masks.contains(id))
+        .map(|(_, id)| PageImage::Object(id))
+        .collect()
+}
+
+/// An inline image's dictionary as an image XObject would state it, its
+/// abbreviations spelled out and a named colour space looked up
+fn expand_inline(doc: &Document, inline: &Dictionary, resources: &[&Dictionary]) -> Dictionary {
+    let mut dict = Dictionary::new();
+    dict.set("Subtype", Object::Name(b"Image".to_vec()));
+    for (key, value) in inline.iter() {
+        let key: &[u8] = match key.as_slice() {
+            b"BPC" => b"BitsPerComponent",
+            b"CS" => b"ColorSpace",
+            b"D" => b"Decode",
+            b"DP" => b"DecodeParms",
+            b"F" => b"Filter",
+            b"H" => b"Height",
+            b"IM" => b"ImageMask",
+            b"I" => b"Interpolate",
+            b"W" => b"Width",
+            key => key,
+        };
+        let value = match (key, value) {
+            (b"ColorSpace", Object::Name(name)) => color_space(doc, name, resources),
+            (b"ColorSpace", Object::Array(array)) => {
+                Object::Array(array.iter().map(|item| match item {
+                    Object::Name(name) => color_space(doc, name, resources),
+                    item => item.clone(),
+                }).collect())
+            }
+            (b"Filter", Object::Name(name)) => Object::Name(filter(name)),
+            (b"Filter", Object::Array(array)) => Object::Array(
+                array.iter().map(|item| item.as_name().map_or_else(|_| item.clone(), |name| Object::Name(filter(name)))).collect(),
+            ),
+            (_, value) => value.clone(),
+        };
+        dict.set(key, value);
+    }
+    dict
+}
+
+/// A colour space named in an inline image: an abbreviation, a device
+/// space, or one of the page's `ColorSpace` resources
+fn color_space(doc: &Document, name: &[u8], resources: &[&Dictionary]) -> Object {
+    let full: &[u8] = match name {
+        b"G" => b"DeviceGray",
+        b"RGB" => b"DeviceRGB",
+        b"CMYK" => b"DeviceCMYK",
+        b"I" => b"Indexed",
+        name => name,
+    };
+    resources
+        .iter()
+        .filter_map(|resources| dictionary(doc, resources.get(b"ColorSpace").ok()))
+        .find_map(|spaces| spaces.get(full).ok())
+        .cloned()
+        .unwrap_or_else(|| Object::Name(full.to_vec()))
+}
+
+/// A filter named in an inline image, spelled out
+fn filter(name: &[u8]) -> Vec<u8> {
+    match name {
+        b"AHx" => b"ASCIIHexDecode".to_vec(),
+        b"A85" => b"ASCII85Decode".to_vec(),
+        b"LZW" => b"LZWDecode".to_vec(),
+        b"Fl" => b"FlateDecode".to_vec(),
+        b"RL" => b"RunLengthDecode".to_vec(),
+        b"CCF" => b"CCITTFaxDecode".to_vec(),
+        b"DCT" => b"DCTDecode".to_vec(),
+        name => name.to_vec(),
+    }
+}
@@ -0,0 +1,88 @@
+//! Rasterised PDF pages: pages drawn as vector art, or built from images the
+//! extractor cannot decode, have no image to take out and were left out,
+//! down to "no images found" for whole PDFs. With `--pdf-render dpi=N` such
+//! pages are rendered at N dpi by Poppler's `pdftoppm` (or MuPDF's `mutool`)
+//! into lossless PNGs under the names extracted pages get.
+
+use anyhow::{Context, Result};
+use rayon::prelude::*;
+use std::path::Path;
+use std::process::Command;
+
+use crate::paths;
+
+/// Settings of `--pdf-render`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PdfRender {
+    /// Resolution to render at
+    pub dpi: u32,
+}
+
+/// Parse `dpi=N` (N from 36 to 1200)
+pub(crate) fn parse(value: &str) -> Result<PdfRender, String> {
+    let error = || format!("expected dpi=N with N from 36 to 1200, such as dpi=300, not '{}'", value);
+    let (key, dpi) = value.trim().split_once('=').ok_or_else(error)?;
+    if !key.trim().eq_ignore_ascii_case("dpi") {
+        return Err(error());
+    }
+    match dpi.trim().parse() {
+        Ok(dpi @ 36..=1200) => Ok(PdfRender { dpi }),
+        _ => Err(error()),
+    }
+}
+
+/// The external renderers, in order of preference
+#[derive(Debug, Clone, Copy)]
+enum Renderer {
+    Pdftoppm,
+    Mutool,
+}
+
+impl Renderer {
+    fn tool(self) -> &'static str {
+        match self {
+            Renderer::Pdftoppm => "pdftoppm",
+            Renderer::Mutool => "mutool",
+        }
+    }
+}
+
+/// The first renderer on PATH
+fn renderer() -> Result<Renderer> {
+    [Renderer::Pdftoppm, Renderer::Mutool]
+        .into_iter()
+        .find(|renderer| Command::new(renderer.tool()).arg("-v").output().is_ok())
+        .ok_or_else(|| anyhow::anyhow!("--pdf-render requires Poppler's `pdftoppm` or MuPDF's `mutool` to be installed and on PATH"))
+}
+
+/// Fail early when no renderer is installed
+pub(crate) fn check_available() -> Result<()> {
+    renderer().map(|_| ())
+}
+
+/// Render the 1-based `pages` of `pdf` to `page_NNNN.png` in `dir`
+pub(crate) fn render_pages(pdf: &Path, pages: &[usize], settings: PdfRender, dir: &Path) -> Result<()> {
+    let renderer = renderer()?;
+    let pdf = paths::long_path(pdf);
+    pages.par_iter().try_for_each(|&page| -> Result<()> {
+        let output = dir.join(format!("page_{:04}.png", page));
+        let mut command = Command::new(renderer.tool());
+        match renderer {
+            // `-singlefile` writes PREFIX.png instead of PREFIX-N.png
+            Renderer::Pdftoppm => command
+                .args(["-png", "-singlefile", "-r", &settings.dpi.to_string(), "-f", &page.to_string(), "-l", &page.to_string()])
+                .arg(pdf.as_ref())
+                .arg(paths::long_path(&output.with_extension("")).as_ref()),
+            Renderer::Mutool => command
+                .args(["draw", "-q", "-r", &settings.dpi.to_string(), "-o"])
+                .arg(paths::long_path(&output).as_ref())
+                .arg(pdf.as_ref())
+                .arg(page.to_string()),
+        };
+        let result = command.output().with_context(|| format!("Failed to run {}", renderer.tool()))?;
+        if !result.status.success() || !output.exists() {
+            anyhow::bail!("Rendering PDF page {} failed: {}", page, String::from_utf8_lossy(&result.stderr).trim());
+        }
+        Ok(())
+    })
+}
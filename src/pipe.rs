@@ -0,0 +1,129 @@
+//! `--stdin` (or INPUT `-`) and `--stdout`: pipe one comic through the tool,
+//! e.g. `cat x.cbz | compress_comics - --stdout > y.cbz`. The extractors need
+//! seekable files (ZIP central directories, `unrar`, PDF cross-references),
+//! so the comic read from standard input is spooled into the temporary
+//! directory first, like remote sources are, and the output is written to a
+//! temporary folder and then copied to standard output. A source kept as it
+//! was (savings below `--min-savings`) is copied through unchanged, so the
+//! pipe always carries a comic.
+
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::PathBuf;
+use tempfile::TempDir;
+
+use crate::{temp, Options, Report};
+
+/// Leading bytes read to tell the comic type, enough for a tar header
+const SNIFF_LEN: usize = 512;
+
+/// Temporary files of a piped run, removed when it ends
+pub(crate) struct Piping {
+    _spool: Option<TempDir>,
+    output: Option<TempDir>,
+    source: PathBuf,
+}
+
+impl Piping {
+    /// Spool standard input into a file and point INPUT at it (`--stdin`), and
+    /// send outputs to a temporary folder (`--stdout`)
+    pub(crate) fn prepare(args: &mut Options) -> Result<Option<Piping>> {
+        if !args.stdin && !args.stdout {
+            return Ok(None);
+        }
+        let mut spool = None;
+        if args.stdin {
+            let stdin = io::stdin();
+            if stdin.is_terminal() {
+                anyhow::bail!("--stdin reads a comic piped into compress_comics, e.g. cat comic.cbz | compress_comics - --stdout");
+            }
+            let dir = temp::create_dir_in(args.temp_dir.as_deref())?;
+            let path = spool_stdin(&mut stdin.lock(), &dir, args.stdin_name.as_deref())?;
+            args.input = vec![path];
+            spool = Some(dir);
+            // Next to the spooled source, the output would be removed with it
+            if !args.stdout && args.output_dir.is_none() {
+                args.output_dir = Some(PathBuf::from("."));
+            }
+        }
+
+        let source = match args.input.as_slice() {
+            [source] => source.clone(),
+            _ => PathBuf::new(),
+        };
+        let mut output = None;
+        if args.stdout {
+            if !source.is_file() {
+                anyhow::bail!("--stdout writes one comic; give a single input file or pipe one in with --stdin");
+            }
+            let dir = temp::create_dir_in(args.temp_dir.as_deref())?;
+            args.output_dir = Some(dir.path().to_path_buf());
+            output = Some(dir);
+        }
+        Ok(Some(Piping { _spool: spool, output, source }))
+    }
+
+    /// With `--stdout`, copy the output of the piped comic to standard output,
+    /// or the source itself when it was kept. Nothing is written for a failed file
+    pub(crate) fn deliver(&self, stats: &HashMap<PathBuf, Report>) -> Result<()> {
+        if self.output.is_none() {
+            return Ok(());
+        }
+        let Some(report) = stats.get(&self.source).filter(|report| report.error_message.is_none()) else {
+            return Ok(());
+        };
+        let path = report.output_path.as_ref().filter(|output| output.is_file()).unwrap_or(&self.source);
+        let mut file = File::open(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let mut stdout = io::stdout().lock();
+        io::copy(&mut file, &mut stdout).context("Failed to write to standard output")?;
+        stdout.flush().context("Failed to write to standard output")
+    }
+}
+
+/// Write `input` into `dir` under `name`, or under `stdin.<ext>` with the
+/// extension of the type its first bytes show
+fn spool_stdin(input: &mut impl Read, dir: &TempDir, name: Option<&str>) -> Result<PathBuf> {
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    input.take(SNIFF_LEN as u64).read_to_end(&mut head).context("Failed to read standard input")?;
+    if head.is_empty() {
+        anyhow::bail!("Standard input is empty");
+    }
+
+    let name = match name {
+        // Only the file name; the comic is always spooled into the temporary folder
+        Some(name) => PathBuf::from(name).file_name().context("--stdin-name must be a file name")?.to_os_string(),
+        None => {
+            let extension = sniff_extension(&head)
+                .context("Could not tell the type of the comic on standard input; name it with --stdin-name, e.g. comic.cbz")?;
+            format!("stdin.{}", extension).into()
+        }
+    };
+    let path = dir.path().join(name);
+    let mut file = File::create(&path).with_context(|| format!("Failed to create {}", path.display()))?;
+    file.write_all(&head)?;
+    io::copy(input, &mut file).context("Failed to read standard input")?;
+    Ok(path)
+}
+
+/// The comic extension for a file starting with `head`
+fn sniff_extension(head: &[u8]) -> Option<&'static str> {
+    if head.starts_with(b"PK\x03\x04") {
+        // EPUBs store an uncompressed `mimetype` entry first
+        let epub = head.get(30..38) == Some(b"mimetype") && head.windows(20).any(|window| window == b"application/epub+zip");
+        Some(if epub { "epub" } else { "cbz" })
+    } else if head.starts_with(b"Rar!\x1a\x07") {
+        Some("cbr")
+    } else if head.starts_with(b"7z\xbc\xaf\x27\x1c") {
+        Some("cb7")
+    } else if head.starts_with(b"%PDF") {
+        Some("pdf")
+    } else if head.starts_with(b"AT&TFORM") {
+        Some("djvu")
+    } else if head.get(257..262) == Some(b"ustar") {
+        Some("cbt")
+    } else {
+        None
+    }
+}
@@ -0,0 +1,217 @@
+//! Embeddable entry point: a [`Pipeline`] processes one comic file at a time
+//! with fixed [`Options`], reporting page outcomes as they happen.
+
+use anyhow::{Context, Result};
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use indicatif::ProgressBar;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+
+use crate::progress::FileShare;
+use crate::timeout::{self, Watch};
+use crate::{checksums, contact_sheet, interrupt, state, throttle, times};
+use crate::{check_supported, detect_comic_file, process_comic_file, ComicFile, Options, PageOutcome, Report};
+
+/// Processes comic files with one set of validated options
+#[derive(Clone)]
+pub struct Pipeline {
+    options: Options,
+    input_root: Option<PathBuf>,
+}
+
+/// A page finished processing
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PageEvent {
+    /// Page path inside the archive (or extracted publication), `/`-separated
+    pub page: String,
+    pub outcome: PageOutcome,
+    /// Source page size in bytes
+    pub original_size: u64,
+    /// Bytes written for the page (all parts of a sliced page; the source size when kept or failed)
+    pub output_size: u64,
+}
+
+/// Page events of a file processed on a background thread; iteration ends
+/// when the file is done, after which [`PageEvents::finish`] returns its report
+pub struct PageEvents {
+    receiver: Receiver<PageEvent>,
+    worker: thread::JoinHandle<Result<Report>>,
+}
+
+impl Iterator for PageEvents {
+    type Item = PageEvent;
+
+    fn next(&mut self) -> Option<PageEvent> {
+        self.receiver.recv().ok()
+    }
+}
+
+impl PageEvents {
+    /// Wait for the file to finish and return its report; unread events are dropped
+    pub fn finish(self) -> Result<Report> {
+        self.worker
+            .join()
+            .unwrap_or_else(|_| Err(anyhow::anyhow!("Processing thread panicked")))
+    }
+}
+
+/// Progress of one file: drives its progress bar (0-100), its share of the
+/// run's byte-weighted bar, and forwards page events
+#[derive(Clone)]
+pub(crate) struct FileProgress {
+    bar: ProgressBar,
+    events: Option<Sender<PageEvent>>,
+    share: Option<Arc<FileShare>>,
+    watch: Arc<Watch>,
+}
+
+impl FileProgress {
+    pub(crate) fn new(bar: ProgressBar) -> Self {
+        FileProgress { bar, events: None, share: None, watch: Arc::default() }
+    }
+
+    /// Also send an event for every finished page to `events`
+    pub(crate) fn with_events(mut self, events: Sender<PageEvent>) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Also advance the file's `share` of the overall bar
+    pub(crate) fn with_share(mut self, share: Arc<FileShare>) -> Self {
+        self.share = Some(share);
+        self
+    }
+
+    pub(crate) fn set_position(&self, position: u64) {
+        self.watch.touch();
+        self.bar.set_position(position);
+        if let Some(share) = &self.share {
+            share.advance(position);
+        }
+    }
+
+    pub(crate) fn page(&self, event: PageEvent) {
+        self.watch.touch();
+        if let Some(events) = &self.events {
+            // The receiver may be gone when the caller stopped listening
+            let _ = events.send(event);
+        }
+    }
+
+    pub(crate) fn watch(&self) -> &Watch {
+        &self.watch
+    }
+
+    /// Fail once the run is interrupted or the file timed out
+    pub(crate) fn check(&self) -> Result<()> {
+        interrupt::check()?;
+        self.watch.check()
+    }
+
+    /// Fail once the run is interrupted or the file timed out, and otherwise
+    /// keep the file from timing out while its output is put in place
+    pub(crate) fn commit(&self) -> Result<()> {
+        interrupt::check()?;
+        self.watch.commit()
+    }
+
+    /// Whether no more pages should start: the run is interrupted or the file timed out
+    pub(crate) fn stopped(&self) -> bool {
+        interrupt::requested() || self.watch.cancelled()
+    }
+}
+
+impl Pipeline {
+    /// Validate `options` and build a pipeline. Options that select files
+    /// (input, globs, include/exclude, resume) are ignored: callers pass each file
+    pub fn new(options: Options) -> Result<Self> {
+        options.validate()?;
+        throttle::configure(options.io_throttle);
+        Ok(Pipeline { options, input_root: None })
+    }
+
+    /// Directory whose tree `--output-dir` mirrors; by default each file's own
+    /// directory, so outputs land directly in `--output-dir`
+    pub fn with_input_root(mut self, input_root: impl Into<PathBuf>) -> Self {
+        self.input_root = Some(input_root.into());
+        self
+    }
+
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    /// Process one comic file, blocking until its output is written
+    pub fn process_file(&self, path: &Path) -> Result<Report> {
+        let comic_file = detect_comic_file(path)?;
+        check_supported(&comic_file, &self.options)?;
+        self.process(&comic_file, &FileProgress::new(ProgressBar::hidden()))
+    }
+
+    /// Process one comic file on a background thread, yielding page events as pages finish
+    pub fn process_file_events(&self, path: &Path) -> PageEvents {
+        let (sender, receiver) = unbounded();
+        let pipeline = self.clone();
+        let path = path.to_path_buf();
+        let worker = thread::spawn(move || {
+            let comic_file = detect_comic_file(&path)?;
+            check_supported(&comic_file, &pipeline.options)?;
+            let progress = FileProgress::new(ProgressBar::hidden()).with_events(sender);
+            pipeline.process(&comic_file, &progress)
+        });
+        PageEvents { receiver, worker }
+    }
+
+    pub(crate) fn process(&self, comic_file: &ComicFile, progress: &FileProgress) -> Result<Report> {
+        if self.options.timeout_per_file.is_some() || self.options.timeout_per_page.is_some() {
+            return timeout::supervise(self, comic_file, progress);
+        }
+        self.process_now(comic_file, progress)
+    }
+
+    /// Process a file on the calling thread, without time limits
+    pub(crate) fn process_now(&self, comic_file: &ComicFile, progress: &FileProgress) -> Result<Report> {
+        let input_root = match &self.input_root {
+            Some(root) => root.clone(),
+            None => comic_file.path.parent().map(Path::to_path_buf).unwrap_or_default(),
+        };
+        let run = || {
+            // Read first: --in-place, --rename-original and --trash-original move the source
+            let source = std::fs::metadata(&comic_file.path).ok().filter(|_| !self.options.no_preserve_times);
+            let source_sha256 = match self.options.checksums {
+                Some(_) if !self.options.dry_run => Some(state::sha256_file(&comic_file.path).context("Failed to hash source file")?),
+                _ => None,
+            };
+            let report = process_comic_file(comic_file, &self.options, &input_root, progress)?;
+            if let (Some(source), Some(output)) = (&source, &report.output_path) {
+                if let Err(e) = times::copy_file_times(source, output) {
+                    eprintln!("⚠️  Could not keep the timestamps of {}: {}", comic_file.path.display(), e);
+                }
+            }
+            if let (Some(mode), Some(sha256), Some(output)) = (self.options.checksums, source_sha256, &report.output_path) {
+                // Recorded after the timestamps, which the database keeps to tell bit-rot from edits
+                checksums::record(output, &comic_file.path, sha256, mode, self.options.checksum_db.as_deref())
+                    .with_context(|| format!("Failed to record the checksum of {}", output.display()))?;
+            }
+            if let (true, Some(output)) = (self.options.contact_sheet, &report.output_path) {
+                // The output is already in place, so a missing sheet does not fail the file
+                if let Err(e) = contact_sheet::write(output, &self.options) {
+                    eprintln!("⚠️  No contact sheet for {}: {:#}", output.display(), e);
+                }
+            }
+            Ok(report)
+        };
+        match self.options.page_parallelism {
+            Some(threads) => {
+                let pool = rayon::ThreadPoolBuilder::new()
+                    .num_threads(threads.get())
+                    .build()
+                    .context("Failed to start page workers")?;
+                pool.install(run)
+            }
+            None => run(),
+        }
+    }
+}
@@ -0,0 +1,124 @@
+//! Page processors from outside the crate, e.g. watermark removal, run as
+//! `--filters` steps. A `PageProcessor` gets the page as interleaved 8-bit
+//! pixels and edits them in place. Library users implement the trait and add
+//! it with `FilterChain::with_processor`; everyone else builds a dynamic
+//! library with the C interface below and names it in the chain as
+//! `plugin:PATH[=ARGS]`.
+//!
+//! A plugin exports three functions:
+//!
+//! - `uint32_t compress_comics_plugin_abi(uint32_t host)`: the interface
+//!   version the plugin speaks, at most `host` (the newest the host speaks),
+//!   or 0 when it cannot speak any of them
+//! - `const char *compress_comics_plugin_name(void)`: a static name
+//! - `int32_t compress_comics_plugin_process(uint8_t *pixels, uint32_t width,
+//!   uint32_t height, uint32_t channels, const char *args)`: process a page
+//!   in place (1 channel for gray, 3 for RGB, 4 for RGBA), returning 1 when it
+//!   changed it, 0 when not and a negative code on failure. It is called from
+//!   several threads at once
+//!
+//! `plugins/paper-white` is an example plugin.
+
+use anyhow::{Context, Result};
+use image::DynamicImage;
+use std::ffi::{c_char, CStr, CString};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::filters::{with_pixels, PageFilter};
+use crate::Options;
+
+/// Newest plugin interface version this build speaks
+pub const PLUGIN_ABI_VERSION: u32 = 1;
+
+/// Oldest plugin interface version this build still loads
+const MIN_PLUGIN_ABI_VERSION: u32 = 1;
+
+/// A page processing step
+pub trait PageProcessor: Send + Sync {
+    /// Name shown in errors
+    fn name(&self) -> &str;
+
+    /// Process the page in place: `pixels` holds `channels` interleaved 8-bit
+    /// values per pixel (1 for gray, 3 for RGB, 4 for RGBA). Returns whether the page changed
+    fn process(&self, pixels: &mut [u8], width: u32, height: u32, channels: u32) -> Result<bool>;
+}
+
+type AbiFn = unsafe extern "C" fn(u32) -> u32;
+type NameFn = unsafe extern "C" fn() -> *const c_char;
+type ProcessFn = unsafe extern "C" fn(*mut u8, u32, u32, u32, *const c_char) -> i32;
+
+/// A plugin library, loaded for the rest of the run
+struct Plugin {
+    name: String,
+    args: CString,
+    process: ProcessFn,
+    // Keeps `process` valid; dropped last
+    _library: libloading::Library,
+}
+
+/// Load the plugin at `path`, agreeing on an interface version with it
+pub(crate) fn load(path: &Path, args: &str) -> Result<Arc<dyn PageProcessor>> {
+    // Loading runs the library's initialisers: plugins are trusted code, like hooks
+    let library = unsafe { libloading::Library::new(path) }
+        .with_context(|| format!("Failed to load plugin {}", path.display()))?;
+    let symbol = |name: &str| format!("Plugin {} does not export {}", path.display(), name);
+
+    let abi: AbiFn = *unsafe { library.get(b"compress_comics_plugin_abi") }.with_context(|| symbol("compress_comics_plugin_abi"))?;
+    let version = unsafe { abi(PLUGIN_ABI_VERSION) };
+    if !(MIN_PLUGIN_ABI_VERSION..=PLUGIN_ABI_VERSION).contains(&version) {
+        anyhow::bail!(
+            "Plugin {} speaks interface version {}, but this build of compress_comics loads versions {} to {}",
+            path.display(),
+            version,
+            MIN_PLUGIN_ABI_VERSION,
+            PLUGIN_ABI_VERSION
+        );
+    }
+
+    let name: NameFn = *unsafe { library.get(b"compress_comics_plugin_name") }.with_context(|| symbol("compress_comics_plugin_name"))?;
+    let name = unsafe { name() };
+    let name = if name.is_null() {
+        path.display().to_string()
+    } else {
+        unsafe { CStr::from_ptr(name) }.to_string_lossy().into_owned()
+    };
+    let process: ProcessFn =
+        *unsafe { library.get(b"compress_comics_plugin_process") }.with_context(|| symbol("compress_comics_plugin_process"))?;
+
+    Ok(Arc::new(Plugin {
+        name,
+        args: CString::new(args).context("Plugin arguments must not contain NUL")?,
+        process,
+        _library: library,
+    }))
+}
+
+impl PageProcessor for Plugin {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn process(&self, pixels: &mut [u8], width: u32, height: u32, channels: u32) -> Result<bool> {
+        // The plugin was handed exactly width × height × channels bytes, which it promises to stay within
+        match unsafe { (self.process)(pixels.as_mut_ptr(), width, height, channels, self.args.as_ptr()) } {
+            0 => Ok(false),
+            1 => Ok(true),
+            code => anyhow::bail!("Plugin {} failed with code {}", self.name, code),
+        }
+    }
+}
+
+/// A `PageProcessor` as a `--filters` step; processors retouch pages
+pub(crate) struct Processor(pub Arc<dyn PageProcessor>);
+
+impl PageFilter for Processor {
+    fn apply(&self, img: &DynamicImage, _: &Options) -> Result<Option<DynamicImage>> {
+        let (width, height) = (img.width(), img.height());
+        let mut changed = Ok(false);
+        let page = with_pixels(img, |pixels, channels, _| {
+            changed = self.0.process(pixels, width, height, channels as u32);
+        });
+        Ok(changed?.then_some(page))
+    }
+}
@@ -0,0 +1,94 @@
+//! `--precheck`: test every source archive before processing starts, so
+//! damaged sources are reported up front instead of turning into compressed
+//! copies of broken comics. ZIP, RAR and 7z entries are read in full against
+//! their CRCs, TAR headers against their checksums, and PDFs must load.
+//! Damaged files are skipped and listed in `damaged_files.txt`.
+
+use anyhow::{Context, Result};
+use rayon::prelude::*;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+
+use crate::{paths, throttle, ComicFile, ComicType};
+
+/// File listing the damaged sources, one `path<TAB>reason` line each
+pub const DAMAGED_LIST_FILE_NAME: &str = "damaged_files.txt";
+
+/// Check all files in parallel; returns the damaged ones with the reason
+pub(crate) fn scan(files: &[ComicFile]) -> Vec<(PathBuf, String)> {
+    files
+        .par_iter()
+        .filter_map(|file| check(file).err().map(|e| (file.path.clone(), format!("{:#}", e))))
+        .collect()
+}
+
+/// Write the damaged file list into `dir`, returning its path
+pub(crate) fn write_list(dir: &Path, damaged: &[(PathBuf, String)]) -> Result<PathBuf> {
+    let mut content = String::new();
+    for (path, reason) in damaged {
+        let _ = writeln!(content, "{}\t{}", path.display(), reason);
+    }
+    let list_path = dir.join(DAMAGED_LIST_FILE_NAME);
+    fs::create_dir_all(dir)?;
+    fs::write(&list_path, content).with_context(|| format!("Failed to write {}", list_path.display()))?;
+    Ok(list_path)
+}
+
+/// Read a comic's archive in full, failing on the first damaged entry
+fn check(comic_file: &ComicFile) -> Result<()> {
+    let path = &comic_file.path;
+    match comic_file.file_type {
+        ComicType::Cbz | ComicType::Epub => check_zip(path),
+        // Many "CBR" files are ZIP archives with the wrong extension
+        ComicType::Cbr => check_rar(path).or_else(|rar_error| check_zip(path).map_err(|_| rar_error)),
+        ComicType::Cb7 => check_7z(path),
+        ComicType::Cbt => check_tar(path),
+        ComicType::Pdf => {
+            lopdf::Document::load_from(throttle::open(path)?).context("PDF does not load")?;
+            Ok(())
+        }
+        // DjVu files carry no checksums to test
+        ComicType::Djvu => Ok(()),
+    }
+}
+
+fn check_zip(path: &Path) -> Result<()> {
+    let mut archive = zip::ZipArchive::new(BufReader::new(throttle::open(path)?)).context("Not a readable ZIP archive")?;
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index)?;
+        // The CRC is compared once the entry is read to the end
+        io::copy(&mut entry, &mut io::sink()).with_context(|| format!("Damaged entry {}", entry.name()))?;
+    }
+    Ok(())
+}
+
+fn check_rar(path: &Path) -> Result<()> {
+    let mut archive = unrar::Archive::new(&paths::long_path(path))
+        .open_for_processing()
+        .map_err(|e| anyhow::anyhow!("Not a readable RAR archive: {:?}", e))?;
+    while let Some(header) = archive.read_header().map_err(|e| anyhow::anyhow!("Damaged RAR header: {:?}", e))? {
+        let name = header.entry().filename.to_string_lossy().into_owned();
+        archive = header.test().map_err(|e| anyhow::anyhow!("Damaged entry {}: {:?}", name, e))?;
+    }
+    Ok(())
+}
+
+fn check_7z(path: &Path) -> Result<()> {
+    sevenz_rust::decompress_with_extract_fn(throttle::open(path)?, "", |_, reader, _| {
+        // Entries are CRC-checked as they are read to the end
+        io::copy(reader, &mut io::sink()).map_err(sevenz_rust::Error::io)?;
+        Ok(true)
+    })
+    .map_err(|e| anyhow::anyhow!("Damaged 7z archive: {:?}", e))
+}
+
+fn check_tar(path: &Path) -> Result<()> {
+    let mut archive = tar::Archive::new(BufReader::new(throttle::open(path)?));
+    for entry in archive.entries().context("Not a readable TAR archive")? {
+        let mut entry = entry.context("Damaged TAR entry header")?;
+        io::copy(&mut entry, &mut io::sink()).context("Truncated TAR entry")?;
+    }
+    Ok(())
+}
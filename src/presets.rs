@@ -0,0 +1,175 @@
+//! Named presets (`--preset`): the target size, fit, grayscale handling,
+//! quality and format for a reading device or purpose, in one flag. The
+//! built-in presets are written in the same TOML as `[presets.NAME]` tables
+//! in `compress_comics.toml`, which add presets or replace built-in ones.
+//! Flags given on the command line override the preset, and the preset
+//! overrides the plain defaults in the config file.
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::process::ExitCode;
+
+use crate::config;
+use crate::{FitMode, GrayscaleMode, ImageFormat, Options, ResizePolicy};
+
+const BUILT_IN: &str = r#"
+[kindle-paperwhite]
+description = "Kindle Paperwhite (6.8\", 1236×1648 e-ink)"
+target-height = 1648
+target-width = 1236
+fit = "fit-within"
+grayscale = "force"
+quality = 75
+
+[kobo-clara]
+description = "Kobo Clara (6\", 1072×1448 e-ink)"
+target-height = 1448
+target-width = 1072
+fit = "fit-within"
+grayscale = "force"
+quality = 75
+
+[kobo-libra]
+description = "Kobo Libra (7\", 1264×1680 e-ink)"
+target-height = 1680
+target-width = 1264
+fit = "fit-within"
+grayscale = "force"
+quality = 75
+
+[ipad]
+description = "iPad and iPad Air (10.9\", 1640×2360)"
+target-height = 2360
+target-width = 1640
+fit = "fit-within"
+quality = 85
+
+[ipad-pro]
+description = "iPad Pro (12.9\", 2048×2732)"
+target-height = 2732
+target-width = 2048
+fit = "fit-within"
+quality = 85
+
+[phone]
+description = "Phones (1080×2400)"
+target-height = 2400
+target-width = 1080
+fit = "fit-within"
+quality = 80
+
+[archive]
+description = "Long-term storage: original page sizes, near-lossless quality"
+resize-policy = "never"
+quality = 95
+"#;
+
+/// Settings a preset sets; anything left out keeps its default
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Preset {
+    pub description: Option<String>,
+    pub quality: Option<u8>,
+    pub format: Option<String>,
+    pub target_height: Option<u32>,
+    pub target_width: Option<u32>,
+    pub fit: Option<String>,
+    pub grayscale: Option<String>,
+    pub resize_policy: Option<String>,
+    pub eink: Option<bool>,
+    pub dither: Option<bool>,
+}
+
+fn built_in() -> BTreeMap<String, Preset> {
+    toml::from_str(BUILT_IN).expect("built-in presets are valid")
+}
+
+/// Built-in presets with the configured ones added (or replacing them)
+fn all(configured: &BTreeMap<String, Preset>) -> BTreeMap<String, Preset> {
+    let mut presets = built_in();
+    presets.extend(configured.iter().map(|(name, preset)| (name.clone(), preset.clone())));
+    presets
+}
+
+/// Apply the preset `name` to `args`, except for the settings `from_cli`
+/// says were given on the command line
+pub(crate) fn apply(
+    args: &mut Options,
+    name: &str,
+    configured: &BTreeMap<String, Preset>,
+    from_cli: impl Fn(&str) -> bool,
+) -> Result<()> {
+    let presets = all(configured);
+    let preset = presets.get(name).with_context(|| {
+        format!("Unknown preset {}. Available: {}", name, presets.keys().cloned().collect::<Vec<_>>().join(", "))
+    })?;
+    let parse = |key: &str, value: &str| format!("Invalid {} in preset {}: {}", key, name, value);
+
+    if let (Some(quality), false) = (preset.quality, from_cli("quality")) {
+        if !(1..=100).contains(&quality) {
+            anyhow::bail!(parse("quality", &quality.to_string()));
+        }
+        args.quality = quality;
+    }
+    if let (Some(format), false) = (&preset.format, from_cli("format")) {
+        args.format = ImageFormat::from_str(format, true).map_err(|_| anyhow::anyhow!(parse("format", format)))?;
+    }
+    if let (Some(target_height), false) = (preset.target_height, from_cli("target_height")) {
+        args.target_height = target_height;
+    }
+    if let (Some(target_width), false) = (preset.target_width, from_cli("target_width")) {
+        args.target_width = Some(target_width);
+    }
+    if let (Some(fit), false) = (&preset.fit, from_cli("fit")) {
+        args.fit = FitMode::from_str(fit, true).map_err(|_| anyhow::anyhow!(parse("fit", fit)))?;
+    }
+    if let (Some(grayscale), false) = (&preset.grayscale, from_cli("grayscale")) {
+        args.grayscale =
+            GrayscaleMode::from_str(grayscale, true).map_err(|_| anyhow::anyhow!(parse("grayscale", grayscale)))?;
+    }
+    if let (Some(resize_policy), false) = (&preset.resize_policy, from_cli("resize_policy")) {
+        args.resize_policy = ResizePolicy::from_str(resize_policy, true)
+            .map_err(|_| anyhow::anyhow!(parse("resize-policy", resize_policy)))?;
+    }
+    if let (Some(eink), false) = (preset.eink, from_cli("eink")) {
+        args.eink = eink;
+    }
+    if let (Some(dither), false) = (preset.dither, from_cli("dither")) {
+        args.dither = dither && args.eink;
+    }
+    Ok(())
+}
+
+/// `presets`: list the built-in presets and those of the config files read from the current directory
+pub(crate) fn list() -> Result<ExitCode> {
+    let (config, _) = config::load_layered(Path::new("."))?;
+    for (name, preset) in all(&config.presets) {
+        println!("{}", name);
+        if let Some(description) = &preset.description {
+            println!("    {}", description);
+        }
+        let settings: Vec<String> = [
+            preset.format.as_ref().map(|format| format!("format {}", format)),
+            preset.quality.map(|quality| format!("quality {}", quality)),
+            match (preset.target_width, preset.target_height) {
+                (Some(width), Some(height)) => Some(format!("{}×{}", width, height)),
+                (None, Some(height)) => Some(format!("height {}", height)),
+                (Some(width), None) => Some(format!("width {}", width)),
+                (None, None) => None,
+            },
+            preset.fit.as_ref().map(|fit| format!("fit {}", fit)),
+            preset.grayscale.as_ref().map(|grayscale| format!("grayscale {}", grayscale)),
+            preset.resize_policy.as_ref().map(|policy| format!("resize {}", policy)),
+            preset.eink.filter(|&eink| eink).map(|_| "e-ink".to_string()),
+            preset.dither.filter(|&dither| dither).map(|_| "dithered".to_string()),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        println!("    {}", settings.join(", "));
+    }
+    Ok(ExitCode::SUCCESS)
+}
@@ -0,0 +1,103 @@
+//! Byte-weighted progress of a whole run. The overall bar counts source
+//! bytes instead of files, so its ETA holds when a 2 GB omnibus sits between
+//! 30 MB issues. Each file owns a share of the total: its share is credited
+//! as the file's own bar advances (encoding pages moves it from 30% to 80%),
+//! and whatever is left once the file finishes.
+
+use indicatif::ProgressBar;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::ComicFile;
+
+/// Weight of a file whose size is not known yet when no other size is either
+const DEFAULT_WEIGHT: u64 = 64 * 1024 * 1024;
+
+/// The overall bar, in bytes, with the file count as its message
+pub(crate) struct RunProgress {
+    bar: ProgressBar,
+    weights: HashMap<PathBuf, u64>,
+    files: usize,
+    finished: Arc<AtomicUsize>,
+}
+
+impl RunProgress {
+    /// Size the bar for `files`. Files not on disk yet (remote sources, fetched
+    /// when they start) count as the average of the others until then
+    pub(crate) fn new(bar: ProgressBar, files: &[ComicFile]) -> Self {
+        let sizes: Vec<Option<u64>> = files.iter().map(|file| fs::metadata(&file.path).ok().map(|m| m.len())).collect();
+        let known: Vec<u64> = sizes.iter().flatten().copied().collect();
+        let estimate = match known.len() {
+            0 => DEFAULT_WEIGHT,
+            count => known.iter().sum::<u64>() / count as u64,
+        };
+        let weights: HashMap<PathBuf, u64> = files
+            .iter()
+            .zip(sizes)
+            .map(|(file, size)| (file.path.clone(), size.unwrap_or(estimate)))
+            .collect();
+        bar.set_length(weights.values().sum());
+        bar.set_message(file_count(0, files.len()));
+        RunProgress { bar, weights, files: files.len(), finished: Arc::new(AtomicUsize::new(0)) }
+    }
+
+    /// The share of a file about to be processed, which must be on disk by now
+    pub(crate) fn start(&self, comic_file: &ComicFile) -> Arc<FileShare> {
+        let weight = self.weights.get(&comic_file.path).copied().unwrap_or_default();
+        let size = fs::metadata(&comic_file.path).map(|m| m.len()).unwrap_or(weight);
+        if size > weight {
+            self.bar.inc_length(size - weight);
+        } else {
+            self.bar.dec_length(weight - size);
+        }
+        Arc::new(FileShare {
+            bar: self.bar.clone(),
+            size,
+            credited: AtomicU64::new(0),
+            files: self.files,
+            finished: self.finished.clone(),
+        })
+    }
+
+    pub(crate) fn finish_with_message(&self, message: &'static str) {
+        self.bar.finish_with_message(message);
+    }
+
+    pub(crate) fn abandon_with_message(&self, message: &'static str) {
+        self.bar.abandon_with_message(message);
+    }
+}
+
+/// One file's bytes on the overall bar
+pub(crate) struct FileShare {
+    bar: ProgressBar,
+    size: u64,
+    credited: AtomicU64,
+    files: usize,
+    finished: Arc<AtomicUsize>,
+}
+
+impl FileShare {
+    /// Credit the share up to `percent` of the file's size; progress never goes back
+    pub(crate) fn advance(&self, percent: u64) {
+        let target = self.size.saturating_mul(percent.min(100)) / 100;
+        let previous = self.credited.fetch_max(target, Ordering::SeqCst);
+        if target > previous {
+            self.bar.inc(target - previous);
+        }
+    }
+
+    /// Credit the rest of the share and count the file as done
+    pub(crate) fn finish(&self) {
+        self.advance(100);
+        let finished = self.finished.fetch_add(1, Ordering::SeqCst) + 1;
+        self.bar.set_message(file_count(finished, self.files));
+    }
+}
+
+fn file_count(finished: usize, files: usize) -> String {
+    format!("{}/{} files", finished, files)
+}
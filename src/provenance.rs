@@ -0,0 +1,88 @@
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+const TOOL_NAME: &str = "compress_comics";
+
+/// Marker embedded as the archive comment of every output, recording how it was made
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProcessingMarker {
+    pub tool: String,
+    pub version: String,
+    pub settings: String,
+    pub source_name: String,
+    pub source_sha256: String,
+    pub created_at: u64,
+}
+
+impl ProcessingMarker {
+    pub fn new(settings: &str, source_path: &Path, source_sha256: String) -> Self {
+        ProcessingMarker {
+            tool: TOOL_NAME.to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            settings: settings.to_string(),
+            source_name: source_path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            source_sha256,
+            created_at: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+        }
+    }
+
+    pub fn to_comment(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+
+    /// `created_at` as `YYYY-MM-DD HH:MM:SS UTC`; `None` for `--deterministic` outputs, which record no time
+    pub fn created_at_utc(&self) -> Option<String> {
+        if self.created_at == 0 {
+            return None;
+        }
+        let (year, month, day) = crate::civil_from_days((self.created_at / 86_400) as i64);
+        let seconds = self.created_at % 86_400;
+        Some(format!(
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+            year,
+            month,
+            day,
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        ))
+    }
+
+    pub fn from_comment(comment: &str) -> Option<Self> {
+        serde_json::from_str::<ProcessingMarker>(comment.trim())
+            .ok()
+            .filter(|marker| marker.tool == TOOL_NAME)
+    }
+}
+
+/// Read the marker from a ZIP-based archive's comment (or a PDF's Info
+/// dictionary), if it was written by this tool
+pub fn read_marker(path: &Path) -> Option<ProcessingMarker> {
+    if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("pdf")) {
+        return ProcessingMarker::from_comment(&crate::pdf::read_comment(path)?);
+    }
+    let file = File::open(path).ok()?;
+    let archive = zip::ZipArchive::new(BufReader::new(file)).ok()?;
+    ProcessingMarker::from_comment(&String::from_utf8_lossy(archive.comment()))
+}
+
+/// Whether a file name looks like one of this tool's own outputs or backups
+pub fn is_tool_artifact(path: &Path) -> bool {
+    let Some(stem) = path.file_stem().map(|s| s.to_string_lossy()) else {
+        return false;
+    };
+    // Numbered backups: <name>_original_2
+    let backup = stem.trim_end_matches(|c: char| c.is_ascii_digit());
+    stem.ends_with("_temp_compressed")
+        || stem.ends_with("_original")
+        || (backup.len() < stem.len() && backup.ends_with("_original_"))
+        || stem.contains(" optimized_")
+}
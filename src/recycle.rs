@@ -0,0 +1,38 @@
+//! `--trash-original`: once a file's output is in place and verified, move
+//! the original out of the library instead of leaving it next to the output:
+//! to the system trash, or into `--trash-dir` mirroring the input tree, so it
+//! can still be restored.
+
+use anyhow::{Context, Result};
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::{move_file, output_dir_for, Options};
+
+/// Move `original` to the system trash or below `--trash-dir`
+pub(crate) fn trash_original(original: &Path, args: &Options, input_root: &Path) -> Result<()> {
+    let Some(trash_root) = &args.trash_dir else {
+        return trash::delete(original).with_context(|| format!("Failed to move {} to the trash", original.display()));
+    };
+    let dir = output_dir_for(original, Some(trash_root), input_root);
+    fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    let target = free_path(&dir, original.file_name().unwrap_or_default());
+    move_file(original, &target).with_context(|| format!("Failed to move {} to {}", original.display(), target.display()))
+}
+
+/// `dir/name`, or `dir/<stem> (2).<ext>` and so on when an earlier original
+/// of that name is already there
+pub(crate) fn free_path(dir: &Path, name: &OsStr) -> PathBuf {
+    let path = dir.join(name);
+    if !path.exists() {
+        return path;
+    }
+    let name = Path::new(name);
+    let stem = name.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = name.extension().map(|ext| format!(".{}", ext.to_string_lossy())).unwrap_or_default();
+    (2..)
+        .map(|n| dir.join(format!("{} ({}){}", stem, n, extension)))
+        .find(|path| !path.exists())
+        .unwrap_or(path)
+}
@@ -0,0 +1,645 @@
+//! Remote INPUT and `--output-dir` locations:
+//!
+//! - `s3://bucket/prefix`: credentials, region and an S3-compatible endpoint
+//!   come from the usual `AWS_*` environment variables
+//! - `webdav://[user:password@]host/path` over HTTPS, `webdav+http://` without
+//! - `sftp://[user@]host[:port]/path` through the `sftp` program, which must
+//!   log in without a prompt (key or agent)
+//!
+//! Sources are downloaded into the temp directory one at a time, just before
+//! they are processed, and outputs are uploaded and deleted as soon as they
+//! are finished, so only the files in flight take local space.
+
+use anyhow::{Context, Result};
+use base64::prelude::*;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tempfile::TempDir;
+use ureq::http::{request, Request};
+use ureq::Agent;
+use walkdir::WalkDir;
+
+use crate::{civil_from_days, contact_sheet, detect_comic_file, sanitized_entry_path, temp, ComicFile, Options, Report};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+/// RFC 3986 unreserved characters, the only ones S3 signatures leave unencoded
+const COMPONENT: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'.').remove(b'_').remove(b'~');
+const PATH: &AsciiSet = &COMPONENT.remove(b'/');
+const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+const PROPFIND_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?><propfind xmlns="DAV:"><prop><resourcetype/></prop></propfind>"#;
+
+/// Local stand-ins for a remote INPUT and `--output-dir` during a run
+pub(crate) struct Staging {
+    agent: Agent,
+    dir: TempDir,
+    /// Remote INPUT with the `/`-separated paths of its files
+    input: Option<(Remote, Vec<String>)>,
+    /// Where files written below `output_root` are uploaded to
+    output: Option<(String, Remote)>,
+    output_root: PathBuf,
+}
+
+impl Staging {
+    /// Point a remote INPUT or `--output-dir` in `args` at staging directories,
+    /// listing the remote INPUT. None when both are local
+    pub(crate) fn prepare(args: &mut Options) -> Result<Option<Staging>> {
+        let input = match args.input.as_slice() {
+            [input] => remote_location(Some(input))?,
+            inputs => {
+                for input in inputs {
+                    if remote_location(Some(input))?.is_some() {
+                        anyhow::bail!("A remote INPUT must be the only INPUT");
+                    }
+                }
+                None
+            }
+        };
+        let output = remote_location(args.output_dir.as_deref())?;
+        if input.is_none() && output.is_none() {
+            return Ok(None);
+        }
+        if args.watch.is_some() {
+            anyhow::bail!("--watch cannot write to a remote --output-dir");
+        }
+        if input.is_some()
+            && (args.in_place
+                || args.rename_original
+                || args.trash_original
+                || args.glob_pattern.is_some()
+                || args.precheck
+                || args.quarantine_dir.is_some())
+        {
+            anyhow::bail!(
+                "A remote INPUT cannot be used with --in-place, --rename-original, --trash-original, --glob-pattern, --precheck or --quarantine-dir"
+            );
+        }
+
+        let dir = temp::create_dir(args)?;
+        let agent: Agent = Agent::config_builder()
+            .timeout_connect(Some(CONNECT_TIMEOUT))
+            .allow_non_standard_methods(true)
+            .build()
+            .into();
+        let input_root = dir.path().join("input");
+        let output_root = dir.path().join("output");
+
+        let input = match input {
+            Some((location, remote)) => {
+                let files = remote.list(&agent).with_context(|| format!("Failed to list {}", location))?;
+                fs::create_dir_all(&input_root)?;
+                args.input = vec![input_root.clone()];
+                Some((location, remote, files))
+            }
+            None => None,
+        };
+        let (output, output_root) = match (output, &input) {
+            (Some((location, remote)), _) => {
+                remote.create_root(&agent).with_context(|| format!("Failed to create {}", location))?;
+                fs::create_dir_all(&output_root)?;
+                args.output_dir = Some(output_root.clone());
+                (Some((location, remote)), output_root)
+            }
+            // Outputs go next to their sources, i.e. back to the remote INPUT
+            (None, Some((location, remote, _))) if args.output_dir.is_none() => {
+                (Some((location.clone(), remote.clone())), input_root)
+            }
+            (None, _) => (None, output_root),
+        };
+
+        Ok(Some(Staging {
+            agent,
+            dir,
+            input: input.map(|(_, remote, files)| (remote, files)),
+            output,
+            output_root,
+        }))
+    }
+
+    /// The comic files of a remote INPUT, at their (not yet downloaded) local paths
+    pub(crate) fn comic_files(&self) -> Option<Vec<ComicFile>> {
+        let (_, files) = self.input.as_ref()?;
+        let input_root = self.input_root();
+        Some(
+            files
+                .iter()
+                .filter_map(|name| match sanitized_entry_path(&input_root, name) {
+                    Ok(path) => detect_comic_file(&path).ok(),
+                    // A listing is as untrusted as an archive: `../x.cbz` would download outside the temp dir
+                    Err(_) => {
+                        eprintln!("⚠️  Skipping remote file with an unsafe name: {}", name);
+                        None
+                    }
+                })
+                .collect(),
+        )
+    }
+
+    /// Download a remote source to its local path
+    pub(crate) fn fetch(&self, comic_file: &ComicFile) -> Result<()> {
+        let Some((remote, _)) = &self.input else {
+            return Ok(());
+        };
+        let path = relative_path(&comic_file.path, &self.input_root())?;
+        if let Some(parent) = comic_file.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        remote.download(&self.agent, &path, &comic_file.path).with_context(|| format!("Failed to download {}", path))
+    }
+
+    /// Upload a finished output (and its contact sheet) and delete it locally;
+    /// the report then points at the remote copy
+    pub(crate) fn deliver(&self, mut report: Report) -> Result<Report> {
+        let (Some((location, remote)), Some(output)) = (&self.output, &report.output_path) else {
+            return Ok(report);
+        };
+        if !output.is_file() {
+            return Ok(report);
+        }
+        let sheet = contact_sheet::sheet_path(output);
+        for local in [output.clone(), sheet].iter().filter(|path| path.is_file()) {
+            let path = relative_path(local, &self.output_root)?;
+            remote.upload(&self.agent, local, &path).with_context(|| format!("Failed to upload {} to {}", path, location))?;
+            fs::remove_file(local)?;
+        }
+        let path = relative_path(output, &self.output_root)?;
+        report.output_path = Some(PathBuf::from(format!("{}/{}", location.trim_end_matches('/'), path)));
+        Ok(report)
+    }
+
+    /// Delete the local copy of a remote source
+    pub(crate) fn release(&self, comic_file: &ComicFile) {
+        if self.input.is_some() {
+            let _ = fs::remove_file(&comic_file.path);
+        }
+    }
+
+    /// Upload the files the run left in the output directory, e.g. the damaged file list
+    pub(crate) fn upload_remaining(&self) -> Result<()> {
+        let Some((location, remote)) = &self.output else {
+            return Ok(());
+        };
+        for entry in WalkDir::new(&self.output_root).into_iter().filter_map(|e| e.ok()) {
+            if entry.file_type().is_file() {
+                let path = relative_path(entry.path(), &self.output_root)?;
+                remote
+                    .upload(&self.agent, entry.path(), &path)
+                    .with_context(|| format!("Failed to upload {} to {}", path, location))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn input_root(&self) -> PathBuf {
+        self.dir.path().join("input")
+    }
+}
+
+/// A remote location given as a path argument, with the location as it is
+/// shown in messages and reports: without a password
+fn remote_location(path: Option<&Path>) -> Result<Option<(String, Remote)>> {
+    let Some(location) = path.and_then(Path::to_str) else {
+        return Ok(None);
+    };
+    let shown = match location.split_once("://") {
+        Some((scheme, rest)) => {
+            let (authority, path) = rest.split_once('/').map_or((rest, ""), |(authority, path)| (authority, path));
+            let authority = match authority.rsplit_once('@') {
+                Some((credentials, host)) => format!("{}@{}", credentials.split(':').next().unwrap_or_default(), host),
+                None => authority.to_string(),
+            };
+            format!("{}://{}/{}", scheme, authority, path)
+        }
+        None => location.to_string(),
+    };
+    Ok(Remote::parse(location)?.map(|remote| (shown, remote)))
+}
+
+/// `path` below `root` with `/` separators, as remote paths are written
+fn relative_path(path: &Path, root: &Path) -> Result<String> {
+    let relative = path.strip_prefix(root).with_context(|| format!("{} is outside {}", path.display(), root.display()))?;
+    Ok(relative.iter().map(|part| part.to_string_lossy()).collect::<Vec<_>>().join("/"))
+}
+
+#[derive(Clone)]
+enum Remote {
+    S3(S3),
+    WebDav(WebDav),
+    Sftp { host: String, port: Option<u16>, root: String },
+}
+
+impl Remote {
+    /// None for anything that is not a URL, i.e. a local path
+    fn parse(location: &str) -> Result<Option<Remote>> {
+        let Some((scheme, rest)) = location.split_once("://") else {
+            return Ok(None);
+        };
+        let rest = rest.trim_end_matches('/');
+        let remote = match scheme {
+            "s3" => {
+                let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+                Remote::S3(S3::from_env(bucket, prefix)?)
+            }
+            "webdav" => Remote::WebDav(WebDav::new("https", rest)),
+            "webdav+http" => Remote::WebDav(WebDav::new("http", rest)),
+            "sftp" => {
+                let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+                let (host, port) = match authority.rsplit_once(':') {
+                    Some((host, port)) => (host, Some(port.parse().with_context(|| format!("Invalid port in {}", location))?)),
+                    None => (authority, None),
+                };
+                Remote::Sftp { host: host.to_string(), port, root: format!("/{}", path) }
+            }
+            _ => anyhow::bail!("Unsupported location {}: use s3://, sftp://, webdav:// or webdav+http://", location),
+        };
+        Ok(Some(remote))
+    }
+
+    /// Create the location's directories that do not exist yet; S3 has none
+    fn create_root(&self, agent: &Agent) -> Result<()> {
+        match self {
+            Remote::S3(_) => {}
+            Remote::WebDav(webdav) => {
+                // Every collection from the server root down, e.g. /a/, /a/b/
+                let origin = webdav.base.match_indices('/').nth(2).map_or(webdav.base.len(), |(index, _)| index);
+                let ends = webdav.base[origin..].match_indices('/').skip(1).map(|(index, _)| origin + index);
+                for end in ends.chain([webdav.base.len()]).filter(|&end| end > origin) {
+                    let _ = agent.run(webdav.request("MKCOL", &format!("{}/", &webdav.base[..end])).body(())?);
+                }
+            }
+            Remote::Sftp { host, port, root } => {
+                let commands: String = root
+                    .match_indices('/')
+                    .skip(1)
+                    .map(|(index, _)| &root[..index])
+                    .chain([root.as_str()])
+                    .map(|directory| format!("-mkdir {}\n", sftp_quote(directory)))
+                    .collect();
+                sftp(host, *port, &commands)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Paths of all files below the location, recursively
+    fn list(&self, agent: &Agent) -> Result<Vec<String>> {
+        match self {
+            Remote::S3(s3) => s3.list(agent),
+            Remote::WebDav(webdav) => webdav.list(agent),
+            Remote::Sftp { host, port, root } => sftp_list(host, *port, root),
+        }
+    }
+
+    fn download(&self, agent: &Agent, path: &str, local: &Path) -> Result<()> {
+        match self {
+            Remote::S3(s3) => save_body(agent.run(s3.signed("GET", path, &[]).body(())?)?, local),
+            Remote::WebDav(webdav) => save_body(agent.run(webdav.request("GET", &webdav.url(path)).body(())?)?, local),
+            Remote::Sftp { host, port, root } => {
+                sftp(host, *port, &format!("get {} {}\n", sftp_quote(&join(root, path)), sftp_quote(&local.to_string_lossy())))?;
+                Ok(())
+            }
+        }
+    }
+
+    fn upload(&self, agent: &Agent, local: &Path, path: &str) -> Result<()> {
+        match self {
+            Remote::S3(s3) => {
+                agent.run(s3.signed("PUT", path, &[]).body(File::open(local)?)?)?;
+            }
+            Remote::WebDav(webdav) => {
+                // Collections must exist before files can be put into them
+                for (index, _) in path.match_indices('/') {
+                    let _ = agent.run(webdav.request("MKCOL", &webdav.url(&path[..index])).body(())?);
+                }
+                agent.run(webdav.request("PUT", &webdav.url(path)).body(File::open(local)?)?)?;
+            }
+            Remote::Sftp { host, port, root } => {
+                let mut commands = String::new();
+                // A leading "-" ignores the error for directories that already exist
+                for (index, _) in path.match_indices('/') {
+                    commands.push_str(&format!("-mkdir {}\n", sftp_quote(&join(root, &path[..index]))));
+                }
+                commands.push_str(&format!("put {} {}\n", sftp_quote(&local.to_string_lossy()), sftp_quote(&join(root, path))));
+                sftp(host, *port, &commands)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn save_body(mut response: ureq::http::Response<ureq::Body>, local: &Path) -> Result<()> {
+    let mut file = File::create(local)?;
+    io::copy(&mut response.body_mut().as_reader(), &mut file)?;
+    Ok(())
+}
+
+fn join(root: &str, path: &str) -> String {
+    format!("{}/{}", root.trim_end_matches('/'), path)
+}
+
+/// An S3 bucket (or prefix in it), addressed with Signature Version 4
+#[derive(Clone)]
+struct S3 {
+    bucket: String,
+    prefix: String,
+    region: String,
+    /// S3-compatible server (MinIO, Wasabi, ...), addressed path-style
+    endpoint: Option<String>,
+    access_key: String,
+    secret_key: String,
+    session_token: Option<String>,
+}
+
+impl S3 {
+    fn from_env(bucket: &str, prefix: &str) -> Result<Self> {
+        let var = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+        let missing = || anyhow::anyhow!("s3:// locations need AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY");
+        Ok(S3 {
+            bucket: bucket.to_string(),
+            prefix: prefix.to_string(),
+            region: var("AWS_REGION").or_else(|| var("AWS_DEFAULT_REGION")).unwrap_or_else(|| "us-east-1".to_string()),
+            endpoint: var("AWS_ENDPOINT_URL_S3")
+                .or_else(|| var("AWS_ENDPOINT_URL"))
+                .map(|endpoint| endpoint.trim_end_matches('/').to_string()),
+            access_key: var("AWS_ACCESS_KEY_ID").ok_or_else(missing)?,
+            secret_key: var("AWS_SECRET_ACCESS_KEY").ok_or_else(missing)?,
+            session_token: var("AWS_SESSION_TOKEN"),
+        })
+    }
+
+    fn key(&self, path: &str) -> String {
+        if self.prefix.is_empty() {
+            path.to_string()
+        } else {
+            format!("{}/{}", self.prefix, path)
+        }
+    }
+
+    fn list(&self, agent: &Agent) -> Result<Vec<String>> {
+        let prefix = self.key("");
+        let mut files = Vec::new();
+        let mut continuation: Option<String> = None;
+        loop {
+            let mut query = vec![("list-type", "2"), ("prefix", prefix.as_str())];
+            if let Some(token) = &continuation {
+                query.push(("continuation-token", token.as_str()));
+            }
+            let body = agent
+                .run(self.signed_for_key("GET", "", &query).body(())?)?
+                .body_mut()
+                .read_to_string()?;
+            let xml = roxmltree::Document::parse(&body).context("Unexpected bucket listing")?;
+            for entry in xml.descendants().filter(|node| node.tag_name().name() == "Contents") {
+                let key = child_text(entry, "Key").unwrap_or_default();
+                match key.strip_prefix(&prefix) {
+                    Some(path) if !path.is_empty() && !path.ends_with('/') => files.push(path.to_string()),
+                    _ => {}
+                }
+            }
+            continuation = match child_text(xml.root_element(), "IsTruncated") {
+                Some("true") => child_text(xml.root_element(), "NextContinuationToken").map(str::to_string),
+                _ => None,
+            };
+            if continuation.is_none() {
+                return Ok(files);
+            }
+        }
+    }
+
+    /// A signed request for the object at `path` below the prefix
+    fn signed(&self, method: &str, path: &str, query: &[(&str, &str)]) -> request::Builder {
+        self.signed_for_key(method, &self.key(path), query)
+    }
+
+    /// A request for `key` (the bucket itself when empty), signed with the
+    /// payload left unsigned so that files can be streamed
+    fn signed_for_key(&self, method: &str, key: &str, query: &[(&str, &str)]) -> request::Builder {
+        let key = utf8_percent_encode(key, PATH).to_string();
+        let (origin, host, path) = match &self.endpoint {
+            Some(endpoint) => {
+                let host = endpoint.split_once("://").map_or(endpoint.as_str(), |(_, host)| host);
+                (endpoint.clone(), host.to_string(), format!("/{}/{}", self.bucket, key))
+            }
+            None => {
+                let host = format!("{}.s3.{}.amazonaws.com", self.bucket, self.region);
+                (format!("https://{}", host), host, format!("/{}", key))
+            }
+        };
+        let mut query: Vec<String> = query
+            .iter()
+            .map(|(name, value)| format!("{}={}", utf8_percent_encode(name, COMPONENT), utf8_percent_encode(value, COMPONENT)))
+            .collect();
+        query.sort();
+        let query = query.join("&");
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let (year, month, day) = civil_from_days((now / 86_400) as i64);
+        let date = format!("{:04}{:02}{:02}", year, month, day);
+        let timestamp = format!("{}T{:02}{:02}{:02}Z", date, now % 86_400 / 3_600, now % 3_600 / 60, now % 60);
+
+        // Sorted by name, as the canonical request requires
+        let mut headers = vec![
+            ("host", host),
+            ("x-amz-content-sha256", UNSIGNED_PAYLOAD.to_string()),
+            ("x-amz-date", timestamp.clone()),
+        ];
+        if let Some(token) = &self.session_token {
+            headers.push(("x-amz-security-token", token.clone()));
+        }
+        let signed_headers = headers.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(";");
+        let canonical_headers: String = headers.iter().map(|(name, value)| format!("{}:{}\n", name, value)).collect();
+        let canonical_request =
+            format!("{}\n{}\n{}\n{}\n{}\n{}", method, path, query, canonical_headers, signed_headers, UNSIGNED_PAYLOAD);
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            timestamp,
+            scope,
+            hex(&Sha256::digest(canonical_request.as_bytes()))
+        );
+        let signing_key = [date.as_str(), &self.region, "s3", "aws4_request"]
+            .iter()
+            .fold(format!("AWS4{}", self.secret_key).into_bytes(), |key, part| hmac_sha256(&key, part.as_bytes()).to_vec());
+        let signature = hex(&hmac_sha256(&signing_key, string_to_sign.as_bytes()));
+
+        let uri = if query.is_empty() { format!("{}{}", origin, path) } else { format!("{}{}?{}", origin, path, query) };
+        // ureq sets Host from the URL
+        let mut builder = Request::builder().method(method).uri(uri);
+        for (name, value) in headers.into_iter().skip(1) {
+            builder = builder.header(name, value);
+        }
+        builder.header(
+            "authorization",
+            format!(
+                "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+                self.access_key, scope, signed_headers, signature
+            ),
+        )
+    }
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut block = [0u8; 64];
+    if key.len() > block.len() {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let inner = Sha256::new().chain_update(block.map(|b| b ^ 0x36)).chain_update(data).finalize();
+    Sha256::new().chain_update(block.map(|b| b ^ 0x5c)).chain_update(inner).finalize().into()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn child_text<'a>(node: roxmltree::Node<'a, '_>, name: &str) -> Option<&'a str> {
+    node.children().find(|child| child.tag_name().name() == name).and_then(|child| child.text())
+}
+
+/// A WebDAV collection. Credentials are taken out of the URL, as they may
+/// be percent-encoded there
+#[derive(Clone)]
+struct WebDav {
+    /// Collection URL, without a trailing slash
+    base: String,
+    authorization: Option<String>,
+}
+
+impl WebDav {
+    fn new(scheme: &str, rest: &str) -> Self {
+        let (authority, path) = rest.split_once('/').map_or((rest, ""), |(authority, path)| (authority, path));
+        let (credentials, host) = match authority.rsplit_once('@') {
+            Some((credentials, host)) => (Some(credentials), host),
+            None => (None, authority),
+        };
+        let authorization = credentials.map(|credentials| {
+            let decoded: Vec<u8> = percent_decode_str(credentials).collect();
+            format!("Basic {}", BASE64_STANDARD.encode(decoded))
+        });
+        WebDav { base: format!("{}://{}/{}", scheme, host, path).trim_end_matches('/').to_string(), authorization }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/{}", self.base, utf8_percent_encode(path, PATH))
+    }
+
+    fn request(&self, method: &str, url: &str) -> request::Builder {
+        let builder = Request::builder().method(method).uri(url);
+        match &self.authorization {
+            Some(authorization) => builder.header("Authorization", authorization),
+            None => builder,
+        }
+    }
+
+    /// Walk the collections with one `Depth: 1` PROPFIND each, as many servers
+    /// refuse infinite depth
+    fn list(&self, agent: &Agent) -> Result<Vec<String>> {
+        let base_path = href_path(&self.base);
+        let mut files = Vec::new();
+        let mut collections = vec![String::new()];
+        while let Some(collection) = collections.pop() {
+            let url = if collection.is_empty() { format!("{}/", self.base) } else { format!("{}/", self.url(&collection)) };
+            let request = self
+                .request("PROPFIND", &url)
+                .header("Depth", "1")
+                .header("Content-Type", "application/xml")
+                .body(PROPFIND_BODY)?;
+            let body = agent.run(request).with_context(|| format!("PROPFIND {}", url))?.body_mut().read_to_string()?;
+            let xml = roxmltree::Document::parse(&body).context("Unexpected PROPFIND response")?;
+
+            for response in xml.descendants().filter(|node| node.tag_name().name() == "response") {
+                let Some(href) = response.descendants().find(|node| node.tag_name().name() == "href").and_then(|node| node.text()) else {
+                    continue;
+                };
+                let path = href_path(href);
+                let Some(path) = path.strip_prefix(&base_path).map(|path| path.trim_matches('/')) else {
+                    continue;
+                };
+                // The collection itself is listed too
+                if path == collection {
+                    continue;
+                }
+                if response.descendants().any(|node| node.tag_name().name() == "collection") {
+                    collections.push(path.to_string());
+                } else {
+                    files.push(path.to_string());
+                }
+            }
+        }
+        Ok(files)
+    }
+}
+
+/// Decoded path of an href or URL, without slashes around it
+fn href_path(href: &str) -> String {
+    let path = match href.split_once("://") {
+        Some((_, rest)) => rest.find('/').map_or("", |start| &rest[start..]),
+        None => href,
+    };
+    percent_decode_str(path).decode_utf8_lossy().trim_matches('/').to_string()
+}
+
+/// Run `commands` in one batch-mode `sftp` session, returning its output
+fn sftp(host: &str, port: Option<u16>, commands: &str) -> Result<String> {
+    let mut command = Command::new("sftp");
+    command.args(["-q", "-b", "-"]);
+    if let Some(port) = port {
+        command.arg("-P").arg(port.to_string());
+    }
+    let mut child = command
+        .arg(host)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to run sftp; is the OpenSSH client installed?")?;
+    child.stdin.take().context("No sftp stdin")?.write_all(commands.as_bytes())?;
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        anyhow::bail!("sftp {} failed: {}", host, String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Double-quote an sftp argument; quoted glob characters are taken literally
+fn sftp_quote(argument: &str) -> String {
+    format!("\"{}\"", argument.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// List the files below `root` with `ls -ln` per directory. Its lines read
+/// `mode links uid gid size month day time-or-year name`
+fn sftp_list(host: &str, port: Option<u16>, root: &str) -> Result<Vec<String>> {
+    let mut files = Vec::new();
+    let mut directories = vec![String::new()];
+    while let Some(directory) = directories.pop() {
+        let output = sftp(host, port, &format!("ls -ln {}\n", sftp_quote(&join(root, &directory))))?;
+        for line in output.lines().filter(|line| !line.starts_with("sftp>")) {
+            let mut rest = line;
+            let mut mode = "";
+            for field in 0..8 {
+                rest = rest.trim_start();
+                let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                if field == 0 {
+                    mode = &rest[..end];
+                }
+                rest = &rest[end..];
+            }
+            // Names are printed with the listed directory in front
+            let Some(name) = rest.strip_prefix(' ').and_then(|name| name.rsplit('/').next()) else {
+                continue;
+            };
+            let path = if directory.is_empty() { name.to_string() } else { format!("{}/{}", directory, name) };
+            match mode.chars().next() {
+                Some('d') => directories.push(path),
+                Some('-') => files.push(path),
+                _ => {}
+            }
+        }
+    }
+    Ok(files)
+}
@@ -0,0 +1,184 @@
+//! Machine-readable run reports for `--report json|ndjson`: one record per
+//! file with its page results, written as a single document at the end of the
+//! run or streamed as one JSON line per finished file.
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::{PageError, PageEvent, PageText, RemovedPage, Report, ReportFormat};
+
+/// How a file ended up, matching the sections of the text summary
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileStatus {
+    Compressed,
+    /// Written without any page being recompressed
+    Converted,
+    /// Savings below --min-savings; the original was kept
+    Skipped,
+    /// --dry-run prediction
+    Estimated,
+    Failed,
+}
+
+/// One file of the run with its page results
+#[derive(Debug, Serialize)]
+pub struct FileRecord {
+    pub path: PathBuf,
+    pub status: FileStatus,
+    pub original_size: u64,
+    /// Size of the output; for skipped files the size the rejected output would have had
+    pub output_size: u64,
+    pub output_path: Option<PathBuf>,
+    pub pages_processed: usize,
+    pub pages_resized_only: usize,
+    pub pages_skipped: usize,
+    pub page_errors: Vec<PageError>,
+    pub removed_pages: Vec<RemovedPage>,
+    /// Text recognised on each page (`--ocr-text`)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub page_text: Vec<PageText>,
+    pub message: Option<String>,
+    pub error: Option<String>,
+    pub duration_ms: u64,
+    pub pages: Vec<PageEvent>,
+}
+
+impl FileStatus {
+    pub fn of(report: &Report) -> Self {
+        if report.error_message.is_some() {
+            FileStatus::Failed
+        } else if report.estimated {
+            FileStatus::Estimated
+        } else if report.compression_skipped {
+            FileStatus::Skipped
+        } else if report.status_message.is_some() {
+            FileStatus::Converted
+        } else {
+            FileStatus::Compressed
+        }
+    }
+
+    /// The name used in JSON reports
+    pub fn name(self) -> &'static str {
+        match self {
+            FileStatus::Compressed => "compressed",
+            FileStatus::Converted => "converted",
+            FileStatus::Skipped => "skipped",
+            FileStatus::Estimated => "estimated",
+            FileStatus::Failed => "failed",
+        }
+    }
+}
+
+impl FileRecord {
+    pub fn new(path: &Path, report: &Report, duration: Duration, pages: Vec<PageEvent>) -> Self {
+        FileRecord {
+            path: path.to_path_buf(),
+            status: FileStatus::of(report),
+            original_size: report.original_size,
+            output_size: report.compressed_size,
+            output_path: report.output_path.clone(),
+            pages_processed: report.images_processed,
+            pages_resized_only: report.images_resized_only,
+            pages_skipped: report.images_skipped,
+            page_errors: report.page_errors.clone(),
+            removed_pages: report.removed_pages.clone(),
+            page_text: report.page_text.clone(),
+            message: report.status_message.clone(),
+            error: report.error_message.clone(),
+            duration_ms: duration.as_millis() as u64,
+            pages,
+        }
+    }
+}
+
+/// Run totals closing a JSON report. Skipped files count at their original
+/// size, as in the text summary
+#[derive(Debug, Default, Serialize)]
+struct Totals {
+    files: usize,
+    failed: usize,
+    original_size: u64,
+    output_size: u64,
+    pages_processed: usize,
+    pages_skipped: usize,
+    duration_ms: u64,
+}
+
+#[derive(Serialize)]
+struct JsonReport<'a> {
+    files: &'a [FileRecord],
+    totals: Totals,
+}
+
+/// Collects file records and writes them to stdout or `--report-file`
+pub struct ReportWriter {
+    format: ReportFormat,
+    out: Mutex<Box<dyn Write + Send>>,
+    records: Mutex<Vec<FileRecord>>,
+    started: Instant,
+}
+
+impl ReportWriter {
+    pub fn create(format: ReportFormat, path: Option<&Path>) -> Result<Self> {
+        let out: Box<dyn Write + Send> = match path {
+            Some(path) => Box::new(BufWriter::new(
+                File::create(path).with_context(|| format!("Failed to create report file {}", path.display()))?,
+            )),
+            None => Box::new(io::stdout()),
+        };
+        Ok(ReportWriter {
+            format,
+            out: Mutex::new(out),
+            records: Mutex::new(Vec::new()),
+            started: Instant::now(),
+        })
+    }
+
+    /// Add a finished file; NDJSON reports write its line right away
+    pub fn record(&self, record: FileRecord) -> Result<()> {
+        if self.format == ReportFormat::Ndjson {
+            let mut out = self.out.lock().unwrap();
+            serde_json::to_writer(&mut *out, &record)?;
+            writeln!(out)?;
+            out.flush()?;
+        } else {
+            self.records.lock().unwrap().push(record);
+        }
+        Ok(())
+    }
+
+    /// Write the JSON document (NDJSON lines are already out) and flush
+    pub fn finish(self) -> Result<()> {
+        let records = self.records.into_inner().unwrap();
+        let mut out = self.out.into_inner().unwrap();
+        if self.format == ReportFormat::Json {
+            let mut totals = Totals { duration_ms: self.started.elapsed().as_millis() as u64, ..Totals::default() };
+            for record in &records {
+                totals.files += 1;
+                if record.status == FileStatus::Failed {
+                    totals.failed += 1;
+                    continue;
+                }
+                totals.original_size += record.original_size;
+                totals.output_size += if record.status == FileStatus::Skipped {
+                    record.original_size
+                } else {
+                    record.output_size
+                };
+                totals.pages_processed += record.pages_processed;
+                totals.pages_skipped += record.pages_skipped;
+            }
+            serde_json::to_writer_pretty(&mut out, &JsonReport { files: &records, totals })?;
+            writeln!(out)?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+}
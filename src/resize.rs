@@ -0,0 +1,181 @@
+//! Lanczos3 resizing of 8-bit pages, the hot spot of a run: weights are
+//! computed once per output row and column instead of once per pixel, and
+//! applied in fixed point straight on the bytes (as Pillow does), in loops
+//! the compiler vectorises, rather than through `image`'s per-pixel float
+//! conversion. Two to six times faster than `resize_exact` on typical pages
+//! (see `cargo bench --bench resize`), within one level of its output.
+//! 16-bit and float pages still go through `image`.
+
+use image::imageops::FilterType;
+use image::{DynamicImage, GrayAlphaImage, GrayImage, RgbImage, RgbaImage};
+
+/// Fraction bits of the weights of the row pass; the sums of 8-bit values
+/// times weights (at most about 1.3 in total, with the negative lobes) fit an i32
+const ROW_PRECISION: u32 = 22;
+
+/// Fraction bits kept in the rows between the passes, unclamped, so that the
+/// overshoot at hard edges is not cut off before the column pass
+const BETWEEN_PRECISION: u32 = 6;
+
+/// Fraction bits of the weights of the column pass, which get the wider
+/// values of the rows in between
+const COLUMN_PRECISION: u32 = 14;
+
+/// `img` resized to exactly `width` × `height` with a Lanczos3 filter, like
+/// `img.resize_exact(width, height, FilterType::Lanczos3)`
+pub fn resize_lanczos3(img: &DynamicImage, width: u32, height: u32) -> DynamicImage {
+    let (src_width, src_height) = (img.width(), img.height());
+    if width == 0 || height == 0 || src_width == 0 || src_height == 0 {
+        return img.resize_exact(width, height, FilterType::Lanczos3);
+    }
+    let columns = Weights::new(src_width, width, ROW_PRECISION);
+    let rows = Weights::new(src_height, height, COLUMN_PRECISION);
+    match img {
+        DynamicImage::ImageLuma8(page) => {
+            let pixels = resize::<1>(page.as_raw(), src_width, src_height, &columns, &rows);
+            DynamicImage::ImageLuma8(GrayImage::from_raw(width, height, pixels).expect("resized buffer size"))
+        }
+        DynamicImage::ImageLumaA8(page) => {
+            let pixels = resize::<2>(page.as_raw(), src_width, src_height, &columns, &rows);
+            DynamicImage::ImageLumaA8(GrayAlphaImage::from_raw(width, height, pixels).expect("resized buffer size"))
+        }
+        DynamicImage::ImageRgb8(page) => {
+            let pixels = resize::<3>(page.as_raw(), src_width, src_height, &columns, &rows);
+            DynamicImage::ImageRgb8(RgbImage::from_raw(width, height, pixels).expect("resized buffer size"))
+        }
+        DynamicImage::ImageRgba8(page) => {
+            let pixels = resize::<4>(page.as_raw(), src_width, src_height, &columns, &rows);
+            DynamicImage::ImageRgba8(RgbaImage::from_raw(width, height, pixels).expect("resized buffer size"))
+        }
+        _ => img.resize_exact(width, height, FilterType::Lanczos3),
+    }
+}
+
+/// Rows first, then columns; an axis that keeps its size is copied as is
+fn resize<const C: usize>(pixels: &[u8], src_width: u32, src_height: u32, columns: &Weights, rows: &Weights) -> Vec<u8> {
+    let src_width = src_width as usize;
+    if rows.len() == src_height as usize {
+        return horizontal::<C, _>(pixels, src_width, columns, to_level);
+    }
+    let narrowed = if columns.len() == src_width {
+        pixels.iter().map(|&value| i16::from(value) << BETWEEN_PRECISION).collect()
+    } else {
+        horizontal::<C, _>(pixels, src_width, columns, to_between)
+    };
+    vertical(&narrowed, columns.len() * C, rows)
+}
+
+/// Resize every row of `pixels` to `columns.len()` pixels, storing the
+/// weighted sums with `store`
+fn horizontal<const C: usize, T: Copy + Default>(pixels: &[u8], src_width: usize, columns: &Weights, store: impl Fn(i32) -> T) -> Vec<T> {
+    let dst_width = columns.len();
+    let height = pixels.len() / (src_width * C);
+    let mut resized = vec![T::default(); dst_width * height * C];
+    for (source, row) in pixels.chunks_exact(src_width * C).zip(resized.chunks_exact_mut(dst_width * C)) {
+        for (x, pixel) in row.chunks_exact_mut(C).enumerate() {
+            let (start, weights) = columns.window(x);
+            let mut sums = [0; C];
+            for (value, &weight) in source[start * C..].chunks_exact(C).zip(weights) {
+                for channel in 0..C {
+                    sums[channel] += i32::from(value[channel]) * weight;
+                }
+            }
+            for channel in 0..C {
+                pixel[channel] = store(sums[channel]);
+            }
+        }
+    }
+    resized
+}
+
+/// Resize the columns of `pixels`, rows of `row_len` values, to `rows.len()` rows
+fn vertical(pixels: &[i16], row_len: usize, rows: &Weights) -> Vec<u8> {
+    const SHIFT: u32 = COLUMN_PRECISION + BETWEEN_PRECISION;
+    let mut resized = vec![0; rows.len() * row_len];
+    let mut sums = vec![0i32; row_len];
+    for (y, row) in resized.chunks_exact_mut(row_len).enumerate() {
+        let (start, weights) = rows.window(y);
+        sums.fill(1 << (SHIFT - 1));
+        for (source, &weight) in pixels[start * row_len..].chunks_exact(row_len).zip(weights) {
+            for (sum, &value) in sums.iter_mut().zip(source) {
+                *sum += i32::from(value) * weight;
+            }
+        }
+        for (value, &sum) in row.iter_mut().zip(&sums) {
+            *value = (sum >> SHIFT).clamp(0, 255) as u8;
+        }
+    }
+    resized
+}
+
+/// A row-pass sum as a final 8-bit level
+fn to_level(sum: i32) -> u8 {
+    ((sum + (1 << (ROW_PRECISION - 1))) >> ROW_PRECISION).clamp(0, 255) as u8
+}
+
+/// A row-pass sum as a value for the column pass
+fn to_between(sum: i32) -> i16 {
+    const SHIFT: u32 = ROW_PRECISION - BETWEEN_PRECISION;
+    ((sum + (1 << (SHIFT - 1))) >> SHIFT).clamp(i16::MIN.into(), i16::MAX.into()) as i16
+}
+
+/// The fixed-point Lanczos3 weights of every output pixel along one axis
+struct Weights {
+    /// Source window of each output pixel: first pixel and number of pixels
+    windows: Vec<(usize, usize)>,
+    /// `taps` slots per output pixel, of which its window uses the first
+    weights: Vec<i32>,
+    taps: usize,
+}
+
+impl Weights {
+    /// Weights from `src_len` source pixels to `dst_len` output pixels with
+    /// `precision` fraction bits, the kernel widened when shrinking so that
+    /// every source pixel counts, as in `image`'s resize
+    fn new(src_len: u32, dst_len: u32, precision: u32) -> Weights {
+        let ratio = src_len as f32 / dst_len as f32;
+        let scale = ratio.max(1.0);
+        let support = 3.0 * scale;
+        let taps = (2.0 * support).ceil() as usize + 2;
+        let mut windows = Vec::with_capacity(dst_len as usize);
+        let mut weights = vec![0; dst_len as usize * taps];
+        let mut window = Vec::with_capacity(taps);
+        for (i, fixed) in weights.chunks_exact_mut(taps).enumerate() {
+            let center = (i as f32 + 0.5) * ratio;
+            let left = (center - support).floor().max(0.0) as usize;
+            let right = ((center + support).ceil() as usize).clamp(left + 1, src_len as usize).min(left + taps);
+            window.clear();
+            window.extend((left..right).map(|j| lanczos3((j as f32 + 0.5 - center) / scale)));
+            let total: f32 = window.iter().sum();
+            for (fixed, weight) in fixed.iter_mut().zip(&window) {
+                *fixed = (weight / total * (1 << precision) as f32).round() as i32;
+            }
+            windows.push((left, window.len()));
+        }
+        Weights { windows, weights, taps }
+    }
+
+    fn len(&self) -> usize {
+        self.windows.len()
+    }
+
+    fn window(&self, i: usize) -> (usize, &[i32]) {
+        let (start, len) = self.windows[i];
+        (start, &self.weights[i * self.taps..i * self.taps + len])
+    }
+}
+
+fn lanczos3(x: f32) -> f32 {
+    if x.abs() >= 3.0 {
+        return 0.0;
+    }
+    sinc(x) * sinc(x / 3.0)
+}
+
+fn sinc(x: f32) -> f32 {
+    if x == 0.0 {
+        return 1.0;
+    }
+    let a = x * std::f32::consts::PI;
+    a.sin() / a
+}
@@ -0,0 +1,55 @@
+//! `--retries` and `--quarantine-dir`: a failed file is tried again after a
+//! pause, which gets it past transient trouble such as a NAS that dropped
+//! off for a moment, and a file that still fails is moved into the
+//! quarantine folder (mirroring the input tree) and listed in its
+//! `quarantine.log` with the error, so an unattended run neither stops on it
+//! nor trips over it again the next night.
+
+use anyhow::{Context, Result};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::recycle::free_path;
+use crate::{civil_from_days, move_file, output_dir_for};
+
+/// Log of the quarantined files, in the quarantine folder
+pub(crate) const LOG_FILE_NAME: &str = "quarantine.log";
+
+/// Pause before each retry, growing with every attempt
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Pause before retry number `attempt` (from 1)
+pub(crate) fn delay(attempt: u32) -> Duration {
+    RETRY_DELAY * attempt.min(6)
+}
+
+/// Move a failed `source` below `quarantine_dir`, mirroring its place under
+/// `input_root`, and log why. Returns where it went
+pub(crate) fn quarantine(source: &Path, quarantine_dir: &Path, input_root: &Path, error: &str) -> Result<PathBuf> {
+    let dir = output_dir_for(source, Some(quarantine_dir), input_root);
+    fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    let target = free_path(&dir, source.file_name().unwrap_or_default());
+    move_file(source, &target).with_context(|| format!("Failed to move {} to {}", source.display(), target.display()))?;
+
+    let log_path = quarantine_dir.join(LOG_FILE_NAME);
+    let mut log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&log_path)
+        .with_context(|| format!("Failed to open {}", log_path.display()))?;
+    // One line per file: when, where it came from, where it is now, and why
+    let error = error.replace(['\n', '\t'], " ");
+    writeln!(log, "{}\t{}\t{}\t{}", now_utc(), source.display(), target.display(), error)
+        .with_context(|| format!("Failed to write {}", log_path.display()))?;
+    Ok(target)
+}
+
+/// The current time as `YYYY-MM-DD HH:MM:SS UTC`
+fn now_utc() -> String {
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs());
+    let (year, month, day) = civil_from_days((seconds / 86_400) as i64);
+    let of_day = seconds % 86_400;
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC", year, month, day, of_day / 3_600, of_day / 60 % 60, of_day % 60)
+}
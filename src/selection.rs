@@ -0,0 +1,104 @@
+//! `--min-size`, `--max-size`, `--newer-than` and `--older-than`: select the
+//! found comic files by size and modification time, e.g. only the bloated
+//! archives (`--min-size 300M`) or only what was added since the last run
+//! (`--newer-than 7d`, or `--newer-than last-run.stamp` to compare with a
+//! file's modification time, like `find -newer`).
+
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::{times, Options};
+
+/// Whether `path` passes the size and date filters of `args`; files that
+/// cannot be read are left for processing to report
+pub(crate) fn matches(path: &Path, args: &Options) -> bool {
+    let Ok(metadata) = fs::metadata(path) else {
+        return true;
+    };
+    let size = metadata.len();
+    if args.min_size.is_some_and(|min| size < min) || args.max_size.is_some_and(|max| size > max) {
+        return false;
+    }
+    if args.newer_than.is_none() && args.older_than.is_none() {
+        return true;
+    }
+    let Ok(modified) = metadata.modified() else {
+        return true;
+    };
+    args.newer_than.is_none_or(|newer| modified > newer) && args.older_than.is_none_or(|older| modified < older)
+}
+
+/// Parse a file size: a number of MB, or with a `K`, `M`, `G` or `T` suffix
+/// (binary units, optional `B`/`iB`). Returns bytes
+pub(crate) fn parse_size(value: &str) -> Result<u64, String> {
+    let upper = value.trim().to_ascii_uppercase();
+    let number = upper.trim_end_matches("IB").trim_end_matches('B');
+    let (digits, unit) = match number.chars().last() {
+        Some('K') => (&number[..number.len() - 1], 1u64 << 10),
+        Some('M') => (&number[..number.len() - 1], 1 << 20),
+        Some('G') => (&number[..number.len() - 1], 1 << 30),
+        Some('T') => (&number[..number.len() - 1], 1 << 40),
+        _ => (number, 1 << 20),
+    };
+    match digits.trim().parse::<f64>() {
+        Ok(amount) if amount >= 0.0 && amount.is_finite() => Ok((amount * unit as f64).round() as u64),
+        _ => Err("expected a size such as 300M, 1.5G or 800K".to_string()),
+    }
+}
+
+/// Parse a point in time: a UTC date (`2026-10-01`, or `2026-10-01T18:30`),
+/// an age before now (`30m`, `12h`, `7d`, `2w`), or the modification time of
+/// an existing file
+pub(crate) fn parse_time(value: &str) -> Result<SystemTime, String> {
+    if let Some(time) = parse_date(value) {
+        return Ok(time);
+    }
+    if let Some(age) = parse_age(value) {
+        return SystemTime::now().checked_sub(age).ok_or_else(|| format!("age '{}' is too large", value));
+    }
+    match fs::metadata(value).and_then(|metadata| metadata.modified()) {
+        Ok(modified) => Ok(modified),
+        Err(_) => Err("expected a date (2026-10-01 or 2026-10-01T18:30), an age (12h, 7d, 2w) or an existing file".to_string()),
+    }
+}
+
+/// `YYYY-MM-DD` with an optional `THH:MM[:SS]` (or a space instead of `T`), in UTC
+fn parse_date(value: &str) -> Option<SystemTime> {
+    let (date, time) = value.split_once(['T', ' ']).unwrap_or((value, ""));
+    let mut parts = date.splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: u32 = parts.next()?.parse().ok()?;
+    let day: u32 = parts.next()?.parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let mut seconds = 0;
+    if !time.is_empty() {
+        let fields = time.split(':').map(|field| field.parse::<u32>().ok()).collect::<Option<Vec<_>>>()?;
+        let (hour, minute, second) = match fields.as_slice() {
+            [hour, minute] => (*hour, *minute, 0),
+            [hour, minute, second] => (*hour, *minute, *second),
+            _ => return None,
+        };
+        if hour > 23 || minute > 59 || second > 59 {
+            return None;
+        }
+        seconds = hour as i64 * 3_600 + minute as i64 * 60 + second as i64;
+    }
+    let seconds = times::days_from_civil(year, month, day) * 86_400 + seconds;
+    UNIX_EPOCH.checked_add(Duration::from_secs(u64::try_from(seconds).ok()?))
+}
+
+/// A number of minutes, hours, days or weeks: `30m`, `12h`, `7d`, `2w`
+fn parse_age(value: &str) -> Option<Duration> {
+    let unit = match value.chars().last()? {
+        'm' => 60,
+        'h' => 3_600,
+        'd' => 86_400,
+        'w' => 7 * 86_400,
+        _ => return None,
+    };
+    let amount: u64 = value[..value.len() - 1].parse().ok()?;
+    Some(Duration::from_secs(amount.checked_mul(unit)?))
+}
@@ -0,0 +1,224 @@
+//! `serve`: a small HTTP API in front of an in-memory job queue, for tools
+//! (e.g. library post-processing hooks) that want to hand over files instead
+//! of spawning processes.
+//!
+//! - `POST /jobs` with `{"path": "..."}` queues a file on the server's disk;
+//!   any other body is an upload, named by the `name` query parameter
+//! - `GET /jobs` lists jobs, `GET /jobs/{id}` returns one with its report
+//! - `GET /jobs/{id}/output` downloads the finished output
+
+use anyhow::Result;
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::report::FileRecord;
+use crate::{detect_comic_file, Pipeline};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum JobStatus {
+    Queued,
+    Running,
+    Done,
+    Failed,
+}
+
+#[derive(Debug, Serialize)]
+struct Job {
+    id: usize,
+    path: PathBuf,
+    status: JobStatus,
+    error: Option<String>,
+    report: Option<FileRecord>,
+}
+
+#[derive(Deserialize)]
+struct SubmitPath {
+    path: PathBuf,
+}
+
+/// Jobs in submission order; a job's id is its index plus one
+type Jobs = Arc<Mutex<Vec<Job>>>;
+
+/// Serve until the listener fails. `workers` jobs run at a time; uploads are
+/// stored below `upload_dir`
+pub fn run(listen: &str, upload_dir: &Path, workers: usize, pipeline: Pipeline) -> Result<ExitCode> {
+    let server = Server::http(listen).map_err(|e| anyhow::anyhow!("Failed to listen on {}: {}", listen, e))?;
+    let jobs: Jobs = Arc::new(Mutex::new(Vec::new()));
+    let (queue, queued) = unbounded::<usize>();
+
+    for _ in 0..workers {
+        let (jobs, queued, pipeline) = (Arc::clone(&jobs), queued.clone(), pipeline.clone());
+        thread::spawn(move || work(&jobs, &queued, &pipeline));
+    }
+
+    println!("🌐 Serving on http://{} ({} worker(s))", listen, workers);
+    for mut request in server.incoming_requests() {
+        let reply = handle(&mut request, &jobs, &queue, upload_dir)
+            .unwrap_or_else(|e| Reply::Json(400, serde_json::json!({ "error": format!("{:#}", e) })));
+        let result = match reply {
+            Reply::Json(status, body) => request.respond(json_response(status, &body)),
+            Reply::File(path) => match File::open(&path) {
+                Ok(file) => request.respond(Response::from_file(file)),
+                Err(e) => request.respond(json_response(500, &serde_json::json!({ "error": e.to_string() }))),
+            },
+        };
+        if let Err(e) = result {
+            eprintln!("Warning: Failed to send response: {}", e);
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+enum Reply {
+    Json(u16, serde_json::Value),
+    File(PathBuf),
+}
+
+/// Route a request. Requests are handled one at a time, so an upload can
+/// take the id of the job it is about to become
+fn handle(request: &mut Request, jobs: &Jobs, queue: &Sender<usize>, upload_dir: &Path) -> Result<Reply> {
+    let url = request.url().to_string();
+    let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+    match (request.method(), segments.as_slice()) {
+        (Method::Post, ["jobs"]) => {
+            let is_json = request.headers().iter().any(|header| {
+                header.field.equiv("Content-Type") && header.value.as_str().starts_with("application/json")
+            });
+            let path = if is_json {
+                let mut body = String::new();
+                request.as_reader().read_to_string(&mut body)?;
+                serde_json::from_str::<SubmitPath>(&body)?.path
+            } else {
+                let name = query_param(query, "name")
+                    .ok_or_else(|| anyhow::anyhow!("Uploads need a ?name=<file name> query parameter"))?;
+                let id = jobs.lock().unwrap().len() + 1;
+                store_upload(request.as_reader(), upload_dir, id, &name)?
+            };
+            detect_comic_file(&path)?;
+            if !path.is_file() {
+                anyhow::bail!("No such file: {}", path.display());
+            }
+
+            let mut jobs = jobs.lock().unwrap();
+            let id = jobs.len() + 1;
+            jobs.push(Job { id, path, status: JobStatus::Queued, error: None, report: None });
+            queue.send(id)?;
+            Ok(Reply::Json(202, serde_json::to_value(&jobs[id - 1])?))
+        }
+        (Method::Get, ["jobs"]) => Ok(Reply::Json(200, serde_json::to_value(&*jobs.lock().unwrap())?)),
+        (Method::Get, ["jobs", id]) => match find_job(jobs, id, |job| serde_json::to_value(job)) {
+            Some(job) => Ok(Reply::Json(200, job?)),
+            None => Ok(not_found("No such job")),
+        },
+        (Method::Get, ["jobs", id, "output"]) => {
+            let output = find_job(jobs, id, |job| job.report.as_ref().and_then(|report| report.output_path.clone()));
+            match output {
+                Some(Some(output)) => Ok(Reply::File(output)),
+                Some(None) => Ok(Reply::Json(409, serde_json::json!({ "error": "Job has no output (yet)" }))),
+                None => Ok(not_found("No such job")),
+            }
+        }
+        _ => Ok(not_found("Not found")),
+    }
+}
+
+fn not_found(message: &str) -> Reply {
+    Reply::Json(404, serde_json::json!({ "error": message }))
+}
+
+fn find_job<T>(jobs: &Jobs, id: &str, f: impl FnOnce(&Job) -> T) -> Option<T> {
+    let index = id.parse::<usize>().ok()?.checked_sub(1)?;
+    jobs.lock().unwrap().get(index).map(f)
+}
+
+/// Save an upload as `upload_dir/<id>/<file name>`; directory parts of `name` are dropped
+fn store_upload(body: &mut dyn Read, upload_dir: &Path, id: usize, name: &str) -> Result<PathBuf> {
+    let file_name = Path::new(name)
+        .file_name()
+        .ok_or_else(|| anyhow::anyhow!("Invalid upload name: {}", name))?;
+    let dir = upload_dir.join(id.to_string());
+    fs::create_dir_all(&dir)?;
+    let path = dir.join(file_name);
+    io::copy(body, &mut File::create(&path)?)?;
+    Ok(path)
+}
+
+fn query_param(query: &str, key: &str) -> Option<String> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(k, _)| *k == key)
+        .map(|(_, value)| percent_decode(value))
+}
+
+/// Decode `%XX` escapes and `+` in a query value
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+                match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                    Some(byte) => {
+                        decoded.push(byte);
+                        i += 3;
+                        continue;
+                    }
+                    None => decoded.push(b'%'),
+                }
+            }
+            b'+' => decoded.push(b' '),
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn json_response(status: u16, body: &serde_json::Value) -> Response<io::Cursor<Vec<u8>>> {
+    Response::from_string(body.to_string())
+        .with_status_code(status)
+        .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
+}
+
+/// Worker loop: run queued jobs one at a time
+fn work(jobs: &Jobs, queued: &Receiver<usize>, pipeline: &Pipeline) {
+    for id in queued {
+        let path = {
+            let mut jobs = jobs.lock().unwrap();
+            jobs[id - 1].status = JobStatus::Running;
+            jobs[id - 1].path.clone()
+        };
+
+        let started = Instant::now();
+        let mut events = pipeline.process_file_events(&path);
+        let pages: Vec<_> = events.by_ref().collect();
+        let result = events.finish();
+
+        let mut jobs = jobs.lock().unwrap();
+        let job = &mut jobs[id - 1];
+        match result {
+            Ok(report) => {
+                job.status = JobStatus::Done;
+                job.report = Some(FileRecord::new(&path, &report, started.elapsed(), pages));
+            }
+            Err(e) => {
+                job.status = JobStatus::Failed;
+                job.error = Some(format!("{:#}", e));
+            }
+        }
+    }
+}
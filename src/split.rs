@@ -0,0 +1,183 @@
+//! `split`: cut one comic into several CBZ files, by page ranges, at the
+//! chapter markers in page names (`c001`, `ch02`, `Chapter 3/`), or before a
+//! part grows past a size. Pages are renumbered from 001 in each part, and
+//! each part gets a ComicInfo.xml: the source's, when it has one, with its
+//! `<Pages>` entries narrowed to the part, the part's number and the series.
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use crate::comicinfo::{self, ComicInfo, COMICINFO_FILE_NAME};
+use crate::{create_zip_archive, detect_comic_file, page_name, temp, times, unpack_pages, update_comicinfo, ZipCompression};
+
+/// How `split` cuts the comic
+pub(crate) enum Mode {
+    /// 1-based page ranges, e.g. "1-24,25-50,51-"
+    Ranges(String),
+    /// A part per chapter marker
+    Chapters,
+    /// Parts of at most this many bytes of pages
+    MaxSize(u64),
+}
+
+/// One output file: source page indices and the number its ComicInfo.xml
+/// gives it (the chapter number with `--chapters`)
+struct Part {
+    pages: Vec<usize>,
+    number: u32,
+}
+
+/// Split `file` into parts written to `output_dir` (default: next to the file)
+pub(crate) fn run(file: &Path, mode: &Mode, output_dir: Option<&Path>) -> Result<ExitCode> {
+    let comic_file = detect_comic_file(file)?;
+    let work_dir = temp::create_dir_in(None)?;
+    let pages = unpack_pages(&comic_file, work_dir.path()).with_context(|| format!("Failed to read {}", file.display()))?;
+    if pages.is_empty() {
+        anyhow::bail!("{} has no pages", file.display());
+    }
+
+    let parts = match mode {
+        Mode::Ranges(ranges) => by_ranges(ranges, pages.len())?,
+        Mode::Chapters => by_chapters(&pages.iter().map(|page| page_name(page, work_dir.path())).collect::<Vec<_>>())?,
+        Mode::MaxSize(max_bytes) => by_size(&pages, *max_bytes)?,
+    };
+
+    let stem = file.file_stem().unwrap_or_default().to_string_lossy().to_string();
+    let output_dir = output_dir.map(Path::to_path_buf).unwrap_or_else(|| file.parent().unwrap_or(Path::new(".")).to_path_buf());
+    fs::create_dir_all(&output_dir).with_context(|| format!("Failed to create {}", output_dir.display()))?;
+    // Named in order, as chapter numbers can repeat (a `c001` in every volume)
+    let digits = parts.len().to_string().len().max(2);
+    let outputs: Vec<PathBuf> = (1..=parts.len())
+        .map(|index| output_dir.join(format!("{} - Part {:0width$}.cbz", stem, index, width = digits)))
+        .collect();
+    if let Some(existing) = outputs.iter().find(|output| output.exists()) {
+        anyhow::bail!("{} already exists", existing.display());
+    }
+
+    let source_info = comicinfo::find_in_dir(work_dir.path()).map(|path| ComicInfo::load(&path)).transpose()?;
+    for (part, output) in parts.iter().zip(&outputs) {
+        write_part(part, parts.len(), &pages, source_info.as_ref(), &stem, output)
+            .with_context(|| format!("Failed to write {}", output.display()))?;
+        println!("📦 {} ({} pages)", output.display(), part.pages.len());
+    }
+    println!("✂️  Split {} pages into {} parts", pages.len(), parts.len());
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Lay out one part with renumbered pages and its ComicInfo.xml, then zip it
+fn write_part(part: &Part, count: usize, pages: &[PathBuf], source_info: Option<&ComicInfo>, stem: &str, output: &Path) -> Result<()> {
+    let part_dir = temp::create_dir_in(None)?;
+    let digits = part.pages.len().to_string().len().max(3);
+    for (number, &index) in part.pages.iter().enumerate() {
+        let page = &pages[index];
+        let extension = page.extension().map(|ext| ext.to_string_lossy().to_lowercase()).unwrap_or_default();
+        fs::copy(page, part_dir.path().join(format!("{:0width$}.{}", number + 1, extension, width = digits)))?;
+    }
+
+    let mut info = source_info.cloned().unwrap_or_else(ComicInfo::new);
+    info.select_pages(&part.pages);
+    let series = info.fields().into_iter().find(|(name, _)| name == "Series").map(|(_, series)| series);
+    if series.is_none_or(|series| series.is_empty()) {
+        info.set_field("Series", stem);
+    }
+    info.set_field("Number", &part.number.to_string());
+    info.set_field("Count", &count.to_string());
+    info.save(&part_dir.path().join(COMICINFO_FILE_NAME))?;
+    update_comicinfo(part_dir.path(), false, false)?;
+
+    let partial = output.with_extension("cbz.partial");
+    create_zip_archive(part_dir.path(), &partial, "", ZipCompression::Auto, times::EntryDates::Now)
+        .inspect_err(|_| {
+            let _ = fs::remove_file(&partial);
+        })?;
+    fs::rename(&partial, output)?;
+    Ok(())
+}
+
+/// Parts from comma-separated 1-based ranges: `N`, `N-M` or `N-` (to the last page)
+fn by_ranges(ranges: &str, page_count: usize) -> Result<Vec<Part>> {
+    let parts = ranges
+        .split(',')
+        .map(str::trim)
+        .filter(|range| !range.is_empty())
+        .enumerate()
+        .map(|(index, range)| {
+            let invalid = || anyhow::anyhow!("Invalid page range {} (the comic has {} pages)", range, page_count);
+            let (first, last) = match range.split_once('-') {
+                Some((first, "")) => (first.trim().parse().map_err(|_| invalid())?, page_count),
+                Some((first, last)) => {
+                    (first.trim().parse().map_err(|_| invalid())?, last.trim().parse().map_err(|_| invalid())?)
+                }
+                None => {
+                    let page = range.parse().map_err(|_| invalid())?;
+                    (page, page)
+                }
+            };
+            if first == 0 || first > last || last > page_count {
+                return Err(invalid());
+            }
+            Ok(Part { pages: (first - 1..last).collect(), number: index as u32 + 1 })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if parts.is_empty() {
+        anyhow::bail!("No page ranges given");
+    }
+    Ok(parts)
+}
+
+/// A part per chapter: a new one starts where the chapter marker of the page
+/// names changes. Pages without a marker stay in the part before them (or the
+/// first, for a cover)
+fn by_chapters(names: &[String]) -> Result<Vec<Part>> {
+    let chapters: Vec<Option<u32>> = names.iter().map(|name| chapter_number(name)).collect();
+    let Some(first) = chapters.iter().flatten().next() else {
+        anyhow::bail!("No chapter markers (c001, ch02, Chapter 3) found in the page names");
+    };
+    let mut parts = vec![Part { pages: Vec::new(), number: *first }];
+    for (index, chapter) in chapters.iter().enumerate() {
+        let current = parts.last_mut().unwrap();
+        match chapter {
+            Some(chapter) if *chapter != current.number && !current.pages.is_empty() => {
+                parts.push(Part { pages: vec![index], number: *chapter })
+            }
+            _ => current.pages.push(index),
+        }
+    }
+    Ok(parts)
+}
+
+/// The chapter number in a page path: `c`, `ch`, `chap` or `chapter`, not
+/// preceded by a letter, then an optional separator and digits
+fn chapter_number(name: &str) -> Option<u32> {
+    let name = name.to_lowercase();
+    let bytes = name.as_bytes();
+    (0..bytes.len())
+        .filter(|&start| name.is_char_boundary(start) && (start == 0 || !bytes[start - 1].is_ascii_alphabetic()))
+        .find_map(|start| {
+            let rest = &name[start..];
+            let rest = ["chapter", "chap", "ch", "c"].iter().find_map(|prefix| rest.strip_prefix(prefix))?;
+            let rest = rest.strip_prefix([' ', '.', '_', '-']).unwrap_or(rest);
+            let digits: String = rest.chars().take_while(char::is_ascii_digit).collect();
+            digits.parse().ok()
+        })
+}
+
+/// Parts of consecutive pages, each as many as fit in `max_bytes` (at least one page)
+fn by_size(pages: &[PathBuf], max_bytes: u64) -> Result<Vec<Part>> {
+    let mut parts: Vec<Part> = Vec::new();
+    let mut size = 0;
+    for (index, page) in pages.iter().enumerate() {
+        let page_size = fs::metadata(page)?.len();
+        match parts.last_mut() {
+            Some(part) if size + page_size <= max_bytes => part.pages.push(index),
+            _ => {
+                parts.push(Part { pages: vec![index], number: parts.len() as u32 + 1 });
+                size = 0;
+            }
+        }
+        size += page_size;
+    }
+    Ok(parts)
+}
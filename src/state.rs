@@ -0,0 +1,186 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+/// Default state file name, created in the input directory
+pub const STATE_FILE_NAME: &str = ".compress_comics_state.json";
+
+const STATE_VERSION: u32 = 1;
+
+/// Persistent record of files finished by earlier (possibly interrupted) runs
+#[derive(Debug, Serialize, Deserialize)]
+struct JobState {
+    version: u32,
+    files: BTreeMap<String, StateEntry>,
+}
+
+impl Default for JobState {
+    fn default() -> Self {
+        JobState {
+            version: STATE_VERSION,
+            files: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StateEntry {
+    pub size: u64,
+    pub modified: u64,
+    pub sha256: String,
+    /// Settings fingerprint the file was processed with
+    pub settings: String,
+    pub output: Option<FileFingerprint>,
+    pub completed_at: u64,
+}
+
+/// Identity of a file produced by a run, so an in-place replacement is not
+/// mistaken for a new source
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileFingerprint {
+    pub path: String,
+    pub size: u64,
+    pub modified: u64,
+}
+
+/// A state file shared by all worker threads; every update is flushed to disk
+pub struct StateFile {
+    path: PathBuf,
+    state: Mutex<JobState>,
+}
+
+impl StateFile {
+    pub fn load(path: &Path) -> Result<Self> {
+        let state = if path.exists() {
+            let content = fs::read_to_string(path)
+                .with_context(|| format!("Failed to read state file {}", path.display()))?;
+            serde_json::from_str(&content)
+                .with_context(|| format!("Failed to parse state file {}", path.display()))?
+        } else {
+            JobState::default()
+        };
+
+        Ok(StateFile {
+            path: path.to_path_buf(),
+            state: Mutex::new(state),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether `path` was completed with the same settings and is unchanged
+    /// since (either the source itself or the output that replaced it)
+    pub fn is_completed(&self, path: &Path, settings: &str) -> bool {
+        let state = self.state.lock().unwrap();
+        let Some(entry) = state.files.get(&state_key(path)) else {
+            return false;
+        };
+        if entry.settings != settings {
+            return false;
+        }
+        let Ok((size, modified)) = size_and_mtime(path) else {
+            return false;
+        };
+        let source_matches = entry.size == size && entry.modified == modified;
+        let output_matches = entry.output.as_ref().is_some_and(|output| {
+            Path::new(&output.path) == absolute(path) && output.size == size && output.modified == modified
+        });
+        source_matches || output_matches
+    }
+
+    pub fn record(&self, path: &Path, entry: StateEntry) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.files.insert(state_key(path), entry);
+
+        // Write-then-rename so a crash never leaves a truncated state file
+        let temp_path = self.path.with_extension("json.tmp");
+        fs::write(&temp_path, serde_json::to_string_pretty(&*state)?)
+            .with_context(|| format!("Failed to write state file {}", temp_path.display()))?;
+        fs::rename(&temp_path, &self.path)
+            .with_context(|| format!("Failed to update state file {}", self.path.display()))?;
+        Ok(())
+    }
+}
+
+/// Size, mtime and hash of a source file, taken before it is processed
+#[derive(Debug, Clone)]
+pub struct SourceFingerprint {
+    pub size: u64,
+    pub modified: u64,
+    pub sha256: String,
+}
+
+pub fn fingerprint_source(path: &Path) -> Result<SourceFingerprint> {
+    let (size, modified) = size_and_mtime(path)?;
+    Ok(SourceFingerprint {
+        size,
+        modified,
+        sha256: sha256_file(path)?,
+    })
+}
+
+pub fn entry_for(source: &SourceFingerprint, settings: &str, output: Option<&Path>) -> Result<StateEntry> {
+    let output = output.map(fingerprint_file).transpose()?;
+
+    Ok(StateEntry {
+        size: source.size,
+        modified: source.modified,
+        sha256: source.sha256.clone(),
+        settings: settings.to_string(),
+        output,
+        completed_at: std::time::SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+    })
+}
+
+/// Absolute path, size and mtime of a file on disk
+pub fn fingerprint_file(path: &Path) -> Result<FileFingerprint> {
+    let (size, modified) = size_and_mtime(path)?;
+    Ok(FileFingerprint {
+        path: absolute(path).to_string_lossy().into_owned(),
+        size,
+        modified,
+    })
+}
+
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 1 << 20];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+fn size_and_mtime(path: &Path) -> Result<(u64, u64)> {
+    let metadata = fs::metadata(path)?;
+    let modified = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    Ok((metadata.len(), modified))
+}
+
+fn absolute(path: &Path) -> PathBuf {
+    std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn state_key(path: &Path) -> String {
+    absolute(path).to_string_lossy().into_owned()
+}
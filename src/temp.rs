@@ -0,0 +1,172 @@
+//! Temporary extraction directories: `--temp-dir` chooses where they go, and
+//! a pre-flight check refuses files whose extracted pages would not fit,
+//! instead of failing halfway through extraction.
+//!
+//! Every directory is named `compress_comics-<pid>-<random>`, which makes the
+//! temp folders their own registry: a run sweeps away the directories of
+//! processes that no longer exist (crashed or killed runs) when it starts,
+//! and the `clean` subcommand does the same on demand.
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tempfile::TempDir;
+use walkdir::WalkDir;
+
+use crate::{ComicFile, ComicType, Options};
+
+const DIR_PREFIX: &str = "compress_comics-";
+
+/// Age after which a directory counts as left behind where process IDs
+/// cannot be checked
+#[cfg(not(unix))]
+const ORPHAN_AGE: std::time::Duration = std::time::Duration::from_secs(24 * 60 * 60);
+
+/// Create the extraction directory for one file, under `--temp-dir` when given
+pub(crate) fn create_dir(args: &Options) -> Result<TempDir> {
+    create_dir_in(args.temp_dir.as_deref())
+}
+
+/// Create a temporary directory under `root`, or the system temp directory
+pub(crate) fn create_dir_in(root: Option<&Path>) -> Result<TempDir> {
+    let root = root.map(Path::to_path_buf).unwrap_or_else(std::env::temp_dir);
+    tempfile::Builder::new()
+        .prefix(&format!("{}{}-", DIR_PREFIX, std::process::id()))
+        .tempdir_in(&root)
+        .with_context(|| format!("Failed to create temporary directory in {}", root.display()))
+}
+
+/// Directories removed by a sweep and the bytes they held
+#[derive(Debug, Default)]
+pub(crate) struct Swept {
+    pub dirs: usize,
+    pub bytes: u64,
+}
+
+/// Remove the temporary directories of runs that are no longer running, from
+/// the system temp directory and `temp_dir`. Directories that cannot be
+/// removed are skipped with a warning
+pub(crate) fn sweep(temp_dir: Option<&Path>) -> Swept {
+    let mut roots = vec![std::env::temp_dir()];
+    roots.extend(temp_dir.map(Path::to_path_buf));
+    roots.dedup();
+
+    let mut swept = Swept::default();
+    for dir in roots.iter().flat_map(|root| orphaned_dirs(root)) {
+        let bytes = dir_size(&dir);
+        match fs::remove_dir_all(&dir) {
+            Ok(()) => {
+                swept.dirs += 1;
+                swept.bytes += bytes;
+            }
+            Err(e) => eprintln!("Warning: Could not remove leftover {}: {}", dir.display(), e),
+        }
+    }
+    swept
+}
+
+fn orphaned_dirs(root: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(root) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_ok_and(|t| t.is_dir()))
+        .filter(|entry| {
+            let name = entry.file_name();
+            let Some(pid) = name.to_str().and_then(owner_pid) else {
+                return false;
+            };
+            let modified = entry.metadata().and_then(|m| m.modified()).unwrap_or_else(|_| SystemTime::now());
+            pid != std::process::id() && is_orphaned(pid, modified)
+        })
+        .map(|entry| entry.path())
+        .collect()
+}
+
+/// Process ID in a directory name made by `create_dir_in`
+fn owner_pid(name: &str) -> Option<u32> {
+    name.strip_prefix(DIR_PREFIX)?.split_once('-')?.0.parse().ok()
+}
+
+#[cfg(unix)]
+fn is_orphaned(pid: u32, _modified: SystemTime) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // SAFETY: signal 0 only checks whether the process exists
+    if unsafe { libc::kill(pid, 0) } == 0 {
+        return false;
+    }
+    // EPERM: it exists, but belongs to another user
+    std::io::Error::last_os_error().raw_os_error() == Some(libc::ESRCH)
+}
+
+#[cfg(not(unix))]
+fn is_orphaned(_pid: u32, modified: SystemTime) -> bool {
+    modified.elapsed().is_ok_and(|age| age > ORPHAN_AGE)
+}
+
+fn dir_size(dir: &Path) -> u64 {
+    WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.metadata().ok())
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+/// Rough temporary space needed to extract and re-encode a file. Archive pages
+/// are already compressed; PDF images are stored as PNG when they are not
+/// JPEG, and DjVu pages are rendered to uncompressed images first
+fn required_space(comic_file: &ComicFile, original_size: u64) -> u64 {
+    let factor = match comic_file.file_type {
+        ComicType::Pdf => 3,
+        ComicType::Djvu => 20,
+        _ => 2,
+    };
+    original_size.saturating_mul(factor)
+}
+
+/// Fail when the filesystem holding `dir` has less free space than extracting
+/// `comic_file` is estimated to need. Filesystems whose free space cannot be
+/// read are not checked
+pub(crate) fn check_space(dir: &Path, comic_file: &ComicFile, original_size: u64) -> Result<()> {
+    let required = required_space(comic_file, original_size);
+    let Some(available) = available_space(dir) else {
+        return Ok(());
+    };
+    if available < required {
+        let mb = |bytes: u64| bytes as f64 / 1_048_576.0;
+        anyhow::bail!(
+            "Not enough temporary space in {}: extracting needs about {:.0} MB, {:.0} MB free (choose another location with --temp-dir)",
+            dir.parent().unwrap_or(dir).display(),
+            mb(required),
+            mb(available)
+        );
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn available_space(dir: &Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(dir.as_os_str().as_bytes()).ok()?;
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `path` is NUL-terminated and `stat` is only read after statvfs filled it in
+    if unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    let stat = unsafe { stat.assume_init() };
+    // The field types differ between platforms
+    #[allow(clippy::unnecessary_cast)]
+    Some((stat.f_bavail as u64).saturating_mul(stat.f_frsize as u64))
+}
+
+#[cfg(not(unix))]
+fn available_space(_dir: &Path) -> Option<u64> {
+    None
+}
@@ -0,0 +1,134 @@
+//! `--io-throttle` and `--nice`: keep a library-wide run from starving media
+//! servers on the same disk. Source archives are read and output archives
+//! written through [`Throttled`], which paces transfers against one
+//! process-wide rate; temporary files are not throttled.
+
+use anyhow::Result;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Nice level applied by `--nice` (as with the `nice` command's default)
+#[cfg(unix)]
+const NICE_LEVEL: i32 = 10;
+
+struct Throttle {
+    bytes_per_second: f64,
+    /// When the transfers so far are paid for
+    next_free: Mutex<Instant>,
+}
+
+static IO_THROTTLE: OnceLock<Throttle> = OnceLock::new();
+
+/// Set the process-wide I/O rate in MB/s; the first configured rate applies
+pub(crate) fn configure(mb_per_second: Option<f64>) {
+    if let Some(rate) = mb_per_second {
+        IO_THROTTLE.get_or_init(|| Throttle {
+            bytes_per_second: rate * 1_000_000.0,
+            next_free: Mutex::new(Instant::now()),
+        });
+    }
+}
+
+/// Account for `bytes` transferred and sleep while the rate is exceeded
+fn consume(bytes: usize) {
+    let Some(throttle) = IO_THROTTLE.get() else {
+        return;
+    };
+    if bytes == 0 {
+        return;
+    }
+    let wait = {
+        let mut next_free = throttle.next_free.lock().unwrap();
+        let now = Instant::now();
+        *next_free = (*next_free).max(now) + Duration::from_secs_f64(bytes as f64 / throttle.bytes_per_second);
+        next_free.saturating_duration_since(now)
+    };
+    thread::sleep(wait);
+}
+
+/// A reader or writer paced by `--io-throttle`
+pub(crate) struct Throttled<T> {
+    inner: T,
+}
+
+impl<T> Throttled<T> {
+    pub(crate) fn new(inner: T) -> Self {
+        Throttled { inner }
+    }
+}
+
+/// Open a source file for throttled reading
+pub(crate) fn open(path: &Path) -> io::Result<Throttled<File>> {
+    File::open(path).map(Throttled::new)
+}
+
+/// Create an output file for throttled writing
+pub(crate) fn create(path: &Path) -> io::Result<Throttled<File>> {
+    File::create(path).map(Throttled::new)
+}
+
+impl<T: Read> Read for Throttled<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        consume(read);
+        Ok(read)
+    }
+}
+
+impl<T: Write> Write for Throttled<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        consume(written);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T: Seek> Seek for Throttled<T> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+/// `--nice`: lower the CPU priority of the process and, on Linux, its I/O
+/// priority to the lowest best-effort level. Threads started afterwards
+/// inherit both, so call this before the worker pool starts
+#[cfg(unix)]
+pub(crate) fn lower_priority() -> Result<()> {
+    // SAFETY: setpriority only changes the scheduling priority of the calling thread
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS as _, 0, NICE_LEVEL) } != 0 {
+        anyhow::bail!("Failed to lower CPU priority: {}", io::Error::last_os_error());
+    }
+    #[cfg(target_os = "linux")]
+    {
+        const IOPRIO_WHO_PROCESS: libc::c_long = 1;
+        const IOPRIO_CLASS_BEST_EFFORT: libc::c_long = 2;
+        const IOPRIO_CLASS_SHIFT: libc::c_long = 13;
+        const LOWEST_LEVEL: libc::c_long = 7;
+        // SAFETY: ioprio_set takes plain integers and only affects the calling thread
+        let result = unsafe {
+            libc::syscall(
+                libc::SYS_ioprio_set,
+                IOPRIO_WHO_PROCESS,
+                0,
+                (IOPRIO_CLASS_BEST_EFFORT << IOPRIO_CLASS_SHIFT) | LOWEST_LEVEL,
+            )
+        };
+        if result != 0 {
+            anyhow::bail!("Failed to lower I/O priority: {}", io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn lower_priority() -> Result<()> {
+    anyhow::bail!("--nice is only supported on Unix-like systems")
+}
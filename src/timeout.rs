@@ -0,0 +1,161 @@
+//! `--timeout-per-file` and `--timeout-per-page`: give up on a file that runs
+//! too long or stops making progress, e.g. a malformed PDF that keeps lopdf
+//! busy for an hour, instead of stalling the batch. A thread cannot be stopped
+//! from outside, so a watched file runs on a thread of its own that is
+//! abandoned when it times out: the file is reported as failed, its temporary
+//! directory is removed, and its worker stops at its next checkpoint (or when
+//! the stuck call returns) without writing an output. Once a worker commits
+//! to putting its output in place (replacing, renaming or trashing the
+//! original), it is no longer given up on, so the two never both happen.
+
+use anyhow::Result;
+use crossbeam_channel::{bounded, RecvTimeoutError};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::{ComicFile, FileProgress, Pipeline, Report};
+
+/// How often the watchdog looks at a running file
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// `Watch::state` of a file still being worked on
+const RUNNING: u8 = 0;
+/// `Watch::state` of a file the watchdog gave up on
+const CANCELLED: u8 = 1;
+/// `Watch::state` of a file whose worker is putting its output in place
+const COMMITTED: u8 = 2;
+
+/// Error of a file given up on, with the limit it exceeded
+#[derive(Debug)]
+pub(crate) struct TimedOut(String);
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+/// Whether `error` comes from a file that timed out
+pub(crate) fn is_timed_out(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| cause.is::<TimedOut>())
+}
+
+/// What the watchdog knows of one file, shared with its workers
+#[derive(Debug, Default)]
+pub(crate) struct Watch {
+    state: AtomicU8,
+    last_progress: Mutex<Option<Instant>>,
+    temp_dir: Mutex<Option<PathBuf>>,
+}
+
+impl Watch {
+    /// Record progress: a finished page or processing stage
+    pub(crate) fn touch(&self) {
+        *self.last_progress.lock().unwrap() = Some(Instant::now());
+    }
+
+    /// Remember the file's extraction directory, removed if it times out
+    pub(crate) fn set_temp_dir(&self, dir: &Path) {
+        *self.temp_dir.lock().unwrap() = Some(dir.to_path_buf());
+    }
+
+    /// Whether the file was given up on
+    pub(crate) fn cancelled(&self) -> bool {
+        self.state.load(Ordering::SeqCst) == CANCELLED
+    }
+
+    /// Claim the file for its irreversible steps; fails when it was given up
+    /// on, and afterwards the watchdog waits for the worker instead
+    pub(crate) fn commit(&self) -> Result<()> {
+        match self.state.compare_exchange(RUNNING, COMMITTED, Ordering::SeqCst, Ordering::SeqCst) {
+            Ok(_) | Err(COMMITTED) => Ok(()),
+            Err(_) => anyhow::bail!("Timed out"),
+        }
+    }
+
+    /// Give up on the file unless its worker already committed
+    fn cancel(&self) -> bool {
+        matches!(self.state.compare_exchange(RUNNING, CANCELLED, Ordering::SeqCst, Ordering::SeqCst), Ok(_) | Err(CANCELLED))
+    }
+
+    /// Fail once the file was given up on
+    pub(crate) fn check(&self) -> Result<()> {
+        if self.cancelled() {
+            anyhow::bail!("Timed out");
+        }
+        Ok(())
+    }
+
+    fn idle(&self) -> Duration {
+        self.last_progress.lock().unwrap().map_or(Duration::ZERO, |last| last.elapsed())
+    }
+}
+
+/// Process `comic_file` on a thread of its own, failing it when it exceeds
+/// `--timeout-per-file` or makes no progress for `--timeout-per-page`
+pub(crate) fn supervise(pipeline: &Pipeline, comic_file: &ComicFile, progress: &FileProgress) -> Result<Report> {
+    let options = pipeline.options();
+    let per_file = options.timeout_per_file.map(Duration::from_secs);
+    let per_page = options.timeout_per_page.map(Duration::from_secs);
+    let watch = progress.watch();
+    watch.touch();
+
+    let (sender, receiver) = bounded(1);
+    let (worker_pipeline, worker_file, worker_progress) = (pipeline.clone(), comic_file.clone(), progress.clone());
+    thread::Builder::new()
+        .name("compress_comics-file".to_string())
+        .spawn(move || {
+            // Nobody listens any more once the file timed out
+            let _ = sender.send(worker_pipeline.process_now(&worker_file, &worker_progress));
+        })?;
+
+    let started = Instant::now();
+    loop {
+        match receiver.recv_timeout(POLL_INTERVAL) {
+            Ok(result) => return result,
+            Err(RecvTimeoutError::Disconnected) => anyhow::bail!("Processing thread panicked"),
+            Err(RecvTimeoutError::Timeout) => {}
+        }
+        let reason = match (per_file, per_page) {
+            (Some(limit), _) if started.elapsed() >= limit => format!("Timed out after {}s (--timeout-per-file)", limit.as_secs()),
+            (_, Some(limit)) if watch.idle() >= limit => {
+                format!("Stalled: no page finished for {}s (--timeout-per-page)", limit.as_secs())
+            }
+            _ => continue,
+        };
+        if !watch.cancel() {
+            // The output is being put in place; that is finished, not interrupted
+            return receiver.recv().unwrap_or_else(|_| Err(anyhow::anyhow!("Processing thread panicked")));
+        }
+        if let Some(dir) = watch.temp_dir.lock().unwrap().take() {
+            // Files the stuck worker still holds open may keep parts of it
+            let _ = fs::remove_dir_all(dir);
+        }
+        return Err(TimedOut(reason).into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commit_and_cancel_exclude_each_other() {
+        let committed = Watch::default();
+        assert!(committed.commit().is_ok());
+        assert!(!committed.cancel());
+        assert!(!committed.cancelled());
+
+        let cancelled = Watch::default();
+        assert!(cancelled.cancel());
+        assert!(cancelled.commit().is_err());
+        assert!(cancelled.check().is_err());
+    }
+}
@@ -0,0 +1,108 @@
+//! Timestamps carried over from the source (unless `--no-preserve-times`):
+//! the output file gets the source archive's modification and access times
+//! (and creation time on Windows; Unix has no way to set it), and each ZIP
+//! entry keeps the date of the entry it came from. ZIP dates carry no time
+//! zone; they are read and written as UTC so they come back unchanged.
+//! `--deterministic` dates every entry 1980-01-01 00:00 instead, the
+//! earliest date ZIP can store, so the same input always gives the same bytes.
+
+use std::fs::{self, File, FileTimes, Metadata};
+use std::io;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::{civil_from_days, Options};
+
+/// How the entries of a written ZIP-based archive are dated
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum EntryDates {
+    /// The time of writing (`--no-preserve-times`)
+    Now,
+    /// The date of the entry or file the entry was made from
+    Preserved,
+    /// 1980-01-01 00:00 (`--deterministic`)
+    Fixed,
+}
+
+impl EntryDates {
+    pub(crate) fn of(args: &Options) -> Self {
+        if args.deterministic {
+            EntryDates::Fixed
+        } else if args.no_preserve_times {
+            EntryDates::Now
+        } else {
+            EntryDates::Preserved
+        }
+    }
+
+    /// Date of an entry made from a source dated `source`; `None` is now
+    pub(crate) fn date(self, source: impl FnOnce() -> Option<zip::DateTime>) -> Option<zip::DateTime> {
+        match self {
+            EntryDates::Now => None,
+            EntryDates::Preserved => source(),
+            EntryDates::Fixed => Some(zip::DateTime::default()),
+        }
+    }
+}
+
+/// Give the file at `target` the times of the file `source` was read from
+pub(crate) fn copy_file_times(source: &Metadata, target: &Path) -> io::Result<()> {
+    let mut times = FileTimes::new().set_modified(source.modified()?);
+    if let Ok(accessed) = source.accessed() {
+        times = times.set_accessed(accessed);
+    }
+    #[cfg(windows)]
+    if let Ok(created) = source.created() {
+        use std::os::windows::fs::FileTimesExt;
+        times = times.set_created(created);
+    }
+    File::options().write(true).open(target)?.set_times(times)
+}
+
+/// Set the modification time of an extracted or re-encoded file, if known
+pub(crate) fn set_modified(path: &Path, modified: Option<SystemTime>) {
+    if let Some(modified) = modified {
+        // Only the entry date in a later archive depends on it
+        let _ = File::options().write(true).open(path).and_then(|file| file.set_modified(modified));
+    }
+}
+
+/// Modification time of a file, as a ZIP entry date
+pub(crate) fn entry_time(path: &Path) -> Option<zip::DateTime> {
+    zip_time(fs::metadata(path).ok()?.modified().ok()?)
+}
+
+/// A point in time as a ZIP entry date; `None` outside 1980–2107
+pub(crate) fn zip_time(time: SystemTime) -> Option<zip::DateTime> {
+    let seconds = time.duration_since(UNIX_EPOCH).ok()?.as_secs() as i64;
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86_400));
+    let of_day = seconds.rem_euclid(86_400);
+    zip::DateTime::from_date_and_time(
+        u16::try_from(year).ok()?,
+        month as u8,
+        day as u8,
+        (of_day / 3_600) as u8,
+        (of_day / 60 % 60) as u8,
+        (of_day % 60) as u8,
+    )
+    .ok()
+}
+
+/// A ZIP entry date as a point in time
+pub(crate) fn system_time(date: zip::DateTime) -> Option<SystemTime> {
+    let days = days_from_civil(date.year() as i64, date.month() as u32, date.day() as u32);
+    let seconds = days * 86_400 + date.hour() as i64 * 3_600 + date.minute() as i64 * 60 + date.second() as i64;
+    UNIX_EPOCH.checked_add(Duration::from_secs(u64::try_from(seconds).ok()?))
+}
+
+/// Convert a (year, month, day) civil date to days since 1970-01-01; the
+/// inverse of `civil_from_days`
+pub(crate) fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
@@ -0,0 +1,169 @@
+//! `verify`: check that comics open and that every page decodes, e.g. before
+//! deleting the originals of a batch. `--verify` runs the same checks on each
+//! output before it is kept.
+
+use anyhow::Result;
+use image::ImageReader;
+use rayon::prelude::*;
+use serde::Serialize;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitCode};
+
+use crate::cli::EXIT_FILES_FAILED;
+use crate::{detect_comic_file, page_name, temp, unpack_pages, ComicFile, ComicType, OutputFormat, PageError, VerifyMode};
+
+/// Result of verifying one comic file
+#[derive(Debug, Clone, Serialize)]
+pub struct Verification {
+    pub path: PathBuf,
+    pub pages: usize,
+    /// Pages that failed to decode
+    pub page_errors: Vec<PageError>,
+}
+
+impl Verification {
+    pub fn is_ok(&self) -> bool {
+        self.pages > 0 && self.page_errors.is_empty()
+    }
+}
+
+/// Unpack a comic and fully decode every page. Archive-level damage (e.g. a
+/// ZIP CRC mismatch) fails the whole call
+pub fn verify(path: &Path) -> Result<Verification> {
+    verify_comic(&detect_comic_file(path)?, VerifyMode::Full)
+}
+
+fn verify_comic(comic_file: &ComicFile, mode: VerifyMode) -> Result<Verification> {
+    let temp_dir = temp::create_dir_in(None)?;
+    let pages = unpack_pages(comic_file, temp_dir.path())?;
+
+    let page_errors = pages
+        .par_iter()
+        .filter_map(|page| {
+            check_page(page, mode)
+                .err()
+                .map(|e| PageError::new(&page_name(page, temp_dir.path()), &e))
+        })
+        .collect();
+
+    Ok(Verification { path: comic_file.path.clone(), pages: pages.len(), page_errors })
+}
+
+/// `--verify`: fail unless the output at `path` unpacks, its pages pass
+/// `mode` and, with `expected_pages`, the page count matches the source
+/// (sliced pages may add pages). `source_errors` pages were copied unreadable
+/// from the source, so that many unreadable pages are tolerated
+pub(crate) fn check_output(
+    path: &Path,
+    output_format: OutputFormat,
+    mode: VerifyMode,
+    expected_pages: Option<usize>,
+    sliced: bool,
+    source_errors: usize,
+) -> Result<()> {
+    let file_type = match output_format {
+        OutputFormat::Cbz | OutputFormat::Zip => ComicType::Cbz,
+        OutputFormat::Cbr => ComicType::Cbr,
+        OutputFormat::Epub => ComicType::Epub,
+        OutputFormat::Pdf => ComicType::Pdf,
+    };
+    let verification = verify_comic(&ComicFile { path: path.to_path_buf(), file_type }, mode)?;
+
+    if verification.page_errors.len() > source_errors {
+        let error = &verification.page_errors[0];
+        anyhow::bail!(
+            "{} page(s) unreadable, first {}: {}",
+            verification.page_errors.len(),
+            error.page,
+            error.message
+        );
+    }
+    if let Some(expected) = expected_pages {
+        let matches = if sliced { verification.pages >= expected } else { verification.pages == expected };
+        if !matches {
+            anyhow::bail!("output has {} page(s), the source {}", verification.pages, expected);
+        }
+    }
+    Ok(())
+}
+
+/// Read the page header, or with `VerifyMode::Full` decode the whole page
+fn check_page(page: &Path, mode: VerifyMode) -> Result<()> {
+    let extension = page
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_lowercase)
+        .unwrap_or_default();
+    match extension.as_str() {
+        "jp2" => {
+            let image = jpeg2k::Image::from_file(page)
+                .map_err(|e| anyhow::anyhow!("Failed to open JPEG 2000 image: {:?}", e))?;
+            image
+                .get_pixels(None)
+                .map_err(|e| anyhow::anyhow!("Failed to decode JPEG 2000 image: {:?}", e))?;
+        }
+        "jxl" if mode == VerifyMode::Headers => {
+            let mut signature = [0u8; 12];
+            let read = File::open(page)?.read(&mut signature)?;
+            let codestream = read >= 2 && signature[..2] == [0xFF, 0x0A];
+            let container = read == 12 && signature == *b"\0\0\0\x0cJXL \r\n\x87\n";
+            if !codestream && !container {
+                anyhow::bail!("Not a JPEG XL image");
+            }
+        }
+        "jxl" => {
+            let decoded = tempfile::Builder::new().suffix(".png").tempfile()?;
+            let result = Command::new("djxl")
+                .arg(page)
+                .arg(decoded.path())
+                .output()
+                .map_err(|_| anyhow::anyhow!("Verifying JPEG XL pages requires the `djxl` tool (libjxl) on PATH"))?;
+            if !result.status.success() {
+                anyhow::bail!("djxl failed: {}", String::from_utf8_lossy(&result.stderr).trim());
+            }
+        }
+        _ if mode == VerifyMode::Headers => {
+            ImageReader::open(page)?.with_guessed_format()?.into_dimensions()?;
+        }
+        _ => {
+            ImageReader::open(page)?.with_guessed_format()?.decode()?;
+        }
+    }
+    Ok(())
+}
+
+/// Verify each file and print one line per file, listing unreadable pages
+pub(crate) fn run(files: &[PathBuf]) -> Result<ExitCode> {
+    let mut failed = 0;
+    for path in files {
+        let name = path.display();
+        match verify(path) {
+            Ok(verification) if verification.is_ok() => {
+                println!("✅ {} — {} page(s) OK", name, verification.pages);
+            }
+            Ok(verification) if verification.pages == 0 => {
+                println!("❌ {} — no pages found", name);
+                failed += 1;
+            }
+            Ok(verification) => {
+                println!(
+                    "❌ {} — {} of {} page(s) unreadable",
+                    name,
+                    verification.page_errors.len(),
+                    verification.pages
+                );
+                for error in &verification.page_errors {
+                    println!("   {}: {}: {}", error.page, error.kind, error.message);
+                }
+                failed += 1;
+            }
+            Err(e) => {
+                println!("❌ {} — {:#}", name, e);
+                failed += 1;
+            }
+        }
+    }
+    Ok(if failed > 0 { ExitCode::from(EXIT_FILES_FAILED) } else { ExitCode::SUCCESS })
+}
@@ -0,0 +1,188 @@
+//! `--watch`: a drop-folder daemon. New comic files below the watched
+//! directory are processed once they stop changing, so files still being
+//! copied are not picked up half-written.
+
+use anyhow::{Context, Result};
+use crossbeam_channel::{unbounded, RecvTimeoutError};
+use indicatif::ProgressBar;
+use notify::{RecursiveMode, Watcher};
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::cli::{failed_report, record_completed};
+use crate::history::History;
+use crate::report::FileRecord;
+use crate::state::{self, StateFile};
+use crate::{detect_comic_file, find_comic_files, hooks, library_scan, provenance, settings_fingerprint, FileProgress, Options, Pipeline, Report};
+
+/// How often pending files are checked for changes
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A file waiting for its size and modification time to settle
+struct Pending {
+    size: u64,
+    modified: Option<SystemTime>,
+    changed_at: Instant,
+}
+
+/// Watch `dir` until the watcher fails. Finished files are recorded in the
+/// state file, so restarting the service does not process them again
+pub fn run(dir: &Path, pipeline: &Pipeline, args: &Options) -> Result<ExitCode> {
+    let settle = Duration::from_secs(args.settle_secs);
+    let settings = settings_fingerprint(args);
+    let state_path = args.state_file.clone().unwrap_or_else(|| dir.join(state::STATE_FILE_NAME));
+    let job_state = StateFile::load(&state_path)?;
+    let history = args.history_db.as_deref().map(|path| History::open(path, args)).transpose()?;
+
+    let (sender, receiver) = unbounded();
+    let mut watcher = notify::recommended_watcher(move |event| {
+        let _ = sender.send(event);
+    })
+    .context("Failed to start file watcher")?;
+    watcher
+        .watch(dir, RecursiveMode::Recursive)
+        .with_context(|| format!("Failed to watch {}", dir.display()))?;
+
+    println!("👀 Watching {} (files are processed after {}s without changes)", dir.display(), args.settle_secs);
+    if let Some(output_dir) = &args.output_dir {
+        println!("   Results go to {}", output_dir.display());
+    }
+
+    // Files dropped while the service was down
+    let mut pending: HashMap<PathBuf, Pending> = HashMap::new();
+    for comic_file in find_comic_files(dir, args)? {
+        enqueue(&mut pending, comic_file.path, &job_state, &settings);
+    }
+
+    loop {
+        match receiver.recv_timeout(POLL_INTERVAL) {
+            Ok(Ok(event)) => {
+                for path in event.paths {
+                    enqueue(&mut pending, path, &job_state, &settings);
+                }
+            }
+            Ok(Err(e)) => eprintln!("Warning: File watcher error: {}", e),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return Ok(ExitCode::SUCCESS),
+        }
+
+        let mut changed = BTreeSet::new();
+        for path in settled(&mut pending, settle) {
+            if let Some(report) = process(&path, pipeline, args, &job_state, history.as_ref(), &settings) {
+                changed.extend(library_scan::changed_folders(&path, &report));
+            }
+        }
+        for scan in library_scan::rescan(args, &changed) {
+            println!("📚 {}", scan);
+        }
+    }
+}
+
+/// Start tracking a comic file, or restart its settle time when it changed
+fn enqueue(pending: &mut HashMap<PathBuf, Pending>, path: PathBuf, job_state: &StateFile, settings: &str) {
+    // Outputs written into the watched tree must not be compressed again
+    if detect_comic_file(&path).is_err() || provenance::is_tool_artifact(&path) || !path.is_file() {
+        return;
+    }
+    if !pending.contains_key(&path) && job_state.is_completed(&path, settings) {
+        return;
+    }
+    let Ok(metadata) = fs::metadata(&path) else {
+        return;
+    };
+    pending.insert(
+        path,
+        Pending { size: metadata.len(), modified: metadata.modified().ok(), changed_at: Instant::now() },
+    );
+}
+
+/// Remove and return files unchanged for `settle`; vanished files are dropped
+fn settled(pending: &mut HashMap<PathBuf, Pending>, settle: Duration) -> Vec<PathBuf> {
+    let mut ready = Vec::new();
+    pending.retain(|path, entry| {
+        let Ok(metadata) = fs::metadata(path) else {
+            return false;
+        };
+        let modified = metadata.modified().ok();
+        if metadata.len() != entry.size || modified != entry.modified {
+            *entry = Pending { size: metadata.len(), modified, changed_at: Instant::now() };
+            return true;
+        }
+        if entry.changed_at.elapsed() < settle {
+            return true;
+        }
+        ready.push(path.clone());
+        false
+    });
+    ready.sort();
+    ready
+}
+
+/// Process a settled file; returns its report unless it was not processed
+fn process(
+    path: &Path,
+    pipeline: &Pipeline,
+    args: &Options,
+    job_state: &StateFile,
+    history: Option<&History>,
+    settings: &str,
+) -> Option<Report> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+    // Re-check here: an output may have been written under a source-like name
+    if provenance::read_marker(path).is_some() {
+        return None;
+    }
+    let comic_file = detect_comic_file(path).ok()?;
+    let source = state::fingerprint_source(path);
+
+    let (page_sender, page_events) = unbounded();
+    let started = Instant::now();
+    let result = pipeline.process(&comic_file, &FileProgress::new(ProgressBar::hidden()).with_events(page_sender));
+    let report = match result {
+        Ok(report) => {
+            println!("{}", outcome_line(&name, &report));
+            if let Ok(source) = &source {
+                if let Err(e) = record_completed(job_state, &comic_file, &report, source, settings, args) {
+                    eprintln!("Warning: Failed to update state file: {}", e);
+                }
+            }
+            report
+        }
+        Err(e) => {
+            eprintln!("❌ {} — {:#}", name, e);
+            failed_report(path, format!("{:#}", e))
+        }
+    };
+    if let Some(history) = history {
+        let record = FileRecord::new(path, &report, started.elapsed(), page_events.try_iter().collect());
+        if let Err(e) = history.record(&record) {
+            eprintln!("Warning: Failed to update history database: {:#}", e);
+        }
+    }
+    hooks::after_file(args, path, &report);
+    Some(report)
+}
+
+/// One line per finished file, for service logs
+fn outcome_line(name: &str, report: &Report) -> String {
+    let mb = |bytes: u64| bytes as f64 / 1_048_576.0;
+    if report.compression_skipped {
+        return format!("⏭️  {} — already optimal, original kept ({})", name, report.page_summary());
+    }
+    let output = report
+        .output_path
+        .as_ref()
+        .map(|p| p.display().to_string())
+        .unwrap_or_default();
+    format!(
+        "✅ {} — {:.1} MB → {:.1} MB ({}) → {}",
+        name,
+        mb(report.original_size),
+        mb(report.compressed_size),
+        report.page_summary(),
+        output
+    )
+}